@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use serde::Serialize;
 use tg::Username;
 use v_utils::macros::{LiveSettings, MyConfigPrimitives, Settings};
@@ -8,4 +10,11 @@ pub struct Settings {
 	pub tg_token: String,
 	#[serde(default)]
 	pub admin_list: Option<Vec<Username>>,
+	/// JSON Schema the target has to fit, rather than the one its `$schema` key points to. Only for a single target; see `schemas` for several.
+	#[serde(default)]
+	pub schema: Option<String>,
+	/// JSON Schema each target has to fit, by the target's path, rather than the one its `$schema` key points to.
+	#[serde(default)]
+	#[settings(skip)]
+	pub schemas: HashMap<String, String>,
 }
@@ -1,163 +1,542 @@
 use std::{
-	fs::File,
-	io::{BufReader, BufWriter, Read, Write},
 	path::{Path, PathBuf},
-	process::Command,
+	sync::Arc,
 };
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use serde_yaml::Value as YamlValue;
-use toml::Value as TomlValue;
 use v_utils::prelude::*;
 
-use crate::utils::get_json_type;
+use crate::{
+	formats::{self, FormatBackend, Options, Registry},
+	utils::{self, get_json_type},
+};
+
+mod git;
+pub mod history;
+mod patch;
+mod schema;
+pub use git::{Git, GitError};
+pub use history::{History, Retention};
+pub use patch::PatchOp;
+pub use schema::{Range, Schema, Violation};
 
 #[derive(Clone, Debug, Default, derive_new::new)]
 pub struct Data {
 	inner: JsonValue,
 	path: PathBuf,
+	/// What the file is read and written as; detected from the path when unset.
+	#[new(default)]
+	format: Option<Arc<dyn FormatBackend>>,
+	#[new(default)]
+	options: formats::Options,
+	/// Hash of the file contents this data was last loaded from or written as; a mismatch means someone else changed the file.
+	#[new(default)]
+	disk_hash: Option<u64>,
+	/// Where the versions writes replace are kept; none are when unset.
+	#[new(default)]
+	history: Option<History>,
+	/// Who the edits being made are by, for the history and commits.
+	#[new(default)]
+	editor: Option<String>,
+	/// Paths edited since the last write, with what they held before, for the history and commits.
+	#[new(default)]
+	changed: Vec<(ValuePath, Option<JsonValue>)>,
+	/// What the last write changed.
+	#[new(default)]
+	last_edits: Vec<Edit>,
+	/// What the data has to fit before it's written; anything goes when unset.
+	#[new(default)]
+	schema: Option<Schema>,
+	/// How the file as last read or written already didn't fit the schema. Writes are only refused for violations they add, so that a file that's out of schema can still be fixed one edit at a time.
+	#[new(default)]
+	violations_on_disk: Vec<Violation>,
+	/// Where writes are committed; they aren't when unset.
+	#[new(default)]
+	git: Option<Git>,
+	/// Why the last write couldn't be committed, if it couldn't; the write itself stands.
+	#[new(default)]
+	git_failure: Option<Arc<GitError>>,
+}
+
+/// Everything that can go wrong in [`Data`] operations.
+#[derive(Debug, thiserror::Error)]
+pub enum DataError {
+	#[error("`{0}` doesn't exist")]
+	PathNotFound(ValuePath),
+	#[error("Type mismatch at `{path}`: expected {expected}, got {actual}")]
+	TypeMismatch { path: ValuePath, expected: &'static str, actual: &'static str },
+	#[error("`{path}` is {actual}, not {expected}")]
+	NotAContainer { path: ValuePath, expected: &'static str, actual: &'static str },
+	#[error("`{0}` already exists")]
+	AlreadyExists(ValuePath),
+	#[error("The root itself can't be created, deleted or renamed")]
+	AtRoot,
+	#[error("`{value}` is not in `{path}`")]
+	NotInArray { path: ValuePath, value: JsonValue },
+	#[error("Can't write this as {format}: {reason}")]
+	NotRepresentable { format: &'static str, reason: String },
+	#[error("Failed to read the {format} file: {reason}")]
+	Parse { format: &'static str, reason: String },
+	#[error("`{}` was changed outside the bot since it was last loaded", .0.display())]
+	Conflict(PathBuf),
+	#[error("Test failed: `{path}` is not `{expected}`")]
+	TestFailed { path: ValuePath, expected: JsonValue },
+	#[error("Invalid patch: {0}")]
+	InvalidPatch(String),
+	#[error("Patch operation #{index} failed: {source}")]
+	Patch { index: usize, source: Box<DataError> },
+	#[error("Couldn't tell the format of `{}` from its name or contents", .0.display())]
+	UnsupportedFormat(PathBuf),
+	#[error("Version #{0} of the file isn't kept (anymore)")]
+	VersionNotFound(u64),
+	#[error("`{0}` has changed since")]
+	ChangedSince(ValuePath),
+	#[error("Doesn't fit the schema: {}", .0.iter().map(Violation::to_string).collect::<Vec<_>>().join("; "))]
+	SchemaViolation(Vec<Violation>),
+	#[error("Couldn't use `{}` as a JSON Schema: {reason}", .path.display())]
+	InvalidSchema { path: PathBuf, reason: String },
+	#[error(transparent)]
+	Io(#[from] std::io::Error),
+}
+
+/// Violations reported at most per refused write; the first few say enough.
+pub const MAX_VIOLATIONS: usize = 5;
+
+fn content_hash(content: &str) -> u64 {
+	use std::hash::{DefaultHasher, Hash as _, Hasher as _};
+	let mut hasher = DefaultHasher::new();
+	content.hash(&mut hasher);
+	hasher.finish()
 }
 
 impl Data {
-	/// Load data from a file
-	pub fn load(path: &Path) -> Result<Self> {
-		let file = File::open(path)?;
-		let mut reader = BufReader::new(file);
-		let extension = path.extension().and_then(std::ffi::OsStr::to_str).unwrap_or("");
-
-		let data: JsonValue = match extension {
-			"json" | "json5" => {
-				let mut content = String::new();
-				reader.read_to_string(&mut content)?;
-				let json5_value: JsonValue = json5::from_str(&content).context("Failed to read JSON file")?;
-				json5_value
-			}
-			"yaml" | "yml" => {
-				let yaml_value: YamlValue = serde_yaml::from_reader(reader).context("Failed to read YAML file")?;
-				serde_json::to_value(yaml_value).context("Failed to convert YAML to JSON")?
-			}
-			"toml" => {
-				let mut content = String::new();
-				reader.into_inner().read_to_string(&mut content)?;
-				let toml_value: TomlValue = toml::from_str(&content).context("Failed to read TOML file")?;
-				serde_json::to_value(toml_value).context("Failed to convert TOML to JSON")?
-			}
-			"nix" => {
-				drop(reader); // Close file before running nix eval
-				let json_str = eval_nix_file(path)?;
-				serde_json::from_str(&json_str).context("Failed to parse Nix output as JSON")?
-			}
-			_ => return Err(eyre!("Unsupported file format")),
+	/// Load data from a file, in whichever built-in format its name or contents suggest, with default options.
+	pub fn load(path: &Path) -> Result<Self, DataError> {
+		Self::load_with(path, &Registry::default(), Options::default())
+	}
+
+	/// Load data from a file, in whichever format of `registry` recognizes its name or contents.
+	pub fn load_with(path: &Path, registry: &Registry, options: Options) -> Result<Self, DataError> {
+		let content = std::fs::read_to_string(path)?;
+		let format = registry.resolve(path, &content).ok_or_else(|| DataError::UnsupportedFormat(path.to_path_buf()))?;
+		Self::load_as(path, format, options)
+	}
+
+	/// Load data from a file in the given format, whatever its name says. The file is read with `options` from the start, as evaluating it may depend on them.
+	pub fn load_as(path: &Path, format: Arc<dyn FormatBackend>, options: Options) -> Result<Self, DataError> {
+		let mut data = Self {
+			format: Some(format),
+			options,
+			..Self::new(JsonValue::Null, path.to_path_buf())
 		};
+		data.reload()?;
+		Ok(data)
+	}
 
-		Ok(Self::new(data, path.to_path_buf()))
+	/// Whether [`load`](Self::load) knows the format of `path` from its name alone.
+	pub fn supports(path: &Path) -> bool {
+		Registry::default().detect(path).is_some()
 	}
 
-	/// Write data to the source file
-	pub fn write(&self) -> Result<()> {
-		let file = File::create(&self.path)?;
-		let mut writer = BufWriter::new(file);
-		let extension = self.path.extension().and_then(std::ffi::OsStr::to_str).unwrap_or("");
+	/// The backend the file is read and written with, as resolved when it was loaded.
+	pub fn format(&self) -> Result<Arc<dyn FormatBackend>, DataError> {
+		match &self.format {
+			Some(format) => Ok(format.clone()),
+			None => Registry::default().detect(&self.path).ok_or_else(|| DataError::UnsupportedFormat(self.path.clone())),
+		}
+	}
 
-		match extension {
-			"json" | "json5" => serde_json::to_writer(writer, &self.inner).context("Failed to write JSON file")?,
-			"yaml" | "yml" => {
-				let yaml_value: YamlValue = serde_json::from_value(self.inner.clone()).context("Failed to convert JSON to YAML")?;
-				serde_yaml::to_writer(writer, &yaml_value).context("Failed to write YAML file")?
-			}
-			"toml" => {
-				let toml_value: TomlValue = serde_json::from_value(self.inner.clone()).context("Failed to convert JSON to TOML")?;
-				writer.write_all(toml::to_string(&toml_value).context("Failed to write TOML file")?.as_bytes())?;
-			}
-			"nix" => {
-				let nix_content = json_to_nix_file(&self.inner);
-				writer.write_all(nix_content.as_bytes())?;
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Write data to the source file, unless it has been changed by someone else since it was loaded.
+	pub fn write(&mut self) -> Result<(), DataError> {
+		let mut added = Schema::added(&self.violations_on_disk, self.violations());
+		if !added.is_empty() {
+			added.truncate(MAX_VIOLATIONS);
+			return Err(DataError::SchemaViolation(added));
+		}
+		let original = self.unchanged_original()?;
+		// nothing touches the file until serialization has succeeded
+		let content = self.serialize(original.as_deref())?;
+		let change = history::Change::Edit(self.changed.iter().map(|(path, _)| path.clone()).collect());
+		self.replace(original.as_deref(), &content, change)?;
+		self.last_edits = std::mem::take(&mut self.changed)
+			.into_iter()
+			.map(|(path, before)| Edit {
+				after: self.at(&path).ok(),
+				path,
+				before,
+			})
+			.collect();
+		self.violations_on_disk = self.violations();
+		let message = self.commit_message(&self.edit_summary(), &self.last_edits);
+		self.commit_to_git(&message);
+		Ok(())
+	}
+
+	/// What the last write changed, for [`revert`](Self::revert) to undo.
+	pub fn last_edits(&self) -> &[Edit] {
+		&self.last_edits
+	}
+
+	/// Undo `edits`, as made by a write, in a write of its own; refused if anything they changed has been changed again since.
+	///
+	/// Redoing them is reverting their [inverse](Edit::inverse), in reverse order.
+	pub fn revert(&mut self, edits: &[Edit]) -> Result<(), DataError> {
+		if let Some(edit) = edits.iter().find(|edit| self.at(&edit.path).ok() != edit.after) {
+			return Err(DataError::ChangedSince(edit.path.clone()));
+		}
+		let mut candidate = self.clone();
+		// edits of paths inside others come after them, and are undone first
+		for edit in edits.iter().rev() {
+			match (&edit.before, &edit.after) {
+				(Some(before), _) => candidate.update_at(&edit.path, before.clone(), UpdateAction::Set)?,
+				(None, Some(_)) => candidate.update_at(&edit.path, JsonValue::Null, UpdateAction::Delete)?,
+				(None, None) => {}
 			}
-			_ => return Err(eyre!("Unsupported file format")),
 		}
+		candidate.write()?;
+		*self = candidate;
+		Ok(())
+	}
 
+	/// Roll the file back to version `id` of its history.
+	pub fn restore(&mut self, id: u64) -> Result<(), DataError> {
+		let content = self.history.as_ref().and_then(|history| history.content(id).ok()).ok_or(DataError::VersionNotFound(id))?;
+		let original = self.unchanged_original()?;
+		let format = self.format()?;
+		let inner = format.parse(&self.path, &content, &self.options).map_err(|e| DataError::Parse {
+			format: format.name(),
+			reason: format!("{e:#}"),
+		})?;
+		self.replace(original.as_deref(), &content, history::Change::Restore(id))?;
+		self.inner = inner;
+		self.violations_on_disk = self.violations();
+		self.changed.clear();
+		self.last_edits.clear();
+		let message = self.commit_message(&format!("Restore {} to version #{id}", self.file_name()), &[]);
+		self.commit_to_git(&message);
 		Ok(())
 	}
 
+	/// The current contents of the file, unless they have been changed by someone else since it was loaded.
+	fn unchanged_original(&self) -> Result<Option<String>, DataError> {
+		let original = std::fs::read_to_string(&self.path).ok();
+		if self.disk_hash.is_some() && original.as_deref().map(content_hash) != self.disk_hash {
+			return Err(DataError::Conflict(self.path.clone()));
+		}
+		Ok(original)
+	}
+
+	/// Replace `original`, the current contents of the file, with `content`, keeping `original` in the history.
+	fn replace(&mut self, original: Option<&str>, content: &str, change: history::Change) -> Result<(), DataError> {
+		if let (Some(history), Some(original)) = (&self.history, original) {
+			// an edit that couldn't be undone isn't made
+			history.record(original, self.editor.clone(), change)?;
+		}
+		utils::write_atomic(&self.path, content.as_bytes())?;
+		self.disk_hash = Some(content_hash(content));
+		Ok(())
+	}
+
+	/// Commit the file as just written, remembering why if that fails.
+	fn commit_to_git(&mut self, message: &str) {
+		self.git_failure = self.git.as_ref().and_then(|git| git.commit(message).err()).map(Arc::new);
+	}
+
+	/// "Set /a, /b in config.toml", or so, for the last write.
+	fn edit_summary(&self) -> String {
+		let file = self.file_name();
+		match self.last_edits.len() {
+			0 => format!("Update {file}"),
+			1..=3 => format!("Set {} in {file}", self.last_edits.iter().map(|edit| edit.path.to_string()).collect::<Vec<_>>().join(", ")),
+			n => format!("Set {n} values in {file}"),
+		}
+	}
+
+	/// `subject`, then what each of `edits` changed, then who by.
+	fn commit_message(&self, subject: &str, edits: &[Edit]) -> String {
+		const MAX_PREVIEW: usize = 60;
+		let preview = |value: Option<&JsonValue>| match value {
+			None => "(none)".to_owned(),
+			Some(value) => {
+				let value = value.to_string();
+				match value.chars().count() > MAX_PREVIEW {
+					true => format!("{}…", value.chars().take(MAX_PREVIEW).collect::<String>()),
+					false => value,
+				}
+			}
+		};
+		let mut message = subject.to_owned();
+		if !edits.is_empty() {
+			message.push('\n');
+		}
+		for Edit { path, before, after } in edits {
+			message.push_str(&format!("\n{path}: {} -> {}", preview(before.as_ref()), preview(after.as_ref())));
+		}
+		if let Some(editor) = &self.editor {
+			message.push_str(&format!("\n\nEdited-by: {editor}"));
+		}
+		message
+	}
+
+	fn file_name(&self) -> String {
+		self.path.file_name().map_or_else(|| self.path.display().to_string(), |name| name.to_string_lossy().into_owned())
+	}
+
+	/// Remember that `path` is about to be edited, along with what it holds now; the oldest value is the one kept for a path edited twice, and edits inside one already edited are part of it.
+	fn note_change(&mut self, path: &ValuePath) {
+		// an edit inside an array can shift the elements after it, so it counts as an edit of the whole array
+		let path = match path.is_top() {
+			false if self.at(&path.parent()).is_ok_and(|parent| parent.is_array()) => path.parent(),
+			_ => path.clone(),
+		};
+		if !self.changed.iter().any(|(changed, _)| path.to_vec().starts_with(&changed.to_vec())) {
+			let old = self.at(&path).ok();
+			self.changed.push((path, old));
+		}
+	}
+
+	/// Commit every write to the git work tree the file is tracked in, if it is, on `branch` or the checked out one.
+	pub fn with_git(mut self, branch: Option<String>) -> Self {
+		self.git = Git::discover(&self.path, branch);
+		self
+	}
+
+	pub fn git(&self) -> Option<&Git> {
+		self.git.as_ref()
+	}
+
+	/// Why the last write couldn't be committed to git, if it couldn't.
+	pub fn take_git_failure(&mut self) -> Option<Arc<GitError>> {
+		self.git_failure.take()
+	}
+
+	/// Check writes against the JSON Schema at `path`, or failing that the one the file's top-level `$schema` key points to, if it's a local file.
+	///
+	/// A `$schema` path is relative to the file's directory.
+	pub fn with_schema(mut self, path: Option<&Path>) -> Result<Self, DataError> {
+		let path = match path {
+			Some(path) => path.to_path_buf(),
+			None => match self.schema_reference() {
+				Some(path) => path,
+				None => return Ok(self),
+			},
+		};
+		let invalid = |reason: String| DataError::InvalidSchema { path: path.clone(), reason };
+		let raw = Data::load(&path).map_err(|e| invalid(e.to_string()))?.inner;
+		self.schema = Some(Schema::new(raw).map_err(invalid)?);
+		self.violations_on_disk = self.violations();
+		Ok(self)
+	}
+
+	/// Where the file's `$schema` key points, unless it's a remote URL, which isn't fetched.
+	fn schema_reference(&self) -> Option<PathBuf> {
+		let reference = self.inner.get("$schema")?.as_str()?;
+		let reference = reference.strip_prefix("file://").unwrap_or(reference);
+		if reference.contains("://") {
+			return None;
+		}
+		Some(self.path.parent().unwrap_or(Path::new("")).join(reference))
+	}
+
+	/// `$schema` of the file, when it isn't followed.
+	pub fn remote_schema(&self) -> Option<&str> {
+		let reference = self.inner.get("$schema")?.as_str()?;
+		(self.schema_reference().is_none() && self.schema.is_none()).then_some(reference)
+	}
+
+	pub fn schema(&self) -> Option<&Schema> {
+		self.schema.as_ref()
+	}
+
+	/// The ways the data doesn't fit its schema; empty if it does, or has none.
+	///
+	/// Those the file already had when it was read aren't held against writes.
+	pub fn violations(&self) -> Vec<Violation> {
+		self.schema.as_ref().map(|schema| schema.violations(&self.inner)).unwrap_or_default()
+	}
+
+	/// Keep the versions writes replace, within `retention`.
+	pub fn with_history(mut self, retention: Retention) -> Self {
+		self.history = Some(History::new(&self.path, retention));
+		self
+	}
+
+	pub fn history(&self) -> Option<&History> {
+		self.history.as_ref()
+	}
+
+	/// Attribute the edits that follow to `editor` in the history and commits.
+	pub fn set_editor(&mut self, editor: Option<String>) {
+		self.editor = editor;
+	}
+
+	/// Whether the file differs from what this data was last loaded from or written as.
+	pub fn changed_on_disk(&self) -> bool {
+		std::fs::read_to_string(&self.path).ok().as_deref().map(content_hash) != self.disk_hash
+	}
+
+	/// Render the data as the new contents of the source file, which currently holds `original`.
+	fn serialize(&self, original: Option<&str>) -> Result<String, DataError> {
+		let format = self.format()?;
+		// refuse what the format can't hold before the backend gets to try
+		formats::check(&*format, &self.inner)
+			// format-preserving backends patch the current file instead of starting from scratch
+			.and_then(|()| format.serialize(&self.path, original, &self.inner, &self.options))
+			.map_err(|e| DataError::NotRepresentable {
+				format: format.name(),
+				reason: format!("{e:#}"),
+			})
+	}
+
 	/// Load the file without needing to provide the path again
-	pub fn reload(&mut self) -> Result<()> {
-		self.inner = Self::load(&self.path)?.inner;
+	pub fn reload(&mut self) -> Result<(), DataError> {
+		let format = self.format()?;
+		let content = std::fs::read_to_string(&self.path)?;
+		self.inner = format.parse(&self.path, &content, &self.options).map_err(|e| DataError::Parse {
+			format: format.name(),
+			reason: format!("{e:#}"),
+		})?;
+		self.disk_hash = Some(content_hash(&content));
+		self.violations_on_disk = self.violations();
 		Ok(())
 	}
 
-	/// Read raw file contents and return (content, extension)
-	pub fn read_raw(&self) -> Result<(String, String)> {
+	/// Read raw file contents and return (content, language), the language being the tag to highlight them with
+	pub fn read_raw(&self) -> Result<(String, &'static str), DataError> {
 		let content = std::fs::read_to_string(&self.path)?;
-		let ext = self.path.extension().and_then(std::ffi::OsStr::to_str).unwrap_or("").to_owned();
-		Ok((content, ext))
+		Ok((content, self.format()?.language()))
 	}
 
-	pub fn at(&self, level: &ValuePath) -> Option<JsonValue> {
+	pub fn at(&self, level: &ValuePath) -> Result<JsonValue, DataError> {
 		let mut current = &self.inner;
 		for part in level.to_vec() {
-			current = current.get(&part)?;
+			current = child(current, &part).ok_or_else(|| DataError::PathNotFound(level.clone()))?;
 		}
-		Some(current.clone())
+		Ok(current.clone())
 	}
 
 	pub fn update(&mut self, new_value: JsonValue) {
 		self.inner = new_value;
 	}
 
-	pub fn update_at<UA>(&mut self, level: &ValuePath, new_value: JsonValue, into_action: UA) -> Result<(), String>
+	/// [`update_at`](Self::update_at) followed by [`write`](Self::write), as one step: if the edited tree can't be written in the file's format, neither memory nor the file change.
+	pub fn commit_at<UA>(&mut self, level: &ValuePath, new_value: JsonValue, into_action: UA) -> Result<(), DataError>
+	where
+		UA: Into<UpdateAction>, {
+		let mut candidate = self.clone();
+		candidate.update_at(level, new_value, into_action)?;
+		candidate.write()?;
+		*self = candidate;
+		Ok(())
+	}
+
+	pub fn update_at<UA>(&mut self, level: &ValuePath, new_value: JsonValue, into_action: UA) -> Result<(), DataError>
 	where
 		UA: Into<UpdateAction>, {
-		let mut current = &mut self.inner;
-		let path = level.to_vec();
 		let action = into_action.into();
+		let noted = self.changed.len();
+		self.note_change(level);
+		if let (UpdateAction::Rename, JsonValue::String(new_key)) = (action, &new_value) {
+			self.note_change(&level.parent().join(new_key));
+		}
+		let result = self.edit_at(level, new_value, action);
+		if result.is_err() {
+			self.changed.truncate(noted);
+		}
+		result
+	}
+
+	fn edit_at(&mut self, level: &ValuePath, new_value: JsonValue, action: UpdateAction) -> Result<(), DataError> {
+		let path = level.to_vec();
+		// a schema says what arrays may hold, and is checked on write; without one, the first element is the example to follow
+		let same_type_as_first = self.schema.is_none();
 
-		for (i, part) in path.iter().enumerate() {
-			if i == path.len() - 1 {
-				// We're at the last element, so we apply the update action
-				let obj = current.as_object_mut().unwrap();
-				match action {
-					UpdateAction::Set => {
-						obj.insert(part.clone(), new_value);
-					}
-					UpdateAction::AddTo => {
-						let existing = obj.get_mut(part).unwrap();
-						if let JsonValue::Array(existing_arr) = existing {
-							if !existing_arr.is_empty() && get_json_type(&existing_arr[0]) != get_json_type(&new_value) {
-								return Err(format!("Type mismatch: Expected {}, got {}", get_json_type(&existing_arr[0]), get_json_type(&new_value)));
-							}
-							existing_arr.push(new_value);
-						} else {
-							panic!("Target is not an array");
-						}
-					}
-					UpdateAction::RemoveFrom => {
-						dbg!(&part);
-						let existing = obj.get_mut(part).unwrap();
-						if let JsonValue::Array(existing_arr) = existing {
-							if existing_arr.is_empty() {
-								return Err("Cannot remove from an empty array".to_string());
-							}
-							if get_json_type(&existing_arr[0]) != get_json_type(&new_value) {
-								return Err(format!("Type mismatch: Expected {}, got {}", get_json_type(&existing_arr[0]), get_json_type(&new_value)));
-							}
-							let initial_len = existing_arr.len();
-							existing_arr.retain(|item| item != &new_value);
-							if existing_arr.len() == initial_len {
-								return Err("Value not found in the array".to_string());
-							}
-						} else {
-							panic!("Target is not an array");
-						}
-					}
+		let Some((last, parents)) = path.split_last() else {
+			// the root itself
+			return match action {
+				UpdateAction::Insert | UpdateAction::Delete | UpdateAction::Rename => Err(DataError::AtRoot),
+				_ => apply(&mut self.inner, level, new_value, action, same_type_as_first),
+			};
+		};
+		let mut current = &mut self.inner;
+		for (i, part) in parents.iter().enumerate() {
+			current = child_mut(current, part).ok_or_else(|| DataError::PathNotFound(ValuePath::from(path[..=i].to_vec())))?;
+		}
+		match (current, action) {
+			(JsonValue::Object(obj), UpdateAction::Set) => {
+				obj.insert(last.clone(), new_value);
+				Ok(())
+			}
+			(JsonValue::Object(obj), UpdateAction::Insert) => match obj.contains_key(last) {
+				true => Err(DataError::AlreadyExists(level.clone())),
+				false => {
+					obj.insert(last.clone(), new_value);
+					Ok(())
 				}
-				return Ok(());
-			} else {
-				// We're not at the last element, so we navigate to the next level
-				current = current.get_mut(part).unwrap();
+			},
+			(JsonValue::Object(obj), UpdateAction::Delete) => obj.shift_remove(last).map(drop).ok_or_else(|| DataError::PathNotFound(level.clone())),
+			(JsonValue::Array(arr), UpdateAction::Delete) => match index(last) {
+				Some(i) if i < arr.len() => {
+					arr.remove(i);
+					Ok(())
+				}
+				_ => Err(DataError::PathNotFound(level.clone())),
+			},
+			(JsonValue::Object(obj), UpdateAction::Rename) => {
+				let JsonValue::String(new_key) = new_value else {
+					return Err(DataError::TypeMismatch {
+						path: level.clone(),
+						expected: "String",
+						actual: get_json_type(&new_value),
+					});
+				};
+				if new_key != *last && obj.contains_key(&new_key) {
+					return Err(DataError::AlreadyExists(level.parent().join(&new_key)));
+				}
+				let value = obj.shift_remove(last).ok_or_else(|| DataError::PathNotFound(level.clone()))?;
+				obj.insert(new_key, value);
+				Ok(())
 			}
+			(current @ JsonValue::Array(_), UpdateAction::Insert | UpdateAction::Rename) => Err(DataError::NotAContainer {
+				path: level.parent(),
+				expected: "Object",
+				actual: get_json_type(current),
+			}),
+			(current @ (JsonValue::Object(_) | JsonValue::Array(_)), _) => {
+				let target = child_mut(current, last).ok_or_else(|| DataError::PathNotFound(level.clone()))?;
+				apply(target, level, new_value, action, same_type_as_first)
+			}
+			(current, _) => Err(DataError::NotAContainer {
+				path: level.parent(),
+				expected: "Object",
+				actual: get_json_type(current),
+			}),
 		}
-		panic!("Empty path");
+	}
+
+	/// Apply a JSON Patch (RFC 6902) document in memory. All operations succeed or none do; `test` operations act as preconditions.
+	pub fn patch(&mut self, ops: &[PatchOp]) -> Result<(), DataError> {
+		let mut candidate = self.inner.clone();
+		patch::apply(&mut candidate, ops)?;
+		for path in ops.iter().flat_map(PatchOp::changes) {
+			self.note_change(path);
+		}
+		self.inner = candidate;
+		Ok(())
+	}
+
+	/// [`patch`](Self::patch) followed by [`write`](Self::write), as one step, like [`commit_at`](Self::commit_at).
+	pub fn apply_patch(&mut self, ops: &[PatchOp]) -> Result<(), DataError> {
+		let mut candidate = self.clone();
+		candidate.patch(ops)?;
+		candidate.write()?;
+		*self = candidate;
+		Ok(())
 	}
 
 	#[doc(hidden)]
@@ -166,55 +545,66 @@ impl Data {
 	}
 }
 
-/// Evaluate a Nix file and return the JSON output
-fn eval_nix_file(path: &Path) -> Result<String> {
-	let output = Command::new("nix")
-		.arg("eval")
-		.arg("--json")
-		.arg("--impure")
-		.arg("--expr")
-		.arg(format!("import {}", path.display()))
-		.output()
-		.context("Failed to execute nix command. Is nix installed?")?;
-
-	if !output.status.success() {
-		let stderr = String::from_utf8_lossy(&output.stderr);
-		return Err(eyre!("Nix evaluation failed: {}", stderr));
+/// The entry `part` of an object, or the element at index `part` of an array.
+fn child<'v>(value: &'v JsonValue, part: &str) -> Option<&'v JsonValue> {
+	match value {
+		JsonValue::Array(arr) => arr.get(index(part)?),
+		_ => value.get(part),
 	}
+}
 
-	String::from_utf8(output.stdout).context("Nix output is not valid UTF-8")
+fn child_mut<'v>(value: &'v mut JsonValue, part: &str) -> Option<&'v mut JsonValue> {
+	match value {
+		JsonValue::Array(arr) => arr.get_mut(index(part)?),
+		_ => value.get_mut(part),
+	}
 }
 
-/// Convert JSON value to a complete Nix file content
-fn json_to_nix_file(json: &JsonValue) -> String {
-	json_to_nix_value(json, 0)
+/// Array indices are plain decimal numbers, without signs or leading zeroes.
+fn index(part: &str) -> Option<usize> {
+	match !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) && (part.len() == 1 || !part.starts_with('0')) {
+		true => part.parse().ok(),
+		false => None,
+	}
 }
 
-/// Convert JSON value to Nix syntax with proper indentation
-fn json_to_nix_value(json: &JsonValue, indent: usize) -> String {
-	let indent_str = "  ".repeat(indent);
-	let inner_indent = "  ".repeat(indent + 1);
-
-	match json {
-		JsonValue::Null => "null".to_string(),
-		JsonValue::Bool(b) => if *b { "true" } else { "false" }.to_string(),
-		JsonValue::Number(n) => n.to_string(),
-		JsonValue::String(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
-		JsonValue::Array(arr) =>
-			if arr.is_empty() {
-				"[]".to_string()
-			} else {
-				let items: Vec<String> = arr.iter().map(|v| format!("{}{}", inner_indent, json_to_nix_value(v, indent + 1))).collect();
-				format!("[\n{}\n{}]", items.join("\n"), indent_str)
-			},
-		JsonValue::Object(obj) =>
-			if obj.is_empty() {
-				"{}".to_string()
-			} else {
-				let items: Vec<String> = obj.iter().map(|(k, v)| format!("{}{} = {};", inner_indent, k, json_to_nix_value(v, indent + 1))).collect();
-				format!("{{\n{}\n{}}}", items.join("\n"), indent_str)
-			},
+/// Apply `action` to `target`, which lives at `path`.
+fn apply(target: &mut JsonValue, path: &ValuePath, new_value: JsonValue, action: UpdateAction, same_type_as_first: bool) -> Result<(), DataError> {
+	if action == UpdateAction::Set {
+		*target = new_value;
+		return Ok(());
+	}
+	let actual = get_json_type(target);
+	let JsonValue::Array(existing_arr) = target else {
+		return Err(DataError::NotAContainer {
+			path: path.clone(),
+			expected: "Array",
+			actual,
+		});
+	};
+	if let Some(first) = existing_arr.first().filter(|_| same_type_as_first) {
+		if get_json_type(first) != get_json_type(&new_value) {
+			return Err(DataError::TypeMismatch {
+				path: path.clone(),
+				expected: get_json_type(first),
+				actual: get_json_type(&new_value),
+			});
+		}
 	}
+	match action {
+		UpdateAction::AddTo => existing_arr.push(new_value),
+		_ => {
+			let initial_len = existing_arr.len();
+			existing_arr.retain(|item| item != &new_value);
+			if existing_arr.len() == initial_len {
+				return Err(DataError::NotInArray {
+					path: path.clone(),
+					value: new_value,
+				});
+			}
+		}
+	}
+	Ok(())
 }
 
 impl AsRef<JsonValue> for Data {
@@ -222,47 +612,55 @@ impl AsRef<JsonValue> for Data {
 		&self.inner
 	}
 }
+/// What a write changed at one path: `None` is for it not existing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+	pub path: ValuePath,
+	pub before: Option<JsonValue>,
+	pub after: Option<JsonValue>,
+}
+impl Edit {
+	/// The same edit the other way around.
+	pub fn inverse(&self) -> Self {
+		Self {
+			path: self.path.clone(),
+			before: self.after.clone(),
+			after: self.before.clone(),
+		}
+	}
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum UpdateAction {
 	Set,
 	AddTo,
 	RemoveFrom,
-}
-impl From<crate::telegram::InputValueType> for UpdateAction {
-	fn from(action: crate::telegram::InputValueType) -> Self {
-		match action {
-			crate::telegram::InputValueType::UpdateAt => Self::Set,
-			crate::telegram::InputValueType::AddTo => Self::AddTo,
-			crate::telegram::InputValueType::RemoveFrom => Self::RemoveFrom,
-		}
-	}
+	/// Create a key that doesn't exist yet.
+	Insert,
+	/// Remove the key or array element, with everything under it. The value is ignored.
+	Delete,
+	/// Rename the key to the string given as the value, keeping what's under it.
+	Rename,
 }
 
-/// Callback data must never be empty, so 0 level is "/" and not ""
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, derive_new::new)]
-pub struct ValuePath(String);
+/// Location of a value: the object keys and array indices leading to it.
+///
+/// Written as an RFC 6901 JSON Pointer, each segment prefixed by `/`, with `~` escaped as `~0` and `/` as `~1`. Callback data must never be empty, so unlike in a JSON Pointer the root is "/" and not "", and an empty key is written as `~2` to keep the two apart.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, derive_new::new)]
+pub struct ValuePath(Vec<String>);
 impl ValuePath {
 	pub fn push(&mut self, part: &str) {
-		if !self.is_top() {
-			self.0.push('/');
-		}
-		self.0.push_str(part);
+		self.0.push(part.to_owned());
 	}
 
 	pub fn parent(&self) -> Self {
-		let mut new_v = self.0.clone();
-		let pos = new_v.rfind("/").unwrap_or(0);
-		new_v.truncate(pos);
-		match new_v.is_empty() {
-			true => Self::default(),
-			false => Self(new_v),
-		}
+		let mut parent = self.clone();
+		parent.0.pop();
+		parent
 	}
 
 	pub fn basename(&self) -> String {
-		let pos = self.0.rfind("/").unwrap_or(0);
-		let offset = Self::default().0.len();
-		self.0[(pos + offset)..].to_string()
+		self.0.last().cloned().unwrap_or_default()
 	}
 
 	pub fn join(&self, part: &str) -> Self {
@@ -272,47 +670,71 @@ impl ValuePath {
 	}
 
 	pub fn is_top(&self) -> bool {
-		assert!(!self.0.is_empty());
-		self.0 == "/"
+		self.0.is_empty()
 	}
 
 	fn to_vec(&self) -> Vec<String> {
-		self.0.split("/").map(String::from).filter(|v| v != "").collect()
+		self.0.clone()
 	}
 
 	pub fn into_string(self) -> String {
-		self.0
-	}
-}
-impl Default for ValuePath {
-	fn default() -> Self {
-		Self("/".to_string())
+		self.to_string()
 	}
 }
 impl From<Vec<String>> for ValuePath {
 	fn from(parts: Vec<String>) -> Self {
-		let s = "/".to_owned() + &parts.join("/");
-		Self(s)
+		Self(parts)
 	}
 }
+/// Parses the escaped form; the leading `/` is optional.
 impl From<&str> for ValuePath {
 	fn from(s: &str) -> Self {
-		Self(s.to_string())
+		let s = s.strip_prefix('/').unwrap_or(s);
+		if s.is_empty() {
+			return Self::default();
+		}
+		Self(
+			s.split('/')
+				.map(|segment| match segment {
+					"~2" => String::new(),
+					_ => segment.replace("~1", "/").replace("~0", "~"),
+				})
+				.collect(),
+		)
 	}
 }
 impl From<String> for ValuePath {
 	fn from(s: String) -> Self {
-		Self(s)
+		Self::from(s.as_str())
 	}
 }
 impl From<ValuePath> for Vec<String> {
 	fn from(level: ValuePath) -> Self {
-		level.to_vec()
+		level.0
 	}
 }
 impl std::fmt::Display for ValuePath {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "{}", self.0)
+		if self.0.is_empty() {
+			return write!(f, "/");
+		}
+		for segment in &self.0 {
+			match segment.as_str() {
+				"" => write!(f, "/~2")?,
+				_ => write!(f, "/{}", segment.replace('~', "~0").replace('/', "~1"))?,
+			}
+		}
+		Ok(())
+	}
+}
+impl Serialize for ValuePath {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+impl<'de> Deserialize<'de> for ValuePath {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Ok(Self::from(String::deserialize(deserializer)?))
 	}
 }
 
@@ -447,21 +869,391 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn update_errors_are_typed() {
+		let mut data = Data::mock(json!({"a": {"b": 1}, "list": [1, 2]}));
+		assert!(matches!(data.at(&ValuePath::from("/a/c")), Err(DataError::PathNotFound(_))));
+		assert!(matches!(data.update_at(&ValuePath::from("/x/y"), json!(1), UpdateAction::Set), Err(DataError::PathNotFound(_))));
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/a/b/c"), json!(1), UpdateAction::Set),
+			Err(DataError::NotAContainer { .. })
+		));
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/a"), json!(1), UpdateAction::AddTo),
+			Err(DataError::NotAContainer { .. })
+		));
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/list"), json!(3), UpdateAction::RemoveFrom),
+			Err(DataError::NotInArray { .. })
+		));
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/list"), json!("3"), UpdateAction::AddTo),
+			Err(DataError::TypeMismatch { .. })
+		));
+		assert_eq!(data.as_ref(), &json!({"a": {"b": 1}, "list": [1, 2]}));
+	}
+
+	#[test]
+	fn paths_index_into_arrays() {
+		let mut data = Data::mock(json!({"servers": [{"host": "a", "ports": [1]}, {"host": "b", "ports": []}]}));
+		assert_eq!(data.at(&ValuePath::from("/servers/1/host")).unwrap(), "b");
+		assert!(matches!(data.at(&ValuePath::from("/servers/2")), Err(DataError::PathNotFound(_))));
+		assert!(matches!(data.at(&ValuePath::from("/servers/x")), Err(DataError::PathNotFound(_))));
+
+		data.update_at(&ValuePath::from("/servers/1/host"), json!("c"), UpdateAction::Set).unwrap();
+		data.update_at(&ValuePath::from("/servers/0/ports"), json!(2), UpdateAction::AddTo).unwrap();
+		data.update_at(&ValuePath::from("/servers/0/ports/0"), json!(3), UpdateAction::Set).unwrap();
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/servers/5"), json!({}), UpdateAction::Set),
+			Err(DataError::PathNotFound(_))
+		));
+		assert_eq!(data.as_ref(), &json!({"servers": [{"host": "a", "ports": [3, 2]}, {"host": "c", "ports": []}]}));
+	}
+
+	#[test]
+	fn structural_updates() {
+		let mut data = Data::mock(json!({"a": {"b": 1}, "list": [1, 2]}));
+		data.update_at(&ValuePath::from("/a/c"), json!([]), UpdateAction::Insert).unwrap();
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/a/c"), json!(1), UpdateAction::Insert),
+			Err(DataError::AlreadyExists(_))
+		));
+		data.update_at(&ValuePath::from("/a/b"), json!("d"), UpdateAction::Rename).unwrap();
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/a/d"), json!("c"), UpdateAction::Rename),
+			Err(DataError::AlreadyExists(_))
+		));
+		data.update_at(&ValuePath::from("/list/0"), JsonValue::Null, UpdateAction::Delete).unwrap();
+		assert!(matches!(
+			data.update_at(&ValuePath::from("/list/5"), JsonValue::Null, UpdateAction::Delete),
+			Err(DataError::PathNotFound(_))
+		));
+		assert!(matches!(data.update_at(&ValuePath::default(), JsonValue::Null, UpdateAction::Delete), Err(DataError::AtRoot)));
+		assert_eq!(data.as_ref(), &json!({"a": {"c": [], "d": 1}, "list": [2]}));
+	}
+
+	#[test]
+	fn failed_commit_changes_nothing() {
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("config.toml");
+		let content = "# comment\nkey = \"value\"\n";
+		write(&path, content).unwrap();
+
+		let mut data = Data::load(&path).unwrap();
+		let e = data.commit_at(&ValuePath::from("key"), JsonValue::Null, UpdateAction::Set).unwrap_err();
+		assert!(matches!(e, DataError::NotRepresentable { format: "TOML", .. }));
+		assert!(e.to_string().contains("TOML has no null"), "{e}");
+		assert_eq!(data.as_ref()["key"], "value");
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+
+		data.commit_at(&ValuePath::from("key"), json!("new_value"), UpdateAction::Set).unwrap();
+		assert_eq!(data.as_ref()["key"], "new_value");
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "# comment\nkey = \"new_value\"\n");
+	}
+
+	#[test]
+	fn external_changes_are_not_overwritten() {
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("config.yaml");
+		write(&path, "key: value\nother: 1\n").unwrap();
+
+		let mut data = Data::load(&path).unwrap();
+		assert!(!data.changed_on_disk());
+		write(&path, "key: value\nother: 2\n").unwrap();
+		assert!(data.changed_on_disk());
+
+		let e = data.commit_at(&ValuePath::from("key"), json!("new_value"), UpdateAction::Set).unwrap_err();
+		assert!(matches!(e, DataError::Conflict(_)));
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "key: value\nother: 2\n");
+
+		data.reload().unwrap();
+		data.commit_at(&ValuePath::from("key"), json!("new_value"), UpdateAction::Set).unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "key: new_value\nother: 2\n");
+		assert!(!data.changed_on_disk());
+	}
+
+	#[test]
+	fn replaced_versions_are_kept_and_restorable() {
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("config.toml");
+		write(&path, "# comment\nport = 8080\n").unwrap();
+
+		let mut data = Data::load(&path).unwrap().with_history(Retention::default());
+		data.set_editor(Some("@admin".to_owned()));
+		data.commit_at(&ValuePath::from("port"), json!(9090), UpdateAction::Set).unwrap();
+		assert!(data.commit_at(&ValuePath::from("port/x"), json!(1), UpdateAction::Set).is_err());
+		data.apply_patch(&serde_json::from_value::<Vec<PatchOp>>(json!([{"op": "add", "path": "/host", "value": "localhost"}])).unwrap())
+			.unwrap();
+
+		let versions = data.history().unwrap().versions().unwrap();
+		assert_eq!(
+			versions.iter().map(|v| (v.id, v.change.to_string())).collect::<Vec<_>>(),
+			[(2, "/host".to_owned()), (1, "/port".to_owned())]
+		);
+		assert_eq!(versions[1].editor.as_deref(), Some("@admin"));
+		assert_eq!(data.history().unwrap().content(1).unwrap(), "# comment\nport = 8080\n");
+
+		data.restore(1).unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "# comment\nport = 8080\n");
+		assert_eq!(data.as_ref(), &json!({"port": 8080}));
+		assert_eq!(data.history().unwrap().versions().unwrap()[0].change.to_string(), "restore of #1");
+		assert!(matches!(data.restore(7), Err(DataError::VersionNotFound(7))));
+
+		// a restore doesn't overwrite changes made by someone else either
+		write(&path, "port = 1\n").unwrap();
+		assert!(matches!(data.restore(2), Err(DataError::Conflict(_))));
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 1\n");
+	}
+
+	#[test]
+	fn writes_are_committed_with_what_changed() {
+		let dir = tempdir().unwrap();
+		let git = |args: &[&str]| {
+			let output = std::process::Command::new("git").arg("-C").arg(dir.path()).args(args).output().unwrap();
+			assert!(output.status.success(), "git {args:?}: {}", String::from_utf8_lossy(&output.stderr));
+			String::from_utf8(output.stdout).unwrap().trim().to_owned()
+		};
+		let path = dir.path().join("config.yaml");
+		write(&path, "port: 8080\nname: svc\n").unwrap();
+		git(&["init", "-q"]);
+		git(&["config", "user.name", "Test"]);
+		git(&["config", "user.email", "test@example.com"]);
+
+		let mut data = Data::load(&path).unwrap().with_history(Retention::default()).with_git(None);
+		data.set_editor(Some("@admin".to_owned()));
+		data.commit_at(&ValuePath::from("port"), json!(9090), UpdateAction::Set).unwrap();
+		assert!(data.take_git_failure().is_none());
+		insta::assert_snapshot!(git(&["log", "-1", "--format=%B"]), @r"
+		Set /port in config.yaml
+
+		/port: 8080 -> 9090
+
+		Edited-by: @admin
+		");
+		// the history next to the file stays out of the repository
+		assert_eq!(git(&["status", "--porcelain"]), "");
+
+		data.apply_patch(
+			&serde_json::from_value::<Vec<PatchOp>>(json!([
+				{"op": "add", "path": "/tags", "value": ["a"]},
+				{"op": "add", "path": "/tags/-", "value": "b"},
+				{"op": "remove", "path": "/name"}
+			]))
+			.unwrap(),
+		)
+		.unwrap();
+		insta::assert_snapshot!(git(&["log", "-1", "--format=%B"]), @r#"
+		Set /tags, /name in config.yaml
+
+		/tags: (none) -> ["a","b"]
+		/name: "svc" -> (none)
+
+		Edited-by: @admin
+		"#);
+
+		data.restore(1).unwrap();
+		assert_eq!(git(&["log", "-1", "--format=%s"]), "Restore config.yaml to version #1");
+
+		// a failed commit doesn't undo the write
+		write(dir.path().join(".git/index.lock"), "").unwrap();
+		data.commit_at(&ValuePath::from("port"), json!(1), UpdateAction::Set).unwrap();
+		assert!(data.take_git_failure().unwrap().to_string().contains("index.lock"));
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "port: 1\nname: svc\n");
+	}
+
+	#[test]
+	fn edits_revert_and_redo() {
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("config.json");
+		let content = "{\"port\": 8080, \"hosts\": [\"a\", \"b\", \"c\"], \"old\": {\"x\": 1}}";
+		write(&path, content).unwrap();
+		let mut data = Data::load(&path).unwrap();
+		let original = data.as_ref().clone();
+
+		let mut undo = Vec::new();
+		data.commit_at(&ValuePath::from("port"), json!(9090), UpdateAction::Set).unwrap();
+		undo.push(data.last_edits().to_vec());
+		data.commit_at(&ValuePath::from("/hosts/0"), JsonValue::Null, UpdateAction::Delete).unwrap();
+		assert_eq!(
+			data.last_edits(),
+			[Edit {
+				path: ValuePath::from("hosts"),
+				before: Some(json!(["a", "b", "c"])),
+				after: Some(json!(["b", "c"])),
+			}]
+		);
+		undo.push(data.last_edits().to_vec());
+		data.commit_at(&ValuePath::from("old"), json!("new"), UpdateAction::Rename).unwrap();
+		assert_eq!(data.last_edits().iter().map(|edit| edit.path.to_string()).collect::<Vec<_>>(), ["/old", "/new"]);
+		undo.push(data.last_edits().to_vec());
+
+		for edits in undo.iter().rev() {
+			data.revert(edits).unwrap();
+		}
+		assert_eq!(data.as_ref(), &original);
+		assert_eq!(Data::load(&path).unwrap().as_ref(), &original);
+
+		// redoing is reverting the inverse
+		let redo: Vec<Edit> = undo[0].iter().rev().map(Edit::inverse).collect();
+		data.revert(&redo).unwrap();
+		assert_eq!(data.as_ref()["port"], 9090);
+
+		data.commit_at(&ValuePath::from("port"), json!(1), UpdateAction::Set).unwrap();
+		assert!(matches!(data.revert(&undo[0]), Err(DataError::ChangedSince(path)) if path == ValuePath::from("port")));
+		assert_eq!(data.as_ref()["port"], 1);
+	}
+
+	#[test]
+	fn writes_have_to_fit_the_schema() {
+		let dir = tempdir().unwrap();
+		write(
+			dir.path().join("schema.yaml"),
+			"type: object\nproperties:\n  port: {type: integer, maximum: 65535}\n  tags: {type: array, items: {type: [string, integer]}}\n",
+		)
+		.unwrap();
+		let path = dir.path().join("config.json");
+		let content = r#"{"$schema": "schema.yaml", "port": 8080, "tags": ["a"]}"#;
+		write(&path, content).unwrap();
+
+		let mut data = Data::load(&path).unwrap().with_schema(None).unwrap();
+		assert!(data.schema().is_some() && data.remote_schema().is_none());
+		let e = data.commit_at(&ValuePath::from("port"), json!(70000), UpdateAction::Set).unwrap_err();
+		let DataError::SchemaViolation(violations) = &e else { panic!("{e}") };
+		assert_eq!((violations[0].path.to_string().as_str(), violations[0].keyword.as_str()), ("/port", "maximum"));
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+		assert_eq!(data.as_ref()["port"], 8080);
+
+		// what an array may hold is up to the schema, not its first element
+		data.commit_at(&ValuePath::from("tags"), json!(2), UpdateAction::AddTo).unwrap();
+		assert!(data.commit_at(&ValuePath::from("tags"), json!(true), UpdateAction::AddTo).is_err());
+		assert_eq!(data.as_ref()["tags"], json!(["a", 2]));
+
+		// an explicit schema wins over `$schema`, and remote ones aren't fetched
+		write(dir.path().join("strict.json"), r#"{"properties": {"port": {"maximum": 1000}}}"#).unwrap();
+		let mut data = Data::load(&path).unwrap().with_schema(Some(&dir.path().join("strict.json"))).unwrap();
+		assert_eq!(data.violations().len(), 1);
+		// a file that's out of schema already can still be edited elsewhere, and fixed, but not broken further
+		data.commit_at(&ValuePath::from("tags"), json!("b"), UpdateAction::AddTo).unwrap();
+		data.commit_at(&ValuePath::from("port"), json!(2000), UpdateAction::Set).unwrap();
+		data.commit_at(&ValuePath::from("port"), json!(900), UpdateAction::Set).unwrap();
+		assert!(data.violations().is_empty());
+		assert!(data.commit_at(&ValuePath::from("port"), json!(8080), UpdateAction::Set).is_err());
+		write(&path, r#"{"$schema": "https://example.com/schema.json"}"#).unwrap();
+		let data = Data::load(&path).unwrap().with_schema(None).unwrap();
+		assert_eq!(data.remote_schema(), Some("https://example.com/schema.json"));
+		assert!(matches!(
+			Data::load(&path).unwrap().with_schema(Some(&dir.path().join("missing.json"))),
+			Err(DataError::InvalidSchema { .. })
+		));
+	}
+
+	#[test]
+	fn only_new_violations_are_refused() {
+		let dir = tempdir().unwrap();
+		write(dir.path().join("schema.json"), r#"{"properties": {"hosts": {"items": {"minLength": 1}}}}"#).unwrap();
+		let path = dir.path().join("config.json");
+		write(&path, r#"{"$schema": "schema.json", "hosts": ["a", "b", ""]}"#).unwrap();
+		let mut data = Data::load(&path).unwrap().with_schema(None).unwrap();
+
+		// the empty host moves up an index, and is still the only one
+		data.commit_at(&ValuePath::from("/hosts/0"), JsonValue::Null, UpdateAction::Delete).unwrap();
+		assert_eq!(data.violations()[0].path, ValuePath::from("/hosts/1"));
+		let e = data.commit_at(&ValuePath::from("/hosts"), json!(""), UpdateAction::AddTo).unwrap_err();
+		let DataError::SchemaViolation(violations) = &e else { panic!("{e}") };
+		assert_eq!(violations.iter().map(|v| v.path.to_string()).collect::<Vec<_>>(), ["/hosts/2"]);
+		assert!(data.commit_at(&ValuePath::from("/hosts/0"), json!(""), UpdateAction::Set).is_err());
+		assert_eq!(data.as_ref()["hosts"], json!(["b", ""]));
+
+		// once fixed, it can't be broken again
+		data.commit_at(&ValuePath::from("/hosts/1"), json!("c"), UpdateAction::Set).unwrap();
+		assert!(data.commit_at(&ValuePath::from("/hosts/1"), json!(""), UpdateAction::Set).is_err());
+	}
+
+	#[test]
+	fn dotenv_files_and_type_inference() {
+		let dir = tempdir().unwrap();
+		let path = dir.path().join(".env");
+		write(&path, "# service\nPORT=8080\nDEBUG=false\n").unwrap();
+		assert!(Data::supports(&path));
+
+		assert_eq!(Data::load(&path).unwrap().as_ref(), &json!({"PORT": "8080", "DEBUG": "false"}));
+		let options = Options { infer_types: true, ..Options::default() };
+		let mut data = Data::load_with(&path, &Registry::default(), options).unwrap();
+		assert_eq!(data.as_ref(), &json!({"PORT": 8080, "DEBUG": false}));
+
+		data.commit_at(&ValuePath::from("PORT"), json!(9090), UpdateAction::Set).unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "# service\nPORT=9090\nDEBUG=false\n");
+		data.reload().unwrap();
+		assert_eq!(data.as_ref()["PORT"], 9090);
+	}
+
+	#[test]
+	fn sniffed_format_sticks() {
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("app.conf");
+		write(&path, "# app\nport = 8080\n").unwrap();
+		assert!(!Data::supports(&path));
+
+		let mut data = Data::load(&path).unwrap();
+		assert_eq!(data.format().unwrap().name(), "TOML");
+		data.commit_at(&ValuePath::from("port"), json!(9090), UpdateAction::Set).unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "# app\nport = 9090\n");
+		assert_eq!(data.read_raw().unwrap().1, "toml");
+
+		// an explicit format wins over what the content looks like
+		let data = Data::load_as(&path, Registry::default().get("ini").unwrap(), Options::default()).unwrap();
+		assert_eq!(data.as_ref(), &json!({"port": "9090"}));
+		assert!(matches!(Data::load(&dir.path().join("missing.conf")), Err(DataError::Io(_))));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn write_keeps_permissions_and_leaves_no_temp_files() {
+		use std::os::unix::fs::PermissionsExt as _;
+
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("config.json");
+		write(&path, r#"{"key": "value"}"#).unwrap();
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+		let mut data = Data::load(&path).unwrap();
+		data.update(json!({"key": "new_value"}));
+		data.write().unwrap();
+
+		assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+		assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+		assert_eq!(Data::load(&path).unwrap().as_ref()["key"], "new_value");
+	}
+
 	#[test]
 	fn test_value_path() {
 		let mut level = ValuePath::default();
 		let path = ["key1", "key2", "key3"];
 
-		assert!(level.0 != "");
+		assert!(level.to_string() != "");
 		assert!(level.basename() == "");
 		assert!(!level.to_vec().contains(&"".to_string()));
 
 		for part in &path {
 			level.push(part);
-			assert!(level.parent().0 != "");
+			assert!(level.parent().to_string() != "");
 			assert!(!level.to_vec().contains(&"".to_string()));
 			assert!(level.basename() == *part);
 		}
 		assert!(level.to_vec() == path.to_vec());
 	}
+
+	#[test]
+	fn value_path_escaping_round_trips() {
+		let level = ValuePath::default().join("routes").join("api/v1").join("~home").join("").join("0");
+		assert_eq!(level.to_string(), "/routes/api~1v1/~0home/~2/0");
+		assert_eq!(ValuePath::from(level.to_string()), level);
+		assert_eq!(serde_json::from_str::<ValuePath>(&serde_json::to_string(&level).unwrap()).unwrap(), level);
+		assert_eq!(level.parent().parent().basename(), "~home");
+		assert_eq!(ValuePath::from("/a~01"), ValuePath::default().join("a~1"));
+		assert_eq!(serde_json::to_string(&ValuePath::default()).unwrap(), r#""/""#);
+
+		let data = Data::mock(json!({"routes": {"api/v1": {"~home": {"": [true]}}}}));
+		assert_eq!(data.at(&level).unwrap(), json!(true));
+	}
 }
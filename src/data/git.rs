@@ -0,0 +1,213 @@
+//! Committing each write of a target that lives in a git work tree.
+use std::{
+	io,
+	path::{Path, PathBuf},
+	process::{Command, Output, Stdio},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum GitError {
+	#[error("`git {command}` failed: {stderr}")]
+	Failed { command: String, stderr: String },
+	#[error("Couldn't run git: {0}")]
+	Io(#[from] io::Error),
+}
+
+/// The git work tree a target is in, and the branch its changes go to.
+#[derive(Clone, Debug)]
+pub struct Git {
+	top: PathBuf,
+	/// The target, relative to `top`.
+	path: PathBuf,
+	/// Where commits go; the checked out branch when unset.
+	branch: Option<String>,
+}
+impl Git {
+	/// The work tree `target` is tracked in, if any; ignored files aren't.
+	pub fn discover(target: &Path, branch: Option<String>) -> Option<Self> {
+		let target = target.canonicalize().ok()?;
+		let output = Command::new("git").arg("-C").arg(target.parent()?).args(["rev-parse", "--show-toplevel"]).output().ok()?;
+		if !output.status.success() {
+			return None;
+		}
+		let top = PathBuf::from(String::from_utf8(output.stdout).ok()?.trim_end()).canonicalize().ok()?;
+		let path = target.strip_prefix(&top).ok()?.to_path_buf();
+		let git = Self { top, path, branch };
+		let ignored = git.command(["check-ignore", "-q", "--"]).arg(&git.path).status().ok()?.success();
+		(!ignored).then_some(git)
+	}
+
+	/// Commit the target as it is on disk with `message`, returning the new commit's hash; `None` if it's unchanged since the last commit.
+	///
+	/// Commits to the checked out branch go through `git commit`, hooks included. Those to another branch are made without checking it out, so no hooks run for them.
+	pub fn commit(&self, message: &str) -> Result<Option<String>, GitError> {
+		let current = self.run(self.command(["symbolic-ref", "-q", "--short", "HEAD"])).ok();
+		match &self.branch {
+			Some(branch) if current.as_deref() != Some(branch.as_str()) => self.commit_to(branch, message),
+			_ => self.commit_checked_out(message),
+		}
+	}
+
+	fn commit_checked_out(&self, message: &str) -> Result<Option<String>, GitError> {
+		self.run(self.command(["add", "--"]).arg(&self.path))?;
+		let unchanged = self.command(["diff", "--cached", "--quiet", "--"]).arg(&self.path).status()?.success();
+		if unchanged {
+			return Ok(None);
+		}
+		// only the target is committed, whatever else is staged
+		self.run(self.command(["commit", "-q", "-m", message, "--"]).arg(&self.path))?;
+		self.run(self.command(["rev-parse", "HEAD"])).map(Some)
+	}
+
+	fn commit_to(&self, branch: &str, message: &str) -> Result<Option<String>, GitError> {
+		let reference = format!("refs/heads/{branch}");
+		// a new branch starts from the checked out commit
+		let parent = self
+			.run(self.command(["rev-parse", "--verify", "-q"]).arg(format!("{reference}^{{commit}}")))
+			.or_else(|_| self.run(self.command(["rev-parse", "--verify", "-q", "HEAD^{commit}"])))
+			.ok();
+
+		// the branch's tree with the target swapped in is put together in an index of its own, leaving the real one alone
+		let index = PathBuf::from(self.run(self.command(["rev-parse", "--git-path", "tg_admin-index"]))?);
+		let index = self.top.join(index);
+		let with_index = |args: &[&str]| {
+			let mut command = self.command(args);
+			command.env("GIT_INDEX_FILE", &index);
+			command
+		};
+		let tree = (|| {
+			match &parent {
+				Some(parent) => self.run(with_index(&["read-tree", parent]))?,
+				None => self.run(with_index(&["read-tree", "--empty"]))?,
+			};
+			self.run(with_index(&["update-index", "--add", "--"]).arg(&self.path))?;
+			self.run(with_index(&["write-tree"]))
+		})();
+		let _ = std::fs::remove_file(&index);
+		let tree = tree?;
+
+		if let Some(parent) = &parent {
+			if self.run(self.command(["rev-parse"]).arg(format!("{parent}^{{tree}}")))? == tree {
+				return Ok(None);
+			}
+		}
+		let mut commit_tree = self.command(["commit-tree", &tree, "-m", message]);
+		if let Some(parent) = &parent {
+			commit_tree.args(["-p", parent]);
+		}
+		let commit = self.run(&mut commit_tree)?;
+		// refuses to move the branch if it changed in the meantime
+		let expected = match self.run(self.command(["rev-parse", "--verify", "-q"]).arg(&reference)) {
+			Ok(_) => parent.unwrap_or_default(),
+			Err(_) => String::new(),
+		};
+		self.run(self.command(["update-ref", &reference, &commit, &expected]))?;
+		Ok(Some(commit))
+	}
+
+	fn command<I, S>(&self, args: I) -> Command
+	where
+		I: IntoIterator<Item = S>,
+		S: AsRef<std::ffi::OsStr>, {
+		let mut command = Command::new("git");
+		command.arg("-C").arg(&self.top).args(args).stdin(Stdio::null());
+		command
+	}
+
+	/// Run a git command, returning its trimmed output.
+	fn run(&self, mut command: impl std::borrow::BorrowMut<Command>) -> Result<String, GitError> {
+		let command = command.borrow_mut();
+		let Output { status, stdout, stderr } = command.output()?;
+		if !status.success() {
+			let args: Vec<_> = command.get_args().skip(2).map(|arg| arg.to_string_lossy().into_owned()).collect();
+			let stderr = String::from_utf8_lossy(&stderr).trim().to_owned();
+			return Err(GitError::Failed {
+				command: args.join(" "),
+				stderr: match stderr.is_empty() {
+					true => format!("exited with {status}"),
+					false => stderr,
+				},
+			});
+		}
+		Ok(String::from_utf8_lossy(&stdout).trim().to_owned())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn git(dir: &Path, args: &[&str]) -> String {
+		let output = Command::new("git").arg("-C").arg(dir).args(args).output().unwrap();
+		assert!(output.status.success(), "git {args:?}: {}", String::from_utf8_lossy(&output.stderr));
+		String::from_utf8(output.stdout).unwrap().trim().to_owned()
+	}
+
+	fn repo() -> tempfile::TempDir {
+		let dir = tempfile::tempdir().unwrap();
+		git(dir.path(), &["init", "-q", "-b", "main"]);
+		git(dir.path(), &["config", "user.name", "Test"]);
+		git(dir.path(), &["config", "user.email", "test@example.com"]);
+		std::fs::write(dir.path().join("config.json"), "{\"port\": 1}\n").unwrap();
+		std::fs::write(dir.path().join(".gitignore"), "secret.json\n").unwrap();
+		git(dir.path(), &["add", "."]);
+		git(dir.path(), &["commit", "-q", "-m", "init"]);
+		dir
+	}
+
+	#[test]
+	fn commits_only_the_target_to_the_checked_out_branch() {
+		let dir = repo();
+		let target = dir.path().join("config.json");
+		std::fs::write(dir.path().join("secret.json"), "{}").unwrap();
+		assert!(Git::discover(&dir.path().join("secret.json"), None).is_none());
+		assert!(Git::discover(&std::env::temp_dir().join("missing.json"), None).is_none());
+
+		let repo = Git::discover(&target, None).unwrap();
+		assert_eq!(repo.commit("nothing").unwrap(), None);
+		std::fs::write(dir.path().join("staged.txt"), "wip").unwrap();
+		git(dir.path(), &["add", "staged.txt"]);
+		std::fs::write(&target, "{\"port\": 2}\n").unwrap();
+		let commit = repo.commit("Set /port in config.json").unwrap().unwrap();
+		assert_eq!(git(dir.path(), &["rev-parse", "HEAD"]), commit);
+		assert_eq!(git(dir.path(), &["show", "--name-only", "--format=%s", "HEAD"]), "Set /port in config.json\n\nconfig.json");
+		assert_eq!(git(dir.path(), &["diff", "--cached", "--name-only"]), "staged.txt");
+
+		// hooks run, and their refusal comes back as an error
+		let hook = dir.path().join(".git/hooks/pre-commit");
+		std::fs::write(&hook, "#!/bin/sh\necho 'no commits today' >&2\nexit 1\n").unwrap();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt as _;
+			std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+		}
+		std::fs::write(&target, "{\"port\": 3}\n").unwrap();
+		let e = repo.commit("Set /port in config.json").unwrap_err();
+		assert!(e.to_string().contains("no commits today"), "{e}");
+		assert_eq!(git(dir.path(), &["rev-parse", "HEAD"]), commit);
+	}
+
+	#[test]
+	fn commits_to_a_branch_that_isnt_checked_out() {
+		let dir = repo();
+		let target = dir.path().join("config.json");
+		let head = git(dir.path(), &["rev-parse", "HEAD"]);
+		let repo = Git::discover(&target, Some("config".to_owned())).unwrap();
+
+		std::fs::write(&target, "{\"port\": 2}\n").unwrap();
+		let first = repo.commit("first").unwrap().unwrap();
+		std::fs::write(&target, "{\"port\": 3}\n").unwrap();
+		let second = repo.commit("second").unwrap().unwrap();
+		assert_eq!(repo.commit("again").unwrap(), None);
+
+		assert_eq!(git(dir.path(), &["rev-parse", "HEAD"]), head);
+		assert_eq!(git(dir.path(), &["symbolic-ref", "--short", "HEAD"]), "main");
+		assert_eq!(git(dir.path(), &["rev-parse", "config"]), second);
+		assert_eq!(git(dir.path(), &["rev-parse", "config^"]), first);
+		assert_eq!(git(dir.path(), &["rev-parse", "config^^"]), head);
+		assert_eq!(git(dir.path(), &["show", "config:config.json"]), "{\"port\": 3}");
+		assert_eq!(git(dir.path(), &["ls-tree", "--name-only", "config"]), ".gitignore\nconfig.json");
+		// the real index is left alone
+		assert_eq!(git(dir.path(), &["diff", "--cached", "--name-only"]), "");
+	}
+}
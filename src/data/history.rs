@@ -0,0 +1,192 @@
+//! Previous versions of a target file, kept in a sidecar directory next to it.
+use std::{
+	io,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use super::ValuePath;
+
+/// Index of the versions in the sidecar directory; each version's contents are in a file named by its id.
+const INDEX: &str = "versions.json";
+
+/// How many versions are kept, and for how long.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Retention {
+	/// The newest this many are kept, the rest are dropped.
+	pub max_versions: usize,
+	/// Versions older than this are dropped, however few there are.
+	pub max_age: Option<Duration>,
+}
+impl Default for Retention {
+	fn default() -> Self {
+		Self { max_versions: 20, max_age: None }
+	}
+}
+
+/// A previous version of the file: what it was before `change` replaced it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Version {
+	pub id: u64,
+	/// When the version was replaced.
+	pub time: Timestamp,
+	/// Who replaced it, if known.
+	pub editor: Option<String>,
+	pub change: Change,
+}
+
+/// What replaced a version.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Change {
+	/// An edit at these paths.
+	Edit(Vec<ValuePath>),
+	/// A rollback to the version with this id.
+	Restore(u64),
+}
+impl std::fmt::Display for Change {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Edit(paths) if paths.is_empty() => write!(f, "edit"),
+			Self::Edit(paths) => write!(f, "{}", paths.iter().map(ValuePath::to_string).collect::<Vec<_>>().join(", ")),
+			Self::Restore(id) => write!(f, "restore of #{id}"),
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct History {
+	dir: PathBuf,
+	retention: Retention,
+}
+impl History {
+	/// History of the file at `target`, kept in `.<file name>.history` next to it.
+	pub fn new(target: &Path, retention: Retention) -> Self {
+		let name = target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+		Self {
+			dir: target.with_file_name(format!(".{name}.history")),
+			retention,
+		}
+	}
+
+	/// The kept versions, newest first.
+	pub fn versions(&self) -> io::Result<Vec<Version>> {
+		let mut versions = self.index()?;
+		versions.reverse();
+		Ok(versions)
+	}
+
+	/// Contents of the file as of version `id`.
+	pub fn content(&self, id: u64) -> io::Result<String> {
+		std::fs::read_to_string(self.dir.join(id.to_string()))
+	}
+
+	/// Keep `content` as a new version, replaced by `change` of `editor` just now.
+	pub fn record(&self, content: &str, editor: Option<String>, change: Change) -> io::Result<Version> {
+		self.record_at(content, editor, change, Timestamp::now())
+	}
+
+	fn record_at(&self, content: &str, editor: Option<String>, change: Change, time: Timestamp) -> io::Result<Version> {
+		std::fs::create_dir_all(&self.dir)?;
+		let gitignore = self.dir.join(".gitignore");
+		if !gitignore.exists() {
+			// versions are no business of a repository the target is in
+			std::fs::write(gitignore, "*\n")?;
+		}
+		let mut versions = self.index()?;
+		let version = Version {
+			id: versions.last().map_or(1, |last| last.id + 1),
+			time,
+			editor,
+			change,
+		};
+		std::fs::write(self.dir.join(version.id.to_string()), content)?;
+		versions.push(version.clone());
+
+		// oldest first, so whatever is dropped is at the front
+		let over = versions.len().saturating_sub(self.retention.max_versions);
+		let expired = match self.retention.max_age {
+			Some(max_age) => versions.iter().take_while(|v| time.duration_since(v.time).unsigned_abs() > max_age).count(),
+			None => 0,
+		};
+		for dropped in versions.drain(..over.max(expired)) {
+			let _ = std::fs::remove_file(self.dir.join(dropped.id.to_string()));
+		}
+		let index = serde_json::to_string_pretty(&versions).map_err(io::Error::other)?;
+		crate::utils::write_atomic(&self.dir.join(INDEX), index.as_bytes())?;
+		Ok(version)
+	}
+
+	/// Oldest first.
+	fn index(&self) -> io::Result<Vec<Version>> {
+		match std::fs::read_to_string(self.dir.join(INDEX)) {
+			Ok(index) => serde_json::from_str(&index).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+			Err(e) => Err(e),
+		}
+	}
+}
+
+/// What would change in `current` if it were replaced by `version`, as a unified diff; empty if nothing would.
+pub fn diff(current: &str, version: &str, version_name: &str) -> String {
+	similar::TextDiff::from_lines(current, version)
+		.unified_diff()
+		.context_radius(2)
+		.header("current", version_name)
+		.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn retention_by_count_and_age() {
+		let dir = tempfile::tempdir().unwrap();
+		let target = dir.path().join("config.toml");
+		let history = History::new(
+			&target,
+			Retention {
+				max_versions: 3,
+				max_age: Some(Duration::from_secs(3600)),
+			},
+		);
+		let start: Timestamp = "2026-01-01T00:00:00Z".parse().unwrap();
+		let at = |minutes: i64| start + jiff::SignedDuration::from_mins(minutes);
+		for (i, minutes) in [0, 10, 20, 30].into_iter().enumerate() {
+			history
+				.record_at(&format!("v{i}"), Some("@admin".to_owned()), Change::Edit(vec![ValuePath::from("/port")]), at(minutes))
+				.unwrap();
+		}
+		let ids: Vec<u64> = history.versions().unwrap().iter().map(|v| v.id).collect();
+		assert_eq!(ids, [4, 3, 2], "only the newest 3 are kept");
+		assert!(history.content(1).is_err());
+		assert_eq!(history.content(2).unwrap(), "v1");
+
+		// over an hour after the second and third, they have expired
+		history.record_at("v4", None, Change::Restore(2), at(81)).unwrap();
+		let versions = history.versions().unwrap();
+		assert_eq!(versions.iter().map(|v| v.id).collect::<Vec<_>>(), [5, 4]);
+		assert_eq!(versions[0].change.to_string(), "restore of #2");
+		assert_eq!(versions[1].change.to_string(), "/port");
+		assert!(dir.path().join(".config.toml.history").join(INDEX).exists());
+	}
+
+	#[test]
+	fn diff_shows_what_restoring_would_change() {
+		let current = "a = 1\nb = 2\nc = 3\n";
+		let version = "a = 1\nb = 5\nc = 3\n";
+		insta::assert_snapshot!(diff(current, version, "#3"), @r"
+		--- current
+		+++ #3
+		@@ -1,3 +1,3 @@
+		 a = 1
+		-b = 2
+		+b = 5
+		 c = 3
+		");
+		assert_eq!(diff(current, current, "#3"), "");
+	}
+}
@@ -0,0 +1,234 @@
+//! JSON Patch (RFC 6902) support for [`Data`](super::Data).
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use super::{DataError, ValuePath, child_mut, index};
+use crate::utils::get_json_type;
+
+/// One operation of a JSON Patch document.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+	Add {
+		#[serde(with = "pointer")]
+		path: ValuePath,
+		value: JsonValue,
+	},
+	Remove {
+		#[serde(with = "pointer")]
+		path: ValuePath,
+	},
+	Replace {
+		#[serde(with = "pointer")]
+		path: ValuePath,
+		value: JsonValue,
+	},
+	Move {
+		#[serde(with = "pointer")]
+		from: ValuePath,
+		#[serde(with = "pointer")]
+		path: ValuePath,
+	},
+	Copy {
+		#[serde(with = "pointer")]
+		from: ValuePath,
+		#[serde(with = "pointer")]
+		path: ValuePath,
+	},
+	/// Precondition: the patch fails unless the value at `path` equals `value`.
+	Test {
+		#[serde(with = "pointer")]
+		path: ValuePath,
+		value: JsonValue,
+	},
+}
+
+impl PatchOp {
+	/// The paths this operation changes; `test` changes none.
+	pub fn changes(&self) -> Vec<&ValuePath> {
+		match self {
+			Self::Add { path, .. } | Self::Remove { path } | Self::Replace { path, .. } | Self::Copy { path, .. } => vec![path],
+			Self::Move { from, path } => vec![from, path],
+			Self::Test { .. } => Vec::new(),
+		}
+	}
+}
+
+/// Apply all of `ops` to `root`, stopping at the first one that fails.
+pub(super) fn apply(root: &mut JsonValue, ops: &[PatchOp]) -> Result<(), DataError> {
+	for (index, op) in ops.iter().enumerate() {
+		apply_op(root, op).map_err(|source| DataError::Patch { index, source: Box::new(source) })?;
+	}
+	Ok(())
+}
+
+fn apply_op(root: &mut JsonValue, op: &PatchOp) -> Result<(), DataError> {
+	match op {
+		PatchOp::Add { path, value } => add(root, path, value.clone()),
+		PatchOp::Remove { path } => remove(root, path).map(drop),
+		PatchOp::Replace { path, value } => {
+			*get_mut(root, path)? = value.clone();
+			Ok(())
+		}
+		PatchOp::Move { from, path } => {
+			if path.to_vec().starts_with(&from.to_vec()) && path != from {
+				return Err(DataError::InvalidPatch(format!("can't move `{from}` into its own child `{path}`")));
+			}
+			let value = remove(root, from)?;
+			add(root, path, value)
+		}
+		PatchOp::Copy { from, path } => {
+			let value = get_mut(root, from)?.clone();
+			add(root, path, value)
+		}
+		PatchOp::Test { path, value } => match get_mut(root, path)? == value {
+			true => Ok(()),
+			false => Err(DataError::TestFailed {
+				path: path.clone(),
+				expected: value.clone(),
+			}),
+		},
+	}
+}
+
+fn get_mut<'v>(root: &'v mut JsonValue, path: &ValuePath) -> Result<&'v mut JsonValue, DataError> {
+	let mut current = root;
+	for part in path.to_vec() {
+		current = child_mut(current, &part).ok_or_else(|| DataError::PathNotFound(path.clone()))?;
+	}
+	Ok(current)
+}
+
+/// The container `path` points into, and the last segment of `path`; `None` for the root.
+fn parent_mut<'v>(root: &'v mut JsonValue, path: &ValuePath) -> Result<Option<(&'v mut JsonValue, String)>, DataError> {
+	match path.is_top() {
+		true => Ok(None),
+		false => Ok(Some((get_mut(root, &path.parent())?, path.basename()))),
+	}
+}
+
+fn add(root: &mut JsonValue, path: &ValuePath, value: JsonValue) -> Result<(), DataError> {
+	let Some((parent, last)) = parent_mut(root, path)? else {
+		*root = value;
+		return Ok(());
+	};
+	match parent {
+		JsonValue::Object(obj) => {
+			obj.insert(last, value);
+			Ok(())
+		}
+		JsonValue::Array(arr) => {
+			let i = match last.as_str() {
+				"-" => arr.len(),
+				_ => index(&last).filter(|i| *i <= arr.len()).ok_or_else(|| DataError::PathNotFound(path.clone()))?,
+			};
+			arr.insert(i, value);
+			Ok(())
+		}
+		other => Err(DataError::NotAContainer {
+			path: path.parent(),
+			expected: "Object",
+			actual: get_json_type(other),
+		}),
+	}
+}
+
+fn remove(root: &mut JsonValue, path: &ValuePath) -> Result<JsonValue, DataError> {
+	let Some((parent, last)) = parent_mut(root, path)? else {
+		return Err(DataError::AtRoot);
+	};
+	let removed = match parent {
+		JsonValue::Object(obj) => obj.shift_remove(&last),
+		JsonValue::Array(arr) => index(&last).filter(|i| *i < arr.len()).map(|i| arr.remove(i)),
+		_ => None,
+	};
+	removed.ok_or_else(|| DataError::PathNotFound(path.clone()))
+}
+
+/// Strict JSON Pointer (de)serialization: unlike in callback data, `""` is the root and `"/"` the empty key.
+mod pointer {
+	use serde::{Deserialize as _, Deserializer, Serializer, de::Error as _};
+
+	use super::ValuePath;
+
+	pub fn serialize<S: Serializer>(path: &ValuePath, serializer: S) -> Result<S::Ok, S::Error> {
+		let pointer: String = path.to_vec().iter().map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1"))).collect();
+		serializer.serialize_str(&pointer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ValuePath, D::Error> {
+		let pointer = String::deserialize(deserializer)?;
+		if pointer.is_empty() {
+			return Ok(ValuePath::default());
+		}
+		let rest = pointer
+			.strip_prefix('/')
+			.ok_or_else(|| D::Error::custom(format!("JSON Pointer `{pointer}` must start with `/`")))?;
+		let segments = rest.split('/').map(|segment| segment.replace("~1", "/").replace("~0", "~")).collect::<Vec<_>>();
+		Ok(ValuePath::from(segments))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+	use crate::data::Data;
+
+	fn patched(doc: JsonValue, patch: JsonValue) -> Result<JsonValue, DataError> {
+		let mut data = Data::mock(doc);
+		let ops: Vec<PatchOp> = serde_json::from_value(patch).unwrap();
+		data.patch(&ops)?;
+		Ok(data.as_ref().clone())
+	}
+
+	#[test]
+	fn rfc_operations() {
+		let doc = json!({"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}, "list": ["a", "c"], "": 0});
+		let out = patched(
+			doc,
+			json!([
+				{"op": "add", "path": "/list/1", "value": "b"},
+				{"op": "add", "path": "/list/-", "value": "d"},
+				{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"},
+				{"op": "copy", "from": "/qux/corge", "path": "/foo/corge"},
+				{"op": "replace", "path": "/foo/bar", "value": 1},
+				{"op": "remove", "path": "/"},
+				{"op": "test", "path": "/list", "value": ["a", "b", "c", "d"]},
+			]),
+		)
+		.unwrap();
+		assert_eq!(
+			out,
+			json!({"foo": {"bar": 1, "corge": "grault"}, "qux": {"corge": "grault", "thud": "fred"}, "list": ["a", "b", "c", "d"]})
+		);
+	}
+
+	#[test]
+	fn failed_test_applies_nothing() {
+		let doc = json!({"a": 1});
+		let mut data = Data::mock(doc.clone());
+		let ops: Vec<PatchOp> = serde_json::from_value(json!([{"op": "replace", "path": "/a", "value": 2}, {"op": "test", "path": "/a", "value": 1}])).unwrap();
+		let e = data.patch(&ops).unwrap_err();
+		assert!(matches!(e, DataError::Patch { index: 1, ref source } if matches!(**source, DataError::TestFailed { .. })), "{e}");
+		assert_eq!(data.as_ref(), &doc);
+	}
+
+	#[test]
+	fn invalid_targets() {
+		let doc = json!({"list": [1], "a": {"b": 1}});
+		assert!(patched(doc.clone(), json!([{"op": "add", "path": "/list/2", "value": 1}])).is_err());
+		assert!(patched(doc.clone(), json!([{"op": "add", "path": "/list/01", "value": 1}])).is_err());
+		for path in ["/list/00", "/list/+0", "/list/ 0"] {
+			assert!(patched(doc.clone(), json!([{"op": "replace", "path": path, "value": 2}])).is_err(), "{path}");
+			assert!(patched(doc.clone(), json!([{"op": "test", "path": path, "value": 1}])).is_err(), "{path}");
+			assert!(patched(doc.clone(), json!([{"op": "copy", "from": path, "path": "/c"}])).is_err(), "{path}");
+			assert!(patched(doc.clone(), json!([{"op": "move", "from": path, "path": "/c"}])).is_err(), "{path}");
+			assert!(patched(doc.clone(), json!([{"op": "remove", "path": path}])).is_err(), "{path}");
+		}
+		assert!(patched(doc.clone(), json!([{"op": "remove", "path": "/missing"}])).is_err());
+		assert!(patched(doc.clone(), json!([{"op": "move", "from": "/a", "path": "/a/b/c"}])).is_err());
+		assert!(serde_json::from_value::<Vec<PatchOp>>(json!([{"op": "remove", "path": "a"}])).is_err());
+	}
+}
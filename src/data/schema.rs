@@ -0,0 +1,318 @@
+//! JSON Schema a target has to fit, checked before every write and consulted for what to ask for.
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::Value as JsonValue;
+
+use super::ValuePath;
+
+#[derive(Clone, Debug)]
+pub struct Schema {
+	raw: Arc<JsonValue>,
+	validator: Arc<jsonschema::Validator>,
+}
+
+/// A way a value doesn't fit the schema.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+	/// Where the offending value is.
+	pub path: ValuePath,
+	/// The schema keyword it fails, such as `type` or `maximum`.
+	pub keyword: String,
+	/// Where that keyword is in the schema, as a JSON Pointer.
+	pub location: String,
+	pub message: String,
+}
+impl std::fmt::Display for Violation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "`{}` fails `{}`: {}", self.path, self.keyword, self.message)
+	}
+}
+
+/// Bounds and step of a number, each with whether it's inclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+	pub min: Option<(f64, bool)>,
+	pub max: Option<(f64, bool)>,
+	pub multiple_of: Option<f64>,
+}
+impl Range {
+	pub fn step(&self) -> f64 {
+		self.multiple_of.unwrap_or(1.0)
+	}
+
+	/// The number a step up or down from `from` lands on, if it's in range; it's the next multiple over when there is a `multipleOf`.
+	pub fn stepped(&self, from: f64, up: bool) -> Option<f64> {
+		let to = match self.multiple_of {
+			Some(step) => {
+				let mut steps = from / step;
+				// 0.3 / 0.1 is 2.9999999999999996
+				if (steps - steps.round()).abs() < 1e-9 {
+					steps = steps.round();
+				}
+				match up {
+					true => (steps.floor() + 1.0) * step,
+					false => (steps.ceil() - 1.0) * step,
+				}
+			}
+			None => match up {
+				true => from + 1.0,
+				false => from - 1.0,
+			},
+		};
+		let to = (to * 1e9).round() / 1e9;
+		let above_min = self.min.map_or(true, |(min, inclusive)| to > min || (inclusive && to == min));
+		let below_max = self.max.map_or(true, |(max, inclusive)| to < max || (inclusive && to == max));
+		(above_min && below_max).then_some(to)
+	}
+}
+
+impl Schema {
+	pub fn new(raw: JsonValue) -> Result<Self, String> {
+		let validator = jsonschema::validator_for(&raw).map_err(|e| e.to_string())?;
+		Ok(Self {
+			raw: Arc::new(raw),
+			validator: Arc::new(validator),
+		})
+	}
+
+	/// The ways `value` doesn't fit; empty if it does.
+	///
+	/// A top-level `$schema` key is what points a file to its schema, and not part of the data.
+	pub fn violations(&self, value: &JsonValue) -> Vec<Violation> {
+		let stripped;
+		let value = match value {
+			JsonValue::Object(obj) if obj.contains_key("$schema") => {
+				let mut obj = obj.clone();
+				obj.shift_remove("$schema");
+				stripped = JsonValue::Object(obj);
+				&stripped
+			}
+			_ => value,
+		};
+		self.validator
+			.iter_errors(value)
+			.map(|e| Violation {
+				path: ValuePath::from(e.instance_path.into_iter().map(|segment| segment.to_string()).collect::<Vec<_>>()),
+				keyword: e.schema_path.clone().into_iter().last().map(|segment| segment.to_string()).unwrap_or_default(),
+				location: e.schema_path.to_string(),
+				message: e.to_string(),
+			})
+			.collect()
+	}
+
+	/// Those of `now` that weren't there `before`.
+	///
+	/// Violations are told apart by where in the schema they fail, not by the value or its place: a value that fails the same keyword as before after moving, or after a change that doesn't make it fit, isn't a new one. Only more failures of a keyword than before are, and those that aren't where an old one was are taken to be the new ones.
+	pub fn added(before: &[Violation], now: Vec<Violation>) -> Vec<Violation> {
+		let mut allowed: HashMap<&str, usize> = HashMap::new();
+		for violation in before {
+			*allowed.entry(&violation.location).or_default() += 1;
+		}
+		let (stayed, moved): (Vec<_>, Vec<_>) = now
+			.into_iter()
+			.partition(|violation| before.iter().any(|old| old.path == violation.path && old.location == violation.location));
+		for violation in &stayed {
+			if let Some(left) = allowed.get_mut(violation.location.as_str()) {
+				*left = left.saturating_sub(1);
+			}
+		}
+		moved
+			.into_iter()
+			.filter(|violation| match allowed.get_mut(violation.location.as_str()) {
+				Some(left) if *left > 0 => {
+					*left -= 1;
+					false
+				}
+				_ => true,
+			})
+			.collect()
+	}
+
+	/// The part of the schema that values at `path` have to fit, if it says anything about them.
+	///
+	/// Follows `properties`, `additionalProperties`, `items`, `prefixItems`, the members of `allOf`, and `$ref`s within the schema.
+	pub fn at(&self, path: &ValuePath) -> Option<&JsonValue> {
+		let mut schema = self.resolve(&self.raw)?;
+		for part in path.to_vec() {
+			schema = self.child(schema, &part)?;
+		}
+		Some(schema)
+	}
+
+	fn child<'a>(&'a self, schema: &'a JsonValue, part: &str) -> Option<&'a JsonValue> {
+		let index = match part {
+			// appending to an array
+			"-" => Some(usize::MAX),
+			_ => part.parse::<usize>().ok(),
+		};
+		let direct = schema.get("properties").and_then(|properties| properties.get(part)).or_else(|| {
+			let index = index?;
+			let tuple = schema.get("prefixItems").or_else(|| schema.get("items").filter(|items| items.is_array()));
+			match tuple.and_then(|tuple| tuple.get(index)) {
+				Some(item) => Some(item),
+				None => schema.get("items").filter(|items| items.is_object()),
+			}
+		});
+		let child = direct.or_else(|| schema.get("additionalProperties").filter(|_| index.is_none()).filter(|schema| schema.is_object()));
+		if let Some(child) = child.and_then(|child| self.resolve(child)) {
+			return Some(child);
+		}
+		schema
+			.get("allOf")?
+			.as_array()?
+			.iter()
+			.find_map(|member| self.resolve(member).and_then(|member| self.child(member, part)))
+	}
+
+	/// Follow `$ref`s to other parts of the schema; those to other documents aren't.
+	fn resolve<'a>(&'a self, mut schema: &'a JsonValue) -> Option<&'a JsonValue> {
+		// cycles of references don't resolve to anything
+		for _ in 0..32 {
+			let Some(reference) = schema.get("$ref").and_then(JsonValue::as_str) else {
+				return Some(schema);
+			};
+			schema = self.raw.pointer(reference.strip_prefix('#')?)?;
+		}
+		None
+	}
+
+	/// The values the schema lists for `path` in an `enum`, if it does.
+	pub fn choices(&self, path: &ValuePath) -> Option<&[JsonValue]> {
+		self.at(path)?.get("enum")?.as_array().map(Vec::as_slice)
+	}
+
+	/// The bounds and step of numbers at `path`, if the schema declares any of them.
+	pub fn range(&self, path: &ValuePath) -> Option<Range> {
+		let schema = self.at(path)?;
+		let number = |keyword: &str| schema.get(keyword).and_then(JsonValue::as_f64);
+		let bound = |inclusive: &str, exclusive: &str| number(inclusive).map(|bound| (bound, true)).or_else(|| number(exclusive).map(|bound| (bound, false)));
+		let range = Range {
+			min: bound("minimum", "exclusiveMinimum"),
+			max: bound("maximum", "exclusiveMaximum"),
+			multiple_of: number("multipleOf").filter(|step| *step > 0.0),
+		};
+		(range.min.is_some() || range.max.is_some() || range.multiple_of.is_some()).then_some(range)
+	}
+
+	/// What values at `path` are expected to be, such as "integer, from 1 to 65535", if the schema says.
+	pub fn expectation(&self, path: &ValuePath) -> Option<String> {
+		let schema = self.at(path)?;
+		let mut parts = Vec::new();
+		match schema.get("type") {
+			Some(JsonValue::String(ty)) => parts.push(ty.clone()),
+			Some(JsonValue::Array(types)) => parts.push(types.iter().filter_map(JsonValue::as_str).collect::<Vec<_>>().join(" or ")),
+			_ => {}
+		}
+		if let Some(values) = schema.get("enum").and_then(JsonValue::as_array) {
+			parts.push(format!("one of {}", values.iter().map(|value| format!("`{value}`")).collect::<Vec<_>>().join(", ")));
+		}
+		if let Some(value) = schema.get("const") {
+			parts.push(format!("exactly `{value}`"));
+		}
+		let bound = |inclusive: &str, exclusive: &str| {
+			schema
+				.get(inclusive)
+				.map(|bound| (bound.to_string(), true))
+				.or_else(|| schema.get(exclusive).map(|bound| (bound.to_string(), false)))
+		};
+		match (bound("minimum", "exclusiveMinimum"), bound("maximum", "exclusiveMaximum")) {
+			(Some((min, true)), Some((max, true))) => parts.push(format!("from {min} to {max}")),
+			(min, max) => {
+				if let Some((min, inclusive)) = min {
+					parts.push(format!("{} {min}", if inclusive { "at least" } else { "over" }));
+				}
+				if let Some((max, inclusive)) = max {
+					parts.push(format!("{} {max}", if inclusive { "at most" } else { "under" }));
+				}
+			}
+		}
+		if let Some(step) = schema.get("multipleOf") {
+			parts.push(format!("a multiple of {step}"));
+		}
+		match (schema.get("minLength"), schema.get("maxLength")) {
+			(Some(min), Some(max)) => parts.push(format!("{min} to {max} characters")),
+			(Some(min), None) => parts.push(format!("at least {min} characters")),
+			(None, Some(max)) => parts.push(format!("at most {max} characters")),
+			(None, None) => {}
+		}
+		if let Some(pattern) = schema.get("pattern").and_then(JsonValue::as_str) {
+			parts.push(format!("matching `{pattern}`"));
+		}
+		if let Some(format) = schema.get("format").and_then(JsonValue::as_str) {
+			parts.push(format!("in {format} format"));
+		}
+		let mut expectation = parts.join(", ");
+		if let Some(description) = schema.get("description").and_then(JsonValue::as_str) {
+			match expectation.is_empty() {
+				true => expectation = description.to_owned(),
+				false => expectation.push_str(&format!(" ({description})")),
+			}
+		}
+		(!expectation.is_empty()).then_some(expectation)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	fn schema() -> Schema {
+		Schema::new(json!({
+			"type": "object",
+			"properties": {
+				"port": {"type": "integer", "minimum": 1, "maximum": 65535, "description": "Where to listen"},
+				"mode": {"enum": ["fast", "safe"]},
+				"hosts": {"type": "array", "items": {"$ref": "#/$defs/host"}},
+				"limits": {"type": "object", "additionalProperties": {"type": "number", "exclusiveMinimum": 0}}
+			},
+			"additionalProperties": false,
+			"$defs": {"host": {"type": "string", "minLength": 1, "format": "hostname"}}
+		}))
+		.unwrap()
+	}
+
+	#[test]
+	fn violations_name_the_keyword_and_path() {
+		let schema = schema();
+		assert_eq!(schema.violations(&json!({"$schema": "./schema.json", "port": 80, "hosts": ["a"]})), []);
+		let violations = schema.violations(&json!({"port": 70000, "hosts": ["a", ""], "extra": true}));
+		let found: Vec<_> = violations.iter().map(|v| (v.path.to_string(), v.keyword.as_str())).collect();
+		assert_eq!(
+			found,
+			[("/port".to_owned(), "maximum"), ("/hosts/1".to_owned(), "minLength"), ("/".to_owned(), "additionalProperties")]
+		);
+		assert_eq!(violations[0].to_string(), "`/port` fails `maximum`: 70000 is greater than the maximum of 65535");
+		assert!(Schema::new(json!({"type": 5})).is_err());
+	}
+
+	#[test]
+	fn expectations_follow_the_schema_down() {
+		let schema = schema();
+		let expect = |path: &str| schema.expectation(&ValuePath::from(path));
+		assert_eq!(expect("/port").unwrap(), "integer, from 1 to 65535 (Where to listen)");
+		assert_eq!(expect("/mode").unwrap(), r#"one of `"fast"`, `"safe"`"#);
+		assert_eq!(expect("/hosts/-").unwrap(), "string, at least 1 characters, in hostname format");
+		assert_eq!(expect("/hosts/3"), expect("/hosts/-"));
+		assert_eq!(expect("/limits/cpu").unwrap(), "number, over 0");
+		assert_eq!(expect("/nope"), None);
+	}
+
+	#[test]
+	fn steps_stay_in_range_and_on_multiples() {
+		let schema = Schema::new(json!({"properties": {
+			"workers": {"type": "integer", "minimum": 1, "maximum": 8},
+			"ratio": {"type": "number", "exclusiveMinimum": 0, "maximum": 0.5, "multipleOf": 0.1},
+			"name": {"type": "string"}
+		}}))
+		.unwrap();
+		let workers = schema.range(&ValuePath::from("/workers")).unwrap();
+		assert_eq!((workers.stepped(1.0, false), workers.stepped(1.0, true), workers.stepped(8.0, true)), (None, Some(2.0), None));
+		let ratio = schema.range(&ValuePath::from("/ratio")).unwrap();
+		assert_eq!(ratio.step(), 0.1);
+		assert_eq!((ratio.stepped(0.3, true), ratio.stepped(0.25, true), ratio.stepped(0.1, false)), (Some(0.4), Some(0.3), None));
+		assert_eq!(schema.range(&ValuePath::from("/name")), None);
+		assert_eq!(schema.choices(&ValuePath::from("/name")), None);
+	}
+}
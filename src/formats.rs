@@ -0,0 +1,217 @@
+//! Format-specific parsing and write-back for target files.
+//!
+//! Every backend reads a file into the shared [`serde_json::Value`] tree, and writes it back by patching the original source wherever the format allows it, so that an edit only touches the nodes that actually changed. Backends implement [`FormatBackend`], and a [`Registry`] picks the one a file is handled by.
+use std::{path::Path, sync::Arc};
+
+use serde_json::Value as JsonValue;
+use v_utils::prelude::*;
+
+pub mod ini;
+pub mod json5;
+pub mod jsonnet;
+pub mod nix;
+pub mod ron;
+mod splice;
+pub mod toml;
+pub mod yaml;
+
+/// A file format: how to recognize, read and write it.
+pub trait FormatBackend: std::fmt::Debug + Send + Sync {
+	/// Human-readable name, as used in messages.
+	fn name(&self) -> &'static str;
+	/// Short lowercase name to ask for the format by, as with `--format`.
+	fn id(&self) -> &'static str;
+	/// Whether files at `path` are in this format, judging by the path alone.
+	fn detect(&self, path: &Path) -> bool;
+	/// Whether `content` looks like this format, for files whose path doesn't tell. Formats that would accept almost anything shouldn't claim it.
+	fn sniff(&self, _content: &str) -> bool {
+		false
+	}
+	/// Read `content`, the contents of the file at `path`.
+	fn parse(&self, path: &Path, content: &str, options: &Options) -> Result<JsonValue>;
+	/// Render `value` as the new contents of the file at `path`, which currently holds `original`.
+	fn serialize(&self, path: &Path, original: Option<&str>, value: &JsonValue, options: &Options) -> Result<String>;
+	/// Language tag for showing the source in a code block.
+	fn language(&self) -> &'static str;
+	fn capabilities(&self) -> Capabilities;
+}
+
+/// What a format can hold and keep; values it can't hold are refused before its backend is asked to write them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Capabilities {
+	pub null: bool,
+	/// Comments in the file survive write-back.
+	pub comments: bool,
+}
+
+/// Settings backends may or may not have a use for.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+	/// Indentation for newly written nodes; detected from the file when unset.
+	pub indent: Option<String>,
+	/// Read unquoted values of untyped formats that look like numbers or booleans as such.
+	pub infer_types: bool,
+	/// How long a Nix evaluation may take; [`nix::DEFAULT_EVAL_TIMEOUT`] when unset.
+	pub eval_timeout: Option<std::time::Duration>,
+	/// Evaluate Nix files in pure mode, without access to the environment or to files they aren't pointed at directly.
+	pub pure_eval: bool,
+}
+
+/// The backends a file's format is looked up in; the most recently registered one that recognizes a file wins, whether by its path or its contents.
+#[derive(Clone, Debug)]
+pub struct Registry(Vec<Arc<dyn FormatBackend>>);
+impl Registry {
+	pub fn empty() -> Self {
+		Self(Vec::new())
+	}
+
+	pub fn register(&mut self, backend: impl FormatBackend + 'static) -> &mut Self {
+		self.0.insert(0, Arc::new(backend));
+		self
+	}
+
+	pub fn detect(&self, path: &Path) -> Option<Arc<dyn FormatBackend>> {
+		self.0.iter().find(|backend| backend.detect(path)).cloned()
+	}
+
+	/// The format of the file at `path`, which holds `content`: by its extension, then by the one under an extension nothing knows (`config.json.tmpl`), then by what `content` looks like.
+	pub fn resolve(&self, path: &Path, content: &str) -> Option<Arc<dyn FormatBackend>> {
+		self.detect(path)
+			.or_else(|| path.file_stem().and_then(|stem| self.detect(Path::new(stem))))
+			.or_else(|| self.0.iter().find(|backend| backend.sniff(content)).cloned())
+	}
+
+	/// The backend with the given [`id`](FormatBackend::id).
+	pub fn get(&self, id: &str) -> Option<Arc<dyn FormatBackend>> {
+		self.0.iter().find(|backend| backend.id() == id).cloned()
+	}
+
+	pub fn ids(&self) -> Vec<&'static str> {
+		self.0.iter().map(|backend| backend.id()).collect()
+	}
+}
+/// Every built-in format.
+impl Default for Registry {
+	/// Registered loosest first, so that content which reads as several formats is sniffed as the strictest one: any JSON is also YAML, and a Nix attribute set a YAML flow mapping.
+	fn default() -> Self {
+		let mut registry = Self::empty();
+		registry
+			.register(ini::Backend(ini::Flavor::Properties))
+			.register(ini::Backend(ini::Flavor::Ini))
+			.register(yaml::Backend)
+			.register(nix::Backend)
+			.register(jsonnet::Backend)
+			.register(toml::Backend)
+			.register(ini::Backend(ini::Flavor::Env))
+			.register(ron::Backend)
+			.register(json5::Backend(json5::Flavor::Json5))
+			.register(json5::Backend(json5::Flavor::Json));
+		registry
+	}
+}
+
+/// Refuse values that `backend`'s [`Capabilities`] say it can't hold, before it gets to try.
+pub fn check(backend: &dyn FormatBackend, value: &JsonValue) -> Result<()> {
+	if !backend.capabilities().null {
+		if let Some(path) = find_null(value, "") {
+			bail!("`{}` is null, and {} has no null", if path.is_empty() { "/" } else { &path }, backend.name());
+		}
+	}
+	Ok(())
+}
+
+fn find_null(value: &JsonValue, path: &str) -> Option<String> {
+	match value {
+		JsonValue::Null => Some(path.to_owned()),
+		JsonValue::Object(obj) => obj.iter().find_map(|(key, value)| find_null(value, &key_path(path, key))),
+		JsonValue::Array(arr) => arr.iter().enumerate().find_map(|(i, value)| find_null(value, &format!("{path}/{i}"))),
+		_ => None,
+	}
+}
+
+/// Extension of `path`, or "" if it has none.
+pub fn extension(path: &Path) -> &str {
+	path.extension().and_then(std::ffi::OsStr::to_str).unwrap_or("")
+}
+
+/// Path of `key` inside the value at `path`, spelled the way [`ValuePath`](crate::data::ValuePath) does, for error messages.
+fn key_path(path: &str, key: &str) -> String {
+	format!("{path}{}", crate::data::ValuePath::from(vec![key.to_owned()]))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn registry_detection_and_checks() {
+		let registry = Registry::default();
+		let name = |path: &str| registry.detect(Path::new(path)).map(|backend| backend.name());
+		assert_eq!(name("/etc/app/config.json"), Some("JSON"));
+		assert_eq!(name("settings.yml"), Some("YAML"));
+		assert_eq!(name("/srv/.env.production"), Some(".env"));
+		assert_eq!(name("svc.service"), Some("INI"));
+		assert_eq!(name("notes.txt"), None);
+
+		let toml = registry.detect(Path::new("a.toml")).unwrap();
+		let e = check(&*toml, &serde_json::json!({"a": [1, null]})).unwrap_err();
+		assert_eq!(e.to_string(), "`/a/1` is null, and TOML has no null");
+		assert!(check(&*registry.detect(Path::new("a.json")).unwrap(), &JsonValue::Null).is_ok());
+
+		// later registrations take precedence
+		#[derive(Debug)]
+		struct Jsonc;
+		impl FormatBackend for Jsonc {
+			fn name(&self) -> &'static str {
+				"JSONC"
+			}
+
+			fn id(&self) -> &'static str {
+				"jsonc"
+			}
+
+			fn detect(&self, path: &Path) -> bool {
+				matches!(extension(path), "json" | "jsonc")
+			}
+
+			fn parse(&self, _path: &Path, content: &str, _options: &Options) -> Result<JsonValue> {
+				json5::parse(content)
+			}
+
+			fn serialize(&self, _path: &Path, original: Option<&str>, value: &JsonValue, options: &Options) -> Result<String> {
+				json5::serialize(original, value, json5::Flavor::Json5, options.indent.as_deref())
+			}
+
+			fn language(&self) -> &'static str {
+				"json"
+			}
+
+			fn capabilities(&self) -> Capabilities {
+				Capabilities { null: true, comments: true }
+			}
+		}
+		let mut registry = Registry::default();
+		registry.register(Jsonc);
+		assert_eq!(registry.detect(Path::new("a.json")).unwrap().name(), "JSONC");
+		assert_eq!(registry.detect(Path::new("a.json5")).unwrap().name(), "JSON5");
+		assert_eq!(registry.get("jsonc").unwrap().name(), "JSONC");
+	}
+
+	#[test]
+	fn content_sniffing() {
+		let registry = Registry::default();
+		let sniffed = |path: &str, content: &str| registry.resolve(Path::new(path), content).map(|backend| backend.id());
+		assert_eq!(sniffed("config", r#"{"a": [1, 2]}"#), Some("json"));
+		assert_eq!(sniffed("config", "{a: 1, /* why */ b: 'x',}"), Some("json5"));
+		assert_eq!(sniffed("config", "Config(port: 8080, tls: Some(true))"), Some("ron"));
+		assert_eq!(sniffed("app.conf", "# app\n[server]\nport = 8080\nhost = \"::\"\n"), Some("toml"));
+		assert_eq!(sniffed("config", "{ port = 8080; hosts = [ \"a\" ]; }\n"), Some("nix"));
+		assert_eq!(sniffed("config", "server:\n  port: 8080\n"), Some("yaml"));
+		assert_eq!(sniffed("vars", "# deploy\nexport PORT=8080\nNAME=\"my app\"\n"), Some("env"));
+		assert_eq!(sniffed("app.conf", "[server]\nhost = my host\n"), Some("ini"));
+		assert_eq!(sniffed("notes", "just some text\n"), None);
+		// the extension wins over what the content looks like, and an unknown one is looked under
+		assert_eq!(sniffed("a.yaml", r#"{"a": 1}"#), Some("yaml"));
+		assert_eq!(sniffed("config.json.tmpl", "{\"a\": \"{{ A }}\"}"), Some("json"));
+	}
+}
@@ -0,0 +1,838 @@
+//! INI, `.env` and Java `.properties` backend. All three are line-based: every value is one `key = value` line (give or take quoting and continuations), which write-back rewrites, adds or drops while leaving comments and every other line alone.
+//!
+//! Values are strings; with type inference, unquoted ones that look like numbers or booleans are read as such. INI `[section]`s become objects, and a key repeated within a section (as systemd units do with `ExecStart=` and friends) becomes an array.
+use std::{ops::Range, path::Path};
+
+use color_eyre::eyre::ensure;
+use serde_json::{Map, Value as JsonValue};
+use v_utils::prelude::*;
+
+use super::{
+	Capabilities, FormatBackend, Options, extension, key_path,
+	splice::{self, Dialect, Edit, Entry, Node, NodeKind, eol, line_end},
+};
+use crate::utils::get_json_type;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flavor {
+	/// `[section]` headers and `key = value` lines, commented with `;` or `#`.
+	Ini,
+	/// `KEY=value` shell assignments, optionally `export`ed and quoted.
+	Env,
+	/// `key=value`, `key: value` or `key value`, with backslash escapes and line continuations; commented with `#` or `!`.
+	Properties,
+}
+impl Flavor {
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Ini => "INI",
+			Self::Env => ".env",
+			Self::Properties => "Java properties",
+		}
+	}
+
+	fn comment(self, line: &str) -> bool {
+		match self {
+			Self::Ini => line.starts_with([';', '#']),
+			Self::Env => line.starts_with('#'),
+			Self::Properties => line.starts_with(['#', '!']),
+		}
+	}
+}
+
+/// One [`FormatBackend`] per [`Flavor`].
+#[derive(Clone, Copy, Debug)]
+pub struct Backend(pub Flavor);
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		self.0.name()
+	}
+
+	fn id(&self) -> &'static str {
+		match self.0 {
+			Flavor::Ini => "ini",
+			Flavor::Env => "env",
+			Flavor::Properties => "properties",
+		}
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		let name = path.file_name().and_then(std::ffi::OsStr::to_str).unwrap_or("");
+		match self.0 {
+			Flavor::Ini => matches!(extension(path), "ini" | "cfg" | "service" | "socket" | "timer" | "mount" | "network" | "netdev"),
+			// dotenv files usually have no extension of their own
+			Flavor::Env => name == ".env" || name.starts_with(".env.") || extension(path) == "env",
+			Flavor::Properties => extension(path) == "properties",
+		}
+	}
+
+	/// Any line is a valid `.properties` line, so those are never sniffed; `.env` assignments are told from INI ones by having no space before the `=`.
+	fn sniff(&self, content: &str) -> bool {
+		let spaced_assignment = |line: &str| line.split_once('=').is_some_and(|(key, _)| key.ends_with([' ', '\t']) && !line.trim_start().starts_with('#'));
+		let read = parse(content, self.0, false).is_ok_and(|value| value.as_object().is_some_and(|obj| !obj.is_empty()));
+		match self.0 {
+			Flavor::Ini => read,
+			Flavor::Env => read && !content.lines().any(spaced_assignment),
+			Flavor::Properties => false,
+		}
+	}
+
+	fn parse(&self, _path: &Path, content: &str, options: &Options) -> Result<JsonValue> {
+		parse(content, self.0, options.infer_types)
+	}
+
+	fn serialize(&self, _path: &Path, original: Option<&str>, value: &JsonValue, _options: &Options) -> Result<String> {
+		serialize(original, value, self.0)
+	}
+
+	fn language(&self) -> &'static str {
+		match self.0 {
+			Flavor::Ini => "ini",
+			Flavor::Env => "bash",
+			Flavor::Properties => "properties",
+		}
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities { null: false, comments: true }
+	}
+}
+
+pub fn parse(content: &str, flavor: Flavor, infer_types: bool) -> Result<JsonValue> {
+	layout(content, flavor, infer_types)
+		.map(|(_, value)| value)
+		.with_context(|| format!("Failed to read {} file", flavor.name()))
+}
+
+/// Serialize `value`, rewriting only the lines of `original` whose values changed.
+pub fn serialize(original: Option<&str>, value: &JsonValue, flavor: Flavor) -> Result<String> {
+	let dialect = FlatDialect { flavor };
+	// the file only holds text, so only a change in spelling is a change
+	let new = stringify(value);
+	let Some(obj) = new.as_object() else {
+		bail!("{} files hold keys at the top level, not {}", flavor.name(), get_json_type(value));
+	};
+	let out = match original {
+		Some(src) => {
+			let (root, old) = layout(src, flavor, false).with_context(|| format!("Failed to parse the original {} file", flavor.name()))?;
+			splice::patch(&dialect, src, &root, &old, &new)?
+		}
+		None => dialect.render(obj, "", true)?,
+	};
+	// some values can't be spelled so that they read back the same, e.g. INI values with leading whitespace
+	let read_back = layout(&out, flavor, false)?.1;
+	ensure!(
+		read_back == new,
+		"`{}` can't be written as {} so that it reads back the same",
+		first_difference(&new, &read_back, ""),
+		flavor.name()
+	);
+	Ok(out)
+}
+
+/// Numbers and booleans as the strings they are written as.
+fn stringify(value: &JsonValue) -> JsonValue {
+	match value {
+		JsonValue::Number(n) => JsonValue::String(n.to_string()),
+		JsonValue::Bool(b) => JsonValue::String(b.to_string()),
+		JsonValue::Array(arr) => JsonValue::Array(arr.iter().map(stringify).collect()),
+		JsonValue::Object(obj) => JsonValue::Object(obj.iter().map(|(k, v)| (k.clone(), stringify(v))).collect()),
+		JsonValue::String(_) | JsonValue::Null => value.clone(),
+	}
+}
+
+fn infer_type(s: &str) -> JsonValue {
+	match s {
+		"true" => JsonValue::Bool(true),
+		"false" => JsonValue::Bool(false),
+		_ => serde_json::from_str::<serde_json::Number>(s).map_or_else(|_| JsonValue::String(s.to_owned()), JsonValue::Number),
+	}
+}
+
+fn first_difference(expected: &JsonValue, actual: &JsonValue, path: &str) -> String {
+	if let (JsonValue::Object(expected), JsonValue::Object(actual)) = (expected, actual) {
+		for (key, value) in expected {
+			match actual.get(key) {
+				Some(other) if other == value => continue,
+				Some(other) => return first_difference(value, other, &key_path(path, key)),
+				None => return key_path(path, key),
+			}
+		}
+		if let Some(key) = actual.keys().find(|key| !expected.contains_key(*key)) {
+			return key_path(path, key);
+		}
+	}
+	if path.is_empty() { "/".to_owned() } else { path.to_owned() }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum Quote {
+	#[default]
+	None,
+	Single,
+	Double,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Meta {
+	/// Whole lines, newline included, that make up the value: every line setting a key (repeated keys set it more than once), or a section from its header to its last entry. Empty for the root.
+	lines: Vec<Range<usize>>,
+	/// Everything before the key on its line: indentation, and `export ` in `.env` files.
+	prefix: String,
+	/// The key as spelled in the source.
+	key: String,
+	/// Everything between the key and the value, e.g. ` = `.
+	separator: String,
+	quote: Quote,
+}
+
+struct Record {
+	/// The whole logical line, newline included.
+	line: Range<usize>,
+	kind: RecordKind,
+}
+
+enum RecordKind {
+	Section {
+		name: String,
+		name_span: Range<usize>,
+	},
+	Pair {
+		key: String,
+		key_span: Range<usize>,
+		value: String,
+		value_span: Range<usize>,
+		quote: Quote,
+		prefix: String,
+		separator: String,
+	},
+}
+
+/// Every section header and key-value pair in `src`, skipping blank lines and comments.
+fn records(src: &str, flavor: Flavor) -> Result<Vec<Record>> {
+	let mut records = Vec::new();
+	let mut pos = 0;
+	while pos < src.len() {
+		let end = eol(src, pos);
+		let content = src[pos..end].trim_start();
+		let start = end - content.len();
+		if content.trim_end().is_empty() || flavor.comment(content) {
+			pos = line_end(src, pos);
+			continue;
+		}
+		let (kind, logical_end) = match flavor {
+			Flavor::Ini => ini_record(src, pos, start, end).map(|kind| (kind, end)),
+			Flavor::Env => env_record(src, pos, start, end),
+			Flavor::Properties => Ok(properties_record(src, pos, start, end)),
+		}
+		.with_context(|| format!("line {}", src[..pos].matches('\n').count() + 1))?;
+		let next = line_end(src, logical_end);
+		records.push(Record { line: pos..next, kind });
+		pos = next;
+	}
+	Ok(records)
+}
+
+fn ini_record(src: &str, pos: usize, start: usize, end: usize) -> Result<RecordKind> {
+	let text = src[start..end].trim_end();
+	if let Some(header) = text.strip_prefix('[') {
+		let close = header.find(']').ok_or_eyre("unterminated section header")?;
+		let name = header[..close].trim();
+		let name_start = start + 1 + (header.len() - header.trim_start().len());
+		return Ok(RecordKind::Section {
+			name: name.to_owned(),
+			name_span: name_start..name_start + name.len(),
+		});
+	}
+	let sep = text.find('=').or_else(|| text.find(':')).ok_or_eyre("expected `key = value`")?;
+	let key = text[..sep].trim_end();
+	ensure!(!key.is_empty(), "missing key before `{}`", &text[sep..=sep]);
+	let after = &text[sep + 1..];
+	let value_offset = sep + 1 + (after.len() - after.trim_start().len());
+	let value = &text[value_offset..];
+	Ok(RecordKind::Pair {
+		key: key.to_owned(),
+		key_span: start..start + key.len(),
+		value: value.to_owned(),
+		value_span: start + value_offset..start + text.len(),
+		quote: Quote::None,
+		prefix: src[pos..start].to_owned(),
+		separator: text[key.len()..value_offset].to_owned(),
+	})
+}
+
+/// Also returns where the logical line ends, as quoted values may span several.
+fn env_record(src: &str, pos: usize, start: usize, end: usize) -> Result<(RecordKind, usize)> {
+	let key_start = match src[start..end].strip_prefix("export") {
+		Some(rest) if rest.starts_with([' ', '\t']) => end - rest.trim_start().len(),
+		_ => start,
+	};
+	let eq = src[key_start..end].find('=').ok_or_eyre("expected `KEY=value`")?;
+	let key = src[key_start..key_start + eq].trim_end();
+	ensure!(!key.is_empty() && !key.contains(char::is_whitespace), "`{key}` is not a valid variable name");
+	let after = &src[key_start + eq + 1..end];
+	let v = end - after.trim_start_matches([' ', '\t']).len();
+
+	let (value, value_span, quote, logical_end) = match src[v..].chars().next() {
+		Some(q @ ('"' | '\'')) => {
+			let mut value = String::new();
+			let mut chars = src[v + 1..].char_indices();
+			let close = loop {
+				match chars.next() {
+					Some((i, c)) if c == q => break v + 1 + i,
+					Some((_, '\\')) if q == '"' => match chars.next() {
+						Some((_, 'n')) => value.push('\n'),
+						Some((_, c @ ('\\' | '"' | '$'))) => value.push(c),
+						Some((_, c)) => value.extend(['\\', c]),
+						None => bail!("unterminated quote"),
+					},
+					Some((_, c)) => value.push(c),
+					None => bail!("unterminated quote"),
+				}
+			};
+			let quote = if q == '"' { Quote::Double } else { Quote::Single };
+			(value, v..close + 1, quote, eol(src, close))
+		}
+		_ => {
+			let raw = &src[v..end];
+			// `#` starts a comment only after whitespace, so `a#b` is a value
+			let cut = raw.char_indices().find(|&(i, c)| c == '#' && raw[..i].ends_with([' ', '\t'])).map_or(raw.len(), |(i, _)| i);
+			let value = raw[..cut].trim_end();
+			(value.to_owned(), v..v + value.len(), Quote::None, end)
+		}
+	};
+	let kind = RecordKind::Pair {
+		key: key.to_owned(),
+		key_span: key_start..key_start + key.len(),
+		value,
+		value_span,
+		quote,
+		prefix: src[pos..key_start].to_owned(),
+		separator: src[key_start + key.len()..v].to_owned(),
+	};
+	Ok((kind, logical_end))
+}
+
+/// Also returns where the logical line ends, as a trailing backslash continues it on the next line.
+fn properties_record(src: &str, pos: usize, start: usize, end: usize) -> (RecordKind, usize) {
+	let mut logical_end = end;
+	while logical_end < src.len() {
+		let line = src[..logical_end].trim_end_matches('\r');
+		let backslashes = line.len() - line.trim_end_matches('\\').len();
+		if backslashes % 2 == 0 {
+			break;
+		}
+		logical_end = eol(src, logical_end + 1);
+	}
+	let text = src[start..logical_end].trim_end_matches('\r');
+
+	let mut escaped = false;
+	let key_len = text
+		.char_indices()
+		.find(|&(_, c)| {
+			let terminates = !escaped && matches!(c, '=' | ':' | ' ' | '\t' | '\x0c');
+			escaped = !escaped && c == '\\';
+			terminates
+		})
+		.map_or(text.len(), |(i, _)| i);
+	let rest = text[key_len..].trim_start_matches([' ', '\t', '\x0c']);
+	let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest).trim_start_matches([' ', '\t', '\x0c']);
+	let v = start + text.len() - rest.len();
+
+	let kind = RecordKind::Pair {
+		key: unescape_properties(&text[..key_len]),
+		key_span: start..start + key_len,
+		value: unescape_properties(rest),
+		value_span: v..start + text.len(),
+		quote: Quote::None,
+		prefix: src[pos..start].to_owned(),
+		separator: src[start + key_len..v].to_owned(),
+	};
+	(kind, logical_end)
+}
+
+fn unescape_properties(raw: &str) -> String {
+	let mut out = String::with_capacity(raw.len());
+	let mut chars = raw.chars().peekable();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => out.push('\n'),
+			Some('t') => out.push('\t'),
+			Some('r') => out.push('\r'),
+			Some('f') => out.push('\x0c'),
+			Some('u') => {
+				let hex: String = chars.clone().take(4).collect();
+				match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+					Some(c) if hex.len() == 4 => {
+						out.push(c);
+						chars.nth(3);
+					}
+					_ => out.push('u'),
+				}
+			}
+			// a continuation: the next line's leading whitespace doesn't count
+			Some('\r' | '\n') => {
+				chars.next_if_eq(&'\n');
+				while chars.next_if(|c| matches!(c, ' ' | '\t' | '\x0c')).is_some() {}
+			}
+			Some(c) => out.push(c),
+			None => {}
+		}
+	}
+	out
+}
+
+/// `key` escapes everything that would end a key; values only need leading whitespace escaped. Non-ASCII is written as `\uXXXX`, which every reader understands.
+fn escape_properties(s: &str, key: bool) -> String {
+	let mut out = String::with_capacity(s.len());
+	for (i, c) in s.chars().enumerate() {
+		match c {
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			'\x0c' => out.push_str("\\f"),
+			' ' if key || i == 0 => out.push_str("\\ "),
+			'=' | ':' if key => out.extend(['\\', c]),
+			'#' | '!' if key && i == 0 => out.extend(['\\', c]),
+			c if !c.is_ascii() => {
+				let mut units = [0; 2];
+				for unit in c.encode_utf16(&mut units) {
+					out.push_str(&format!("\\u{unit:04x}"));
+				}
+			}
+			c => out.push(c),
+		}
+	}
+	out
+}
+
+fn layout(src: &str, flavor: Flavor, infer_types: bool) -> Result<(Node<Meta>, JsonValue)> {
+	let mut root: Vec<Entry<Meta>> = Vec::new();
+	let mut json = Map::new();
+	let mut section: Option<String> = None;
+	for record in records(src, flavor)? {
+		let line = record.line;
+		let (key, key_span, value, value_span, quote, prefix, separator) = match record.kind {
+			RecordKind::Section { name, name_span } => {
+				match json.entry(name.clone()).or_insert_with(|| JsonValue::Object(Map::new())) {
+					JsonValue::Object(_) => {}
+					_ => bail!("section `[{name}]` clashes with the top-level key `{name}`"),
+				}
+				match root.iter_mut().find(|e| e.key == name) {
+					// a section may be split over several headers
+					Some(entry) => entry.value.meta.lines.push(line),
+					None => root.push(Entry {
+						key: name.clone(),
+						key_span: name_span,
+						value: Node {
+							span: line.end..line.end,
+							kind: NodeKind::Map(Vec::new()),
+							meta: Meta {
+								lines: vec![line],
+								..Meta::default()
+							},
+						},
+					}),
+				}
+				section = Some(name);
+				continue;
+			}
+			RecordKind::Pair {
+				key,
+				key_span,
+				value,
+				value_span,
+				quote,
+				prefix,
+				separator,
+			} => (key, key_span, value, value_span, quote, prefix, separator),
+		};
+
+		let (entries, obj) = match &section {
+			Some(name) => {
+				let entry = root.iter_mut().find(|e| e.key == *name).expect("sections are added before their entries");
+				entry.value.meta.lines.last_mut().expect("sections have a header").end = line.end;
+				let NodeKind::Map(entries) = &mut entry.value.kind else { unreachable!() };
+				(entries, json[name].as_object_mut().expect("checked when the section was added"))
+			}
+			None => (&mut root, &mut json),
+		};
+		let json_value = match infer_types && quote == Quote::None {
+			true => infer_type(&value),
+			false => JsonValue::String(value),
+		};
+		let mut leaf = Node {
+			span: value_span,
+			kind: NodeKind::Leaf,
+			meta: Meta {
+				lines: vec![line.clone()],
+				prefix,
+				key: src[key_span.clone()].to_owned(),
+				separator,
+				quote,
+			},
+		};
+		let Some(existing) = entries.iter_mut().find(|e| e.key == key) else {
+			entries.push(Entry {
+				key: key.clone(),
+				key_span,
+				value: leaf,
+			});
+			obj.insert(key, json_value);
+			continue;
+		};
+		match flavor {
+			// repeated keys add up
+			Flavor::Ini => {
+				let previous = obj.get_mut(&key).expect("entries and values are added together");
+				match previous {
+					JsonValue::Array(arr) => arr.push(json_value),
+					_ => *previous = JsonValue::Array(vec![previous.take(), json_value]),
+				}
+				let node = &mut existing.value;
+				node.meta.lines.push(line);
+				match &mut node.kind {
+					NodeKind::Seq(items) => items.push(leaf),
+					_ => {
+						let first = node.clone();
+						node.span = first.span.start..leaf.span.end;
+						node.kind = NodeKind::Seq(vec![
+							Node {
+								meta: Meta {
+									lines: vec![first.meta.lines[0].clone()],
+									..first.meta
+								},
+								..first
+							},
+							leaf,
+						]);
+					}
+				}
+			}
+			// the last one wins, but all of them go when the key is removed
+			Flavor::Env | Flavor::Properties => {
+				leaf.meta.lines = existing.value.meta.lines.iter().cloned().chain([line]).collect();
+				existing.key_span = key_span;
+				existing.value = leaf;
+				obj.insert(key, json_value);
+			}
+		}
+	}
+
+	for entry in &mut root {
+		if let NodeKind::Map(entries) = &entry.value.kind {
+			if let Some(span) = extent(entries) {
+				entry.value.span = span;
+			}
+		}
+	}
+	let span = extent(&root).unwrap_or(src.len()..src.len());
+	let node = Node {
+		span,
+		kind: NodeKind::Map(root),
+		meta: Meta::default(),
+	};
+	Ok((node, JsonValue::Object(json)))
+}
+
+/// From the first line of `entries` to the end of their last.
+fn extent(entries: &[Entry<Meta>]) -> Option<Range<usize>> {
+	let lines = entries.iter().flat_map(|e| &e.value.meta.lines);
+	let start = lines.clone().map(|l| l.start).min()?;
+	let end = lines.map(|l| l.end).max()?;
+	Some(start..end)
+}
+
+/// Insert whole lines at `at`, starting a new line first if `at` is the end of a file without a final newline.
+fn insert_lines(src: &str, at: usize, text: &str) -> Edit {
+	match at == src.len() && !src.is_empty() && !src.ends_with('\n') {
+		true => Edit::insert(at, format!("\n{text}")),
+		false => Edit::insert(at, text),
+	}
+}
+
+struct FlatDialect {
+	flavor: Flavor,
+}
+impl FlatDialect {
+	/// How new lines are laid out when there is no neighbour to copy from.
+	fn default_style(&self) -> Meta {
+		Meta {
+			separator: if self.flavor == Flavor::Ini { " = " } else { "=" }.to_owned(),
+			..Meta::default()
+		}
+	}
+
+	fn key(&self, key: &str, path: &str) -> Result<String> {
+		match self.flavor {
+			Flavor::Ini => ensure!(
+				!key.is_empty() && key.trim() == key && !key.contains(['=', ':', '\n', '\r']) && !key.starts_with(['[', ';', '#']),
+				"`{path}`: `{key}` can't be used as an INI key"
+			),
+			Flavor::Env => ensure!(
+				key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+				"`{path}`: .env variable names can only hold letters, digits and underscores, and can't start with a digit"
+			),
+			Flavor::Properties => return Ok(escape_properties(key, true)),
+		}
+		Ok(key.to_owned())
+	}
+
+	fn section_name(&self, name: &str, path: &str) -> Result<String> {
+		ensure!(
+			!name.is_empty() && name.trim() == name && !name.contains([']', '\n', '\r']),
+			"`{path}`: `{name}` can't be used as a section name"
+		);
+		Ok(name.to_owned())
+	}
+
+	fn scalar(&self, value: &JsonValue, quote: Quote, path: &str) -> Result<String> {
+		let JsonValue::String(s) = value else {
+			match (self.flavor, value) {
+				(Flavor::Ini, JsonValue::Object(_)) => bail!("`{path}`: INI sections can't be nested"),
+				_ => bail!("`{path}` is {}, but {} values can only be strings, numbers or booleans", get_json_type(value), self.flavor.name()),
+			}
+		};
+		Ok(match self.flavor {
+			Flavor::Ini => {
+				ensure!(!s.contains(['\n', '\r']), "`{path}`: INI values can't span several lines");
+				s.clone()
+			}
+			Flavor::Env => {
+				let double = || format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"));
+				match quote {
+					Quote::Double => double(),
+					Quote::Single if !s.contains('\'') => format!("'{s}'"),
+					_ if s.contains(|c: char| c.is_whitespace() || matches!(c, '#' | '"' | '\'' | '\\' | '`')) => double(),
+					_ => s.clone(),
+				}
+			}
+			Flavor::Properties => escape_properties(s, false),
+		})
+	}
+
+	/// The lines setting `key_text` (already spelled for the source) to `value`; arrays in INI files repeat the key.
+	fn lines(&self, style: &Meta, key_text: &str, value: &JsonValue, path: &str) -> Result<String> {
+		let values: Vec<&JsonValue> = match value {
+			JsonValue::Array(items) if self.flavor == Flavor::Ini => items.iter().collect(),
+			_ => vec![value],
+		};
+		values
+			.into_iter()
+			.map(|value| Ok(format!("{}{key_text}{}{}\n", style.prefix, style.separator, self.scalar(value, style.quote, path)?)))
+			.collect()
+	}
+
+	/// A whole file's worth of `obj`, or a section's if not at the `root`.
+	fn render(&self, obj: &Map<String, JsonValue>, path: &str, root: bool) -> Result<String> {
+		let mut pairs = String::new();
+		let mut sections = Vec::new();
+		for (key, value) in obj {
+			let item_path = key_path(path, key);
+			match value {
+				JsonValue::Object(section) if root && self.flavor == Flavor::Ini =>
+					sections.push(format!("[{}]\n{}", self.section_name(key, &item_path)?, self.render(section, &item_path, false)?)),
+				_ => pairs.push_str(&self.lines(&self.default_style(), &self.key(key, &item_path)?, value, &item_path)?),
+			}
+		}
+		// top-level keys have to come before the first section
+		Ok(std::iter::once(pairs).filter(|pairs| !pairs.is_empty()).chain(sections).collect::<Vec<_>>().join("\n"))
+	}
+}
+
+impl Dialect for FlatDialect {
+	type Meta = Meta;
+
+	fn replace(&self, src: &str, node: &Node<Meta>, value: &JsonValue, path: &str) -> Result<Edit> {
+		match (&node.kind, value) {
+			(NodeKind::Map(_), JsonValue::Object(obj)) => {
+				let text = self.render(obj, path, node.meta.lines.is_empty())?;
+				Ok(match node.span.is_empty() {
+					true => insert_lines(src, node.span.start, &text),
+					false => Edit::replace(node.span.clone(), text),
+				})
+			}
+			(NodeKind::Map(_), _) => bail!("`{path}` is a section, so it can only hold keys"),
+			(NodeKind::Leaf, JsonValue::Array(_)) if self.flavor == Flavor::Ini => Ok(Edit::replace(node.meta.lines[0].clone(), self.lines(&node.meta, &node.meta.key, value, path)?)),
+			(NodeKind::Leaf, _) => Ok(Edit::replace(node.span.clone(), self.scalar(value, node.meta.quote, path)?)),
+			(NodeKind::Seq(items), _) => bail!("`{path}` is set on {} lines, which can only be added or removed one at a time", items.len()),
+			(NodeKind::Opaque, _) => unreachable!("every value is written out"),
+		}
+	}
+
+	fn insert_entry(&self, src: &str, map: &Node<Meta>, key: &str, value: &JsonValue, path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let root = map.meta.lines.is_empty();
+		if let (true, Flavor::Ini, JsonValue::Object(section)) = (root, self.flavor, value) {
+			let text = format!("\n[{}]\n{}", self.section_name(key, path)?, self.render(section, path, false)?);
+			return Ok(vec![insert_lines(src, src.len(), &text)]);
+		}
+		let last_pair = entries.iter().rev().find(|e| !matches!(e.value.kind, NodeKind::Map(_)));
+		let (at, style) = match last_pair {
+			Some(last) => (
+				last.value.meta.lines.iter().map(|l| l.end).max().expect("values have lines"),
+				Meta {
+					quote: Quote::None,
+					// a bare `key` of a properties file has no separator to copy
+					separator: match last.value.meta.separator.is_empty() {
+						true => self.default_style().separator,
+						false => last.value.meta.separator.clone(),
+					},
+					..last.value.meta.clone()
+				},
+			),
+			// only sections so far: top-level keys go before the first of them
+			None => (entries[0].value.meta.lines[0].start, self.default_style()),
+		};
+		Ok(vec![insert_lines(src, at, &self.lines(&style, &self.key(key, path)?, value, path)?)])
+	}
+
+	fn remove_entry(&self, _src: &str, map: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		Ok(entries[idx].value.meta.lines.iter().map(|line| Edit::replace(line.clone(), "")).collect())
+	}
+
+	fn rename_key(&self, _src: &str, entry: &Entry<Meta>, new_key: &str) -> Result<Edit> {
+		ensure!(entry.value.meta.lines.len() == 1, "`{}` is set on several lines, so it can't be renamed here", entry.key);
+		let spelled = match entry.value.kind {
+			NodeKind::Map(_) => self.section_name(new_key, new_key)?,
+			_ => self.key(new_key, new_key)?,
+		};
+		Ok(Edit::replace(entry.key_span.clone(), spelled))
+	}
+
+	fn insert_item(&self, src: &str, seq: &Node<Meta>, idx: usize, value: &JsonValue, path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let style = Meta {
+			quote: Quote::None,
+			..items[0].meta.clone()
+		};
+		let at = match items.get(idx) {
+			Some(next) => next.meta.lines[0].start,
+			None => items[items.len() - 1].meta.lines[0].end,
+		};
+		Ok(vec![insert_lines(src, at, &self.lines(&style, &style.key, value, path)?)])
+	}
+
+	fn remove_item(&self, _src: &str, seq: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		Ok(vec![Edit::replace(items[idx].meta.lines[0].clone(), "")])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const UNIT: &str = "; managed by hand
+[Unit]
+Description = My service
+After=network.target
+
+[Service]
+# the binary
+ExecStart=/usr/bin/svc --port 8080
+Environment=A=1
+Environment=B=2
+";
+
+	fn edit(src: &str, flavor: Flavor, f: impl FnOnce(&mut JsonValue)) -> Result<String> {
+		let mut value = parse(src, flavor, false).unwrap();
+		f(&mut value);
+		serialize(Some(src), &value, flavor)
+	}
+
+	#[test]
+	fn ini_sections_and_repeated_keys() {
+		let value = parse(UNIT, Flavor::Ini, false).unwrap();
+		assert_eq!(
+			value,
+			json!({
+				"Unit": {"Description": "My service", "After": "network.target"},
+				"Service": {"ExecStart": "/usr/bin/svc --port 8080", "Environment": ["A=1", "B=2"]},
+			})
+		);
+		assert_eq!(serialize(Some(UNIT), &value, Flavor::Ini).unwrap(), UNIT);
+
+		let out = edit(UNIT, Flavor::Ini, |v| {
+			v["Unit"]["Description"] = json!("Other");
+			v["Service"]["Environment"].as_array_mut().unwrap().push(json!("C=3"));
+			v["Service"]["Restart"] = json!("always");
+			v["Install"] = json!({"WantedBy": "multi-user.target"});
+			v["Unit"].as_object_mut().unwrap().remove("After");
+		})
+		.unwrap();
+		insta::assert_snapshot!(out, @r###"
+  ; managed by hand
+  [Unit]
+  Description = Other
+
+  [Service]
+  # the binary
+  ExecStart=/usr/bin/svc --port 8080
+  Environment=A=1
+  Environment=B=2
+  Environment=C=3
+  Restart=always
+
+  [Install]
+  WantedBy = multi-user.target
+  "###);
+
+		let e = edit(UNIT, Flavor::Ini, |v| v["Unit"]["After"] = json!(" padded")).unwrap_err();
+		assert!(e.to_string().contains("`/Unit/After`"), "{e}");
+		let e = edit(UNIT, Flavor::Ini, |v| v["Unit"]["Nested"] = json!({})).unwrap_err();
+		assert!(e.to_string().contains("can't be nested"), "{e}");
+	}
+
+	#[test]
+	fn env_quoting_and_comments() {
+		let src = "# secrets\nexport TOKEN=\"abc\\\"def\" # quoted\nPORT=8080 # inline\nNAME='svc'\n";
+		assert_eq!(parse(src, Flavor::Env, false).unwrap(), json!({"TOKEN": "abc\"def", "PORT": "8080", "NAME": "svc"}));
+		assert_eq!(parse(src, Flavor::Env, true).unwrap()["PORT"], json!(8080));
+		assert_eq!(parse(src, Flavor::Env, true).unwrap()["TOKEN"], json!("abc\"def"));
+
+		let out = edit(src, Flavor::Env, |v| {
+			v["TOKEN"] = json!("new");
+			v["PORT"] = json!(9090);
+			v["NAME"] = json!("it's");
+			v["GREETING"] = json!("hello world");
+		})
+		.unwrap();
+		assert_eq!(out, "# secrets\nexport TOKEN=\"new\" # quoted\nPORT=9090 # inline\nNAME=\"it's\"\nGREETING=\"hello world\"\n");
+		let e = edit(src, Flavor::Env, |v| v["bad key"] = json!("x")).unwrap_err();
+		assert!(e.to_string().contains("letters, digits and underscores"), "{e}");
+	}
+
+	#[test]
+	fn properties_escapes_and_continuations() {
+		let src = "! app\nurl = http\\://example.com/\\\n    path\nkey\\ with\\ spaces: value\nname=caf\\u00e9\nempty\n";
+		assert_eq!(
+			parse(src, Flavor::Properties, false).unwrap(),
+			json!({"url": "http://example.com/path", "key with spaces": "value", "name": "café", "empty": ""})
+		);
+		let out = edit(src, Flavor::Properties, |v| {
+			v["url"] = json!("https://example.com/");
+			v["name"] = json!("naïve");
+			v[" lead"] = json!(" x");
+		})
+		.unwrap();
+		assert_eq!(out, "! app\nurl = https://example.com/\nkey\\ with\\ spaces: value\nname=na\\u00efve\nempty\n\\ lead=\\ x\n");
+	}
+
+	#[test]
+	fn fresh_and_emptied_files() {
+		let value = json!({"top": 1, "s": {"a": true, "list": ["x", "y"]}});
+		assert_eq!(serialize(None, &value, Flavor::Ini).unwrap(), "top = 1\n\n[s]\na = true\nlist = x\nlist = y\n");
+		assert_eq!(edit("# only a comment\n", Flavor::Env, |v| v["A"] = json!("1")).unwrap(), "# only a comment\nA=1\n");
+		assert_eq!(edit("# keep\nA=1", Flavor::Env, |v| *v = json!({})).unwrap(), "# keep\n");
+		assert!(serialize(None, &json!({"a": {"b": 1}}), Flavor::Env).is_err());
+	}
+}
@@ -0,0 +1,521 @@
+//! JSON and JSON5 backend. Existing files are patched in place, keeping comments, key order, quoting and trailing commas; new files are pretty-printed.
+use std::path::Path;
+
+use color_eyre::eyre::ensure;
+use serde_json::Value as JsonValue;
+use v_utils::prelude::*;
+
+use super::{
+	Capabilities, FormatBackend, Options, extension,
+	splice::{self, Dialect, Edit, Entry, Node, NodeKind, Separated, line_end, line_indent},
+};
+
+/// Plain `.json` files never get JSON5-only syntax written into them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flavor {
+	Json,
+	Json5,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backend(pub Flavor);
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		match self.0 {
+			Flavor::Json => "JSON",
+			Flavor::Json5 => "JSON5",
+		}
+	}
+
+	fn id(&self) -> &'static str {
+		match self.0 {
+			Flavor::Json => "json",
+			Flavor::Json5 => "json5",
+		}
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		extension(path) == self.id()
+	}
+
+	fn sniff(&self, content: &str) -> bool {
+		content.trim_start().starts_with(['{', '['])
+			&& match self.0 {
+				Flavor::Json => serde_json::from_str::<JsonValue>(content).is_ok(),
+				Flavor::Json5 => parse(content).is_ok(),
+			}
+	}
+
+	fn parse(&self, _path: &Path, content: &str, _options: &Options) -> Result<JsonValue> {
+		parse(content)
+	}
+
+	fn serialize(&self, _path: &Path, original: Option<&str>, value: &JsonValue, options: &Options) -> Result<String> {
+		serialize(original, value, self.0, options.indent.as_deref())
+	}
+
+	fn language(&self) -> &'static str {
+		"json"
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			null: true,
+			comments: self.0 == Flavor::Json5,
+		}
+	}
+}
+
+pub fn parse(content: &str) -> Result<JsonValue> {
+	json5::from_str(content).context("Failed to read JSON file")
+}
+
+/// Serialize `value`, reusing the layout of `original` for everything that did not change.
+///
+/// Newly written nodes are indented by `indent`, or by whatever the original file uses if that is not set.
+pub fn serialize(original: Option<&str>, value: &JsonValue, flavor: Flavor, indent: Option<&str>) -> Result<String> {
+	let Some(src) = original.filter(|s| !s.trim().is_empty()) else {
+		return Ok(to_string_pretty(value, indent.unwrap_or("  ")) + "\n");
+	};
+	let old = parse(src)?;
+	let root = Parser::new(src).document()?;
+	let dialect = Json5Dialect {
+		unit: indent.map(str::to_owned).unwrap_or_else(|| detect_indent(src)),
+		flavor,
+	};
+	let out = splice::patch(&dialect, src, &root, &old, value)?;
+	if parse(&out)? != *value {
+		bail!("Failed to write JSON file: the patched file doesn't read back as the edited value");
+	}
+	Ok(out)
+}
+
+/// Pretty-print `value` as JSON (which is also valid JSON5), one level of nesting per `indent`.
+pub fn to_string_pretty(value: &JsonValue, indent: &str) -> String {
+	Json5Dialect {
+		unit: indent.to_owned(),
+		flavor: Flavor::Json,
+	}
+	.render(value, "", '"', true)
+}
+
+/// The indentation of the first indented line, or two spaces.
+fn detect_indent(src: &str) -> String {
+	src.lines().map(|l| &l[..l.len() - l.trim_start().len()]).find(|ws| !ws.is_empty()).unwrap_or("  ").to_owned()
+}
+
+#[derive(Clone, Debug, Default)]
+struct Meta {
+	/// Quote character of string literals.
+	quote: Option<char>,
+	/// Offset of the comma following this value in its container.
+	comma: Option<usize>,
+}
+impl Separated for Meta {
+	fn comma(&self) -> Option<usize> {
+		self.comma
+	}
+}
+
+struct Parser<'a> {
+	src: &'a str,
+	pos: usize,
+}
+impl<'a> Parser<'a> {
+	fn new(src: &'a str) -> Self {
+		Self { src, pos: 0 }
+	}
+
+	fn document(&mut self) -> Result<Node<Meta>> {
+		self.skip_trivia();
+		let root = self.value()?;
+		self.skip_trivia();
+		ensure!(self.pos == self.src.len(), "Failed to read JSON file: trailing characters at byte {}", self.pos);
+		Ok(root)
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.src[self.pos..].chars().next()
+	}
+
+	fn expect(&mut self, c: char) -> Result<()> {
+		ensure!(self.peek() == Some(c), "Failed to read JSON file: expected `{c}` at byte {}", self.pos);
+		self.pos += 1;
+		Ok(())
+	}
+
+	fn skip_trivia(&mut self) {
+		loop {
+			let rest = &self.src[self.pos..];
+			let trimmed = rest.trim_start();
+			self.pos += rest.len() - trimmed.len();
+			if trimmed.starts_with("//") {
+				self.pos = line_end(self.src, self.pos);
+			} else if trimmed.starts_with("/*") {
+				self.pos = trimmed.find("*/").map(|i| self.pos + i + 2).unwrap_or(self.src.len());
+			} else {
+				return;
+			}
+		}
+	}
+
+	fn value(&mut self) -> Result<Node<Meta>> {
+		let start = self.pos;
+		let (kind, quote) = match self.peek() {
+			Some('{') => (self.object()?, None),
+			Some('[') => (self.array()?, None),
+			Some(q @ ('"' | '\'')) => {
+				self.string()?;
+				(NodeKind::Leaf, Some(q))
+			}
+			Some(_) => {
+				self.bare_word();
+				ensure!(self.pos > start, "Failed to read JSON file: unexpected character at byte {start}");
+				(NodeKind::Leaf, None)
+			}
+			None => bail!("Failed to read JSON file: unexpected end of input"),
+		};
+		Ok(Node {
+			span: start..self.pos,
+			kind,
+			meta: Meta { quote, comma: None },
+		})
+	}
+
+	fn string(&mut self) -> Result<()> {
+		let quote = self.peek().expect("called on a quote");
+		let mut escaped = false;
+		for (i, c) in self.src[self.pos..].char_indices().skip(1) {
+			match c {
+				_ if escaped => escaped = false,
+				'\\' => escaped = true,
+				c if c == quote => {
+					self.pos += i + 1;
+					return Ok(());
+				}
+				_ => {}
+			}
+		}
+		bail!("Failed to read JSON file: unterminated string at byte {}", self.pos)
+	}
+
+	/// Numbers, literals and unquoted keys.
+	fn bare_word(&mut self) {
+		let rest = &self.src[self.pos..];
+		let len = rest.find(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | ']' | '}' | '/')).unwrap_or(rest.len());
+		self.pos += len;
+	}
+
+	/// Parses a trailing `,` if there is one, recording it on the value before it.
+	fn separator(&mut self, value: &mut Node<Meta>) {
+		self.skip_trivia();
+		if self.peek() == Some(',') {
+			value.meta.comma = Some(self.pos);
+			self.pos += 1;
+			self.skip_trivia();
+		}
+	}
+
+	fn object(&mut self) -> Result<NodeKind<Meta>> {
+		self.expect('{')?;
+		let mut entries = Vec::new();
+		self.skip_trivia();
+		while self.peek() != Some('}') {
+			let key_start = self.pos;
+			match self.peek() {
+				Some('"' | '\'') => self.string()?,
+				_ => self.bare_word(),
+			}
+			let key_span = key_start..self.pos;
+			let raw = &self.src[key_span.clone()];
+			let key = match raw.starts_with(['"', '\'']) {
+				true => json5::from_str(raw).context("Failed to read JSON file: invalid key")?,
+				false => raw.to_owned(),
+			};
+			self.skip_trivia();
+			self.expect(':')?;
+			self.skip_trivia();
+			let mut value = self.value()?;
+			let had_comma = {
+				self.separator(&mut value);
+				value.meta.comma.is_some()
+			};
+			entries.push(Entry { key, key_span, value });
+			if !had_comma {
+				break;
+			}
+		}
+		self.expect('}')?;
+		Ok(NodeKind::Map(entries))
+	}
+
+	fn array(&mut self) -> Result<NodeKind<Meta>> {
+		self.expect('[')?;
+		let mut items = Vec::new();
+		self.skip_trivia();
+		while self.peek() != Some(']') {
+			let mut value = self.value()?;
+			self.separator(&mut value);
+			let had_comma = value.meta.comma.is_some();
+			items.push(value);
+			if !had_comma {
+				break;
+			}
+		}
+		self.expect(']')?;
+		Ok(NodeKind::Seq(items))
+	}
+}
+
+struct Json5Dialect {
+	/// One level of indentation, as used by the file.
+	unit: String,
+	flavor: Flavor,
+}
+
+impl Json5Dialect {
+	fn render(&self, value: &JsonValue, indent: &str, quote: char, quote_keys: bool) -> String {
+		let inner = format!("{indent}{}", self.unit);
+		match value {
+			JsonValue::String(s) => quote_str(s, quote),
+			JsonValue::Object(o) if !o.is_empty() => {
+				let entries = o
+					.iter()
+					.map(|(k, v)| format!("{inner}{}: {}", self.key(k, quote_keys, quote), self.render(v, &inner, quote, quote_keys)));
+				format!("{{\n{}\n{indent}}}", entries.collect::<Vec<_>>().join(",\n"))
+			}
+			JsonValue::Array(a) if !a.is_empty() => {
+				let items = a.iter().map(|v| format!("{inner}{}", self.render(v, &inner, quote, quote_keys)));
+				format!("[\n{}\n{indent}]", items.collect::<Vec<_>>().join(",\n"))
+			}
+			_ => value.to_string(),
+		}
+	}
+
+	fn key(&self, key: &str, quoted: bool, quote: char) -> String {
+		match quoted || self.flavor == Flavor::Json || !is_identifier(key) {
+			true => quote_str(key, quote),
+			false => key.to_owned(),
+		}
+	}
+
+	/// Key quoting and quote character used by the file around `map`.
+	fn key_style(&self, src: &str, map: &Node<Meta>) -> (bool, char) {
+		let NodeKind::Map(entries) = &map.kind else { return (true, '"') };
+		match entries.first().and_then(|e| src[e.key_span.clone()].chars().next()) {
+			Some(q @ ('"' | '\'')) => (true, q),
+			Some(_) => (false, self.string_quote(src, map)),
+			None => (true, '"'),
+		}
+	}
+
+	/// Quote used for string values directly inside `container`.
+	fn string_quote(&self, _src: &str, container: &Node<Meta>) -> char {
+		let values: Vec<&Node<Meta>> = match &container.kind {
+			NodeKind::Map(entries) => entries.iter().map(|e| &e.value).collect(),
+			NodeKind::Seq(items) => items.iter().collect(),
+			NodeKind::Leaf | NodeKind::Opaque => vec![container],
+		};
+		values.iter().find_map(|v| v.meta.quote).unwrap_or('"')
+	}
+}
+
+impl Dialect for Json5Dialect {
+	type Meta = Meta;
+
+	fn replace(&self, src: &str, node: &Node<Meta>, value: &JsonValue, _path: &str) -> Result<Edit> {
+		let quote = node.meta.quote.unwrap_or_else(|| self.string_quote(src, node));
+		let (quote_keys, _) = self.key_style(src, node);
+		Ok(Edit::replace(node.span.clone(), self.render(value, line_indent(src, node.span.start), quote, quote_keys)))
+	}
+
+	fn insert_entry(&self, src: &str, map: &Node<Meta>, key: &str, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let (quote_keys, quote) = self.key_style(src, map);
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let indent = line_indent(src, entries[0].key_span.start);
+		let text = format!("{}: {}", self.key(key, quote_keys, quote), self.render(value, indent, self.string_quote(src, map), quote_keys));
+		Ok(splice::insert_separated(src, map, map.span.end - 1, entries.len(), &text))
+	}
+
+	fn remove_entry(&self, src: &str, map: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, map, &[idx]))
+	}
+
+	fn remove_entries(&self, src: &str, map: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, map, indices))
+	}
+
+	fn rename_key(&self, src: &str, entry: &Entry<Meta>, new_key: &str) -> Result<Edit> {
+		let old = &src[entry.key_span.clone()];
+		let quote = old.chars().next().filter(|c| matches!(c, '"' | '\''));
+		Ok(Edit::replace(entry.key_span.clone(), self.key(new_key, quote.is_some(), quote.unwrap_or('"'))))
+	}
+
+	fn insert_item(&self, src: &str, seq: &Node<Meta>, idx: usize, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let text = self.render(value, line_indent(src, items[0].span.start), self.string_quote(src, seq), true);
+		Ok(splice::insert_separated(src, seq, seq.span.end - 1, idx, &text))
+	}
+
+	fn append_items(&self, src: &str, seq: &Node<Meta>, values: &[JsonValue], _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let indent = line_indent(src, items[0].span.start);
+		let quote = self.string_quote(src, seq);
+		let texts: Vec<_> = values.iter().map(|value| self.render(value, indent, quote, true)).collect();
+		Ok(splice::append_separated(src, seq, seq.span.end - 1, &texts))
+	}
+
+	fn remove_item(&self, src: &str, seq: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, seq, &[idx]))
+	}
+
+	fn remove_items(&self, src: &str, seq: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, seq, indices))
+	}
+}
+
+fn is_identifier(s: &str) -> bool {
+	let mut chars = s.chars();
+	chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$') && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// String literal in `quote`s, escaped the way JSON (and JSON5, and Jsonnet) read it.
+pub fn quote_str(s: &str, quote: char) -> String {
+	if quote == '"' {
+		return serde_json::to_string(s).unwrap();
+	}
+	let mut out = String::from(quote);
+	for c in s.chars() {
+		match c {
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if c == quote => {
+				out.push('\\');
+				out.push(c);
+			}
+			c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push(quote);
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const SOURCE: &str = r#"// service config
+{
+	name: 'svc', // the name
+	/* connection */
+	server: {
+		host: 'localhost',
+		port: 8080,
+	},
+	tags: ['a', 'b'],
+}
+"#;
+
+	fn edited(f: impl FnOnce(&mut JsonValue)) -> String {
+		let mut value = parse(SOURCE).unwrap();
+		f(&mut value);
+		let out = serialize(Some(SOURCE), &value, Flavor::Json5, None).unwrap();
+		assert_eq!(parse(&out).unwrap(), value);
+		out
+	}
+
+	#[test]
+	fn untouched_roundtrip_is_identical() {
+		assert_eq!(edited(|_| {}), SOURCE);
+	}
+
+	#[test]
+	fn edits_keep_comments_and_style() {
+		let out = edited(|v| {
+			v["name"] = json!("api");
+			v["server"]["tls"] = json!(true);
+			v["tags"].as_array_mut().unwrap().push(json!("c"));
+		});
+		insta::assert_snapshot!(out, @r"
+		// service config
+		{
+			name: 'api', // the name
+			/* connection */
+			server: {
+				host: 'localhost',
+				port: 8080,
+				tls: true,
+			},
+			tags: ['a', 'b', 'c'],
+		}
+		");
+	}
+
+	#[test]
+	fn removing_last_entry_without_trailing_comma() {
+		let src = "{\n  \"a\": 1,\n  \"b\": 2\n}\n";
+		let mut value = parse(src).unwrap();
+		value.as_object_mut().unwrap().remove("b");
+		assert_eq!(serialize(Some(src), &value, Flavor::Json, None).unwrap(), "{\n  \"a\": 1\n}\n");
+	}
+
+	#[test]
+	fn removing_several_elements_at_once() {
+		let write = |src: &str, value: JsonValue| {
+			let out = serialize(Some(src), &value, Flavor::Json, None).unwrap();
+			assert_eq!(parse(&out).unwrap(), value);
+			out
+		};
+		assert_eq!(write("[1, 2, 3]", json!([1])), "[1]");
+		assert_eq!(write("[1, 2, 3]", json!([3])), "[3]");
+		assert_eq!(write("[1, 2, 3, 4, 5]", json!([1, 3])), "[1, 3]");
+		assert_eq!(write("[\"x\", \"a\", \"a\"]", json!(["x"])), "[\"x\"]");
+		assert_eq!(write(r#"{"a":1,"b":2,"c":3}"#, json!({"a": 1})), r#"{"a":1}"#);
+		assert_eq!(write(r#"{"a":1,"b":2,"c":3}"#, json!({"b": 2})), r#"{"b":2}"#);
+		assert_eq!(write("{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": 3\n}\n", json!({"a": 1})), "{\n  \"a\": 1\n}\n");
+		assert_eq!(write("{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": 3,\n}\n", json!({"c": 3})), "{\n  \"c\": 3,\n}\n");
+	}
+
+	#[test]
+	fn removing_every_element() {
+		let src = "{\n  \"list\": [1, 2, 3],\n  \"map\": {\"a\": 1, \"b\": 2}\n}\n";
+		let value = json!({"list": [], "map": {}});
+		let out = serialize(Some(src), &value, Flavor::Json, None).unwrap();
+		assert_eq!(out, "{\n  \"list\": [],\n  \"map\": {}\n}\n");
+	}
+
+	#[test]
+	fn appending_several_items_to_a_multiline_array() {
+		let src = "{\n  \"list\": [\n    \"a\",\n    \"b\" // last\n  ]\n}\n";
+		let mut value = parse(src).unwrap();
+		value["list"].as_array_mut().unwrap().extend([json!("c"), json!("d")]);
+		let out = serialize(Some(src), &value, Flavor::Json, None).unwrap();
+		assert_eq!(out, "{\n  \"list\": [\n    \"a\",\n    \"b\", // last\n    \"c\",\n    \"d\"\n  ]\n}\n");
+		assert_eq!(parse(&out).unwrap(), value);
+	}
+
+	#[test]
+	fn crlf_files_keep_their_line_endings() {
+		let src = "{\r\n  \"a\": 1, // one\r\n  \"list\": [\r\n    1\r\n  ]\r\n}\r\n";
+		let mut value = parse(src).unwrap();
+		value["b"] = json!(2);
+		value["list"].as_array_mut().unwrap().extend([json!(2), json!(3)]);
+		let out = serialize(Some(src), &value, Flavor::Json, None).unwrap();
+		assert_eq!(out, "{\r\n  \"a\": 1, // one\r\n  \"list\": [\r\n    1,\r\n    2,\r\n    3\r\n  ],\r\n  \"b\": 2\r\n}\r\n");
+		assert_eq!(parse(&out).unwrap(), value);
+	}
+
+	#[test]
+	fn fresh_file_is_pretty_printed() {
+		let value = json!({"b": [1, 2], "a": {"c": "d"}});
+		assert_eq!(
+			serialize(None, &value, Flavor::Json5, Some("\t")).unwrap(),
+			"{\n\t\"b\": [\n\t\t1,\n\t\t2\n\t],\n\t\"a\": {\n\t\t\"c\": \"d\"\n\t}\n}\n"
+		);
+	}
+}
@@ -0,0 +1,341 @@
+//! Jsonnet backend. Files are evaluated in-process; writing edits the literals the edited fields are spelled with, and refuses to touch anything computed (`self` references, function calls, `+:` fields, object inheritance, imports).
+//!
+//! The evaluator is our own rather than jrsonnet's: jrsonnet-evaluator and jrsonnet-parser aren't available to the offline builds this crate has to support. Write-back only relies on `syntax`, whose tree keeps the byte range of every field name and literal, so swapping evaluators means replacing `eval::evaluate` and nothing else. Until then, `eval`'s conformance tests pin its behaviour to cases from the Jsonnet test suite.
+use std::path::Path;
+
+use serde_json::Value as JsonValue;
+use v_utils::prelude::*;
+
+use super::{
+	Capabilities, FormatBackend, Options, extension,
+	json5::quote_str,
+	splice::{self, Dialect, Edit, Entry, Node, NodeKind, Separated, line_indent},
+};
+
+mod eval;
+mod syntax;
+
+use syntax::{Expr, ExprKind, Field, FieldName, Member, UnOp};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backend;
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		"Jsonnet"
+	}
+
+	fn id(&self) -> &'static str {
+		"jsonnet"
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		matches!(extension(path), "jsonnet" | "libsonnet")
+	}
+
+	fn parse(&self, path: &Path, content: &str, _options: &Options) -> Result<JsonValue> {
+		eval(path, content)
+	}
+
+	fn serialize(&self, path: &Path, original: Option<&str>, value: &JsonValue, options: &Options) -> Result<String> {
+		let Some(src) = original.filter(|s| !s.trim().is_empty()) else {
+			return Ok(JsonnetDialect::new(options.indent.as_deref().unwrap_or("  ")).render(value, "", '\'') + "\n");
+		};
+		let old = eval(path, src)?;
+		let root = layout(src, &*syntax::parse(src).map_err(|e| eyre!("Failed to read Jsonnet file: {e}"))?);
+		let dialect = JsonnetDialect::new(options.indent.as_deref().unwrap_or(&detect_indent(src)));
+		let out = splice::patch(&dialect, src, &root, &old, value)?;
+		if let Some(differs) = difference(&eval(path, &out)?, value, "") {
+			bail!("Changing this would also change `{differs}`, which is computed from it, so the write was refused");
+		}
+		Ok(out)
+	}
+
+	fn language(&self) -> &'static str {
+		"jsonnet"
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities { null: true, comments: true }
+	}
+}
+
+/// Evaluate `content`, the contents of the Jsonnet file at `path`, with imports resolved relative to it.
+pub fn eval(path: &Path, content: &str) -> Result<JsonValue> {
+	eval::evaluate(path, content).map_err(|e| eyre!("Jsonnet evaluation failed: {e}"))
+}
+
+/// Path of the first place `evaluated` and `expected` differ at, if any.
+fn difference(evaluated: &JsonValue, expected: &JsonValue, path: &str) -> Option<String> {
+	match (evaluated, expected) {
+		(JsonValue::Object(a), JsonValue::Object(b)) => a
+			.keys()
+			.chain(b.keys())
+			.find_map(|k| difference(a.get(k).unwrap_or(&JsonValue::Null), b.get(k).unwrap_or(&JsonValue::Null), &super::key_path(path, k))),
+		(JsonValue::Array(a), JsonValue::Array(b)) if a.len() == b.len() => a.iter().zip(b).enumerate().find_map(|(i, (a, b))| difference(a, b, &format!("{path}/{i}"))),
+		_ => (!splice::same(evaluated, expected)).then(|| if path.is_empty() { "/".to_owned() } else { path.to_owned() }),
+	}
+}
+
+/// The indentation of the first indented line, or two spaces.
+fn detect_indent(src: &str) -> String {
+	src.lines().map(|l| &l[..l.len() - l.trim_start().len()]).find(|ws| !ws.is_empty()).unwrap_or("  ").to_owned()
+}
+
+#[derive(Clone, Debug, Default)]
+struct Meta {
+	/// Quote character of string literals.
+	quote: Option<char>,
+	/// Offset of the comma following this value in its container.
+	comma: Option<usize>,
+}
+impl Separated for Meta {
+	fn comma(&self) -> Option<usize> {
+		self.comma
+	}
+}
+
+/// Object and array literals become containers, plain literals leaves, and anything else is opaque.
+fn layout(src: &str, expr: &Expr) -> Node<Meta> {
+	let separated = |value: &Expr| {
+		let mut node = layout(src, value);
+		let next = syntax::next_token(src, value.span.end);
+		node.meta.comma = src[next..].starts_with(',').then_some(next);
+		node
+	};
+	let kind = match &expr.kind {
+		// the value of these is their body, which is where edits go
+		ExprKind::Local { body, .. } | ExprKind::Assert { body, .. } => return layout(src, body),
+
+		ExprKind::Object(members) => NodeKind::Map(
+			members
+				.iter()
+				.filter_map(|member| match member {
+					Member::Field(Field {
+						name: FieldName::Fixed(name),
+						name_span,
+						plus,
+						value,
+						..
+					}) => {
+						let mut node = separated(value);
+						// `a+: ...` adds to whatever `a` is inherited as
+						if *plus {
+							node.kind = NodeKind::Opaque;
+						}
+						Some(Entry {
+							key: name.to_string(),
+							key_span: name_span.clone(),
+							value: node,
+						})
+					}
+					_ => None,
+				})
+				.collect(),
+		),
+		ExprKind::Array(items) => NodeKind::Seq(items.iter().map(|item| separated(item)).collect()),
+		ExprKind::Null | ExprKind::Bool(_) | ExprKind::Num(_) | ExprKind::Str(_) => NodeKind::Leaf,
+		ExprKind::Unary { op: UnOp::Neg, expr } if matches!(expr.kind, ExprKind::Num(_)) => NodeKind::Leaf,
+		_ => NodeKind::Opaque,
+	};
+	let quote = match expr.kind {
+		ExprKind::Str(_) => src[expr.span.clone()].chars().find(|c| matches!(c, '"' | '\'')),
+		_ => None,
+	};
+	Node {
+		span: expr.span.clone(),
+		kind,
+		meta: Meta { quote, comma: None },
+	}
+}
+
+struct JsonnetDialect {
+	/// One level of indentation, as used by the file.
+	unit: String,
+}
+
+impl JsonnetDialect {
+	fn new(unit: &str) -> Self {
+		Self { unit: unit.to_owned() }
+	}
+
+	/// Laid out the way `jsonnetfmt` does it: unquoted keys where possible, and trailing commas.
+	fn render(&self, value: &JsonValue, indent: &str, quote: char) -> String {
+		let inner = format!("{indent}{}", self.unit);
+		match value {
+			JsonValue::String(s) => quote_str(s, quote),
+			JsonValue::Object(o) if !o.is_empty() => {
+				let entries = o.iter().map(|(k, v)| format!("{inner}{}: {},\n", key(k, quote), self.render(v, &inner, quote)));
+				format!("{{\n{}{indent}}}", entries.collect::<String>())
+			}
+			JsonValue::Array(a) if !a.is_empty() => {
+				let items = a.iter().map(|v| format!("{inner}{},\n", self.render(v, &inner, quote)));
+				format!("[\n{}{indent}]", items.collect::<String>())
+			}
+			_ => value.to_string(),
+		}
+	}
+
+	/// Quote used for string values directly inside `container`.
+	fn string_quote(container: &Node<Meta>) -> char {
+		let values: Vec<&Node<Meta>> = match &container.kind {
+			NodeKind::Map(entries) => entries.iter().map(|e| &e.value).collect(),
+			NodeKind::Seq(items) => items.iter().collect(),
+			NodeKind::Leaf | NodeKind::Opaque => vec![container],
+		};
+		values.iter().find_map(|v| v.meta.quote).unwrap_or('\'')
+	}
+}
+
+/// Field name, quoted unless it is a plain identifier.
+fn key(key: &str, quote: char) -> String {
+	match syntax::is_identifier(key) {
+		true => key.to_owned(),
+		false => quote_str(key, quote),
+	}
+}
+
+impl Dialect for JsonnetDialect {
+	type Meta = Meta;
+
+	fn replace(&self, src: &str, node: &Node<Meta>, value: &JsonValue, _path: &str) -> Result<Edit> {
+		let quote = node.meta.quote.unwrap_or_else(|| Self::string_quote(node));
+		Ok(Edit::replace(node.span.clone(), self.render(value, line_indent(src, node.span.start), quote)))
+	}
+
+	fn insert_entry(&self, src: &str, map: &Node<Meta>, name: &str, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let quote = Self::string_quote(map);
+		let text = format!("{}: {}", key(name, quote), self.render(value, line_indent(src, entries[0].key_span.start), quote));
+		Ok(splice::insert_separated(src, map, map.span.end - 1, entries.len(), &text))
+	}
+
+	fn remove_entry(&self, src: &str, map: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, map, &[idx]))
+	}
+
+	fn remove_entries(&self, src: &str, map: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, map, indices))
+	}
+
+	fn rename_key(&self, src: &str, entry: &Entry<Meta>, new_key: &str) -> Result<Edit> {
+		let quote = src[entry.key_span.clone()].chars().next().filter(|c| matches!(c, '"' | '\''));
+		let text = match quote {
+			Some(quote) => quote_str(new_key, quote),
+			None => key(new_key, '\''),
+		};
+		Ok(Edit::replace(entry.key_span.clone(), text))
+	}
+
+	fn insert_item(&self, src: &str, seq: &Node<Meta>, idx: usize, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let text = self.render(value, line_indent(src, items[0].span.start), Self::string_quote(seq));
+		Ok(splice::insert_separated(src, seq, seq.span.end - 1, idx, &text))
+	}
+
+	fn append_items(&self, src: &str, seq: &Node<Meta>, values: &[JsonValue], _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let indent = line_indent(src, items[0].span.start);
+		let quote = Self::string_quote(seq);
+		let texts: Vec<_> = values.iter().map(|value| self.render(value, indent, quote)).collect();
+		Ok(splice::append_separated(src, seq, seq.span.end - 1, &texts))
+	}
+
+	fn remove_item(&self, src: &str, seq: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, seq, &[idx]))
+	}
+
+	fn remove_items(&self, src: &str, seq: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, seq, indices))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const SOURCE: &str = r#"// service config
+local port = 8080;
+{
+  name: 'svc', // the name
+  port: port,
+  replicas: 2,
+  url: 'http://%s:%d' % [self.name, self.port],
+  labels: {
+    tier: 'backend',
+  },
+  tags+: [],
+  helper:: 'hidden',
+}
+"#;
+
+	fn edited(f: impl FnOnce(&mut JsonValue)) -> Result<String> {
+		let path = Path::new("service.jsonnet");
+		let mut value = Backend.parse(path, SOURCE, &Options::default()).unwrap();
+		f(&mut value);
+		Backend.serialize(path, Some(SOURCE), &value, &Options::default())
+	}
+
+	#[test]
+	fn evaluates_in_process() {
+		let value = Backend.parse(Path::new("service.jsonnet"), SOURCE, &Options::default()).unwrap();
+		assert_eq!(
+			value,
+			json!({"name": "svc", "port": 8080, "replicas": 2, "url": "http://svc:8080", "labels": {"tier": "backend"}, "tags": []})
+		);
+	}
+
+	#[test]
+	fn literal_edits_keep_the_source() {
+		assert_eq!(edited(|_| {}).unwrap(), SOURCE);
+		let out = edited(|v| {
+			v["replicas"] = json!(3);
+			v["labels"]["zone"] = json!("eu-1");
+			v["debug"] = json!(true);
+		})
+		.unwrap();
+		insta::assert_snapshot!(out, @r"
+		// service config
+		local port = 8080;
+		{
+		  name: 'svc', // the name
+		  port: port,
+		  replicas: 3,
+		  url: 'http://%s:%d' % [self.name, self.port],
+		  labels: {
+		    tier: 'backend',
+		    zone: 'eu-1',
+		  },
+		  tags+: [],
+		  helper:: 'hidden',
+		  debug: true,
+		}
+		");
+	}
+
+	#[test]
+	fn computed_values_are_refused() {
+		let e = edited(|v| v["port"] = json!(9090)).unwrap_err();
+		assert_eq!(
+			e.to_string(),
+			"`/port` is computed by an expression in the source rather than written out, so it can't be edited here"
+		);
+		let e = edited(|v| v["url"] = json!("http://other")).unwrap_err();
+		assert!(e.to_string().starts_with("`/url` is computed"), "{e}");
+		// `name` is a literal, but `url` would change along with it
+		let e = edited(|v| v["name"] = json!("api")).unwrap_err();
+		assert_eq!(e.to_string(), "Changing this would also change `/url`, which is computed from it, so the write was refused");
+	}
+
+	#[test]
+	fn imports_resolve_next_to_the_file() {
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::write(dir.path().join("base.libsonnet"), "{ port: 80, host: 'localhost' }").unwrap();
+		std::fs::write(dir.path().join("banner.txt"), "hello\n").unwrap();
+		let path = dir.path().join("main.jsonnet");
+		let src = "(import 'base.libsonnet') + { port: 8080, banner: importstr 'banner.txt' }";
+		let value = Backend.parse(&path, src, &Options::default()).unwrap();
+		assert_eq!(value, json!({"port": 8080, "host": "localhost", "banner": "hello\n"}));
+	}
+}
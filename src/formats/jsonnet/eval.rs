@@ -0,0 +1,1635 @@
+//! Jsonnet evaluator: lazy, with late-bound `self`, object inheritance, imports, and the parts of the standard library configuration files tend to use.
+use std::{
+	cell::{Cell, RefCell},
+	cmp::Ordering,
+	collections::{BTreeMap, HashMap},
+	ops::Range,
+	path::{Path, PathBuf},
+	rc::Rc,
+};
+
+use color_eyre::eyre::{Report, ensure};
+use serde_json::{Map, Value as JsonValue};
+use v_utils::prelude::*;
+
+use super::syntax::{self, Assert, BinOp, Bind, CompSpec, Expr, ExprKind, FieldName, Member, Param, UnOp, Visibility};
+
+/// Calls and lazy values nested deeper than this are taken for infinite recursion.
+const MAX_DEPTH: usize = 500;
+/// Enough for [`MAX_DEPTH`] levels of evaluation, which recurses on the native stack.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Evaluate `src`, the contents of the file at `path`, to JSON. Imports are resolved relative to `path`.
+///
+/// Runs on a thread of its own, as evaluation recurses deeper than async runtime threads allow for.
+pub fn evaluate(path: &Path, src: &str) -> Result<JsonValue> {
+	let (path, src) = (path.to_owned(), src.to_owned());
+	std::thread::Builder::new()
+		.name("jsonnet".to_owned())
+		.stack_size(STACK_SIZE)
+		.spawn(move || Evaluator::default().run(&path, &src))
+		.context("Failed to start Jsonnet evaluation")?
+		.join()
+		.map_err(|_| eyre!("Jsonnet evaluation crashed"))?
+}
+
+struct File {
+	path: PathBuf,
+	src: Rc<str>,
+}
+
+#[derive(Clone)]
+enum Val {
+	Null,
+	Bool(bool),
+	Num(f64),
+	Str(Rc<str>),
+	Arr(Rc<[Thunk]>),
+	Obj(Obj),
+	Func(Rc<Func>),
+}
+
+enum Func {
+	Closure { params: Rc<[Param]>, body: Rc<Expr>, env: Rc<Env> },
+	Builtin(&'static str),
+}
+
+/// A lazily evaluated value.
+#[derive(Clone)]
+struct Thunk(Rc<RefCell<State>>);
+enum State {
+	Pending(Rc<Expr>, Rc<Env>),
+	Forcing,
+	Done(Val),
+}
+impl Thunk {
+	fn done(value: Val) -> Self {
+		Self(Rc::new(RefCell::new(State::Done(value))))
+	}
+}
+
+struct Env {
+	vars: RefCell<HashMap<Rc<str>, Thunk>>,
+	parent: Option<Rc<Env>>,
+	/// `self`, and the layer of it whose fields are being evaluated, which is what `super` is relative to.
+	this: Option<(Obj, usize)>,
+	/// `$`, the outermost object.
+	dollar: Option<Obj>,
+	file: Rc<File>,
+}
+impl Env {
+	fn lookup(&self, name: &str) -> Option<Thunk> {
+		match self.vars.borrow().get(name) {
+			Some(thunk) => Some(thunk.clone()),
+			None => self.parent.as_ref()?.lookup(name),
+		}
+	}
+}
+
+type Obj = Rc<ObjData>;
+/// An object is a stack of layers, one per object literal it was built from with `+`, the topmost one last.
+struct ObjData {
+	layers: Vec<Rc<Layer>>,
+	cache: RefCell<HashMap<Rc<str>, Val>>,
+	asserted: Cell<bool>,
+}
+
+struct Layer {
+	fields: Vec<LayerField>,
+	locals: Vec<Bind>,
+	asserts: Vec<Assert>,
+	env: Rc<Env>,
+}
+
+struct LayerField {
+	name: Rc<str>,
+	visibility: Visibility,
+	plus: bool,
+	body: Body,
+	/// Differs between fields of a comprehension.
+	env: Rc<Env>,
+}
+
+enum Body {
+	Expr(Rc<Expr>),
+	Val(Val),
+}
+
+/// An error that already says where in which file it happened.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct Located(String);
+
+#[derive(Default)]
+struct Evaluator {
+	/// Everything that can close a reference cycle, so that all cycles can be broken once evaluation is over.
+	envs: RefCell<Vec<Rc<Env>>>,
+	thunks: RefCell<Vec<Thunk>>,
+	objs: RefCell<Vec<Obj>>,
+	imports: RefCell<HashMap<PathBuf, Thunk>>,
+	depth: Cell<usize>,
+}
+impl Drop for Evaluator {
+	fn drop(&mut self) {
+		self.envs.borrow().iter().for_each(|env| env.vars.borrow_mut().clear());
+		self.thunks.borrow().iter().for_each(|thunk| *thunk.0.borrow_mut() = State::Forcing);
+		self.objs.borrow().iter().for_each(|obj| obj.cache.borrow_mut().clear());
+	}
+}
+
+/// Decrements the depth when a call returns.
+struct Depth<'e>(&'e Cell<usize>);
+impl Drop for Depth<'_> {
+	fn drop(&mut self) {
+		self.0.set(self.0.get() - 1);
+	}
+}
+
+impl Evaluator {
+	fn run(&self, path: &Path, src: &str) -> Result<JsonValue> {
+		let expr = syntax::parse(src).map_err(|e| eyre!("{}:{e}", path.display()))?;
+		let env = self.root_env(Rc::new(File {
+			path: path.to_owned(),
+			src: src.into(),
+		}));
+		let value = self.eval(&expr, &env)?;
+		self.manifest(&value)
+	}
+
+	fn enter(&self) -> Result<Depth<'_>> {
+		ensure!(self.depth.get() < MAX_DEPTH, "max stack depth exceeded; is something defined in terms of itself?");
+		self.depth.set(self.depth.get() + 1);
+		Ok(Depth(&self.depth))
+	}
+
+	fn env(&self, parent: Option<Rc<Env>>, this: Option<(Obj, usize)>, dollar: Option<Obj>, file: Rc<File>) -> Rc<Env> {
+		let env = Rc::new(Env {
+			vars: RefCell::default(),
+			parent,
+			this,
+			dollar,
+			file,
+		});
+		self.envs.borrow_mut().push(env.clone());
+		env
+	}
+
+	fn child(&self, parent: &Rc<Env>) -> Rc<Env> {
+		self.env(Some(parent.clone()), parent.this.clone(), parent.dollar.clone(), parent.file.clone())
+	}
+
+	fn root_env(&self, file: Rc<File>) -> Rc<Env> {
+		let env = self.env(None, None, None, file);
+		let std = self.std(&env);
+		env.vars.borrow_mut().insert("std".into(), Thunk::done(std));
+		env
+	}
+
+	fn thunk(&self, expr: &Rc<Expr>, env: &Rc<Env>) -> Thunk {
+		let thunk = Thunk(Rc::new(RefCell::new(State::Pending(expr.clone(), env.clone()))));
+		self.thunks.borrow_mut().push(thunk.clone());
+		thunk
+	}
+
+	fn obj(&self, layers: Vec<Rc<Layer>>) -> Obj {
+		let obj = Rc::new(ObjData {
+			layers,
+			cache: RefCell::default(),
+			asserted: Cell::new(false),
+		});
+		self.objs.borrow_mut().push(obj.clone());
+		obj
+	}
+
+	fn force(&self, thunk: &Thunk) -> Result<Val> {
+		let state = std::mem::replace(&mut *thunk.0.borrow_mut(), State::Forcing);
+		match state {
+			State::Done(value) => {
+				*thunk.0.borrow_mut() = State::Done(value.clone());
+				Ok(value)
+			}
+			State::Forcing => bail!("infinite recursion: a value is defined in terms of itself"),
+			State::Pending(expr, env) => {
+				let evaluated = self.enter().and_then(|_depth| self.eval(&expr, &env));
+				*thunk.0.borrow_mut() = match &evaluated {
+					Ok(value) => State::Done(value.clone()),
+					Err(_) => State::Pending(expr, env),
+				};
+				evaluated
+			}
+		}
+	}
+
+	/// Errors that don't say where they happened yet are taken to have happened at `span`.
+	fn locate(&self, env: &Env, span: &Range<usize>, e: Report) -> Report {
+		match e.downcast_ref::<Located>() {
+			Some(_) => e,
+			None => Located(format!("{}:{}: {e}", env.file.path.display(), syntax::location(&env.file.src, span.start))).into(),
+		}
+	}
+
+	fn eval(&self, expr: &Rc<Expr>, env: &Rc<Env>) -> Result<Val> {
+		self.eval_kind(expr, env).map_err(|e| self.locate(env, &expr.span, e))
+	}
+
+	fn eval_kind(&self, expr: &Rc<Expr>, env: &Rc<Env>) -> Result<Val> {
+		Ok(match &expr.kind {
+			ExprKind::Null => Val::Null,
+			ExprKind::Bool(b) => Val::Bool(*b),
+			ExprKind::Num(n) => Val::Num(*n),
+			ExprKind::Str(s) => Val::Str(s.as_str().into()),
+			ExprKind::SelfRef => Val::Obj(env.this.as_ref().ok_or_eyre("`self` outside of an object")?.0.clone()),
+			ExprKind::Dollar => Val::Obj(env.dollar.clone().ok_or_eyre("`$` outside of an object")?),
+			ExprKind::Var(name) => self.force(&env.lookup(name).ok_or_else(|| eyre!("unknown variable `{name}`"))?)?,
+			ExprKind::Array(items) => Val::Arr(items.iter().map(|item| self.thunk(item, env)).collect()),
+			ExprKind::ArrayComp { body, specs } => {
+				let mut items = Vec::new();
+				self.comprehension(specs, env.clone(), &mut |env| {
+					items.push(self.thunk(body, &env));
+					Ok(())
+				})?;
+				Val::Arr(items.into())
+			}
+			ExprKind::Object(members) => self.object(members, env)?,
+			ExprKind::ObjectComp { locals, key, value, specs } => {
+				let mut fields: Vec<LayerField> = Vec::new();
+				self.comprehension(specs, env.clone(), &mut |env| {
+					let name = match self.eval(key, &env)? {
+						Val::Str(name) => name,
+						Val::Null => return Ok(()),
+						other => bail!("field names must be strings, not {}", type_of(&other)),
+					};
+					ensure!(!fields.iter().any(|f| f.name == name), "duplicate field `{name}`");
+					fields.push(LayerField {
+						name,
+						visibility: Visibility::Default,
+						plus: false,
+						body: Body::Expr(value.clone()),
+						env,
+					});
+					Ok(())
+				})?;
+				Val::Obj(self.obj(vec![Rc::new(Layer {
+					fields,
+					locals: locals.clone(),
+					asserts: Vec::new(),
+					env: env.clone(),
+				})]))
+			}
+			ExprKind::Index { target, index } => {
+				let target = self.eval(target, env)?;
+				let index = self.eval(index, env)?;
+				self.index(&target, &index)?
+			}
+			ExprKind::Slice { target, start, end, step } => {
+				let target = self.eval(target, env)?;
+				let bound = |e: &Option<Rc<Expr>>| -> Result<Option<usize>> {
+					match e {
+						None => Ok(None),
+						Some(e) => self.eval(e, env).and_then(|v| index_number(&v)).map(Some),
+					}
+				};
+				let (start, end, step) = (bound(start)?, bound(end)?, bound(step)?);
+				slice(&target, start, end, step)?
+			}
+			ExprKind::SuperIndex(index) => {
+				let (obj, layer) = env.this.clone().ok_or_eyre("`super` outside of an object")?;
+				let name = self.eval(index, env)?;
+				let Val::Str(name) = name else {
+					bail!("fields are named by strings, not {}", type_of(&name))
+				};
+				self.field(&obj, &name, layer)?.ok_or_else(|| eyre!("`super` has no field `{name}`"))?
+			}
+			ExprKind::InSuper(name) => {
+				let (obj, layer) = env.this.clone().ok_or_eyre("`super` outside of an object")?;
+				let name = self.eval(name, env)?;
+				let Val::Str(name) = name else {
+					bail!("fields are named by strings, not {}", type_of(&name))
+				};
+				Val::Bool(obj.layers[..layer].iter().any(|l| l.fields.iter().any(|f| f.name == name)))
+			}
+			ExprKind::Apply { target, args } => {
+				let function = self.eval(target, env)?;
+				let args = args.iter().map(|arg| (arg.name.clone(), self.thunk(&arg.value, env))).collect();
+				self.call(&function, args, env)?
+			}
+			ExprKind::Binary { op, lhs, rhs } => self.binary(*op, lhs, rhs, env)?,
+			ExprKind::Unary { op, expr } => match (op, self.eval(expr, env)?) {
+				(UnOp::Neg, Val::Num(n)) => Val::Num(-n),
+				(UnOp::Plus, Val::Num(n)) => Val::Num(n),
+				(UnOp::BitNot, Val::Num(n)) => Val::Num(!(n as i64) as f64),
+				(UnOp::Not, Val::Bool(b)) => Val::Bool(!b),
+				(op, value) => bail!("unary `{op}` doesn't apply to {}", type_of(&value)),
+			},
+			ExprKind::If { cond, then, otherwise } => match (self.truthy(cond, env)?, otherwise) {
+				(true, _) => self.eval(then, env)?,
+				(false, Some(otherwise)) => self.eval(otherwise, env)?,
+				(false, None) => Val::Null,
+			},
+			ExprKind::Function { params, body } => Val::Func(Rc::new(Func::Closure {
+				params: params.clone(),
+				body: body.clone(),
+				env: env.clone(),
+			})),
+			ExprKind::Local { binds, body } => {
+				let env = self.child(env);
+				for bind in binds {
+					let thunk = self.thunk(&bind.value, &env);
+					env.vars.borrow_mut().insert(bind.name.clone(), thunk);
+				}
+				self.eval(body, &env)?
+			}
+			ExprKind::Error(message) => {
+				let message = self.eval(message, env)?;
+				bail!("error: {}", self.to_string(&message)?)
+			}
+			ExprKind::Assert { assert, body } => {
+				self.assert(assert, env)?;
+				self.eval(body, env)?
+			}
+			ExprKind::Import { path, raw } => self.import(env, path, *raw)?,
+		})
+	}
+
+	fn truthy(&self, cond: &Rc<Expr>, env: &Rc<Env>) -> Result<bool> {
+		match self.eval(cond, env)? {
+			Val::Bool(b) => Ok(b),
+			other => bail!("conditions must be booleans, not {}", type_of(&other)),
+		}
+	}
+
+	fn assert(&self, assert: &Assert, env: &Rc<Env>) -> Result<()> {
+		if self.truthy(&assert.cond, env)? {
+			return Ok(());
+		}
+		match &assert.message {
+			Some(message) => {
+				let message = self.eval(message, env)?;
+				bail!("assertion failed: {}", self.to_string(&message)?)
+			}
+			None => bail!("assertion failed"),
+		}
+	}
+
+	/// Runs `emit` in the environment of every combination the `for`s and `if`s of `specs` let through.
+	fn comprehension(&self, specs: &[CompSpec], env: Rc<Env>, emit: &mut dyn FnMut(Rc<Env>) -> Result<()>) -> Result<()> {
+		let Some((first, rest)) = specs.split_first() else {
+			return emit(env);
+		};
+		match first {
+			CompSpec::For(name, items) => {
+				let items = match self.eval(items, &env)? {
+					Val::Arr(items) => items,
+					other => bail!("`for` goes over arrays, not {}", type_of(&other)),
+				};
+				for item in items.iter() {
+					let inner = self.child(&env);
+					inner.vars.borrow_mut().insert(name.clone(), item.clone());
+					self.comprehension(rest, inner, emit)?;
+				}
+				Ok(())
+			}
+			CompSpec::If(cond) => match self.truthy(cond, &env)? {
+				true => self.comprehension(rest, env, emit),
+				false => Ok(()),
+			},
+		}
+	}
+
+	fn object(&self, members: &[Member], env: &Rc<Env>) -> Result<Val> {
+		let (mut fields, mut locals, mut asserts) = (Vec::<LayerField>::new(), Vec::new(), Vec::new());
+		for member in members {
+			match member {
+				Member::Local(bind) => locals.push(bind.clone()),
+				Member::Assert(assert) => asserts.push(assert.clone()),
+				Member::Field(field) => {
+					let name = match &field.name {
+						FieldName::Fixed(name) => name.clone(),
+						FieldName::Computed(key) => match self.eval(key, env)? {
+							Val::Str(name) => name,
+							Val::Null => continue,
+							other => bail!("field names must be strings, not {}", type_of(&other)),
+						},
+					};
+					ensure!(!fields.iter().any(|f| f.name == name), "duplicate field `{name}`");
+					fields.push(LayerField {
+						name,
+						visibility: field.visibility,
+						plus: field.plus,
+						body: Body::Expr(field.value.clone()),
+						env: env.clone(),
+					});
+				}
+			}
+		}
+		Ok(Val::Obj(self.obj(vec![Rc::new(Layer {
+			fields,
+			locals,
+			asserts,
+			env: env.clone(),
+		})])))
+	}
+
+	/// Where fields of `layer` of `obj` are evaluated: `env` with `self`, `super`, `$` and the object's locals.
+	fn object_env(&self, obj: &Obj, layer: usize, env: &Rc<Env>, locals: &[Bind]) -> Rc<Env> {
+		let dollar = env.dollar.clone().or_else(|| Some(obj.clone()));
+		let inner = self.env(Some(env.clone()), Some((obj.clone(), layer)), dollar, env.file.clone());
+		for bind in locals {
+			let thunk = self.thunk(&bind.value, &inner);
+			inner.vars.borrow_mut().insert(bind.name.clone(), thunk);
+		}
+		inner
+	}
+
+	/// Field `name` of `obj` as seen from the layers below `below`.
+	fn field(&self, obj: &Obj, name: &str, below: usize) -> Result<Option<Val>> {
+		let top = below == obj.layers.len();
+		if top {
+			if let Some(value) = obj.cache.borrow().get(name) {
+				return Ok(Some(value.clone()));
+			}
+		}
+		let found = obj.layers[..below]
+			.iter()
+			.enumerate()
+			.rev()
+			.find_map(|(i, layer)| layer.fields.iter().find(|f| &*f.name == name).map(|f| (i, layer, f)));
+		let Some((i, layer, field)) = found else { return Ok(None) };
+		let _depth = self.enter()?;
+		let value = match &field.body {
+			Body::Val(value) => value.clone(),
+			Body::Expr(expr) => self.eval(expr, &self.object_env(obj, i, &field.env, &layer.locals))?,
+		};
+		let value = match (field.plus, field.plus.then(|| self.field(obj, name, i)).transpose()?.flatten()) {
+			(true, Some(inherited)) => self.add(inherited, value)?,
+			_ => value,
+		};
+		if top {
+			obj.cache.borrow_mut().insert(name.into(), value.clone());
+		}
+		Ok(Some(value))
+	}
+
+	/// Field names in order, with whether each one is visible.
+	fn fields(&self, obj: &Obj) -> BTreeMap<Rc<str>, bool> {
+		let mut fields = BTreeMap::new();
+		for field in obj.layers.iter().flat_map(|layer| &layer.fields) {
+			let visible = match field.visibility {
+				Visibility::Hidden => false,
+				Visibility::Forced => true,
+				Visibility::Default => fields.get(&field.name).copied().unwrap_or(true),
+			};
+			fields.insert(field.name.clone(), visible);
+		}
+		fields
+	}
+
+	fn visible_fields(&self, obj: &Obj) -> Vec<Rc<str>> {
+		self.fields(obj).into_iter().filter(|(_, visible)| *visible).map(|(name, _)| name).collect()
+	}
+
+	fn check_asserts(&self, obj: &Obj) -> Result<()> {
+		if obj.asserted.replace(true) {
+			return Ok(());
+		}
+		for (i, layer) in obj.layers.iter().enumerate().filter(|(_, layer)| !layer.asserts.is_empty()) {
+			let env = self.object_env(obj, i, &layer.env, &layer.locals);
+			for assert in &layer.asserts {
+				self.assert(assert, &env).map_err(|e| self.locate(&env, &assert.cond.span, e))?;
+			}
+		}
+		Ok(())
+	}
+
+	fn call(&self, function: &Val, args: Vec<(Option<Rc<str>>, Thunk)>, env: &Rc<Env>) -> Result<Val> {
+		let Val::Func(function) = function else {
+			bail!("only functions can be called, not {}", type_of(function));
+		};
+		let _depth = self.enter()?;
+		match &**function {
+			Func::Closure { params, body, env } => {
+				let inner = self.child(env);
+				let names: Vec<&str> = params.iter().map(|p| &*p.name).collect();
+				let bound = bind_args(&names, args)?;
+				for (param, arg) in params.iter().zip(bound) {
+					let thunk = match (arg, &param.default) {
+						(Some(arg), _) => arg,
+						(None, Some(default)) => self.thunk(default, &inner),
+						(None, None) => bail!("missing argument `{}`", param.name),
+					};
+					inner.vars.borrow_mut().insert(param.name.clone(), thunk);
+				}
+				self.eval(body, &inner)
+			}
+			Func::Builtin(name) => {
+				let params = builtin_params(name);
+				let names: Vec<&str> = params.iter().map(|p| p.trim_end_matches('?')).collect();
+				let bound = bind_args(&names, args)?;
+				for (param, arg) in params.iter().zip(&bound) {
+					ensure!(arg.is_some() || param.ends_with('?'), "std.{name}: missing argument `{param}`");
+				}
+				self.builtin(name, &bound, env)
+			}
+		}
+	}
+
+	fn import(&self, env: &Rc<Env>, path: &str, raw: bool) -> Result<Val> {
+		let full = env.file.path.parent().unwrap_or(Path::new("")).join(path);
+		let read = || std::fs::read_to_string(&full).map_err(|e| eyre!("couldn't import `{}`: {e}", full.display()));
+		if raw {
+			return Ok(Val::Str(read()?.into()));
+		}
+		let cached = self.imports.borrow().get(&full).cloned();
+		let thunk = match cached {
+			Some(thunk) => thunk,
+			None => {
+				let src = read()?;
+				let expr = syntax::parse(&src).map_err(|e| eyre!("{}:{e}", full.display()))?;
+				let env = self.root_env(Rc::new(File {
+					path: full.clone(),
+					src: src.into(),
+				}));
+				let thunk = self.thunk(&expr, &env);
+				self.imports.borrow_mut().insert(full, thunk.clone());
+				thunk
+			}
+		};
+		self.force(&thunk)
+	}
+
+	fn binary(&self, op: BinOp, lhs: &Rc<Expr>, rhs: &Rc<Expr>, env: &Rc<Env>) -> Result<Val> {
+		let lhs = self.eval(lhs, env)?;
+		// `&&` and `||` only look at the right side when they need to
+		match (op, &lhs) {
+			(BinOp::And, Val::Bool(false)) => return Ok(Val::Bool(false)),
+			(BinOp::Or, Val::Bool(true)) => return Ok(Val::Bool(true)),
+			(BinOp::And | BinOp::Or, Val::Bool(_)) => {}
+			(BinOp::And | BinOp::Or, other) => bail!("`{op}` takes booleans, not {}", type_of(other)),
+			_ => {}
+		}
+		let rhs = self.eval(rhs, env)?;
+		Ok(match (op, lhs, rhs) {
+			(BinOp::Add, lhs, rhs) => self.add(lhs, rhs)?,
+			(BinOp::Mod, Val::Str(format), values) => Val::Str(self.format(&format, &values)?.into()),
+			(BinOp::In, Val::Str(name), Val::Obj(obj)) => Val::Bool(self.fields(&obj).contains_key(&name)),
+			(BinOp::Eq, lhs, rhs) => Val::Bool(self.equals(&lhs, &rhs)?),
+			(BinOp::Ne, lhs, rhs) => Val::Bool(!self.equals(&lhs, &rhs)?),
+			(BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge, lhs, rhs) => {
+				let ordering = self.compare(&lhs, &rhs)?;
+				Val::Bool(match op {
+					BinOp::Lt => ordering.is_lt(),
+					BinOp::Le => ordering.is_le(),
+					BinOp::Gt => ordering.is_gt(),
+					_ => ordering.is_ge(),
+				})
+			}
+			(BinOp::And | BinOp::Or, _, Val::Bool(b)) => Val::Bool(b),
+			(_, Val::Num(a), Val::Num(b)) => Val::Num(match op {
+				BinOp::Sub => a - b,
+				BinOp::Mul => a * b,
+				BinOp::Div => {
+					ensure!(b != 0.0, "division by zero");
+					a / b
+				}
+				BinOp::Mod => {
+					ensure!(b != 0.0, "division by zero");
+					a % b
+				}
+				BinOp::Shl => ((a as i64) << (b as i64 & 63)) as f64,
+				BinOp::Shr => ((a as i64) >> (b as i64 & 63)) as f64,
+				BinOp::BitAnd => ((a as i64) & (b as i64)) as f64,
+				BinOp::BitXor => ((a as i64) ^ (b as i64)) as f64,
+				BinOp::BitOr => ((a as i64) | (b as i64)) as f64,
+				_ => bail!("`{op}` doesn't apply to numbers"),
+			}),
+			(op, lhs, rhs) => bail!("`{op}` doesn't apply to {} and {}", type_of(&lhs), type_of(&rhs)),
+		})
+	}
+
+	fn add(&self, lhs: Val, rhs: Val) -> Result<Val> {
+		Ok(match (lhs, rhs) {
+			(Val::Num(a), Val::Num(b)) => Val::Num(a + b),
+			(Val::Arr(a), Val::Arr(b)) => Val::Arr(a.iter().chain(b.iter()).cloned().collect()),
+			(Val::Obj(a), Val::Obj(b)) => Val::Obj(self.obj(a.layers.iter().chain(&b.layers).cloned().collect())),
+			(lhs @ Val::Str(_), rhs) | (lhs, rhs @ Val::Str(_)) => Val::Str(format!("{}{}", self.to_string(&lhs)?, self.to_string(&rhs)?).into()),
+			(lhs, rhs) => bail!("can't add {} and {}", type_of(&lhs), type_of(&rhs)),
+		})
+	}
+
+	fn index(&self, target: &Val, index: &Val) -> Result<Val> {
+		match (target, index) {
+			(Val::Obj(obj), Val::Str(name)) => self.field(obj, name, obj.layers.len())?.ok_or_else(|| eyre!("field `{name}` doesn't exist")),
+			(Val::Arr(items), Val::Num(_)) => {
+				let i = index_number(index)?;
+				let item = items.get(i).ok_or_else(|| eyre!("index {i} is out of bounds for an array of {}", items.len()))?;
+				self.force(item)
+			}
+			(Val::Str(s), Val::Num(_)) => {
+				let i = index_number(index)?;
+				let c = s.chars().nth(i).ok_or_else(|| eyre!("index {i} is out of bounds for a string of {}", s.chars().count()))?;
+				Ok(Val::Str(c.to_string().into()))
+			}
+			_ => bail!("can't index {} with {}", type_of(target), type_of(index)),
+		}
+	}
+
+	fn equals(&self, a: &Val, b: &Val) -> Result<bool> {
+		Ok(match (a, b) {
+			(Val::Null, Val::Null) => true,
+			(Val::Bool(a), Val::Bool(b)) => a == b,
+			(Val::Num(a), Val::Num(b)) => a == b,
+			(Val::Str(a), Val::Str(b)) => a == b,
+			(Val::Arr(a), Val::Arr(b)) => {
+				if a.len() != b.len() {
+					return Ok(false);
+				}
+				for (a, b) in a.iter().zip(b.iter()) {
+					if !self.equals(&self.force(a)?, &self.force(b)?)? {
+						return Ok(false);
+					}
+				}
+				true
+			}
+			(Val::Obj(a), Val::Obj(b)) => {
+				let fields = self.visible_fields(a);
+				if fields != self.visible_fields(b) {
+					return Ok(false);
+				}
+				for name in fields {
+					let (x, y) = (self.index(&Val::Obj(a.clone()), &Val::Str(name.clone()))?, self.index(&Val::Obj(b.clone()), &Val::Str(name))?);
+					if !self.equals(&x, &y)? {
+						return Ok(false);
+					}
+				}
+				true
+			}
+			(Val::Func(_), _) | (_, Val::Func(_)) => bail!("functions can't be compared"),
+			_ => false,
+		})
+	}
+
+	fn compare(&self, a: &Val, b: &Val) -> Result<Ordering> {
+		Ok(match (a, b) {
+			(Val::Num(a), Val::Num(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+			(Val::Str(a), Val::Str(b)) => a.cmp(b),
+			(Val::Arr(a), Val::Arr(b)) => {
+				for (x, y) in a.iter().zip(b.iter()) {
+					match self.compare(&self.force(x)?, &self.force(y)?)? {
+						Ordering::Equal => {}
+						ordering => return Ok(ordering),
+					}
+				}
+				a.len().cmp(&b.len())
+			}
+			_ => bail!("can't compare {} with {}", type_of(a), type_of(b)),
+		})
+	}
+
+	fn manifest(&self, value: &Val) -> Result<JsonValue> {
+		Ok(match value {
+			Val::Null => JsonValue::Null,
+			Val::Bool(b) => JsonValue::Bool(*b),
+			Val::Num(n) => number(*n)?,
+			Val::Str(s) => JsonValue::String(s.to_string()),
+			Val::Arr(items) => JsonValue::Array(items.iter().map(|item| self.force(item).and_then(|v| self.manifest(&v))).collect::<Result<_>>()?),
+			Val::Obj(obj) => {
+				self.check_asserts(obj)?;
+				let mut out = Map::new();
+				for name in self.visible_fields(obj) {
+					let value = self.field(obj, &name, obj.layers.len())?.expect("listed fields exist");
+					out.insert(name.to_string(), self.manifest(&value)?);
+				}
+				JsonValue::Object(out)
+			}
+			Val::Func(_) => bail!("functions can't be turned into JSON"),
+		})
+	}
+
+	fn of_json(&self, value: &JsonValue, env: &Rc<Env>) -> Val {
+		match value {
+			JsonValue::Null => Val::Null,
+			JsonValue::Bool(b) => Val::Bool(*b),
+			JsonValue::Number(n) => Val::Num(n.as_f64().unwrap_or(f64::NAN)),
+			JsonValue::String(s) => Val::Str(s.as_str().into()),
+			JsonValue::Array(items) => Val::Arr(items.iter().map(|item| Thunk::done(self.of_json(item, env))).collect()),
+			JsonValue::Object(obj) => self.record(obj.iter().map(|(name, value)| (name.as_str().into(), self.of_json(value, env))), env),
+		}
+	}
+
+	/// An object of plain visible fields.
+	fn record(&self, fields: impl IntoIterator<Item = (Rc<str>, Val)>, env: &Rc<Env>) -> Val {
+		let fields = fields
+			.into_iter()
+			.map(|(name, value)| LayerField {
+				name,
+				visibility: Visibility::Default,
+				plus: false,
+				body: Body::Val(value),
+				env: env.clone(),
+			})
+			.collect();
+		Val::Obj(self.obj(vec![Rc::new(Layer {
+			fields,
+			locals: Vec::new(),
+			asserts: Vec::new(),
+			env: env.clone(),
+		})]))
+	}
+
+	/// Strings as they are, everything else as JSON.
+	fn to_string(&self, value: &Val) -> Result<String> {
+		match value {
+			Val::Str(s) => Ok(s.to_string()),
+			other => Ok(compact(&self.manifest(other)?)),
+		}
+	}
+
+	fn std(&self, env: &Rc<Env>) -> Val {
+		let fields = BUILTINS
+			.iter()
+			.map(|(name, _)| LayerField {
+				name: (*name).into(),
+				visibility: Visibility::Hidden,
+				plus: false,
+				body: Body::Val(Val::Func(Rc::new(Func::Builtin(name)))),
+				env: env.clone(),
+			})
+			.chain([LayerField {
+				name: "thisFile".into(),
+				visibility: Visibility::Hidden,
+				plus: false,
+				body: Body::Val(Val::Str(env.file.path.display().to_string().into())),
+				env: env.clone(),
+			}])
+			.collect();
+		Val::Obj(self.obj(vec![Rc::new(Layer {
+			fields,
+			locals: Vec::new(),
+			asserts: Vec::new(),
+			env: env.clone(),
+		})]))
+	}
+
+	fn builtin(&self, name: &str, args: &[Option<Thunk>], env: &Rc<Env>) -> Result<Val> {
+		let arg = |i: usize| -> Result<Val> { args[i].as_ref().map(|thunk| self.force(thunk)).unwrap_or(Ok(Val::Null)) };
+		let given = |i: usize| args[i].is_some();
+		let str_arg = |i: usize| -> Result<Rc<str>> {
+			match arg(i)? {
+				Val::Str(s) => Ok(s),
+				other => bail!("std.{name}: expected a string, got {}", type_of(&other)),
+			}
+		};
+		let num_arg = |i: usize| -> Result<f64> {
+			match arg(i)? {
+				Val::Num(n) => Ok(n),
+				other => bail!("std.{name}: expected a number, got {}", type_of(&other)),
+			}
+		};
+		let arr_arg = |i: usize| -> Result<Rc<[Thunk]>> {
+			match arg(i)? {
+				Val::Arr(items) => Ok(items),
+				other => bail!("std.{name}: expected an array, got {}", type_of(&other)),
+			}
+		};
+		let obj_arg = |i: usize| -> Result<Obj> {
+			match arg(i)? {
+				Val::Obj(obj) => Ok(obj),
+				other => bail!("std.{name}: expected an object, got {}", type_of(&other)),
+			}
+		};
+		let apply = |function: &Val, values: Vec<Val>| self.call(function, values.into_iter().map(|v| (None, Thunk::done(v))).collect(), env);
+		let forced = |items: &[Thunk]| items.iter().map(|item| self.force(item)).collect::<Result<Vec<_>>>();
+		let array = |values: Vec<Val>| Val::Arr(values.into_iter().map(Thunk::done).collect());
+		let string = |s: String| Val::Str(s.into());
+
+		Ok(match name {
+			"length" => Val::Num(match arg(0)? {
+				Val::Str(s) => s.chars().count(),
+				Val::Arr(items) => items.len(),
+				Val::Obj(obj) => self.visible_fields(&obj).len(),
+				Val::Func(f) => match &*f {
+					Func::Closure { params, .. } => params.len(),
+					Func::Builtin(name) => builtin_params(name).len(),
+				},
+				other => bail!("std.length: {} has no length", type_of(&other)),
+			} as f64),
+			"type" => string(type_of(&arg(0)?).to_owned()),
+			"toString" => string(self.to_string(&arg(0)?)?),
+			"parseInt" => {
+				let s = str_arg(0)?;
+				Val::Num(s.parse::<i64>().map_err(|_| eyre!("std.parseInt: `{s}` is not an integer"))? as f64)
+			}
+			"parseJson" => {
+				let json: JsonValue = serde_json::from_str(&str_arg(0)?).context("std.parseJson")?;
+				self.of_json(&json, env)
+			}
+			"codepoint" => {
+				let s = str_arg(0)?;
+				let mut chars = s.chars();
+				match (chars.next(), chars.next()) {
+					(Some(c), None) => Val::Num(c as u32 as f64),
+					_ => bail!("std.codepoint takes a single character"),
+				}
+			}
+			"char" => string(char::from_u32(num_arg(0)? as u32).ok_or_eyre("std.char: not a valid codepoint")?.to_string()),
+			"substr" => {
+				let (s, from, len) = (str_arg(0)?, num_arg(1)? as usize, num_arg(2)? as usize);
+				string(s.chars().skip(from).take(len).collect())
+			}
+			"startsWith" => Val::Bool(str_arg(0)?.starts_with(&*str_arg(1)?)),
+			"endsWith" => Val::Bool(str_arg(0)?.ends_with(&*str_arg(1)?)),
+			"stringChars" => array(str_arg(0)?.chars().map(|c| string(c.to_string())).collect()),
+			"split" => array(str_arg(0)?.split(&*str_arg(1)?).map(|part| string(part.to_owned())).collect()),
+			"splitLimit" => {
+				let (s, sep, limit) = (str_arg(0)?, str_arg(1)?, num_arg(2)?);
+				let parts: Vec<Val> = match limit < 0.0 {
+					true => s.split(&*sep).map(|part| string(part.to_owned())).collect(),
+					false => s.splitn(limit as usize + 1, &*sep).map(|part| string(part.to_owned())).collect(),
+				};
+				array(parts)
+			}
+			"strReplace" => string(str_arg(0)?.replace(&*str_arg(1)?, &str_arg(2)?)),
+			"asciiUpper" => string(str_arg(0)?.to_ascii_uppercase()),
+			"asciiLower" => string(str_arg(0)?.to_ascii_lowercase()),
+			"trim" => string(str_arg(0)?.trim().to_owned()),
+			"isEmpty" => Val::Bool(str_arg(0)?.is_empty()),
+			"join" => {
+				let sep = arg(0)?;
+				let items = forced(&arr_arg(1)?)?.into_iter().filter(|item| !matches!(item, Val::Null));
+				match sep {
+					Val::Str(sep) => {
+						let parts = items
+							.map(|item| match item {
+								Val::Str(s) => Ok(s.to_string()),
+								other => bail!("std.join: expected strings, got {}", type_of(&other)),
+							})
+							.collect::<Result<Vec<_>>>()?;
+						string(parts.join(&sep))
+					}
+					Val::Arr(sep) => {
+						let mut out: Vec<Thunk> = Vec::new();
+						for (i, item) in items.enumerate() {
+							let Val::Arr(item) = item else { bail!("std.join: expected arrays, got {}", type_of(&item)) };
+							if i > 0 {
+								out.extend(sep.iter().cloned());
+							}
+							out.extend(item.iter().cloned());
+						}
+						Val::Arr(out.into())
+					}
+					other => bail!("std.join: the separator must be a string or an array, not {}", type_of(&other)),
+				}
+			}
+			"lines" => {
+				let lines = forced(&arr_arg(0)?)?
+					.into_iter()
+					.filter(|line| !matches!(line, Val::Null))
+					.map(|line| self.to_string(&line).map(|l| l + "\n"))
+					.collect::<Result<String>>()?;
+				string(lines)
+			}
+			"format" => string(self.format(&str_arg(0)?, &arg(1)?)?),
+			"escapeStringJson" => string(serde_json::to_string(&*str_arg(0)?).expect("strings serialize")),
+			"manifestJson" => string(pretty(&self.manifest(&arg(0)?)?, "    ", "\n", ": ", "")),
+			"manifestJsonMinified" => string(serde_json::to_string(&self.manifest(&arg(0)?)?).expect("JSON serializes")),
+			"manifestJsonEx" => {
+				let newline = if given(2) { str_arg(2)?.to_string() } else { "\n".to_owned() };
+				let separator = if given(3) { str_arg(3)?.to_string() } else { ": ".to_owned() };
+				string(pretty(&self.manifest(&arg(0)?)?, &str_arg(1)?, &newline, &separator, ""))
+			}
+			"map" => {
+				let function = arg(0)?;
+				array(forced(&arr_arg(1)?)?.into_iter().map(|item| apply(&function, vec![item])).collect::<Result<_>>()?)
+			}
+			"flatMap" => {
+				let function = arg(0)?;
+				let mut out = Vec::new();
+				for item in forced(&arr_arg(1)?)? {
+					match apply(&function, vec![item])? {
+						Val::Arr(items) => out.extend(items.iter().cloned()),
+						other => bail!("std.flatMap: the function must return arrays, not {}", type_of(&other)),
+					}
+				}
+				Val::Arr(out.into())
+			}
+			"filter" => {
+				let function = arg(0)?;
+				let mut out = Vec::new();
+				for item in forced(&arr_arg(1)?)? {
+					match apply(&function, vec![item.clone()])? {
+						Val::Bool(true) => out.push(item),
+						Val::Bool(false) => {}
+						other => bail!("std.filter: the function must return booleans, not {}", type_of(&other)),
+					}
+				}
+				array(out)
+			}
+			"foldl" | "foldr" => {
+				let function = arg(0)?;
+				let mut items = forced(&arr_arg(1)?)?;
+				if name == "foldr" {
+					items.reverse();
+				}
+				let mut acc = arg(2)?;
+				for item in items {
+					acc = match name {
+						"foldl" => apply(&function, vec![acc, item])?,
+						_ => apply(&function, vec![item, acc])?,
+					};
+				}
+				acc
+			}
+			"range" => {
+				let (from, to) = (num_arg(0)? as i64, num_arg(1)? as i64);
+				array((from..=to).map(|n| Val::Num(n as f64)).collect())
+			}
+			"makeArray" => {
+				let (size, function) = (num_arg(0)? as usize, arg(1)?);
+				array((0..size).map(|i| apply(&function, vec![Val::Num(i as f64)])).collect::<Result<_>>()?)
+			}
+			"member" | "contains" | "count" => {
+				let (haystack, needle) = (arg(0)?, arg(1)?);
+				match haystack {
+					Val::Str(s) => {
+						let Val::Str(needle) = needle else {
+							bail!("std.{name}: can only look for strings in strings")
+						};
+						match name {
+							"count" => Val::Num(s.matches(&*needle).count() as f64),
+							_ => Val::Bool(s.contains(&*needle)),
+						}
+					}
+					Val::Arr(items) => {
+						let mut count = 0;
+						for item in forced(&items)? {
+							count += usize::from(self.equals(&item, &needle)?);
+						}
+						match name {
+							"count" => Val::Num(count as f64),
+							_ => Val::Bool(count > 0),
+						}
+					}
+					other => bail!("std.{name}: expected an array or a string, got {}", type_of(&other)),
+				}
+			}
+			"flattenArrays" => {
+				let mut out = Vec::new();
+				for item in forced(&arr_arg(0)?)? {
+					match item {
+						Val::Arr(items) => out.extend(items.iter().cloned()),
+						other => bail!("std.flattenArrays: expected arrays, got {}", type_of(&other)),
+					}
+				}
+				Val::Arr(out.into())
+			}
+			"reverse" => Val::Arr(arr_arg(0)?.iter().rev().cloned().collect()),
+			"sort" | "uniq" | "set" => {
+				let items = forced(&arr_arg(0)?)?;
+				let key_function = if given(1) { Some(arg(1)?) } else { None };
+				let key = |item: &Val| match &key_function {
+					Some(function) => apply(function, vec![item.clone()]),
+					None => Ok(item.clone()),
+				};
+				let mut keyed = items.into_iter().map(|item| Ok((key(&item)?, item))).collect::<Result<Vec<_>>>()?;
+				if name != "uniq" {
+					let mut failed = None;
+					keyed.sort_by(|(a, _), (b, _)| {
+						self.compare(a, b).unwrap_or_else(|e| {
+							failed.get_or_insert(e);
+							Ordering::Equal
+						})
+					});
+					if let Some(e) = failed {
+						return Err(e);
+					}
+				}
+				if name != "sort" {
+					let mut deduped: Vec<(Val, Val)> = Vec::new();
+					for (key, item) in keyed {
+						match deduped.last() {
+							Some((last, _)) if self.equals(last, &key)? => {}
+							_ => deduped.push((key, item)),
+						}
+					}
+					keyed = deduped;
+				}
+				array(keyed.into_iter().map(|(_, item)| item).collect())
+			}
+			"objectFields" => array(self.visible_fields(&obj_arg(0)?).into_iter().map(Val::Str).collect()),
+			"objectFieldsAll" => array(self.fields(&obj_arg(0)?).into_keys().map(Val::Str).collect()),
+			"objectValues" | "objectValuesAll" | "objectKeysValues" => {
+				let obj = obj_arg(0)?;
+				let names: Vec<Rc<str>> = match name {
+					"objectValuesAll" => self.fields(&obj).into_keys().collect(),
+					_ => self.visible_fields(&obj),
+				};
+				let mut out = Vec::new();
+				for field in names {
+					let value = self.field(&obj, &field, obj.layers.len())?.expect("listed fields exist");
+					out.push(match name {
+						"objectKeysValues" => self.record([("key".into(), Val::Str(field)), ("value".into(), value)], env),
+						_ => value,
+					});
+				}
+				array(out)
+			}
+			"objectHas" => Val::Bool(self.fields(&obj_arg(0)?).get(&str_arg(1)?).copied().unwrap_or(false)),
+			"objectHasAll" => Val::Bool(self.fields(&obj_arg(0)?).contains_key(&str_arg(1)?)),
+			"get" => {
+				let (obj, field) = (obj_arg(0)?, str_arg(1)?);
+				let include_hidden = !given(3) || matches!(arg(3)?, Val::Bool(true));
+				match self.fields(&obj).get(&field) {
+					Some(visible) if *visible || include_hidden => self.field(&obj, &field, obj.layers.len())?.expect("listed fields exist"),
+					_ => arg(2)?,
+				}
+			}
+			"mergePatch" => {
+				let mut target = self.manifest(&arg(0)?)?;
+				merge_patch(&mut target, &self.manifest(&arg(1)?)?);
+				self.of_json(&target, env)
+			}
+			"isString" => Val::Bool(matches!(arg(0)?, Val::Str(_))),
+			"isNumber" => Val::Bool(matches!(arg(0)?, Val::Num(_))),
+			"isBoolean" => Val::Bool(matches!(arg(0)?, Val::Bool(_))),
+			"isObject" => Val::Bool(matches!(arg(0)?, Val::Obj(_))),
+			"isArray" => Val::Bool(matches!(arg(0)?, Val::Arr(_))),
+			"isFunction" => Val::Bool(matches!(arg(0)?, Val::Func(_))),
+			"abs" => Val::Num(num_arg(0)?.abs()),
+			"sign" => Val::Num(match num_arg(0)? {
+				n if n > 0.0 => 1.0,
+				n if n < 0.0 => -1.0,
+				_ => 0.0,
+			}),
+			"floor" => Val::Num(num_arg(0)?.floor()),
+			"ceil" => Val::Num(num_arg(0)?.ceil()),
+			"round" => Val::Num(num_arg(0)?.round()),
+			"sqrt" => Val::Num(num_arg(0)?.sqrt()),
+			"pow" => Val::Num(num_arg(0)?.powf(num_arg(1)?)),
+			"max" => Val::Num(num_arg(0)?.max(num_arg(1)?)),
+			"min" => Val::Num(num_arg(0)?.min(num_arg(1)?)),
+			"equals" => Val::Bool(self.equals(&arg(0)?, &arg(1)?)?),
+			"assertEqual" => {
+				let (a, b) = (arg(0)?, arg(1)?);
+				ensure!(self.equals(&a, &b)?, "assertion failed: {} != {}", self.to_string(&a)?, self.to_string(&b)?);
+				Val::Bool(true)
+			}
+			"trace" => arg(1)?,
+			"extVar" => bail!("std.extVar(\"{}\"): external variables aren't supported", str_arg(0)?),
+			_ => unreachable!("every builtin is implemented"),
+		})
+	}
+
+	/// Python-style `%` formatting, as `std.format` and the `%` operator on strings do it.
+	fn format(&self, format: &str, values: &Val) -> Result<String> {
+		let positional: Vec<Thunk> = match values {
+			Val::Arr(items) => items.to_vec(),
+			Val::Obj(_) => Vec::new(),
+			other => vec![Thunk::done(other.clone())],
+		};
+		let mut next = positional.iter();
+		let mut out = String::new();
+		let mut chars = format.chars().peekable();
+		while let Some(c) = chars.next() {
+			if c != '%' {
+				out.push(c);
+				continue;
+			}
+			let key: Option<String> = match chars.peek() {
+				Some('(') => {
+					chars.next();
+					Some(chars.by_ref().take_while(|c| *c != ')').collect())
+				}
+				_ => None,
+			};
+			let mut flags = String::new();
+			while let Some(flag @ ('#' | '0' | '-' | '+' | ' ')) = chars.peek().copied() {
+				flags.push(flag);
+				chars.next();
+			}
+			let mut number = |chars: &mut std::iter::Peekable<std::str::Chars>| -> Result<Option<usize>> {
+				if chars.peek() == Some(&'*') {
+					chars.next();
+					let value = next.next().ok_or_eyre("not enough values to format")?;
+					return Ok(Some(index_number(&self.force(value)?)?));
+				}
+				let digits: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+				Ok(digits.parse().ok())
+			};
+			let width = number(&mut chars)?;
+			let precision = match chars.peek() {
+				Some('.') => {
+					chars.next();
+					Some(number(&mut chars)?.unwrap_or(0))
+				}
+				_ => None,
+			};
+			while chars.next_if(|c| matches!(c, 'h' | 'l' | 'L')).is_some() {}
+			let conversion = chars.next().ok_or_eyre("truncated format code")?;
+			if conversion == '%' {
+				out.push('%');
+				continue;
+			}
+			let value = match (&key, values) {
+				(Some(key), Val::Obj(obj)) => self.field(obj, key, obj.layers.len())?.ok_or_else(|| eyre!("no field `{key}` to format"))?,
+				(Some(_), _) => bail!("`%(name)` formatting takes an object"),
+				(None, _) => self.force(next.next().ok_or_eyre("not enough values to format")?)?,
+			};
+			out.push_str(&self.format_code(&value, &flags, width, precision, conversion)?);
+		}
+		ensure!(next.next().is_none() || matches!(values, Val::Obj(_)), "too many values to format");
+		Ok(out)
+	}
+
+	fn format_code(&self, value: &Val, flags: &str, width: Option<usize>, precision: Option<usize>, conversion: char) -> Result<String> {
+		let num = || match value {
+			Val::Num(n) => Ok(*n),
+			other => bail!("`%{conversion}` formats numbers, not {}", type_of(other)),
+		};
+		let (sign, body) = match conversion {
+			's' => (String::new(), self.to_string(value)?),
+			'c' => (
+				String::new(),
+				match value {
+					Val::Str(s) => s.to_string(),
+					_ => char::from_u32(num()? as u32).ok_or_eyre("`%c`: not a valid codepoint")?.to_string(),
+				},
+			),
+			_ => {
+				let n = num()?;
+				let sign = match (n < 0.0, flags.contains('+'), flags.contains(' ')) {
+					(true, ..) => "-",
+					(false, true, _) => "+",
+					(false, false, true) => " ",
+					_ => "",
+				};
+				let n = n.abs();
+				let body = match conversion {
+					'd' | 'i' | 'u' => format!("{:0>1$}", n.trunc() as u64, precision.unwrap_or(1)),
+					'o' => format!("{}{:o}", if flags.contains('#') { "0" } else { "" }, n.trunc() as u64),
+					'x' => format!("{}{:x}", if flags.contains('#') { "0x" } else { "" }, n.trunc() as u64),
+					'X' => format!("{}{:X}", if flags.contains('#') { "0X" } else { "" }, n.trunc() as u64),
+					'f' | 'F' => format!("{n:.*}", precision.unwrap_or(6)),
+					'e' | 'E' => exponent(n, precision.unwrap_or(6), conversion == 'E'),
+					'g' | 'G' => {
+						let precision = precision.unwrap_or(6).max(1);
+						let exp = if n == 0.0 { 0 } else { n.log10().floor() as i32 };
+						let text = match exp < -4 || exp >= precision as i32 {
+							true => exponent(n, precision - 1, conversion == 'G'),
+							false => format!("{n:.*}", (precision as i32 - 1 - exp).max(0) as usize),
+						};
+						match flags.contains('#') {
+							true => text,
+							false => strip_zeros(&text),
+						}
+					}
+					_ => bail!("unknown format code `%{conversion}`"),
+				};
+				(sign.to_owned(), body)
+			}
+		};
+		let len = sign.chars().count() + body.chars().count();
+		let pad = width.unwrap_or(0).saturating_sub(len);
+		Ok(match (flags.contains('-'), flags.contains('0') && !matches!(conversion, 's' | 'c')) {
+			(true, _) => format!("{sign}{body}{}", " ".repeat(pad)),
+			(false, true) => format!("{sign}{}{body}", "0".repeat(pad)),
+			(false, false) => format!("{}{sign}{body}", " ".repeat(pad)),
+		})
+	}
+}
+
+/// Parameters of each builtin; optional ones end in `?`.
+const BUILTINS: &[(&str, &[&str])] = &[
+	("length", &["x"]),
+	("type", &["x"]),
+	("toString", &["a"]),
+	("parseInt", &["str"]),
+	("parseJson", &["str"]),
+	("codepoint", &["str"]),
+	("char", &["n"]),
+	("substr", &["str", "from", "len"]),
+	("startsWith", &["a", "b"]),
+	("endsWith", &["a", "b"]),
+	("stringChars", &["str"]),
+	("split", &["str", "c"]),
+	("splitLimit", &["str", "c", "maxsplits"]),
+	("strReplace", &["str", "from", "to"]),
+	("asciiUpper", &["str"]),
+	("asciiLower", &["str"]),
+	("trim", &["str"]),
+	("isEmpty", &["str"]),
+	("join", &["sep", "arr"]),
+	("lines", &["arr"]),
+	("format", &["str", "vals"]),
+	("escapeStringJson", &["str"]),
+	("manifestJson", &["value"]),
+	("manifestJsonMinified", &["value"]),
+	("manifestJsonEx", &["value", "indent", "newline?", "key_val_sep?"]),
+	("map", &["func", "arr"]),
+	("flatMap", &["func", "arr"]),
+	("filter", &["func", "arr"]),
+	("foldl", &["func", "arr", "init"]),
+	("foldr", &["func", "arr", "init"]),
+	("range", &["from", "to"]),
+	("makeArray", &["sz", "func"]),
+	("member", &["arr", "x"]),
+	("contains", &["arr", "elem"]),
+	("count", &["arr", "x"]),
+	("flattenArrays", &["arrs"]),
+	("reverse", &["arr"]),
+	("sort", &["arr", "keyF?"]),
+	("uniq", &["arr", "keyF?"]),
+	("set", &["arr", "keyF?"]),
+	("objectFields", &["o"]),
+	("objectFieldsAll", &["o"]),
+	("objectValues", &["o"]),
+	("objectValuesAll", &["o"]),
+	("objectKeysValues", &["o"]),
+	("objectHas", &["o", "f"]),
+	("objectHasAll", &["o", "f"]),
+	("get", &["o", "f", "default?", "inc_hidden?"]),
+	("mergePatch", &["target", "patch"]),
+	("isString", &["v"]),
+	("isNumber", &["v"]),
+	("isBoolean", &["v"]),
+	("isObject", &["v"]),
+	("isArray", &["v"]),
+	("isFunction", &["v"]),
+	("abs", &["n"]),
+	("sign", &["n"]),
+	("floor", &["x"]),
+	("ceil", &["x"]),
+	("round", &["x"]),
+	("sqrt", &["x"]),
+	("pow", &["x", "n"]),
+	("max", &["a", "b"]),
+	("min", &["a", "b"]),
+	("equals", &["a", "b"]),
+	("assertEqual", &["a", "b"]),
+	("trace", &["str", "rest"]),
+	("extVar", &["x"]),
+];
+
+fn builtin_params(name: &str) -> &'static [&'static str] {
+	BUILTINS.iter().find(|(n, _)| *n == name).map(|(_, params)| *params).unwrap_or(&[])
+}
+
+/// Match positional and then named `args` to `params`.
+fn bind_args(params: &[&str], args: Vec<(Option<Rc<str>>, Thunk)>) -> Result<Vec<Option<Thunk>>> {
+	let mut bound: Vec<Option<Thunk>> = vec![None; params.len()];
+	for (i, (name, thunk)) in args.into_iter().enumerate() {
+		let slot = match name {
+			None => i,
+			Some(name) => params.iter().position(|p| *p == &*name).ok_or_else(|| eyre!("no parameter named `{name}`"))?,
+		};
+		let arg = bound.get_mut(slot).ok_or_else(|| eyre!("too many arguments: expected at most {}", params.len()))?;
+		ensure!(arg.is_none(), "argument `{}` given twice", params[slot]);
+		*arg = Some(thunk);
+	}
+	Ok(bound)
+}
+
+fn type_of(value: &Val) -> &'static str {
+	match value {
+		Val::Null => "null",
+		Val::Bool(_) => "boolean",
+		Val::Num(_) => "number",
+		Val::Str(_) => "string",
+		Val::Arr(_) => "array",
+		Val::Obj(_) => "object",
+		Val::Func(_) => "function",
+	}
+}
+
+/// Whole numbers come out as integers, as Jsonnet prints them.
+fn number(n: f64) -> Result<JsonValue> {
+	if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 {
+		return Ok((n as i64).into());
+	}
+	serde_json::Number::from_f64(n).map(JsonValue::Number).ok_or_else(|| eyre!("{n} can't be turned into JSON"))
+}
+
+fn index_number(value: &Val) -> Result<usize> {
+	match value {
+		Val::Num(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+		Val::Num(n) => bail!("{n} is not a valid index"),
+		other => bail!("indices are numbers, not {}", type_of(other)),
+	}
+}
+
+fn slice(target: &Val, start: Option<usize>, end: Option<usize>, step: Option<usize>) -> Result<Val> {
+	let step = step.unwrap_or(1);
+	ensure!(step > 0, "slice step must be positive");
+	let pick = |len: usize| (start.unwrap_or(0)..end.unwrap_or(len).min(len)).step_by(step);
+	Ok(match target {
+		Val::Arr(items) => Val::Arr(pick(items.len()).map(|i| items[i].clone()).collect()),
+		Val::Str(s) => {
+			let chars: Vec<char> = s.chars().collect();
+			Val::Str(pick(chars.len()).map(|i| chars[i]).collect::<String>().into())
+		}
+		other => bail!("can't slice {}", type_of(other)),
+	})
+}
+
+/// JSON on one line, spaced the way `std.toString` does it.
+fn compact(value: &JsonValue) -> String {
+	pretty(value, "", " ", ": ", "").replace("[ ", "[").replace(" ]", "]").replace("{ ", "{").replace(" }", "}")
+}
+
+/// JSON with every nested value on a line of its own, nested by `indent`.
+fn pretty(value: &JsonValue, indent: &str, newline: &str, separator: &str, current: &str) -> String {
+	let inner = format!("{current}{indent}");
+	let (sep, close) = match newline {
+		" " => (",".to_owned(), String::new()),
+		_ => (",".to_owned(), current.to_owned()),
+	};
+	match value {
+		JsonValue::Array(items) if !items.is_empty() => {
+			let items: Vec<String> = items.iter().map(|item| format!("{inner}{}", pretty(item, indent, newline, separator, &inner))).collect();
+			format!("[{newline}{}{newline}{close}]", items.join(&format!("{sep}{newline}")))
+		}
+		JsonValue::Object(obj) if !obj.is_empty() => {
+			let entries: Vec<String> = obj
+				.iter()
+				.map(|(k, v)| {
+					format!(
+						"{inner}{}{separator}{}",
+						serde_json::to_string(k).expect("strings serialize"),
+						pretty(v, indent, newline, separator, &inner)
+					)
+				})
+				.collect();
+			format!("{{{newline}{}{newline}{close}}}", entries.join(&format!("{sep}{newline}")))
+		}
+		_ => value.to_string(),
+	}
+}
+
+fn exponent(n: f64, precision: usize, upper: bool) -> String {
+	let text = format!("{n:.precision$e}");
+	let (mantissa, exp) = text.split_once('e').expect("`e` formatting has an exponent");
+	let exp: i32 = exp.parse().expect("exponents are numbers");
+	let text = format!("{mantissa}e{}{:02}", if exp < 0 { '-' } else { '+' }, exp.abs());
+	match upper {
+		true => text.to_uppercase(),
+		false => text,
+	}
+}
+
+/// `1.500` to `1.5`, and `2.0e+03` to `2e+03`.
+fn strip_zeros(text: &str) -> String {
+	let (mantissa, exp) = match text.find(['e', 'E']) {
+		Some(i) => text.split_at(i),
+		None => (text, ""),
+	};
+	let mantissa = match mantissa.contains('.') {
+		true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+		false => mantissa,
+	};
+	format!("{mantissa}{exp}")
+}
+
+/// RFC 7396 merge patch.
+fn merge_patch(target: &mut JsonValue, patch: &JsonValue) {
+	let JsonValue::Object(patch) = patch else {
+		*target = patch.clone();
+		return;
+	};
+	if !target.is_object() {
+		*target = JsonValue::Object(Map::new());
+	}
+	let target = target.as_object_mut().expect("just made an object");
+	for (key, value) in patch {
+		match value {
+			JsonValue::Null => _ = target.shift_remove(key),
+			value => merge_patch(target.entry(key.clone()).or_insert(JsonValue::Null), value),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	fn eval(src: &str) -> Result<JsonValue> {
+		evaluate(Path::new("test.jsonnet"), src)
+	}
+
+	#[test]
+	fn objects_inherit_late_bound() {
+		let src = r#"
+			local base = {
+				name: 'svc',
+				port: 80,
+				url: 'http://%s:%d' % [self.name, self.port],
+				hidden:: 'x',
+				tags+: ['base'],
+			};
+			base + {
+				port: 8080,
+				tags+: ['prod'],
+				shown::: super.hidden + '!',
+				has_name: 'name' in super,
+				[if std.length(base.name) == 3 then 'high']: true,
+				upper: std.asciiUpper($.name),
+			}
+		"#;
+		assert_eq!(
+			eval(src).unwrap(),
+			json!({"name": "svc", "port": 8080, "url": "http://svc:8080", "tags": ["base", "prod"], "shown": "x!", "has_name": true, "high": true, "upper": "SVC"})
+		);
+	}
+
+	#[test]
+	fn functions_comprehensions_and_std() {
+		let src = r#"
+			local double(x) = x * 2;
+			local names = ['b', 'a', 'b'];
+			{
+				doubled: [double(x) for x in std.range(1, 3) if x != 2],
+				by_name: { [n]: std.length(n) for n in std.set(names) },
+				joined: std.join(', ', std.map(function(n) n + '!', names)),
+				fmt: std.format('%05.1f|%-3s|%x', [3.14159, 'ab', 255]),
+				defaults: (function(a, b=a + 1) [a, b])(1),
+				named: std.get({ a: 1 }, 'b', default='none'),
+				text: |||
+					two
+					lines
+				|||,
+				sorted: std.sort([3, 1, 2]),
+				fields: std.objectFields({ b: 1, a:: 2, c: 3 }),
+				half: 7 / 2,
+			}
+		"#;
+		assert_eq!(
+			eval(src).unwrap(),
+			json!({
+				"doubled": [2, 6],
+				"by_name": {"a": 1, "b": 1},
+				"joined": "b!, a!, b!",
+				"fmt": "003.1|ab |ff",
+				"defaults": [1, 2],
+				"named": "none",
+				"text": "two\nlines\n",
+				"sorted": [1, 2, 3],
+				"fields": ["b", "c"],
+				"half": 3.5,
+			})
+		);
+	}
+
+	/// Cases adapted from the Jsonnet test suite (`test_suite/*.jsonnet` in google/jsonnet), grouped by the file they come from. Each pair has to evaluate to equal values.
+	#[test]
+	fn conformance() {
+		let cases = [
+			// arith_bool, arith_float, precedence
+			("true && false || !false", "true"),
+			("false && error 'not evaluated'", "false"),
+			("1 + 2 * 3 - 4 / 2", "5"),
+			("(1 + 2) * 3", "9"),
+			("-2 * 3", "-6"),
+			("7 % 3", "1"),
+			("-7 % 3", "-1"),
+			("5.5 % 2", "1.5"),
+			("1 / 4", "0.25"),
+			("1e3 + 1.5E-1", "1000.15"),
+			("[5 & 3, 5 | 3, 5 ^ 3, 1 << 3, 256 >> 4, ~0]", "[1, 7, 6, 8, 16, -1]"),
+			("[1 < 2, 2 <= 2, 'a' < 'b', 'b' >= 'ab']", "[true, true, true, true]"),
+			// arith_string, format
+			("'a' + 1", "'a1'"),
+			("1 + 'a'", "'1a'"),
+			("'x' + [1, 'y']", "'x[1, \"y\"]'"),
+			("'%d-%s' % [1, 'x']", "'1-x'"),
+			("'%(name)s is %(age)d' % { name: 'Al', age: 30 }", "'Al is 30'"),
+			("'%5.2f|%-4d|%03d|%x|%o|%%' % [3.14159, 7, 7, 255, 8]", "' 3.14|7   |007|ff|10|%'"),
+			("'%s' % [[1, 2]]", "'[1, 2]'"),
+			// text_block, verbatim_strings, unicode
+			("|||\n  a\n    b\n|||", "'a\\n  b\\n'"),
+			("@'a''b'", "\"a'b\""),
+			("@\"c:\\n\"", "'c:\\\\n'"),
+			("'\\u00e9\\t'", "'é\\t'"),
+			("std.length('héllo')", "5"),
+			("'abc'[1]", "'b'"),
+			// slice
+			("[1, 2, 3, 4, 5][1:3]", "[2, 3]"),
+			("[1, 2, 3, 4, 5][::2]", "[1, 3, 5]"),
+			("'hello'[1:]", "'ello'"),
+			// local, functions, lazy
+			("local a = 1, b = a + 1; b", "2"),
+			("local even(n) = if n == 0 then true else odd(n - 1), odd(n) = if n == 0 then false else even(n - 1); even(10)", "true"),
+			("(function(x, y=2) x + y)(1)", "3"),
+			("(function(x, y) x - y)(y=1, x=3)", "2"),
+			("local add(x) = function(y) x + y; add(1)(2)", "3"),
+			("local x = error 'not used'; 1", "1"),
+			("[error 'not used', 2][1]", "2"),
+			("{ a: error 'not used', b: 2 }.b", "2"),
+			("if false then 1", "null"),
+			// object, inheritance, merge
+			("{ a: 1 } + { b: 2 }", "{ a: 1, b: 2 }"),
+			("{ a: 1, b: self.a + 1 }.b", "2"),
+			("({ a: 1 } + { a: super.a + 1 }).a", "2"),
+			("({ a: 1, b: self.a } + { a: 10 }).b", "10"),
+			("std.objectFields({ a:: 1 } + { a: 2 })", "[]"),
+			("std.objectFields({ a:: 1 } + { a::: 2 })", "['a']"),
+			("{ a+: [2] }", "{ a: [2] }"),
+			("{ a: { b: [1] } } + { a+: { b+: [2] } }", "{ a: { b: [1, 2] } }"),
+			("{ a: { b: $.c }, c: 3 }.a.b", "3"),
+			("{ local x = 2, a: x * 2 }", "{ a: 4 }"),
+			("{ 'a b': 1 }['a b']", "1"),
+			("['a' in { a: 1 }, 'a' in { a:: 1 }, 'b' in { a: 1 }]", "[true, true, false]"),
+			("{ [if false then 'a']: 1 }", "{}"),
+			("{ assert self.a > 0, a: 1 }", "{ a: 1 }"),
+			// comprehension, object_comprehension
+			("[x * y for x in [1, 2] for y in [10, 20] if x * y != 20]", "[10, 40]"),
+			("{ [k]: std.length(k) for k in ['x', 'yy'] }", "{ x: 1, yy: 2 }"),
+			("{ [k + '_' + v]: true for k in ['a'] for v in ['b', 'c'] }", "{ a_b: true, a_c: true }"),
+			// equality, null
+			("[1, { a: [null] }] == [1, { a: [null] }]", "true"),
+			("{ a: 1, b:: 2 } == { a: 1 }", "true"),
+			("std.type(null)", "'null'"),
+			// std
+			("std.substr('hello', 1, 3)", "'ell'"),
+			("std.split('a,b,c', ',')", "['a', 'b', 'c']"),
+			("std.splitLimit('a,b,c', ',', 1)", "['a', 'b,c']"),
+			("std.strReplace('aXbX', 'X', '-')", "'a-b-'"),
+			("std.join([0], [[1], [2]])", "[1, 0, 2]"),
+			("std.lines(['a', 'b'])", "'a\\nb\\n'"),
+			("std.range(2, 0)", "[]"),
+			("std.makeArray(3, function(i) i * i)", "[0, 1, 4]"),
+			("std.foldl(function(acc, x) acc + [x], [1, 2, 3], [])", "[1, 2, 3]"),
+			("std.foldr(function(x, acc) acc + [x], [1, 2, 3], [])", "[3, 2, 1]"),
+			("std.filter(function(x) x % 2 == 0, [1, 2, 3, 4])", "[2, 4]"),
+			("std.flatMap(function(x) [x, x], [1, 2])", "[1, 1, 2, 2]"),
+			("std.sort([3, 1, 2], keyF=function(x) -x)", "[3, 2, 1]"),
+			("std.set([3, 1, 1, 2])", "[1, 2, 3]"),
+			("std.uniq([1, 1, 2, 1])", "[1, 2, 1]"),
+			("[std.member([1, 2], 2), std.count([1, 2, 1], 1)]", "[true, 2]"),
+			("std.reverse([1, 2])", "[2, 1]"),
+			("std.flattenArrays([[1], [2, 3]])", "[1, 2, 3]"),
+			("std.objectValues({ a: 1, b:: 2 })", "[1]"),
+			("std.objectFieldsAll({ a:: 1 })", "['a']"),
+			("std.objectKeysValues({ a: 1 })", "[{ key: 'a', value: 1 }]"),
+			("[std.objectHas({ a:: 1 }, 'a'), std.objectHasAll({ a:: 1 }, 'a')]", "[false, true]"),
+			("std.length({ a: 1, b:: 2 })", "1"),
+			("std.get({ a:: 1 }, 'a')", "1"),
+			("std.mergePatch({ a: 1, b: { c: 2 } }, { a: null, b: { d: 3 } })", "{ b: { c: 2, d: 3 } }"),
+			("[std.parseInt('-42'), std.parseJson('{\"a\": [1]}')]", "[-42, { a: [1] }]"),
+			("[std.codepoint('A'), std.char(97)]", "[65, 'a']"),
+			("std.toString([1, 'a'])", "'[1, \"a\"]'"),
+			("std.manifestJsonMinified({ a: [1, 2] })", "'{\"a\":[1,2]}'"),
+			("std.manifestJsonEx({ a: 1 }, '  ')", "'{\\n  \"a\": 1\\n}'"),
+			("std.escapeStringJson('a\"b')", "'\"a\\\\\"b\"'"),
+			("[std.asciiLower('AbC'), std.asciiUpper('a'), std.trim(' a ')]", "['abc', 'A', 'a']"),
+			("[std.startsWith('hello', 'he'), std.endsWith('hello', 'lo')]", "[true, true]"),
+			("std.stringChars('ab')", "['a', 'b']"),
+			("[std.abs(-3), std.sign(-2), std.max(1, 2), std.min(1, 2)]", "[3, -1, 2, 1]"),
+			("[std.pow(2, 10), std.sqrt(16), std.floor(-1.5), std.ceil(1.2), std.round(2.5)]", "[1024, 4, -2, 2, 3]"),
+			("[std.type([]), std.type({}), std.type(''), std.type(1), std.type(true), std.type(function() 1)]", "['array', 'object', 'string', 'number', 'boolean', 'function']"),
+			("[std.isEmpty(''), std.isString('a'), std.isNumber(1), std.isBoolean(false), std.isObject({}), std.isArray([]), std.isFunction(std.map)]", "[true, true, true, true, true, true, true]"),
+			("std.equals({ a: [1] }, { a: [1] })", "true"),
+		];
+		for (expr, expected) in cases {
+			let out = eval(&format!("std.assertEqual({expr}, {expected})")).map_err(|e| e.to_string());
+			assert_eq!(out, Ok(json!(true)), "{expr}");
+		}
+	}
+
+	/// The other half of the test suite: programs that have to fail.
+	#[test]
+	fn conformance_errors() {
+		let cases = [
+			"1 + error 'explicit'",
+			"{ a: 1 }.b",
+			"[1][1]",
+			"1 / 0",
+			"'x' - 1",
+			"local f(x) = x; f(1, 2)",
+			"local f(x) = x; f(y=1)",
+			"{ a: 1, a: 2 }",
+			"assert false; 1",
+			"{ assert false }",
+			"std.assertEqual(1, 2)",
+			"local x = x; x",
+			"[1, 2][0.5]",
+			"if 1 then 2",
+			"{ a: 1 } == function() 1",
+		];
+		for src in cases {
+			assert!(eval(src).is_err(), "{src}");
+		}
+	}
+
+	#[test]
+	fn errors_are_readable() {
+		let e = eval("{\n  a: error 'no ' + 'way',\n}").unwrap_err();
+		assert_eq!(e.to_string(), "test.jsonnet:2:6: error: no way");
+		let e = eval("local o = { a: self.b, b: self.a }; o.a").unwrap_err();
+		assert!(e.to_string().contains("max stack depth"), "{e}");
+		let e = eval("{ a: 1 } + 1").unwrap_err();
+		assert_eq!(e.to_string(), "test.jsonnet:1:1: can't add object and number");
+		let e = eval("{ assert self.port > 0 : 'port must be positive', port: -1 }").unwrap_err();
+		assert!(e.to_string().ends_with("assertion failed: port must be positive"), "{e}");
+		assert!(eval("std.extVar('x')").unwrap_err().to_string().contains("external variables"));
+	}
+}
@@ -0,0 +1,952 @@
+//! Jsonnet lexer and parser. Every expression remembers its byte range in the source, for error messages and for write-back.
+use std::{ops::Range, rc::Rc};
+
+use color_eyre::eyre::ensure;
+use v_utils::prelude::*;
+
+#[derive(Debug)]
+pub struct Expr {
+	pub span: Range<usize>,
+	pub kind: ExprKind,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+	Null,
+	Bool(bool),
+	Num(f64),
+	Str(String),
+	SelfRef,
+	/// `$`, the outermost object.
+	Dollar,
+	Var(Rc<str>),
+	Array(Vec<Rc<Expr>>),
+	ArrayComp {
+		body: Rc<Expr>,
+		specs: Vec<CompSpec>,
+	},
+	Object(Vec<Member>),
+	ObjectComp {
+		locals: Vec<Bind>,
+		key: Rc<Expr>,
+		value: Rc<Expr>,
+		specs: Vec<CompSpec>,
+	},
+	/// `a.b` and `a[b]`.
+	Index {
+		target: Rc<Expr>,
+		index: Rc<Expr>,
+	},
+	Slice {
+		target: Rc<Expr>,
+		start: Option<Rc<Expr>>,
+		end: Option<Rc<Expr>>,
+		step: Option<Rc<Expr>>,
+	},
+	/// `super.b` and `super[b]`.
+	SuperIndex(Rc<Expr>),
+	/// `b in super`.
+	InSuper(Rc<Expr>),
+	Apply {
+		target: Rc<Expr>,
+		args: Vec<Arg>,
+	},
+	Binary {
+		op: BinOp,
+		lhs: Rc<Expr>,
+		rhs: Rc<Expr>,
+	},
+	Unary {
+		op: UnOp,
+		expr: Rc<Expr>,
+	},
+	If {
+		cond: Rc<Expr>,
+		then: Rc<Expr>,
+		otherwise: Option<Rc<Expr>>,
+	},
+	Function {
+		params: Rc<[Param]>,
+		body: Rc<Expr>,
+	},
+	Local {
+		binds: Vec<Bind>,
+		body: Rc<Expr>,
+	},
+	Error(Rc<Expr>),
+	Assert {
+		assert: Assert,
+		body: Rc<Expr>,
+	},
+	Import {
+		path: String,
+		raw: bool,
+	},
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinOp {
+	Mul,
+	Div,
+	Mod,
+	Add,
+	Sub,
+	Shl,
+	Shr,
+	Lt,
+	Le,
+	Gt,
+	Ge,
+	In,
+	Eq,
+	Ne,
+	BitAnd,
+	BitXor,
+	BitOr,
+	And,
+	Or,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnOp {
+	Neg,
+	Plus,
+	Not,
+	BitNot,
+}
+
+impl std::fmt::Display for BinOp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Mul => "*",
+			Self::Div => "/",
+			Self::Mod => "%",
+			Self::Add => "+",
+			Self::Sub => "-",
+			Self::Shl => "<<",
+			Self::Shr => ">>",
+			Self::Lt => "<",
+			Self::Le => "<=",
+			Self::Gt => ">",
+			Self::Ge => ">=",
+			Self::In => "in",
+			Self::Eq => "==",
+			Self::Ne => "!=",
+			Self::BitAnd => "&",
+			Self::BitXor => "^",
+			Self::BitOr => "|",
+			Self::And => "&&",
+			Self::Or => "||",
+		})
+	}
+}
+
+impl std::fmt::Display for UnOp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Neg => "-",
+			Self::Plus => "+",
+			Self::Not => "!",
+			Self::BitNot => "~",
+		})
+	}
+}
+
+#[derive(Debug)]
+pub enum Member {
+	Field(Field),
+	Local(Bind),
+	Assert(Assert),
+}
+
+#[derive(Debug)]
+pub struct Field {
+	pub name: FieldName,
+	pub name_span: Range<usize>,
+	/// `+:`, which adds to the inherited value instead of replacing it.
+	pub plus: bool,
+	pub visibility: Visibility,
+	pub value: Rc<Expr>,
+}
+
+#[derive(Debug)]
+pub enum FieldName {
+	Fixed(Rc<str>),
+	Computed(Rc<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Visibility {
+	/// `:`, visible unless inherited as hidden.
+	Default,
+	/// `::`
+	Hidden,
+	/// `:::`
+	Forced,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bind {
+	pub name: Rc<str>,
+	pub value: Rc<Expr>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Assert {
+	pub cond: Rc<Expr>,
+	pub message: Option<Rc<Expr>>,
+}
+
+#[derive(Debug)]
+pub struct Param {
+	pub name: Rc<str>,
+	pub default: Option<Rc<Expr>>,
+}
+
+#[derive(Debug)]
+pub struct Arg {
+	pub name: Option<Rc<str>>,
+	pub value: Rc<Expr>,
+}
+
+#[derive(Clone, Debug)]
+pub enum CompSpec {
+	For(Rc<str>, Rc<Expr>),
+	If(Rc<Expr>),
+}
+
+pub fn parse(src: &str) -> Result<Rc<Expr>> {
+	let tokens = lex(src)?;
+	let mut parser = Parser { src, tokens, pos: 0 };
+	let expr = parser.expr(0)?;
+	ensure!(parser.peek() == &Token::Eof, "{}: unexpected {}", location(src, parser.start()), parser.peek());
+	Ok(expr)
+}
+
+/// `line:column` of `pos`, both counted from 1.
+pub fn location(src: &str, pos: usize) -> String {
+	let line = src[..pos].matches('\n').count() + 1;
+	let column = src[..pos].chars().rev().take_while(|c| *c != '\n').count() + 1;
+	format!("{line}:{column}")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+	Ident(Rc<str>),
+	Num(f64),
+	Str(String),
+	Symbol(&'static str),
+	Eof,
+}
+impl std::fmt::Display for Token {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Ident(name) => write!(f, "`{name}`"),
+			Self::Num(n) => write!(f, "`{n}`"),
+			Self::Str(_) => write!(f, "string"),
+			Self::Symbol(s) => write!(f, "`{s}`"),
+			Self::Eof => write!(f, "end of file"),
+		}
+	}
+}
+
+/// Longest first, so that `:::` isn't read as `::` and `:`.
+const SYMBOLS: [&str; 34] = [
+	":::", "::", "<=", ">=", "==", "!=", "&&", "||", "<<", ">>", "{", "}", "[", "]", "(", ")", ",", ".", ";", ":", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^", "$",
+];
+
+const KEYWORDS: [&str; 17] = [
+	"assert", "else", "error", "false", "for", "function", "if", "import", "importstr", "in", "local", "null", "tailstrict", "then", "self", "super", "true",
+];
+
+pub fn is_identifier(s: &str) -> bool {
+	let mut chars = s.chars();
+	chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !KEYWORDS.contains(&s)
+}
+
+fn lex(src: &str) -> Result<Vec<(Token, Range<usize>)>> {
+	let mut tokens = Vec::new();
+	let mut pos = 0;
+	loop {
+		pos = skip_trivia(src, pos);
+		let start = pos;
+		let rest = &src[pos..];
+		let Some(c) = rest.chars().next() else {
+			tokens.push((Token::Eof, pos..pos));
+			return Ok(tokens);
+		};
+		let token = if c.is_ascii_digit() {
+			let len = number_len(rest);
+			pos += len;
+			Token::Num(rest[..len].parse().map_err(|_| eyre!("{}: invalid number `{}`", location(src, start), &rest[..len]))?)
+		} else if c.is_ascii_alphabetic() || c == '_' {
+			let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+			pos += len;
+			Token::Ident(rest[..len].into())
+		} else if c == '"' || c == '\'' {
+			let (s, len) = quoted(rest, c).map_err(|e| eyre!("{}: {e}", location(src, start)))?;
+			pos += len;
+			Token::Str(s)
+		} else if let Some(q @ ('"' | '\'')) = rest.strip_prefix('@').and_then(|r| r.chars().next()) {
+			let (s, len) = verbatim(&rest[1..], q).ok_or_else(|| eyre!("{}: unterminated string", location(src, start)))?;
+			pos += 1 + len;
+			Token::Str(s)
+		} else if rest.starts_with("|||") {
+			let (s, len) = text_block(rest).map_err(|e| eyre!("{}: {e}", location(src, start)))?;
+			pos += len;
+			Token::Str(s)
+		} else {
+			let symbol = SYMBOLS
+				.iter()
+				.find(|s| rest.starts_with(**s))
+				.ok_or_else(|| eyre!("{}: unexpected character `{c}`", location(src, start)))?;
+			pos += symbol.len();
+			Token::Symbol(symbol)
+		};
+		tokens.push((token, start..pos));
+	}
+}
+
+fn skip_trivia(src: &str, mut pos: usize) -> usize {
+	loop {
+		let rest = &src[pos..];
+		let trimmed = rest.trim_start();
+		pos += rest.len() - trimmed.len();
+		if trimmed.starts_with("//") || trimmed.starts_with('#') {
+			pos += trimmed.find('\n').unwrap_or(trimmed.len());
+		} else if trimmed.starts_with("/*") {
+			pos += trimmed.find("*/").map(|i| i + 2).unwrap_or(trimmed.len());
+		} else {
+			return pos;
+		}
+	}
+}
+
+/// Offset of the first token at or after `pos`.
+pub fn next_token(src: &str, pos: usize) -> usize {
+	skip_trivia(src, pos)
+}
+
+fn number_len(s: &str) -> usize {
+	let bytes = s.as_bytes();
+	let digits = |mut i: usize| {
+		while i < bytes.len() && bytes[i].is_ascii_digit() {
+			i += 1;
+		}
+		i
+	};
+	let mut i = digits(0);
+	if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+		i = digits(i + 1);
+	}
+	if matches!(bytes.get(i), Some(b'e' | b'E')) {
+		let sign = usize::from(matches!(bytes.get(i + 1), Some(b'+' | b'-')));
+		if bytes.get(i + 1 + sign).is_some_and(u8::is_ascii_digit) {
+			i = digits(i + 1 + sign);
+		}
+	}
+	i
+}
+
+/// A `"` or `'` string at the start of `s`, and its length.
+fn quoted(s: &str, quote: char) -> Result<(String, usize)> {
+	let mut out = String::new();
+	let mut chars = s.char_indices().skip(1);
+	while let Some((i, c)) = chars.next() {
+		match c {
+			c if c == quote => return Ok((out, i + 1)),
+			'\\' => {
+				let (_, e) = chars.next().ok_or_eyre("unterminated string")?;
+				out.push(match e {
+					'"' | '\'' | '\\' | '/' => e,
+					'b' => '\u{8}',
+					'f' => '\u{c}',
+					'n' => '\n',
+					'r' => '\r',
+					't' => '\t',
+					'u' => {
+						fn unit(chars: &mut impl Iterator<Item = (usize, char)>) -> Result<u32> {
+							let hex: String = chars.take(4).map(|(_, c)| c).collect();
+							u32::from_str_radix(&hex, 16).map_err(|_| eyre!("invalid `\\u` escape"))
+						}
+						let high = unit(&mut chars)?;
+						let code = match (0xd800..0xdc00).contains(&high) {
+							true => {
+								ensure!(
+									chars.next().map(|(_, c)| c) == Some('\\') && chars.next().map(|(_, c)| c) == Some('u'),
+									"unpaired surrogate in `\\u` escape"
+								);
+								0x10000 + ((high - 0xd800) << 10) + (unit(&mut chars)? - 0xdc00)
+							}
+							false => high,
+						};
+						char::from_u32(code).ok_or_eyre("invalid `\\u` escape")?
+					}
+					_ => bail!("invalid escape `\\{e}`"),
+				});
+			}
+			c => out.push(c),
+		}
+	}
+	bail!("unterminated string")
+}
+
+/// `@"..."`, where only a doubled quote is special; `s` starts at the quote.
+fn verbatim(s: &str, quote: char) -> Option<(String, usize)> {
+	let mut out = String::new();
+	let mut chars = s.char_indices().skip(1).peekable();
+	while let Some((i, c)) = chars.next() {
+		if c == quote {
+			match chars.peek() {
+				Some((_, next)) if *next == quote => {
+					chars.next();
+					out.push(quote);
+				}
+				_ => return Some((out, i + 1)),
+			}
+		} else {
+			out.push(c);
+		}
+	}
+	None
+}
+
+/// A `|||` text block: its lines with the first one's indentation stripped, each ending in a newline unless opened with `|||-`.
+fn text_block(s: &str) -> Result<(String, usize)> {
+	let mut pos = 3;
+	let chomp = s[pos..].starts_with('-');
+	pos += usize::from(chomp);
+	let line_end = s[pos..].find('\n').map(|i| pos + i).ok_or_eyre("text block must start on a new line")?;
+	ensure!(s[pos..line_end].trim().is_empty(), "text block must start on a new line");
+	pos = line_end + 1;
+	let first = &s[pos..];
+	let indent = &first[..first.len() - first.trim_start_matches([' ', '\t']).len()];
+	ensure!(!indent.is_empty(), "text block's first line must be indented");
+	let mut out = String::new();
+	loop {
+		let line = &s[pos..s[pos..].find('\n').map(|i| pos + i).unwrap_or(s.len())];
+		if let Some(text) = line.strip_prefix(indent) {
+			out.push_str(text);
+			out.push('\n');
+		} else if line.trim().is_empty() && pos + line.len() < s.len() {
+			out.push('\n');
+		} else {
+			let close = line.trim_start_matches([' ', '\t']);
+			ensure!(close.starts_with("|||"), "text block lines must be indented at least as much as the first one");
+			if chomp {
+				out.pop();
+			}
+			return Ok((out, pos + (line.len() - close.len()) + 3));
+		}
+		pos += line.len() + 1;
+		ensure!(pos <= s.len(), "unterminated text block");
+	}
+}
+
+struct Parser<'a> {
+	src: &'a str,
+	tokens: Vec<(Token, Range<usize>)>,
+	pos: usize,
+}
+impl Parser<'_> {
+	fn peek(&self) -> &Token {
+		&self.tokens[self.pos].0
+	}
+
+	fn peek_at(&self, offset: usize) -> &Token {
+		&self.tokens[(self.pos + offset).min(self.tokens.len() - 1)].0
+	}
+
+	fn start(&self) -> usize {
+		self.tokens[self.pos].1.start
+	}
+
+	/// End of the last consumed token.
+	fn end(&self) -> usize {
+		self.tokens[self.pos.saturating_sub(1)].1.end
+	}
+
+	fn next(&mut self) -> Token {
+		let token = self.tokens[self.pos].0.clone();
+		if token != Token::Eof {
+			self.pos += 1;
+		}
+		token
+	}
+
+	fn is_symbol(&self, symbol: &str) -> bool {
+		matches!(self.peek(), Token::Symbol(s) if *s == symbol)
+	}
+
+	fn is_keyword(&self, keyword: &str) -> bool {
+		matches!(self.peek(), Token::Ident(s) if &**s == keyword)
+	}
+
+	fn eat_symbol(&mut self, symbol: &str) -> bool {
+		let found = self.is_symbol(symbol);
+		if found {
+			self.pos += 1;
+		}
+		found
+	}
+
+	fn eat_keyword(&mut self, keyword: &str) -> bool {
+		let found = self.is_keyword(keyword);
+		if found {
+			self.pos += 1;
+		}
+		found
+	}
+
+	fn error(&self, expected: &str) -> color_eyre::eyre::Report {
+		eyre!("{}: expected {expected}, found {}", location(self.src, self.start()), self.peek())
+	}
+
+	fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+		match self.eat_symbol(symbol) {
+			true => Ok(()),
+			false => Err(self.error(&format!("`{symbol}`"))),
+		}
+	}
+
+	fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+		match self.eat_keyword(keyword) {
+			true => Ok(()),
+			false => Err(self.error(&format!("`{keyword}`"))),
+		}
+	}
+
+	fn identifier(&mut self) -> Result<Rc<str>> {
+		match self.peek() {
+			Token::Ident(name) if is_identifier(name) => {
+				let name = name.clone();
+				self.pos += 1;
+				Ok(name)
+			}
+			_ => Err(self.error("an identifier")),
+		}
+	}
+
+	fn make(&self, start: usize, kind: ExprKind) -> Rc<Expr> {
+		Rc::new(Expr { span: start..self.end(), kind })
+	}
+
+	/// An expression whose binary operators all bind tighter than `min_prec`.
+	fn expr(&mut self, min_prec: u8) -> Result<Rc<Expr>> {
+		let start = self.start();
+		let mut lhs = self.unary()?;
+		loop {
+			let Some((op, prec)) = self.binary_op() else { return Ok(lhs) };
+			if prec <= min_prec {
+				return Ok(lhs);
+			}
+			self.pos += 1;
+			if op == BinOp::In && self.eat_keyword("super") {
+				lhs = self.make(start, ExprKind::InSuper(lhs));
+				continue;
+			}
+			let rhs = self.expr(prec)?;
+			lhs = self.make(start, ExprKind::Binary { op, lhs, rhs });
+		}
+	}
+
+	fn binary_op(&self) -> Option<(BinOp, u8)> {
+		Some(match self.peek() {
+			Token::Symbol(s) => match *s {
+				"*" => (BinOp::Mul, 10),
+				"/" => (BinOp::Div, 10),
+				"%" => (BinOp::Mod, 10),
+				"+" => (BinOp::Add, 9),
+				"-" => (BinOp::Sub, 9),
+				"<<" => (BinOp::Shl, 8),
+				">>" => (BinOp::Shr, 8),
+				"<" => (BinOp::Lt, 7),
+				"<=" => (BinOp::Le, 7),
+				">" => (BinOp::Gt, 7),
+				">=" => (BinOp::Ge, 7),
+				"==" => (BinOp::Eq, 6),
+				"!=" => (BinOp::Ne, 6),
+				"&" => (BinOp::BitAnd, 5),
+				"^" => (BinOp::BitXor, 4),
+				"|" => (BinOp::BitOr, 3),
+				"&&" => (BinOp::And, 2),
+				"||" => (BinOp::Or, 1),
+				_ => return None,
+			},
+			Token::Ident(s) if &**s == "in" => (BinOp::In, 7),
+			_ => return None,
+		})
+	}
+
+	fn unary(&mut self) -> Result<Rc<Expr>> {
+		let start = self.start();
+		let op = match self.peek() {
+			Token::Symbol("-") => UnOp::Neg,
+			Token::Symbol("+") => UnOp::Plus,
+			Token::Symbol("!") => UnOp::Not,
+			Token::Symbol("~") => UnOp::BitNot,
+			_ => return self.postfix(),
+		};
+		self.pos += 1;
+		let expr = self.unary()?;
+		Ok(self.make(start, ExprKind::Unary { op, expr }))
+	}
+
+	fn postfix(&mut self) -> Result<Rc<Expr>> {
+		let start = self.start();
+		let mut expr = self.primary()?;
+		loop {
+			if self.eat_symbol(".") {
+				let name_start = self.start();
+				let name = self.identifier()?;
+				let index = self.make(name_start, ExprKind::Str(name.to_string()));
+				expr = self.make(start, ExprKind::Index { target: expr, index });
+			} else if self.eat_symbol("[") {
+				expr = self.index_or_slice(start, expr)?;
+			} else if self.eat_symbol("(") {
+				let args = self.args()?;
+				self.eat_keyword("tailstrict");
+				expr = self.make(start, ExprKind::Apply { target: expr, args });
+			} else if self.is_symbol("{") {
+				let rhs = self.object()?;
+				expr = self.make(start, ExprKind::Binary { op: BinOp::Add, lhs: expr, rhs });
+			} else {
+				return Ok(expr);
+			}
+		}
+	}
+
+	/// After `[`: `a[i]`, or `a[start:end:step]` with any of the three left out.
+	fn index_or_slice(&mut self, start: usize, target: Rc<Expr>) -> Result<Rc<Expr>> {
+		let mut parts: Vec<Option<Rc<Expr>>> = vec![None];
+		loop {
+			if self.eat_symbol("]") {
+				break;
+			} else if self.eat_symbol(":") {
+				parts.push(None);
+			} else if self.eat_symbol("::") {
+				parts.extend([None, None]);
+			} else {
+				let last = parts.last_mut().expect("never empty");
+				ensure!(last.is_none(), self.error("`:` or `]`"));
+				*last = Some(self.expr(0)?);
+			}
+		}
+		let mut parts = parts.into_iter();
+		let first = parts.next().flatten();
+		match parts.len() {
+			0 => {
+				let index = first.ok_or_else(|| self.error("an index"))?;
+				Ok(self.make(start, ExprKind::Index { target, index }))
+			}
+			1 | 2 => {
+				let (end, step) = (parts.next().flatten(), parts.next().flatten());
+				Ok(self.make(start, ExprKind::Slice { target, start: first, end, step }))
+			}
+			_ => Err(eyre!("{}: too many `:` in slice", location(self.src, start))),
+		}
+	}
+
+	/// After `(`.
+	fn args(&mut self) -> Result<Vec<Arg>> {
+		let mut args = Vec::new();
+		while !self.eat_symbol(")") {
+			let name = match (self.peek(), self.peek_at(1)) {
+				(Token::Ident(name), Token::Symbol("=")) if is_identifier(name) => {
+					let name = name.clone();
+					self.pos += 2;
+					Some(name)
+				}
+				_ => None,
+			};
+			args.push(Arg { name, value: self.expr(0)? });
+			if !self.eat_symbol(",") {
+				self.expect_symbol(")")?;
+				break;
+			}
+		}
+		Ok(args)
+	}
+
+	/// After `(`.
+	fn params(&mut self) -> Result<Rc<[Param]>> {
+		let mut params = Vec::new();
+		while !self.eat_symbol(")") {
+			let name = self.identifier()?;
+			let default = match self.eat_symbol("=") {
+				true => Some(self.expr(0)?),
+				false => None,
+			};
+			params.push(Param { name, default });
+			if !self.eat_symbol(",") {
+				self.expect_symbol(")")?;
+				break;
+			}
+		}
+		Ok(params.into())
+	}
+
+	/// `name = value` or `name(params) = body`.
+	fn bind(&mut self) -> Result<Bind> {
+		let name = self.identifier()?;
+		let start = self.start();
+		if self.eat_symbol("(") {
+			let params = self.params()?;
+			self.expect_symbol("=")?;
+			let body = self.expr(0)?;
+			return Ok(Bind {
+				name,
+				value: self.make(start, ExprKind::Function { params, body }),
+			});
+		}
+		self.expect_symbol("=")?;
+		Ok(Bind { name, value: self.expr(0)? })
+	}
+
+	fn assert(&mut self) -> Result<Assert> {
+		let cond = self.expr(0)?;
+		let message = match self.eat_symbol(":") {
+			true => Some(self.expr(0)?),
+			false => None,
+		};
+		Ok(Assert { cond, message })
+	}
+
+	fn primary(&mut self) -> Result<Rc<Expr>> {
+		let start = self.start();
+		let token = self.next();
+		let kind = match token {
+			Token::Num(n) => ExprKind::Num(n),
+			Token::Str(s) => ExprKind::Str(s),
+			Token::Symbol("(") => {
+				let expr = self.expr(0)?;
+				self.expect_symbol(")")?;
+				return Ok(expr);
+			}
+			Token::Symbol("{") => {
+				self.pos -= 1;
+				return self.object();
+			}
+			Token::Symbol("[") => self.array()?,
+			Token::Symbol("$") => ExprKind::Dollar,
+			Token::Ident(word) => match &*word {
+				"null" => ExprKind::Null,
+				"true" => ExprKind::Bool(true),
+				"false" => ExprKind::Bool(false),
+				"self" => ExprKind::SelfRef,
+				"super" => {
+					let index = match self.next() {
+						Token::Symbol(".") => {
+							let name_start = self.start();
+							let name = self.identifier()?;
+							self.make(name_start, ExprKind::Str(name.to_string()))
+						}
+						Token::Symbol("[") => {
+							let index = self.expr(0)?;
+							self.expect_symbol("]")?;
+							index
+						}
+						_ => bail!("{}: `super` can only be indexed", location(self.src, start)),
+					};
+					ExprKind::SuperIndex(index)
+				}
+				"local" => {
+					let mut binds = vec![self.bind()?];
+					while self.eat_symbol(",") {
+						binds.push(self.bind()?);
+					}
+					self.expect_symbol(";")?;
+					ExprKind::Local { binds, body: self.expr(0)? }
+				}
+				"if" => {
+					let cond = self.expr(0)?;
+					self.expect_keyword("then")?;
+					let then = self.expr(0)?;
+					let otherwise = match self.eat_keyword("else") {
+						true => Some(self.expr(0)?),
+						false => None,
+					};
+					ExprKind::If { cond, then, otherwise }
+				}
+				"function" => {
+					self.expect_symbol("(")?;
+					let params = self.params()?;
+					ExprKind::Function { params, body: self.expr(0)? }
+				}
+				"assert" => {
+					let assert = self.assert()?;
+					self.expect_symbol(";")?;
+					ExprKind::Assert { assert, body: self.expr(0)? }
+				}
+				"error" => ExprKind::Error(self.expr(0)?),
+				"import" | "importstr" => {
+					let Token::Str(path) = self.next() else {
+						bail!("{}: `{word}` takes a string literal", location(self.src, start));
+					};
+					ExprKind::Import { path, raw: &*word == "importstr" }
+				}
+				_ if is_identifier(&word) => ExprKind::Var(word),
+				_ => bail!("{}: unexpected `{word}`", location(self.src, start)),
+			},
+			token => {
+				self.pos -= 1;
+				bail!("{}: unexpected {token}", location(self.src, start))
+			}
+		};
+		Ok(self.make(start, kind))
+	}
+
+	/// After `[`.
+	fn array(&mut self) -> Result<ExprKind> {
+		let mut items = Vec::new();
+		while !self.eat_symbol("]") {
+			items.push(self.expr(0)?);
+			if self.is_keyword("for") && items.len() == 1 {
+				let specs = self.comp_specs()?;
+				self.expect_symbol("]")?;
+				return Ok(ExprKind::ArrayComp {
+					body: items.pop().expect("one item"),
+					specs,
+				});
+			}
+			if !self.eat_symbol(",") {
+				self.expect_symbol("]")?;
+				break;
+			}
+		}
+		Ok(ExprKind::Array(items))
+	}
+
+	/// `for x in xs` followed by more `for`s and `if`s.
+	fn comp_specs(&mut self) -> Result<Vec<CompSpec>> {
+		let mut specs = Vec::new();
+		loop {
+			if self.eat_keyword("for") {
+				let name = self.identifier()?;
+				self.expect_keyword("in")?;
+				specs.push(CompSpec::For(name, self.expr(0)?));
+			} else if self.eat_keyword("if") {
+				specs.push(CompSpec::If(self.expr(0)?));
+			} else {
+				return Ok(specs);
+			}
+		}
+	}
+
+	fn object(&mut self) -> Result<Rc<Expr>> {
+		let start = self.start();
+		self.expect_symbol("{")?;
+		let mut members = Vec::new();
+		while !self.eat_symbol("}") {
+			if self.is_keyword("for") {
+				return self.object_comp(start, members);
+			}
+			members.push(self.member()?);
+			if self.is_keyword("for") {
+				return self.object_comp(start, members);
+			}
+			if !self.eat_symbol(",") {
+				self.expect_symbol("}")?;
+				break;
+			}
+		}
+		Ok(self.make(start, ExprKind::Object(members)))
+	}
+
+	fn object_comp(&mut self, start: usize, members: Vec<Member>) -> Result<Rc<Expr>> {
+		let mut locals = Vec::new();
+		let mut field = None;
+		for member in members {
+			match member {
+				Member::Local(bind) => locals.push(bind),
+				Member::Field(Field {
+					name: FieldName::Computed(key),
+					plus: false,
+					value,
+					..
+				}) if field.is_none() => field = Some((key, value)),
+				_ => bail!("{}: an object comprehension holds exactly one `[key]: value` field, and locals", location(self.src, start)),
+			}
+		}
+		let (key, value) = field.ok_or_else(|| eyre!("{}: object comprehension without a field", location(self.src, start)))?;
+		let specs = self.comp_specs()?;
+		self.expect_symbol("}")?;
+		Ok(self.make(start, ExprKind::ObjectComp { locals, key, value, specs }))
+	}
+
+	fn member(&mut self) -> Result<Member> {
+		if self.eat_keyword("local") {
+			return Ok(Member::Local(self.bind()?));
+		}
+		if self.eat_keyword("assert") {
+			return Ok(Member::Assert(self.assert()?));
+		}
+		let name_start = self.start();
+		let name = match self.next() {
+			Token::Ident(name) => FieldName::Fixed(name),
+			Token::Str(s) => FieldName::Fixed(s.into()),
+			Token::Symbol("[") => {
+				let key = self.expr(0)?;
+				self.expect_symbol("]")?;
+				FieldName::Computed(key)
+			}
+			_ => {
+				self.pos -= 1;
+				return Err(self.error("a field name"));
+			}
+		};
+		let name_span = name_start..self.end();
+		let method = match self.eat_symbol("(") {
+			true => Some(self.params()?),
+			false => None,
+		};
+		let plus = self.eat_symbol("+");
+		let visibility = match self.next() {
+			Token::Symbol(":") => Visibility::Default,
+			Token::Symbol("::") => Visibility::Hidden,
+			Token::Symbol(":::") => Visibility::Forced,
+			_ => {
+				self.pos -= 1;
+				return Err(self.error("`:`, `::` or `:::`"));
+			}
+		};
+		let value_start = self.start();
+		let mut value = self.expr(0)?;
+		if let Some(params) = method {
+			value = self.make(value_start, ExprKind::Function { params, body: value });
+		}
+		Ok(Member::Field(Field {
+			name,
+			name_span,
+			plus,
+			visibility,
+			value,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn strings_and_blocks() {
+		let expr = parse("[ 'a\\'\\u00e9', @\"x\"\"y\", |||\n  one\n    two\n|||, |||-\n  z\n|||]").unwrap();
+		let ExprKind::Array(items) = &expr.kind else { panic!("{expr:?}") };
+		let strings: Vec<&str> = items
+			.iter()
+			.map(|item| match &item.kind {
+				ExprKind::Str(s) => s.as_str(),
+				other => panic!("{other:?}"),
+			})
+			.collect();
+		assert_eq!(strings, ["a'é", "x\"y", "one\n  two\n", "z"]);
+	}
+
+	#[test]
+	fn errors_point_at_the_source() {
+		let e = parse("{\n  a: 1,\n  b: ,\n}").unwrap_err();
+		assert_eq!(e.to_string(), "3:6: unexpected `,`");
+		assert!(parse("local x = 1; x +").is_err());
+	}
+}
@@ -0,0 +1,585 @@
+//! Nix backend. Reading evaluates the file with `nix eval`, under a timeout and, in pure mode, with results cached by file contents; writing edits the syntax tree, so only attribute literals at the edited paths change, and anything computed (`builtins.getEnv`, `let` bindings, imports, interpolations) is left alone and refused as an edit target.
+use std::{
+	collections::HashMap,
+	hash::{DefaultHasher, Hash as _, Hasher as _},
+	io::Read,
+	path::{Path, PathBuf},
+	process::{Command, Output, Stdio},
+	sync::{LazyLock, Mutex},
+	thread::JoinHandle,
+	time::{Duration, Instant},
+};
+
+use rnix::ast::{self, HasEntry as _, InterpolPart, UnaryOpKind};
+use serde_json::Value as JsonValue;
+use v_utils::prelude::*;
+
+use super::{
+	Capabilities, FormatBackend, Options, extension,
+	splice::{self, Dialect, Edit, Entry, Node, NodeKind, eol, line_end, line_indent, line_start},
+};
+
+/// Values are read by evaluating the file rather than parsing it; `content` only keys the cache of evaluations.
+#[derive(Clone, Copy, Debug)]
+pub struct Backend;
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		"Nix"
+	}
+
+	fn id(&self) -> &'static str {
+		"nix"
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		extension(path) == "nix"
+	}
+
+	/// Only what evaluates to an attribute set at a glance; a bare word is a valid Nix expression too.
+	fn sniff(&self, content: &str) -> bool {
+		let parse = ast::Root::parse(content);
+		parse.errors().is_empty() && matches!(parse.tree().expr(), Some(ast::Expr::AttrSet(_) | ast::Expr::LetIn(_)))
+	}
+
+	fn parse(&self, path: &Path, content: &str, options: &Options) -> Result<JsonValue> {
+		let value = eval(path, content, options)?;
+		remember(path, content, &value);
+		Ok(value)
+	}
+
+	fn serialize(&self, path: &Path, original: Option<&str>, value: &JsonValue, options: &Options) -> Result<String> {
+		let old = match original {
+			Some(original) => evaluated(path, original, options)?,
+			None => JsonValue::Null,
+		};
+		let content = serialize(original, &old, value)?;
+		check(path, &content, value, options)?;
+		remember(path, &content, value);
+		Ok(content)
+	}
+
+	fn language(&self) -> &'static str {
+		"nix"
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities { null: true, comments: true }
+	}
+}
+
+/// How long an evaluation may take when [`Options::eval_timeout`] isn't set.
+pub const DEFAULT_EVAL_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a running evaluation is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Evaluations remembered before the cache starts over.
+const CACHE_SIZE: usize = 64;
+
+/// Directory the file was evaluated in, and hash of its contents.
+type CacheKey = (PathBuf, u64);
+/// Values of files evaluated in pure mode, which evaluate to the same thing for as long as their contents stay the same. Impure evaluations may depend on the environment or on files imported from elsewhere, so they aren't cached.
+static CACHE: LazyLock<Mutex<HashMap<CacheKey, JsonValue>>> = LazyLock::new(Mutex::default);
+/// What each target last evaluated to, and the hash of the contents it was evaluated from.
+///
+/// Kept for impure evaluations too, unlike [`CACHE`]: edits are only diffed against it, and [`check`] evaluates whatever comes out of that, so a stale value can fail an edit but not get a wrong one written.
+static EVALUATED: LazyLock<Mutex<HashMap<PathBuf, (u64, JsonValue)>>> = LazyLock::new(Mutex::default);
+
+/// Evaluate the Nix file at `path`, which holds `content`, and return its value.
+///
+/// Blocks for as long as the evaluation takes, up to the configured timeout.
+pub fn eval(path: &Path, content: &str, options: &Options) -> Result<JsonValue> {
+	let key = (path.parent().unwrap_or(Path::new("")).to_owned(), hash(content));
+	if options.pure_eval {
+		if let Some(value) = CACHE.lock().unwrap().get(&key) {
+			return Ok(value.clone());
+		}
+	}
+
+	let mut command = Command::new("nix");
+	command.arg("eval").arg("--json");
+	match options.pure_eval {
+		// pure evaluation only reads files it is pointed at directly, rather than through an `import` expression
+		true => command.arg("--pure-eval").arg("--file").arg(path),
+		false => command.arg("--impure").arg("--expr").arg(format!("import {}", path.display())),
+	};
+	let output = run(command, options.eval_timeout.unwrap_or(DEFAULT_EVAL_TIMEOUT))?;
+	if !output.status.success() {
+		bail!("Nix evaluation failed: {}", readable(&String::from_utf8_lossy(&output.stderr)));
+	}
+	let json_str = String::from_utf8(output.stdout).context("Nix output is not valid UTF-8")?;
+	let value: JsonValue = serde_json::from_str(&json_str).context("Failed to parse Nix output as JSON")?;
+
+	if options.pure_eval {
+		let mut cache = CACHE.lock().unwrap();
+		if cache.len() >= CACHE_SIZE {
+			cache.clear();
+		}
+		cache.insert(key, value.clone());
+	}
+	Ok(value)
+}
+
+/// Note that `content` at `path` evaluated to `value`.
+fn remember(path: &Path, content: &str, value: &JsonValue) {
+	EVALUATED.lock().unwrap().insert(path.to_owned(), (hash(content), value.clone()));
+}
+
+/// What `content` at `path` evaluates to, going by the last evaluation of the file when that was of the same contents.
+fn evaluated(path: &Path, content: &str, options: &Options) -> Result<JsonValue> {
+	let last = EVALUATED.lock().unwrap().get(path).filter(|(h, _)| *h == hash(content)).map(|(_, value)| value.clone());
+	match last {
+		Some(value) => Ok(value),
+		None => eval(path, content, options),
+	}
+}
+
+fn hash(content: &str) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	content.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Run `command` to completion, killing it once `timeout` has passed.
+fn run(mut command: Command, timeout: Duration) -> Result<Output> {
+	let mut child = command
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.context("Failed to execute nix command. Is nix installed?")?;
+	// drained while waiting, so that a chatty evaluation can't stall on a full pipe
+	let stdout = drain(child.stdout.take());
+	let stderr = drain(child.stderr.take());
+	let deadline = Instant::now() + timeout;
+	let status = loop {
+		if let Some(status) = child.try_wait()? {
+			break status;
+		}
+		if Instant::now() >= deadline {
+			let _ = child.kill();
+			let _ = child.wait();
+			bail!("Nix evaluation took longer than {timeout:?}, so it was stopped");
+		}
+		std::thread::sleep(POLL_INTERVAL);
+	};
+	Ok(Output {
+		status,
+		stdout: stdout.join().unwrap_or_default(),
+		stderr: stderr.join().unwrap_or_default(),
+	})
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+	std::thread::spawn(move || {
+		let mut out = Vec::new();
+		if let Some(mut pipe) = pipe {
+			let _ = pipe.read_to_end(&mut out);
+		}
+		out
+	})
+}
+
+/// The gist of a Nix error: its innermost message and where it happened, without colors or the trace leading up to it.
+fn readable(stderr: &str) -> String {
+	let plain = strip_ansi(stderr);
+	let lines: Vec<&str> = plain.lines().map(str::trim).filter(|l| !l.starts_with("warning:")).collect();
+	let Some(start) = lines.iter().rposition(|l| l.starts_with("error:")) else {
+		return plain.trim().to_owned();
+	};
+	// a bare `error:` heads the trace, the message is the last one with text after it
+	let mut message: Vec<&str> = vec![lines[start].trim_start_matches("error:").trim()];
+	message.extend(lines[start + 1..].iter().take_while(|l| !l.is_empty() && !l.starts_with('…') && !l.starts_with("at ")));
+	let message = message.into_iter().filter(|l| !l.is_empty()).collect::<Vec<_>>().join(" ");
+	let at = |l: &&str| l.strip_prefix("at ").map(|at| at.trim_end_matches(':').to_owned());
+	// the message's own location follows it; failing that, the innermost one of the trace before it
+	let location = lines[start + 1..].iter().find_map(at).or_else(|| lines[..start].iter().rev().find_map(at));
+	match (message.is_empty(), location) {
+		(true, _) => plain.trim().to_owned(),
+		(false, Some(location)) => format!("{message} (at {location})"),
+		(false, None) => message,
+	}
+}
+
+/// `s` without terminal color codes.
+fn strip_ansi(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut chars = s.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\u{1b}' => {
+				// CSI sequences end at their first letter
+				if chars.next() == Some('[') {
+					for c in chars.by_ref() {
+						if c.is_ascii_alphabetic() {
+							break;
+						}
+					}
+				}
+			}
+			c => out.push(c),
+		}
+	}
+	out
+}
+
+/// Make sure `content`, if written at `path`, evaluates to `expected`.
+///
+/// Evaluated from a sibling file, so relative imports resolve the same way they do for the real one; the file is gone again once this returns, or unwinds.
+pub fn check(path: &Path, content: &str, expected: &JsonValue, options: &Options) -> Result<()> {
+	let file_name = path.file_name().ok_or_eyre("Target path has no file name")?.to_string_lossy();
+	let probe = Probe(path.with_file_name(format!(".{file_name}.{}.check.nix", std::process::id())));
+	std::fs::write(&probe.0, content).context("Failed to write the Nix file to check")?;
+	if eval(&probe.0, content, options)? != *expected {
+		bail!("The written Nix file wouldn't evaluate to the edited value, so the write was refused");
+	}
+	Ok(())
+}
+
+/// A file that is removed when this is dropped.
+struct Probe(PathBuf);
+impl Drop for Probe {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.0);
+	}
+}
+
+/// Serialize `value`, editing only the literals of `original` (which evaluates to `old`) that changed.
+pub fn serialize(original: Option<&str>, old: &JsonValue, value: &JsonValue) -> Result<String> {
+	let Some(src) = original.filter(|s| !s.trim().is_empty()) else {
+		return Ok(to_string(value, "") + "\n");
+	};
+	let root = layout(src)?;
+	splice::patch(&NixDialect, src, &root, old, value)
+}
+
+/// Convert JSON value to Nix syntax; nested lines are prefixed with `indent`.
+fn to_string(json: &JsonValue, indent: &str) -> String {
+	let inner_indent = format!("{indent}  ");
+
+	match json {
+		JsonValue::Null => "null".to_string(),
+		JsonValue::Bool(b) => if *b { "true" } else { "false" }.to_string(),
+		JsonValue::Number(n) => n.to_string(),
+		JsonValue::String(s) => string(s),
+		JsonValue::Array(arr) =>
+			if arr.is_empty() {
+				"[]".to_string()
+			} else {
+				let items: Vec<String> = arr.iter().map(|v| format!("{}{}", inner_indent, to_string(v, &inner_indent))).collect();
+				format!("[\n{}\n{}]", items.join("\n"), indent)
+			},
+		JsonValue::Object(obj) =>
+			if obj.is_empty() {
+				"{}".to_string()
+			} else {
+				let items: Vec<String> = obj.iter().map(|(k, v)| format!("{}{} = {};", inner_indent, key(k), to_string(v, &inner_indent))).collect();
+				format!("{{\n{}\n{}}}", items.join("\n"), indent)
+			},
+	}
+}
+
+/// Attribute name, quoted unless it is a plain identifier.
+fn key(key: &str) -> String {
+	let mut chars = key.chars();
+	let is_identifier = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''));
+	match is_identifier && !KEYWORDS.contains(&key) {
+		true => key.to_owned(),
+		false => string(key),
+	}
+}
+const KEYWORDS: [&str; 10] = ["assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with"];
+
+/// Double-quoted string literal; `${` is escaped so it isn't read as an interpolation.
+fn string(s: &str) -> String {
+	let escaped = s
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace("${", "\\${")
+		.replace('\n', "\\n")
+		.replace('\r', "\\r")
+		.replace('\t', "\\t");
+	format!("\"{escaped}\"")
+}
+
+#[derive(Clone, Debug, Default)]
+struct Meta {
+	/// For attribute values, the whole `path = value;` binding.
+	binding: Option<std::ops::Range<usize>>,
+}
+
+fn layout(src: &str) -> Result<Node<Meta>> {
+	let parse = ast::Root::parse(src);
+	if let Some(e) = parse.errors().first() {
+		bail!("Failed to parse Nix file: {e}");
+	}
+	let expr = parse.tree().expr().ok_or_eyre("Nix file is empty")?;
+	Ok(node(expr))
+}
+
+fn span(node: &impl ast::AstNode) -> std::ops::Range<usize> {
+	let range = node.syntax().text_range();
+	range.start().into()..range.end().into()
+}
+
+/// Attribute sets and lists become containers, plain literals leaves, and anything else is opaque.
+fn node(expr: ast::Expr) -> Node<Meta> {
+	let kind = match &expr {
+		// the value of these is their body, which is where edits go
+		ast::Expr::Paren(e) => return e.expr().map(node).unwrap_or_else(|| opaque(&expr)),
+		ast::Expr::LetIn(e) => return e.body().map(node).unwrap_or_else(|| opaque(&expr)),
+		ast::Expr::With(e) => return e.body().map(node).unwrap_or_else(|| opaque(&expr)),
+		ast::Expr::Assert(e) => return e.body().map(node).unwrap_or_else(|| opaque(&expr)),
+
+		ast::Expr::AttrSet(set) => NodeKind::Map(
+			set.attrpath_values()
+				.filter_map(|binding| {
+					let mut attrs = binding.attrpath()?.attrs();
+					let (attr, None) = (attrs.next()?, attrs.next()) else {
+						// `a.b = ...` only spells out part of `a`
+						return None;
+					};
+					let mut value = node(binding.value()?);
+					value.meta.binding = Some(span(&binding));
+					Some(Entry {
+						key: attr_name(&attr)?,
+						key_span: span(&attr),
+						value,
+					})
+				})
+				.collect(),
+		),
+		ast::Expr::List(list) => NodeKind::Seq(list.items().map(node).collect()),
+		ast::Expr::Literal(_) => NodeKind::Leaf,
+		ast::Expr::Str(s) if s.parts().all(|p| matches!(p, InterpolPart::Literal(_))) => NodeKind::Leaf,
+		ast::Expr::Ident(i) if matches!(i.to_string().as_str(), "true" | "false" | "null") => NodeKind::Leaf,
+		ast::Expr::UnaryOp(op) if op.operator() == Some(UnaryOpKind::Negate) && matches!(op.expr(), Some(ast::Expr::Literal(_))) => NodeKind::Leaf,
+		_ => NodeKind::Opaque,
+	};
+	Node {
+		span: span(&expr),
+		kind,
+		meta: Meta::default(),
+	}
+}
+
+fn opaque(expr: &ast::Expr) -> Node<Meta> {
+	Node {
+		span: span(expr),
+		kind: NodeKind::Opaque,
+		meta: Meta::default(),
+	}
+}
+
+/// Name of a statically known attribute; `None` for `${...}` ones.
+fn attr_name(attr: &ast::Attr) -> Option<String> {
+	match attr {
+		ast::Attr::Ident(i) => Some(i.to_string()),
+		ast::Attr::Str(s) => s
+			.normalized_parts()
+			.into_iter()
+			.map(|p| match p {
+				InterpolPart::Literal(l) => Some(l),
+				InterpolPart::Interpolation(_) => None,
+			})
+			.collect(),
+		ast::Attr::Dynamic(_) => None,
+	}
+}
+
+struct NixDialect;
+
+impl NixDialect {
+	/// `text` is the binding or list item as it should appear.
+	fn insert_after(src: &str, container: &Node<Meta>, last: std::ops::Range<usize>, first_start: usize, text: &str) -> Edit {
+		let close = container.span.end - 1;
+		match src[container.span.clone()].contains('\n') && src[last.end..close].contains('\n') {
+			true => Edit::insert(eol(src, last.end), format!("\n{}{text}", line_indent(src, first_start))),
+			false => Edit::insert(last.end, format!(" {text}")),
+		}
+	}
+
+	/// Removes `range`, along with its line if nothing else is on it.
+	fn remove(src: &str, range: std::ops::Range<usize>) -> Edit {
+		let before = &src[line_start(src, range.start)..range.start];
+		let after = &src[range.end..eol(src, range.end)];
+		match before.trim().is_empty() && (after.trim().is_empty() || after.trim_start().starts_with('#')) {
+			true => Edit::replace(line_start(src, range.start)..line_end(src, range.end), ""),
+			false => Edit::replace(range.start..range.end + (after.len() - after.trim_start().len()), ""),
+		}
+	}
+}
+
+impl Dialect for NixDialect {
+	type Meta = Meta;
+
+	fn replace(&self, src: &str, node: &Node<Meta>, value: &JsonValue, _path: &str) -> Result<Edit> {
+		Ok(Edit::replace(node.span.clone(), to_string(value, line_indent(src, node.span.start))))
+	}
+
+	fn insert_entry(&self, src: &str, map: &Node<Meta>, key: &str, value: &JsonValue, path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let (Some(first), Some(last)) = (entries.first(), entries.last().and_then(|e| e.value.meta.binding.clone())) else {
+			bail!("Can't add `{path}`: its attribute set has no plain `name = value;` bindings to put it next to");
+		};
+		let indent = line_indent(src, first.key_span.start);
+		let text = format!("{} = {};", self::key(key), to_string(value, indent));
+		Ok(vec![Self::insert_after(src, map, last, first.key_span.start, &text)])
+	}
+
+	fn remove_entry(&self, src: &str, map: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let binding = entries[idx].value.meta.binding.clone().expect("attribute values record their binding");
+		Ok(vec![Self::remove(src, binding)])
+	}
+
+	fn rename_key(&self, _src: &str, entry: &Entry<Meta>, new_key: &str) -> Result<Edit> {
+		Ok(Edit::replace(entry.key_span.clone(), key(new_key)))
+	}
+
+	fn insert_item(&self, src: &str, seq: &Node<Meta>, idx: usize, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let multiline = src[seq.span.clone()].contains('\n');
+		let indent = line_indent(src, items[0].span.start);
+		let text = to_string(value, indent);
+		let edit = match items.get(idx) {
+			Some(next) if multiline => Edit::insert(next.span.start, format!("{text}\n{indent}")),
+			Some(next) => Edit::insert(next.span.start, format!("{text} ")),
+			None => Self::insert_after(src, seq, items[items.len() - 1].span.clone(), items[0].span.start, &text),
+		};
+		Ok(vec![edit])
+	}
+
+	fn remove_item(&self, src: &str, seq: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		Ok(vec![Self::remove(src, items[idx].span.clone())])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const SOURCE: &str = r#"# bot config
+let
+  port = 8080;
+in
+{
+  tg_token = builtins.getEnv "TG_TOKEN_TEST"; # secret
+  name = "svc";
+  server = {
+    inherit port;
+    host = "localhost";
+  };
+  tags = [ "a" "b" ];
+}
+"#;
+
+	fn old() -> JsonValue {
+		json!({
+			"tg_token": "hunter2",
+			"name": "svc",
+			"server": { "port": 8080, "host": "localhost" },
+			"tags": ["a", "b"],
+		})
+	}
+
+	fn edited(f: impl FnOnce(&mut JsonValue)) -> Result<String> {
+		let mut value = old();
+		f(&mut value);
+		serialize(Some(SOURCE), &old(), &value)
+	}
+
+	#[test]
+	fn literal_edits_keep_expressions() {
+		let out = edited(|v| {
+			v["name"] = json!("api");
+			v["server"]["tls"] = json!(true);
+			v["tags"].as_array_mut().unwrap().push(json!("c"));
+		})
+		.unwrap();
+		insta::assert_snapshot!(out, @r#"
+		# bot config
+		let
+		  port = 8080;
+		in
+		{
+		  tg_token = builtins.getEnv "TG_TOKEN_TEST"; # secret
+		  name = "api";
+		  server = {
+		    inherit port;
+		    host = "localhost";
+		    tls = true;
+		  };
+		  tags = [ "a" "b" "c" ];
+		}
+		"#);
+	}
+
+	#[test]
+	fn removing_attribute_drops_its_line() {
+		let out = edited(|v| _ = v.as_object_mut().unwrap().remove("name")).unwrap();
+		assert!(!out.contains("name"));
+		assert_eq!(out.lines().count(), SOURCE.lines().count() - 1);
+	}
+
+	#[test]
+	fn keys_and_strings_are_escaped() {
+		let value = json!({"plain-key": "a", "with.dot": "${HOME}\n", "1st": "\"q\" \\", "in": 1});
+		insta::assert_snapshot!(to_string(&value, ""), @r#"
+		{
+		  plain-key = "a";
+		  "with.dot" = "\${HOME}\n";
+		  "1st" = "\"q\" \\";
+		  "in" = 1;
+		}
+		"#);
+	}
+
+	#[test]
+	fn edits_are_diffed_against_the_last_evaluation() {
+		let path = Path::new("/nonexistent/remembered.nix");
+		remember(path, SOURCE, &old());
+		// no `nix` involved: the file's contents haven't changed since it was last evaluated
+		assert_eq!(evaluated(path, SOURCE, &Options::default()).unwrap(), old());
+	}
+
+	#[test]
+	fn checks_leave_nothing_behind() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("bot.nix");
+		// whether or not `nix` is around to evaluate it
+		let _ = check(&path, "{ a = 1; }", &json!({"a": 1}), &Options::default());
+		assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+	}
+
+	#[test]
+	fn slow_commands_are_stopped() {
+		let started = Instant::now();
+		let mut command = Command::new("sleep");
+		command.arg("5");
+		let e = run(command, Duration::from_millis(100)).unwrap_err();
+		assert_eq!(e.to_string(), "Nix evaluation took longer than 100ms, so it was stopped");
+		assert!(started.elapsed() < Duration::from_secs(2));
+	}
+
+	#[test]
+	fn errors_are_boiled_down() {
+		let stderr = "warning: Git tree '/etc/nixos' is dirty\n\u{1b}[31;1merror:\u{1b}[0m\n       … while evaluating the attribute 'port'\n         at /srv/bot.nix:3:3:\n            2|   name = \"svc\";\n            3|   port = builtins.fromJSON \"x\";\n             |   ^\n\n       \u{1b}[31;1merror:\u{1b}[0m syntax error, unexpected end of input\n";
+		assert_eq!(readable(stderr), "syntax error, unexpected end of input (at /srv/bot.nix:3:3)");
+		assert_eq!(
+			readable("error: file 'nixpkgs' was not found in the Nix search path\n"),
+			"file 'nixpkgs' was not found in the Nix search path"
+		);
+		let stderr = "error: undefined variable 'prot'\n       at /srv/bot.nix:4:10:\n            3|   host = \"localhost\";\n            4|   port = prot;\n             |          ^\n";
+		assert_eq!(readable(stderr), "undefined variable 'prot' (at /srv/bot.nix:4:10)");
+	}
+
+	#[test]
+	fn computed_values_are_refused() {
+		let e = edited(|v| v["tg_token"] = json!("leaked")).unwrap_err();
+		assert!(e.to_string().contains("`/tg_token` is computed"), "{e}");
+		let e = edited(|v| v["server"]["port"] = json!(1)).unwrap_err();
+		assert!(e.to_string().contains("/server/port"), "{e}");
+	}
+}
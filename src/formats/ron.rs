@@ -0,0 +1,841 @@
+//! RON backend. Existing files are patched in place like JSON5 ones, so comments and layout of untouched values are kept.
+//!
+//! RON has more shapes than JSON, and each one is read as the closest JSON value while its spelling stays in the file:
+//! - structs, named (`Config(port: 80)`) or not (`(port: 80)`), and maps are objects; struct fields stay bare identifiers and map keys stay quoted;
+//! - tuples and tuple structs (`Point(1, 2)`) are arrays, like lists;
+//! - `Some(x)` and newtypes (`Port(80)`) are just `x`; `None` and `()` are null. Editing `x` keeps the wrapper, setting a `None` writes `Some(..)` around the new value, and setting an `Option` to null writes `None`;
+//! - unit enum variants (`Mode: Fast`) are strings, and can only be set to another identifier, which is written bare;
+//! - chars are one-character strings, and floats stay floats when set to a whole number.
+//!
+//! Newly added objects are written as unnamed structs when all their keys are identifiers, and as maps otherwise.
+use std::{ops::Range, path::Path};
+
+use color_eyre::eyre::ensure;
+use serde_json::{Map, Value as JsonValue};
+use v_utils::prelude::*;
+
+use super::{
+	Capabilities, FormatBackend, Options, extension,
+	splice::{self, Dialect, Edit, Entry, Node, NodeKind, Separated, line_end, line_indent},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backend;
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		"RON"
+	}
+
+	fn id(&self) -> &'static str {
+		"ron"
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		extension(path) == "ron"
+	}
+
+	/// Only structs, tuples and lists; bare scalars are valid RON too.
+	fn sniff(&self, content: &str) -> bool {
+		Parser::new(content).document().is_ok_and(|(root, _)| matches!(root.kind, NodeKind::Map(_) | NodeKind::Seq(_)))
+	}
+
+	fn parse(&self, _path: &Path, content: &str, _options: &Options) -> Result<JsonValue> {
+		parse(content)
+	}
+
+	fn serialize(&self, _path: &Path, original: Option<&str>, value: &JsonValue, options: &Options) -> Result<String> {
+		serialize(original, value, options.indent.as_deref())
+	}
+
+	fn language(&self) -> &'static str {
+		"rust"
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities { null: true, comments: true }
+	}
+}
+
+pub fn parse(content: &str) -> Result<JsonValue> {
+	Parser::new(content).document().map(|(_, value)| value).context("Failed to read RON file")
+}
+
+/// Serialize `value`, reusing the layout of `original` for everything that did not change.
+///
+/// Newly written nodes are indented by `indent`, or by whatever the original file uses if that is not set.
+pub fn serialize(original: Option<&str>, value: &JsonValue, indent: Option<&str>) -> Result<String> {
+	let dialect = RonDialect {
+		unit: indent.map(str::to_owned).unwrap_or_else(|| original.map(detect_indent).unwrap_or_else(|| "    ".to_owned())),
+	};
+	let Some(src) = original.filter(|s| !s.trim().is_empty()) else {
+		return Ok(dialect.render(value, "") + "\n");
+	};
+	let (root, old) = Parser::new(src).document().context("Failed to read RON file")?;
+	let out = splice::patch(&dialect, src, &root, &old, value)?;
+	if !splice::same(&parse(&out)?, value) {
+		bail!("Failed to write RON file: the patched file doesn't read back as the edited value");
+	}
+	Ok(out)
+}
+
+/// The indentation of the first indented line, or four spaces.
+fn detect_indent(src: &str) -> String {
+	src.lines().map(|l| &l[..l.len() - l.trim_start().len()]).find(|ws| !ws.is_empty()).unwrap_or("    ").to_owned()
+}
+
+/// How a value is spelled, beyond what its JSON counterpart says.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum Style {
+	#[default]
+	Plain,
+	Float,
+	Str,
+	Char,
+	/// A unit enum variant or unit struct.
+	Variant,
+	None,
+	/// `()`.
+	Unit,
+	/// `(field: value)`, named or not.
+	Struct,
+	/// `{key: value}`.
+	Map,
+	/// `(a, b)`, named or not.
+	Tuple,
+	List,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Meta {
+	style: Style,
+	/// Name of a named struct or tuple struct.
+	name: Option<String>,
+	/// For values inside `Some(..)` or a newtype: where the value itself is, and whether it's the former.
+	wrapped: Option<(Range<usize>, bool)>,
+	/// Offset of the closing bracket of a container.
+	close: usize,
+	/// Offset of the comma following this value in its container.
+	comma: Option<usize>,
+}
+impl Separated for Meta {
+	fn comma(&self) -> Option<usize> {
+		self.comma
+	}
+}
+
+/// A struct field or map entry: its key and where that is, its node and its value.
+type Field = ((String, Range<usize>), Node<Meta>, JsonValue);
+
+struct Parser<'a> {
+	src: &'a str,
+	pos: usize,
+}
+impl<'a> Parser<'a> {
+	fn new(src: &'a str) -> Self {
+		Self { src, pos: 0 }
+	}
+
+	fn document(&mut self) -> Result<(Node<Meta>, JsonValue)> {
+		self.skip_trivia();
+		// `#![enable(implicit_some)]` and friends only change how Rust types are read from the file
+		while self.src[self.pos..].starts_with("#![") {
+			let close = self.src[self.pos..].find(']').ok_or_eyre("unterminated `#![` attribute")?;
+			self.pos += close + 1;
+			self.skip_trivia();
+		}
+		let root = self.value()?;
+		self.skip_trivia();
+		ensure!(self.pos == self.src.len(), "trailing characters at byte {}", self.pos);
+		Ok(root)
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.src[self.pos..].chars().next()
+	}
+
+	fn expect(&mut self, c: char) -> Result<()> {
+		ensure!(self.peek() == Some(c), "expected `{c}` at byte {}", self.pos);
+		self.pos += c.len_utf8();
+		Ok(())
+	}
+
+	fn skip_trivia(&mut self) {
+		loop {
+			let rest = &self.src[self.pos..];
+			let trimmed = rest.trim_start();
+			self.pos += rest.len() - trimmed.len();
+			if trimmed.starts_with("//") {
+				self.pos = line_end(self.src, self.pos);
+			} else if trimmed.starts_with("/*") {
+				self.pos = self.block_comment_end();
+			} else {
+				return;
+			}
+		}
+	}
+
+	/// Block comments nest in RON.
+	fn block_comment_end(&self) -> usize {
+		let mut depth = 0;
+		let mut i = self.pos;
+		while i < self.src.len() {
+			let rest = &self.src[i..];
+			if rest.starts_with("/*") {
+				depth += 1;
+				i += 2;
+			} else if rest.starts_with("*/") {
+				depth -= 1;
+				i += 2;
+				if depth == 0 {
+					return i;
+				}
+			} else {
+				i += rest.chars().next().map_or(1, char::len_utf8);
+			}
+		}
+		self.src.len()
+	}
+
+	/// Identifiers and numbers.
+	fn word(&mut self) -> &'a str {
+		let start = self.pos;
+		let rest = &self.src[self.pos..];
+		let raw_prefix = if rest.starts_with("r#") { 2 } else { 0 };
+		let len = rest[raw_prefix..]
+			.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '.' | '+' | '-')))
+			.unwrap_or(rest.len() - raw_prefix);
+		self.pos += raw_prefix + len;
+		&self.src[start..self.pos]
+	}
+
+	fn leaf(&self, start: usize, style: Style) -> Node<Meta> {
+		Node {
+			span: start..self.pos,
+			kind: NodeKind::Leaf,
+			meta: Meta { style, ..Meta::default() },
+		}
+	}
+
+	fn value(&mut self) -> Result<(Node<Meta>, JsonValue)> {
+		let start = self.pos;
+		match self.peek() {
+			Some('[') => self.list(),
+			Some('{') => self.map(),
+			Some('(') => self.parens(start, None),
+			Some('"') => {
+				let s = self.string()?;
+				Ok((self.leaf(start, Style::Str), JsonValue::String(s)))
+			}
+			Some('r') if self.raw_string_ahead() => {
+				let s = self.raw_string()?;
+				Ok((self.leaf(start, Style::Str), JsonValue::String(s)))
+			}
+			Some('\'') => {
+				let c = self.char()?;
+				Ok((self.leaf(start, Style::Char), JsonValue::String(c.to_string())))
+			}
+			Some(c) if c.is_ascii_digit() || matches!(c, '+' | '-' | '.') => {
+				let word = self.word();
+				let (value, style) = number(word)?;
+				Ok((self.leaf(start, style), value))
+			}
+			Some(c) if c.is_alphabetic() || c == '_' => {
+				let word = self.word();
+				let name = word.strip_prefix("r#").unwrap_or(word);
+				ensure!(is_identifier(name), "`{word}` is not a valid identifier, at byte {start}");
+				match name {
+					"true" | "false" => return Ok((self.leaf(start, Style::Plain), JsonValue::Bool(name == "true"))),
+					"None" => return Ok((self.leaf(start, Style::None), JsonValue::Null)),
+					"inf" | "NaN" => bail!("`{name}` has no JSON equivalent, at byte {start}"),
+					_ => {}
+				}
+				let after_name = self.pos;
+				self.skip_trivia();
+				match self.peek() {
+					Some('(') => self.parens(start, Some(name.to_owned())),
+					_ => {
+						self.pos = after_name;
+						Ok((self.leaf(start, Style::Variant), JsonValue::String(name.to_owned())))
+					}
+				}
+			}
+			Some(_) => bail!("unexpected character at byte {start}"),
+			None => bail!("unexpected end of input"),
+		}
+	}
+
+	fn raw_string_ahead(&self) -> bool {
+		self.src[self.pos + 1..].trim_start_matches('#').starts_with('"')
+	}
+
+	fn raw_string(&mut self) -> Result<String> {
+		self.pos += 1;
+		let hashes = self.src[self.pos..].len() - self.src[self.pos..].trim_start_matches('#').len();
+		self.pos += hashes + 1;
+		let terminator = format!("\"{}", "#".repeat(hashes));
+		let len = self.src[self.pos..].find(&terminator).ok_or_else(|| eyre!("unterminated raw string at byte {}", self.pos))?;
+		let s = self.src[self.pos..self.pos + len].to_owned();
+		self.pos += len + terminator.len();
+		Ok(s)
+	}
+
+	fn string(&mut self) -> Result<String> {
+		let start = self.pos;
+		self.expect('"')?;
+		let mut out = String::new();
+		loop {
+			match self.next_char().ok_or_else(|| eyre!("unterminated string at byte {start}"))? {
+				'"' => return Ok(out),
+				'\\' => out.push(self.escape()?),
+				c => out.push(c),
+			}
+		}
+	}
+
+	fn char(&mut self) -> Result<char> {
+		let start = self.pos;
+		self.expect('\'')?;
+		let c = match self.next_char() {
+			Some('\\') => self.escape()?,
+			Some(c) => c,
+			None => bail!("unterminated char at byte {start}"),
+		};
+		ensure!(self.next_char() == Some('\''), "a char holds exactly one character, at byte {start}");
+		Ok(c)
+	}
+
+	fn next_char(&mut self) -> Option<char> {
+		let c = self.peek()?;
+		self.pos += c.len_utf8();
+		Some(c)
+	}
+
+	/// The character an escape sequence stands for, the backslash having been read.
+	fn escape(&mut self) -> Result<char> {
+		let at = self.pos;
+		Ok(match self.next_char() {
+			Some('n') => '\n',
+			Some('r') => '\r',
+			Some('t') => '\t',
+			Some('0') => '\0',
+			Some('b') => '\u{8}',
+			Some('f') => '\u{c}',
+			Some(c @ ('\\' | '"' | '\'' | '/')) => c,
+			Some('x') => {
+				let hex = self.src.get(self.pos..self.pos + 2).ok_or_eyre("truncated `\\x` escape")?;
+				self.pos += 2;
+				u8::from_str_radix(hex, 16).ok().filter(u8::is_ascii).ok_or_else(|| eyre!("invalid `\\x` escape at byte {at}"))? as char
+			}
+			Some('u') => {
+				let hex = match self.peek() {
+					Some('{') => {
+						let close = self.src[self.pos..].find('}').ok_or_eyre("unterminated `\\u{` escape")?;
+						let hex = &self.src[self.pos + 1..self.pos + close];
+						self.pos += close + 1;
+						hex
+					}
+					_ => {
+						let hex = self.src.get(self.pos..self.pos + 4).ok_or_eyre("truncated `\\u` escape")?;
+						self.pos += 4;
+						hex
+					}
+				};
+				u32::from_str_radix(hex, 16)
+					.ok()
+					.and_then(char::from_u32)
+					.ok_or_else(|| eyre!("invalid `\\u` escape at byte {at}"))?
+			}
+			_ => bail!("invalid escape at byte {at}"),
+		})
+	}
+
+	/// Parses a trailing `,` if there is one, recording it on the value before it.
+	fn separator(&mut self, value: &mut Node<Meta>) {
+		self.skip_trivia();
+		if self.peek() == Some(',') {
+			value.meta.comma = Some(self.pos);
+			self.pos += 1;
+			self.skip_trivia();
+		}
+	}
+
+	/// Elements up to `close`, each read by `element`, which also gets to see the parser.
+	fn elements<T>(&mut self, close: char, mut element: impl FnMut(&mut Self) -> Result<(T, Node<Meta>, JsonValue)>) -> Result<Vec<(T, Node<Meta>, JsonValue)>> {
+		let mut out = Vec::new();
+		self.skip_trivia();
+		while self.peek() != Some(close) {
+			let (head, mut node, value) = element(self)?;
+			self.separator(&mut node);
+			let had_comma = node.meta.comma.is_some();
+			out.push((head, node, value));
+			if !had_comma {
+				break;
+			}
+		}
+		self.skip_trivia();
+		Ok(out)
+	}
+
+	fn container(&self, start: usize, kind: NodeKind<Meta>, style: Style, name: Option<String>) -> Node<Meta> {
+		Node {
+			span: start..self.pos,
+			kind,
+			meta: Meta {
+				style,
+				name,
+				close: self.pos - 1,
+				..Meta::default()
+			},
+		}
+	}
+
+	fn list(&mut self) -> Result<(Node<Meta>, JsonValue)> {
+		let start = self.pos;
+		self.expect('[')?;
+		let items = self.elements(']', |p| p.value().map(|(node, value)| ((), node, value)))?;
+		self.expect(']')?;
+		let (nodes, values) = items.into_iter().map(|(_, node, value)| (node, value)).unzip();
+		Ok((self.container(start, NodeKind::Seq(nodes), Style::List, None), JsonValue::Array(values)))
+	}
+
+	fn map(&mut self) -> Result<(Node<Meta>, JsonValue)> {
+		let start = self.pos;
+		self.expect('{')?;
+		let entries = self.elements('}', |p| {
+			let key_start = p.pos;
+			let (_, key) = p.value()?;
+			let key = match key {
+				JsonValue::String(s) => s,
+				key @ (JsonValue::Number(_) | JsonValue::Bool(_)) => key.to_string(),
+				_ => bail!("map keys other than strings, numbers and booleans can't be edited here, at byte {key_start}"),
+			};
+			let key_span = key_start..p.pos;
+			p.skip_trivia();
+			p.expect(':')?;
+			p.skip_trivia();
+			let (node, value) = p.value()?;
+			Ok(((key, key_span), node, value))
+		})?;
+		self.expect('}')?;
+		Ok(self.fields(start, entries, Style::Map, None))
+	}
+
+	fn fields(&self, start: usize, entries: Vec<Field>, style: Style, name: Option<String>) -> (Node<Meta>, JsonValue) {
+		let mut obj = Map::new();
+		let mut nodes = Vec::new();
+		for ((key, key_span), node, value) in entries {
+			obj.insert(key.clone(), value);
+			nodes.push(Entry { key, key_span, value: node });
+		}
+		(self.container(start, NodeKind::Map(nodes), style, name), JsonValue::Object(obj))
+	}
+
+	/// `(..)` at the current position, preceded by `name` from `start` on if there was one: a struct, a tuple, a newtype or `Some`, or `()`.
+	fn parens(&mut self, start: usize, name: Option<String>) -> Result<(Node<Meta>, JsonValue)> {
+		self.expect('(')?;
+		self.skip_trivia();
+		if self.is_field_ahead() {
+			let entries = self.elements(')', |p| {
+				let key_start = p.pos;
+				let key = p.word().trim_start_matches("r#").to_owned();
+				let key_span = key_start..p.pos;
+				p.skip_trivia();
+				p.expect(':')?;
+				p.skip_trivia();
+				let (node, value) = p.value()?;
+				Ok(((key, key_span), node, value))
+			})?;
+			self.expect(')')?;
+			return Ok(self.fields(start, entries, Style::Struct, name));
+		}
+
+		let items = self.elements(')', |p| p.value().map(|(node, value)| ((), node, value)))?;
+		self.expect(')')?;
+		match (name, items.len()) {
+			(None, 0) => Ok((self.leaf(start, Style::Unit), JsonValue::Null)),
+			(Some(name), 1) => {
+				let (_, mut node, value) = items.into_iter().next().expect("one item");
+				if node.meta.wrapped.is_some() {
+					// `Some(Some(x))` and the like: the inner wrapper has nowhere to go
+					return Ok((
+						Node {
+							span: start..self.pos,
+							kind: NodeKind::Opaque,
+							meta: Meta::default(),
+						},
+						value,
+					));
+				}
+				node.meta.wrapped = Some((node.span.clone(), name == "Some"));
+				node.meta.comma = None;
+				node.span = start..self.pos;
+				Ok((node, value))
+			}
+			(name, _) => {
+				let (nodes, values) = items.into_iter().map(|(_, node, value)| (node, value)).unzip();
+				Ok((self.container(start, NodeKind::Seq(nodes), Style::Tuple, name), JsonValue::Array(values)))
+			}
+		}
+	}
+
+	/// Whether an `identifier:` (and not a path `a::b`) comes next.
+	fn is_field_ahead(&self) -> bool {
+		let mut probe = Parser { src: self.src, pos: self.pos };
+		let word = probe.word();
+		probe.skip_trivia();
+		is_identifier(word.trim_start_matches("r#")) && probe.src[probe.pos..].starts_with(':') && !probe.src[probe.pos..].starts_with("::")
+	}
+}
+
+/// A number literal and how it's spelled.
+fn number(word: &str) -> Result<(JsonValue, Style)> {
+	let clean = word.replace('_', "");
+	let (negative, digits) = match clean.strip_prefix('-') {
+		Some(digits) => (true, digits),
+		None => (false, clean.strip_prefix('+').unwrap_or(&clean)),
+	};
+	let radix = match digits.get(..2) {
+		Some("0x") => Some(16),
+		Some("0o") => Some(8),
+		Some("0b") => Some(2),
+		_ => None,
+	};
+	let invalid = || eyre!("`{word}` is not a number");
+	if let Some(radix) = radix {
+		let n = i128::from_str_radix(&digits[2..], radix).map_err(|_| invalid())?;
+		return integer(if negative { -n } else { n }).map(|n| (n, Style::Plain)).ok_or_else(invalid);
+	}
+	if digits.contains(['.', 'e', 'E']) {
+		let f: f64 = clean.parse().map_err(|_| invalid())?;
+		let n = serde_json::Number::from_f64(f).ok_or_else(invalid)?;
+		return Ok((JsonValue::Number(n), Style::Float));
+	}
+	let n: i128 = clean.parse().map_err(|_| invalid())?;
+	integer(n).map(|n| (n, Style::Plain)).ok_or_else(invalid)
+}
+
+fn integer(n: i128) -> Option<JsonValue> {
+	match (i64::try_from(n), u64::try_from(n)) {
+		(Ok(n), _) => Some(n.into()),
+		(_, Ok(n)) => Some(n.into()),
+		_ => None,
+	}
+}
+
+struct RonDialect {
+	/// One level of indentation, as used by the file.
+	unit: String,
+}
+
+impl RonDialect {
+	/// Spell a new value.
+	fn render(&self, value: &JsonValue, indent: &str) -> String {
+		match value {
+			JsonValue::Object(obj) => match obj.keys().all(|k| is_identifier(k)) {
+				true => self.render_fields(obj, indent, "(", ")", false),
+				false => self.render_fields(obj, indent, "{", "}", true),
+			},
+			JsonValue::Array(arr) => self.render_items(arr, indent, "[", "]"),
+			_ => self.render_scalar(value),
+		}
+	}
+
+	fn render_scalar(&self, value: &JsonValue) -> String {
+		match value {
+			JsonValue::Null => "None".to_owned(),
+			JsonValue::String(s) => quote_str(s),
+			_ => value.to_string(),
+		}
+	}
+
+	fn render_fields(&self, obj: &Map<String, JsonValue>, indent: &str, open: &str, close: &str, quote_keys: bool) -> String {
+		if obj.is_empty() {
+			return format!("{open}{close}");
+		}
+		let inner = format!("{indent}{}", self.unit);
+		let entries: String = obj.iter().map(|(k, v)| format!("{inner}{}: {},\n", self.key(k, quote_keys), self.render(v, &inner))).collect();
+		format!("{open}\n{entries}{indent}{close}")
+	}
+
+	fn render_items(&self, arr: &[JsonValue], indent: &str, open: &str, close: &str) -> String {
+		if arr.is_empty() {
+			return format!("{open}{close}");
+		}
+		let inner = format!("{indent}{}", self.unit);
+		let items: String = arr.iter().map(|v| format!("{inner}{},\n", self.render(v, &inner))).collect();
+		format!("{open}\n{items}{indent}{close}")
+	}
+
+	fn key(&self, key: &str, quoted: bool) -> String {
+		match quoted {
+			true => quote_str(key),
+			false => key.to_owned(),
+		}
+	}
+
+	/// Spell `value` in place of `node`, keeping what RON-specific shape `node` has as far as `value` allows.
+	fn render_like(&self, node: &Node<Meta>, value: &JsonValue, indent: &str, path: &str) -> Result<String> {
+		let name = node.meta.name.as_deref().unwrap_or("");
+		Ok(match (node.meta.style, value) {
+			(Style::Variant, JsonValue::String(s)) => {
+				ensure!(is_identifier(s), "`{path}` is an enum variant, so it can only be set to another variant name, not `{s}`");
+				s.clone()
+			}
+			(Style::Char, JsonValue::String(s)) => {
+				let mut chars = s.chars();
+				let (Some(c), None) = (chars.next(), chars.next()) else {
+					bail!("`{path}` is a char, so it holds exactly one character");
+				};
+				quote_char(c)
+			}
+			(Style::Float, JsonValue::Number(n)) if !n.is_f64() => format!("{n}.0"),
+			(Style::None, value) if !value.is_null() => format!("Some({})", self.render(value, indent)),
+			(Style::Struct, JsonValue::Object(obj)) if obj.keys().all(|k| is_identifier(k)) => format!("{name}{}", self.render_fields(obj, indent, "(", ")", false)),
+			(Style::Map, JsonValue::Object(obj)) => self.render_fields(obj, indent, "{", "}", true),
+			// `()` would read back as null
+			(Style::Tuple, JsonValue::Array(arr)) if !name.is_empty() || !arr.is_empty() => format!("{name}{}", self.render_items(arr, indent, "(", ")")),
+			_ => self.render(value, indent),
+		})
+	}
+
+	/// Whether the keys of `map` are quoted.
+	fn quoted_keys(&self, src: &str, map: &Node<Meta>) -> bool {
+		let NodeKind::Map(entries) = &map.kind else { return true };
+		match map.meta.style {
+			Style::Struct => false,
+			_ => entries.first().map_or(true, |e| is_quoted(&src[e.key_span.clone()])),
+		}
+	}
+
+	/// Check that `key` can be spelled as a key of `map`.
+	fn key_for(&self, src: &str, map: &Node<Meta>, key: &str) -> Result<String> {
+		let quoted = self.quoted_keys(src, map);
+		match (quoted, map.meta.style) {
+			(false, Style::Struct) => ensure!(is_identifier(key), "`{key}` can't be a struct field name, which must be an identifier"),
+			// maps keyed by numbers or booleans
+			(false, _) => ensure!(
+				number(key).is_ok() || matches!(key, "true" | "false"),
+				"`{key}` isn't of the same type as the other keys of this map"
+			),
+			(true, _) => {}
+		}
+		Ok(self.key(key, quoted))
+	}
+}
+
+impl Dialect for RonDialect {
+	type Meta = Meta;
+
+	fn replace(&self, src: &str, node: &Node<Meta>, value: &JsonValue, path: &str) -> Result<Edit> {
+		let indent = line_indent(src, node.span.start);
+		Ok(match &node.meta.wrapped {
+			// `Some(x)` set to null becomes `None` rather than `Some(None)`
+			Some((_, true)) if value.is_null() => Edit::replace(node.span.clone(), "None"),
+			Some((inner, _)) => {
+				let unwrapped = Node {
+					span: inner.clone(),
+					kind: node.kind.clone(),
+					meta: Meta { wrapped: None, ..node.meta.clone() },
+				};
+				Edit::replace(inner.clone(), self.render_like(&unwrapped, value, indent, path)?)
+			}
+			None => Edit::replace(node.span.clone(), self.render_like(node, value, indent, path)?),
+		})
+	}
+
+	fn insert_entry(&self, src: &str, map: &Node<Meta>, key: &str, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let indent = line_indent(src, entries[0].key_span.start);
+		let text = format!("{}: {}", self.key_for(src, map, key)?, self.render(value, indent));
+		Ok(splice::insert_separated(src, map, map.meta.close, entries.len(), &text))
+	}
+
+	fn remove_entry(&self, src: &str, map: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, map, &[idx]))
+	}
+
+	fn remove_entries(&self, src: &str, map: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, map, indices))
+	}
+
+	fn rename_key(&self, src: &str, entry: &Entry<Meta>, new_key: &str) -> Result<Edit> {
+		let quoted = is_quoted(&src[entry.key_span.clone()]);
+		if !quoted {
+			ensure!(
+				is_identifier(new_key) || number(new_key).is_ok(),
+				"`{new_key}` can't be spelled like the key it replaces, which isn't quoted"
+			);
+		}
+		Ok(Edit::replace(entry.key_span.clone(), self.key(new_key, quoted)))
+	}
+
+	fn insert_item(&self, src: &str, seq: &Node<Meta>, idx: usize, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let text = self.render(value, line_indent(src, items[0].span.start));
+		Ok(splice::insert_separated(src, seq, seq.meta.close, idx, &text))
+	}
+
+	fn append_items(&self, src: &str, seq: &Node<Meta>, values: &[JsonValue], _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let indent = line_indent(src, items[0].span.start);
+		let texts: Vec<_> = values.iter().map(|value| self.render(value, indent)).collect();
+		Ok(splice::append_separated(src, seq, seq.meta.close, &texts))
+	}
+
+	fn remove_item(&self, src: &str, seq: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, seq, &[idx]))
+	}
+
+	fn remove_items(&self, src: &str, seq: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		Ok(splice::remove_separated(src, seq, indices))
+	}
+}
+
+/// Whether a key is spelled as a string literal, raw or not.
+fn is_quoted(spelling: &str) -> bool {
+	spelling.starts_with('"') || spelling.strip_prefix('r').is_some_and(|rest| rest.trim_start_matches('#').starts_with('"'))
+}
+
+fn is_identifier(s: &str) -> bool {
+	let mut chars = s.chars();
+	chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn escape(c: char, quote: char, out: &mut String) {
+	match c {
+		'\\' => out.push_str("\\\\"),
+		'\n' => out.push_str("\\n"),
+		'\r' => out.push_str("\\r"),
+		'\t' => out.push_str("\\t"),
+		c if c == quote => {
+			out.push('\\');
+			out.push(c);
+		}
+		c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+		c => out.push(c),
+	}
+}
+
+fn quote_str(s: &str) -> String {
+	let mut out = String::from('"');
+	s.chars().for_each(|c| escape(c, '"', &mut out));
+	out.push('"');
+	out
+}
+
+fn quote_char(c: char) -> String {
+	let mut out = String::from('\'');
+	escape(c, '\'', &mut out);
+	out.push('\'');
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const SOURCE: &str = r#"// service config
+Config(
+    name: "svc", // the name
+    mode: Fast,
+    /* connection */
+    server: Some((
+        host: "localhost",
+        port: Port(8080),
+    )),
+    origin: (0.0, 1.5),
+    fallback: None,
+    sep: ',',
+    env: {
+        "RUST_LOG": "info",
+    },
+)
+"#;
+
+	fn edited(f: impl FnOnce(&mut JsonValue)) -> String {
+		let mut value = parse(SOURCE).unwrap();
+		f(&mut value);
+		let out = serialize(Some(SOURCE), &value, None).unwrap();
+		assert!(splice::same(&parse(&out).unwrap(), &value), "{out}");
+		out
+	}
+
+	#[test]
+	fn reads_as_json() {
+		assert_eq!(
+			parse(SOURCE).unwrap(),
+			json!({
+				"name": "svc",
+				"mode": "Fast",
+				"server": {"host": "localhost", "port": 8080},
+				"origin": [0.0, 1.5],
+				"fallback": null,
+				"sep": ",",
+				"env": {"RUST_LOG": "info"},
+			})
+		);
+		assert_eq!(edited(|_| {}), SOURCE);
+	}
+
+	#[test]
+	fn edits_keep_names_wrappers_and_style() {
+		let out = edited(|v| {
+			v["mode"] = json!("Slow");
+			v["server"]["port"] = json!(9090);
+			v["server"]["tls"] = json!(true);
+			v["origin"][0] = json!(2);
+			v["fallback"] = json!({"host": "backup"});
+			v["sep"] = json!(";");
+			v["env"]["NO_COLOR"] = json!("1");
+		});
+		insta::assert_snapshot!(out, @r#"
+		// service config
+		Config(
+		    name: "svc", // the name
+		    mode: Slow,
+		    /* connection */
+		    server: Some((
+		        host: "localhost",
+		        port: Port(9090),
+		        tls: true,
+		    )),
+		    origin: (2.0, 1.5),
+		    fallback: Some((
+		        host: "backup",
+		    )),
+		    sep: ';',
+		    env: {
+		        "RUST_LOG": "info",
+		        "NO_COLOR": "1",
+		    },
+		)
+		"#);
+
+		let out = edited(|v| v["server"] = JsonValue::Null);
+		assert!(out.contains("    server: None,\n"), "{out}");
+	}
+
+	#[test]
+	fn shape_violations_are_refused() {
+		let value = |f: fn(&mut JsonValue)| {
+			let mut value = parse(SOURCE).unwrap();
+			f(&mut value);
+			serialize(Some(SOURCE), &value, None)
+		};
+		assert!(value(|v| v["mode"] = json!("very fast")).is_err());
+		assert!(value(|v| v["sep"] = json!(", ")).is_err());
+		assert!(value(|v| v["server"]["bad key"] = json!(1)).is_err());
+		assert!(parse("(a: NaN)").is_err());
+	}
+
+	#[test]
+	fn fresh_file_is_pretty_printed() {
+		let value = json!({"b": [1, null], "a": {"c": "d"}, "e": {"x-y": 1}});
+		assert_eq!(
+			serialize(None, &value, None).unwrap(),
+			"(\n    b: [\n        1,\n        None,\n    ],\n    a: (\n        c: \"d\",\n    ),\n    e: {\n        \"x-y\": 1,\n    },\n)\n"
+		);
+	}
+}
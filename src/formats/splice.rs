@@ -0,0 +1,385 @@
+//! Format-agnostic write-back by splicing the original source.
+//!
+//! A backend parses its source into a [`Node`] tree that records where every value lives, and implements [`Dialect`] to say how a value is spelled and how entries are added or removed. [`patch`] then diffs the old and new value trees and only rewrites the byte ranges that changed.
+use std::ops::Range;
+
+use color_eyre::eyre::ensure;
+use serde_json::Value as JsonValue;
+use v_utils::prelude::*;
+
+#[derive(Clone, Debug)]
+pub struct Node<M> {
+	/// Byte range of the value in the source.
+	pub span: Range<usize>,
+	pub kind: NodeKind<M>,
+	/// Whatever the dialect needs to know to re-spell the value in place (indentation, quoting style, etc).
+	pub meta: M,
+}
+
+#[derive(Clone, Debug)]
+pub enum NodeKind<M> {
+	/// Anything that is rewritten as a whole.
+	Leaf,
+	/// A value that is computed rather than written out, and so can't be edited in place.
+	Opaque,
+	Map(Vec<Entry<M>>),
+	Seq(Vec<Node<M>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry<M> {
+	pub key: String,
+	pub key_span: Range<usize>,
+	pub value: Node<M>,
+}
+
+/// A replacement of `range` in the source with `text`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+	pub range: Range<usize>,
+	pub text: String,
+}
+impl Edit {
+	pub fn replace(range: Range<usize>, text: impl Into<String>) -> Self {
+		Self { range, text: text.into() }
+	}
+
+	pub fn insert(at: usize, text: impl Into<String>) -> Self {
+		Self { range: at..at, text: text.into() }
+	}
+}
+
+/// How a particular format spells values and structural changes. `path` arguments are only used for error messages.
+pub trait Dialect {
+	type Meta;
+
+	/// Replace `node` with `value`.
+	fn replace(&self, src: &str, node: &Node<Self::Meta>, value: &JsonValue, path: &str) -> Result<Edit>;
+	/// Add `key` to the non-empty `map`.
+	fn insert_entry(&self, src: &str, map: &Node<Self::Meta>, key: &str, value: &JsonValue, path: &str) -> Result<Vec<Edit>>;
+	/// Remove entry `idx` from `map`, which has at least one other entry.
+	fn remove_entry(&self, src: &str, map: &Node<Self::Meta>, idx: usize) -> Result<Vec<Edit>>;
+	/// Remove entries `indices` from `map`, which keeps at least one other entry. Dialects whose removals reach into the separators around an entry have to remove neighbouring entries as one; the rest can leave it to [`Dialect::remove_entry`], one entry at a time.
+	fn remove_entries(&self, src: &str, map: &Node<Self::Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		let mut edits = Vec::new();
+		for &idx in indices {
+			edits.extend(self.remove_entry(src, map, idx)?);
+		}
+		Ok(edits)
+	}
+	/// Rename the key of an entry, leaving its value and position alone.
+	fn rename_key(&self, src: &str, entry: &Entry<Self::Meta>, new_key: &str) -> Result<Edit>;
+	/// Insert `value` at `idx` into the non-empty `seq`; `idx` may equal its length.
+	fn insert_item(&self, src: &str, seq: &Node<Self::Meta>, idx: usize, value: &JsonValue, path: &str) -> Result<Vec<Edit>>;
+	/// Append `values` to the non-empty `seq`. Dialects whose insertions at the end don't get in each other's way can leave it to [`Dialect::insert_item`], one value at a time.
+	fn append_items(&self, src: &str, seq: &Node<Self::Meta>, values: &[JsonValue], path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let mut edits = Vec::new();
+		for (i, value) in values.iter().enumerate() {
+			edits.extend(self.insert_item(src, seq, items.len(), value, &format!("{path}/{}", items.len() + i))?);
+		}
+		Ok(edits)
+	}
+	/// Remove item `idx` from `seq`, which has at least one other item.
+	fn remove_item(&self, src: &str, seq: &Node<Self::Meta>, idx: usize) -> Result<Vec<Edit>>;
+	/// Remove items `indices` from `seq`, which keeps at least one other item. Like [`Dialect::remove_entries`], by default one at a time.
+	fn remove_items(&self, src: &str, seq: &Node<Self::Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		let mut edits = Vec::new();
+		for &idx in indices {
+			edits.extend(self.remove_item(src, seq, idx)?);
+		}
+		Ok(edits)
+	}
+}
+
+/// Rewrite `src` (whose parsed value is `old`, laid out as `root`) so that it reads back as `new`.
+pub fn patch<D: Dialect>(dialect: &D, src: &str, root: &Node<D::Meta>, old: &JsonValue, new: &JsonValue) -> Result<String> {
+	let mut edits = Vec::new();
+	diff(dialect, src, root, old, new, "", &mut edits)?;
+	// dialects lay out new text with `\n`, which has to follow the file's own line endings
+	if line_ending(src) != "\n" {
+		for edit in &mut edits {
+			edit.text = edit.text.replace("\r\n", "\n").replace('\n', "\r\n");
+		}
+	}
+	apply(src, edits)
+}
+
+fn diff<D: Dialect>(dialect: &D, src: &str, node: &Node<D::Meta>, old: &JsonValue, new: &JsonValue, path: &str, edits: &mut Vec<Edit>) -> Result<()> {
+	if old == new {
+		return Ok(());
+	}
+	let path_or_root = if path.is_empty() { "/" } else { path };
+	match (&node.kind, old, new) {
+		(NodeKind::Map(entries), JsonValue::Object(old_obj), JsonValue::Object(new_obj)) if !old_obj.is_empty() && !new_obj.is_empty() => {
+			let removed: Vec<&String> = old_obj.keys().filter(|k| !new_obj.contains_key(*k)).collect();
+			let added: Vec<&String> = new_obj.keys().filter(|k| !old_obj.contains_key(*k)).collect();
+			let entry_idx = |key: &str| {
+				entries
+					.iter()
+					.position(|e| e.key == key)
+					.ok_or_else(|| eyre!("`{}` is not spelled out in the source", super::key_path(path, key)))
+			};
+
+			// edits inside existing entries come first, so that new entries inserted at the same offset end up after them
+			for (key, new_value) in new_obj {
+				// unchanged entries may come from somewhere the source doesn't spell out, so only look up the changed ones
+				if let Some(old_value) = old_obj.get(key).filter(|old_value| *old_value != new_value) {
+					diff(dialect, src, &entries[entry_idx(key)?].value, old_value, new_value, &super::key_path(path, key), edits)?;
+				}
+			}
+			let renamed = match (&removed[..], &added[..]) {
+				([from], [to]) if old_obj[*from] == new_obj[*to] => Some((*from, *to)),
+				_ => None,
+			};
+			if let Some((from, to)) = renamed {
+				edits.push(dialect.rename_key(src, &entries[entry_idx(from)?], to)?);
+			} else {
+				let removed = removed.into_iter().map(|key| entry_idx(key)).collect::<Result<Vec<_>>>()?;
+				if !removed.is_empty() {
+					edits.extend(dialect.remove_entries(src, node, &removed)?);
+				}
+				for key in added {
+					edits.extend(dialect.insert_entry(src, node, key, &new_obj[key], &super::key_path(path, key))?);
+				}
+			}
+			Ok(())
+		}
+		(NodeKind::Seq(items), JsonValue::Array(old_arr), JsonValue::Array(new_arr)) if !new_arr.is_empty() && !old_arr.is_empty() => {
+			if let Some(i) = single_insertion(new_arr, old_arr) {
+				edits.extend(dialect.remove_item(src, node, i)?);
+			} else if let Some(i) = single_insertion(old_arr, new_arr) {
+				edits.extend(dialect.insert_item(src, node, i, &new_arr[i], &format!("{path}/{i}"))?);
+			} else {
+				for (i, (item, (o, n))) in items.iter().zip(old_arr.iter().zip(new_arr)).enumerate() {
+					diff(dialect, src, item, o, n, &format!("{path}/{i}"), edits)?;
+				}
+				if new_arr.len() > old_arr.len() {
+					edits.extend(dialect.append_items(src, node, &new_arr[old_arr.len()..], path)?);
+				}
+				if old_arr.len() > new_arr.len() {
+					edits.extend(dialect.remove_items(src, node, &(new_arr.len()..old_arr.len()).collect::<Vec<_>>())?);
+				}
+			}
+			Ok(())
+		}
+		(NodeKind::Opaque, ..) => bail!("`{path_or_root}` is computed by an expression in the source rather than written out, so it can't be edited here"),
+		_ => {
+			edits.push(dialect.replace(src, node, new, path_or_root)?);
+			Ok(())
+		}
+	}
+}
+
+/// Equality, except that whole numbers written into floats read back as floats.
+pub fn same(a: &JsonValue, b: &JsonValue) -> bool {
+	match (a, b) {
+		(JsonValue::Number(a), JsonValue::Number(b)) => a == b || a.as_f64() == b.as_f64(),
+		(JsonValue::Array(a), JsonValue::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)),
+		(JsonValue::Object(a), JsonValue::Object(b)) => a.len() == b.len() && a.iter().all(|(k, v)| b.get(k).is_some_and(|w| same(v, w))),
+		_ => a == b,
+	}
+}
+
+/// If `longer` is `shorter` with exactly one element added, returns the index of that element.
+pub fn single_insertion(shorter: &[JsonValue], longer: &[JsonValue]) -> Option<usize> {
+	if longer.len() != shorter.len() + 1 {
+		return None;
+	}
+	let i = shorter.iter().zip(longer).position(|(a, b)| a != b).unwrap_or(shorter.len());
+	(longer[i + 1..] == shorter[i..]).then_some(i)
+}
+
+/// Apply non-overlapping edits. Insertions at the same offset end up in the order they were produced.
+fn apply(src: &str, mut edits: Vec<Edit>) -> Result<String> {
+	// stable sort, then walk backwards: of two insertions at the same offset the later one is applied first and so ends up after the earlier one
+	edits.sort_by_key(|e| e.range.start);
+	let mut covered = 0..0;
+	for edit in &edits {
+		// an insertion may sit at either end of a replaced range, but not inside it; replaced ranges may only touch
+		let overlaps = match edit.range.is_empty() {
+			true => covered.start < edit.range.start && edit.range.start < covered.end,
+			false => edit.range.start < covered.end,
+		};
+		ensure!(!overlaps, "edits {covered:?} and {:?} of the source overlap", edit.range);
+		if !edit.range.is_empty() {
+			covered = edit.range.clone();
+		}
+	}
+
+	let mut out = src.to_owned();
+	for edit in edits.into_iter().rev() {
+		out.replace_range(edit.range, &edit.text);
+	}
+	Ok(out)
+}
+
+/// Metadata of values in comma-separated containers, like JSON objects and arrays.
+pub trait Separated {
+	/// Offset of the comma following this value in its container.
+	fn comma(&self) -> Option<usize>;
+}
+
+/// Where each entry or item of `container` starts, and its value.
+fn elements<M>(container: &Node<M>) -> Vec<(usize, &Node<M>)> {
+	match &container.kind {
+		NodeKind::Map(entries) => entries.iter().map(|e| (e.key_span.start, &e.value)).collect(),
+		NodeKind::Seq(items) => items.iter().map(|i| (i.span.start, i)).collect(),
+		NodeKind::Leaf | NodeKind::Opaque => Vec::new(),
+	}
+}
+
+/// Insert `text` (the entry or item as it should appear, without separators) at `idx` into the non-empty comma-separated `container`, whose closing bracket is at `close`.
+pub fn insert_separated<M: Separated>(src: &str, container: &Node<M>, close: usize, idx: usize, text: &str) -> Vec<Edit> {
+	let elements = elements(container);
+	let multiline = src[container.span.clone()].contains('\n');
+	let indent = line_indent(src, elements[0].0);
+	if let Some((next, _)) = elements.get(idx) {
+		let sep = match multiline {
+			true => format!(",\n{indent}"),
+			false => ", ".to_owned(),
+		};
+		return vec![Edit::insert(*next, format!("{text}{sep}"))];
+	}
+
+	append_separated(src, container, close, &[text.to_owned()])
+}
+
+/// Append `texts` (entries or items as they should appear, without separators) to the non-empty comma-separated `container`, whose closing bracket is at `close`, as one insertion.
+pub fn append_separated<M: Separated>(src: &str, container: &Node<M>, close: usize, texts: &[String]) -> Vec<Edit> {
+	let elements = elements(container);
+	let multiline = src[container.span.clone()].contains('\n');
+	let indent = line_indent(src, elements[0].0);
+	let (_, last) = elements.last().expect("container is not empty");
+	let same_line_as_close = !src[last.span.end..close].contains('\n');
+	match (last.meta.comma(), multiline && !same_line_as_close) {
+		(Some(comma), true) => vec![Edit::insert(eol(src, comma + 1), texts.iter().map(|text| format!("\n{indent}{text},")).collect::<String>())],
+		// the new items are separated among themselves, and from the last one by a comma right after it, before whatever comment ends its line
+		(None, true) => vec![
+			Edit::insert(last.span.end, ","),
+			Edit::insert(eol(src, last.span.end), texts.iter().map(|text| format!("\n{indent}{text}")).collect::<Vec<_>>().join(",")),
+		],
+		(Some(comma), false) => vec![Edit::insert(comma + 1, texts.iter().map(|text| format!(" {text},")).collect::<String>())],
+		(None, false) => vec![Edit::insert(last.span.end, texts.iter().map(|text| format!(", {text}")).collect::<String>())],
+	}
+}
+
+/// Remove entries or items `indices` from the comma-separated `container`, which keeps at least one, along with their lines if nothing else is on them.
+///
+/// Neighbouring ones are removed as one: each removal reaches into the separators around it, which is only right if what's around it stays.
+pub fn remove_separated<M: Separated>(src: &str, container: &Node<M>, indices: &[usize]) -> Vec<Edit> {
+	let elements = elements(container);
+	let multiline = src[container.span.clone()].contains('\n');
+	let mut edits = Vec::new();
+	for (first, last) in runs(indices) {
+		let (start, _) = elements[first];
+		let (_, value) = elements[last];
+		let is_last = last + 1 == elements.len();
+
+		if !multiline {
+			let range = match (elements.get(last + 1), value.meta.comma()) {
+				(Some((next, _)), _) => start..*next,
+				(None, Some(comma)) => elements[first - 1].1.meta.comma().map(|c| c + 1).unwrap_or(start)..comma + 1,
+				(None, None) => elements[first - 1].1.span.end..value.span.end,
+			};
+			edits.push(Edit::replace(range, ""));
+			continue;
+		}
+
+		let start = match src[line_start(src, start)..start].trim().is_empty() {
+			true => line_start(src, start),
+			false => start,
+		};
+		let after = value.meta.comma().map(|c| c + 1).unwrap_or(value.span.end);
+		let rest_of_line = src[after..eol(src, after)].trim();
+		let end = match rest_of_line.is_empty() || rest_of_line.starts_with("//") || rest_of_line.starts_with('#') {
+			true => line_end(src, after),
+			false => after,
+		};
+		edits.push(Edit::replace(start..end, ""));
+		if is_last && value.meta.comma().is_none() {
+			if let Some(comma) = elements[first - 1].1.meta.comma() {
+				edits.push(Edit::replace(comma..comma + 1, ""));
+			}
+		}
+	}
+	edits
+}
+
+/// `indices` as runs of consecutive ones, each given by its first and last index.
+pub fn runs(indices: &[usize]) -> Vec<(usize, usize)> {
+	let mut sorted = indices.to_vec();
+	sorted.sort_unstable();
+	sorted.dedup();
+	let mut runs: Vec<(usize, usize)> = Vec::new();
+	for i in sorted {
+		match runs.last_mut() {
+			Some((_, last)) if *last + 1 == i => *last = i,
+			_ => runs.push((i, i)),
+		}
+	}
+	runs
+}
+
+/// Byte offset of the start of the line containing `pos`.
+pub fn line_start(src: &str, pos: usize) -> usize {
+	src[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+/// Byte offset just past the newline ending the line containing `pos`, or the end of `src`.
+pub fn line_end(src: &str, pos: usize) -> usize {
+	src[pos..].find('\n').map(|i| pos + i + 1).unwrap_or(src.len())
+}
+
+/// Byte offset of the line break (`\n` or `\r\n`) ending the line containing `pos`, or the end of `src`.
+pub fn eol(src: &str, pos: usize) -> usize {
+	src[pos..]
+		.find('\n')
+		.map(|i| match i > 0 && src[..pos + i].ends_with('\r') {
+			true => pos + i - 1,
+			false => pos + i,
+		})
+		.unwrap_or(src.len())
+}
+
+/// The line ending `src` uses, going by its first line.
+pub fn line_ending(src: &str) -> &'static str {
+	match src.find('\n') {
+		Some(i) if src[..i].ends_with('\r') => "\r\n",
+		_ => "\n",
+	}
+}
+
+/// Leading whitespace of the line containing `pos`.
+pub fn line_indent(src: &str, pos: usize) -> &str {
+	let line = &src[line_start(src, pos)..eol(src, pos)];
+	&line[..line.len() - line.trim_start().len()]
+}
+
+/// Column of `pos`, counted in bytes from the start of its line.
+pub fn column(src: &str, pos: usize) -> usize {
+	pos - line_start(src, pos)
+}
+
+/// Indent every line but the first of `text` by `indent` spaces.
+pub fn indent_tail(text: &str, indent: usize) -> String {
+	let pad = " ".repeat(indent);
+	text.lines()
+		.enumerate()
+		.map(|(i, l)| if i == 0 || l.is_empty() { l.to_owned() } else { format!("{pad}{l}") })
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn overlapping_edits_are_refused() {
+		let src = "[1, 2, 3]";
+		assert_eq!(apply(src, vec![Edit::replace(4..7, ""), Edit::insert(7, "9, ")]).unwrap(), "[1, 9, 3]");
+		assert!(apply(src, vec![Edit::replace(4..7, ""), Edit::replace(5..8, "")]).is_err());
+		assert!(apply(src, vec![Edit::replace(4..7, ""), Edit::insert(5, "9")]).is_err());
+	}
+}
@@ -0,0 +1,274 @@
+//! TOML backend. Write-back goes through [`toml_edit`], so comments, key order, inline tables and whitespace of untouched entries survive.
+use std::path::Path;
+
+use serde::Serialize as _;
+use serde_json::{Map, Value as JsonValue};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, TableLike, Value};
+use v_utils::prelude::*;
+
+use super::{Capabilities, FormatBackend, Options, extension};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backend;
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		"TOML"
+	}
+
+	fn id(&self) -> &'static str {
+		"toml"
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		extension(path) == "toml"
+	}
+
+	/// A file of nothing but comments is valid TOML, and valid most everything else too.
+	fn sniff(&self, content: &str) -> bool {
+		parse(content).is_ok_and(|value| value.as_object().is_some_and(|obj| !obj.is_empty()))
+	}
+
+	fn parse(&self, _path: &Path, content: &str, _options: &Options) -> Result<JsonValue> {
+		parse(content)
+	}
+
+	fn serialize(&self, _path: &Path, original: Option<&str>, value: &JsonValue, _options: &Options) -> Result<String> {
+		serialize(original, value)
+	}
+
+	fn language(&self) -> &'static str {
+		"toml"
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities { null: false, comments: true }
+	}
+}
+
+pub fn parse(content: &str) -> Result<JsonValue> {
+	let toml_value: ::toml::Value = ::toml::from_str(content).context("Failed to read TOML file")?;
+	serde_json::to_value(toml_value).context("Failed to convert TOML to JSON")
+}
+
+/// Serialize `value`, reusing the layout of `original` for everything that did not change.
+pub fn serialize(original: Option<&str>, value: &JsonValue) -> Result<String> {
+	let mut doc: DocumentMut = original.unwrap_or_default().parse().context("Failed to parse the original TOML file")?;
+	let obj = value.as_object().ok_or_eyre("TOML document root must be a table")?;
+	merge_table_like(doc.as_table_mut(), obj, "", false)?;
+	Ok(doc.to_string())
+}
+
+/// `inline` tables can only hold values, so new objects inside them become inline tables too.
+fn merge_table_like(table: &mut dyn TableLike, new: &Map<String, JsonValue>, path: &str, inline: bool) -> Result<()> {
+	let stale: Vec<String> = table.iter().map(|(k, _)| k.to_owned()).filter(|k| !new.contains_key(k)).collect();
+	for key in stale {
+		table.remove(&key);
+	}
+	for (key, new_value) in new {
+		let item_path = super::key_path(path, key);
+		match table.get_mut(key) {
+			Some(item) => merge_item(item, new_value, &item_path)?,
+			None => {
+				let item = match (new_value, inline) {
+					(JsonValue::Object(_), false) => Item::Table(new_table(new_value, &item_path)?),
+					_ => Item::Value(new_value_node(new_value, &item_path)?),
+				};
+				table.insert(key, item);
+			}
+		}
+	}
+	Ok(())
+}
+
+fn merge_item(item: &mut Item, new: &JsonValue, path: &str) -> Result<()> {
+	match (item, new) {
+		(Item::Table(table), JsonValue::Object(obj)) => merge_table_like(table, obj, path, false),
+		(Item::ArrayOfTables(tables), JsonValue::Array(arr)) if arr.iter().all(JsonValue::is_object) => merge_array_of_tables(tables, arr, path),
+		(Item::Value(value), _) => merge_value(value, new, path),
+		(item, _) => {
+			*item = match new {
+				JsonValue::Object(_) => Item::Table(new_table(new, path)?),
+				_ => Item::Value(new_value_node(new, path)?),
+			};
+			Ok(())
+		}
+	}
+}
+
+fn merge_value(value: &mut Value, new: &JsonValue, path: &str) -> Result<()> {
+	match (&mut *value, new) {
+		(Value::InlineTable(table), JsonValue::Object(obj)) => merge_table_like(table, obj, path, true),
+		(Value::Array(arr), JsonValue::Array(new_arr)) => merge_array(arr, new_arr, path),
+		_ => {
+			if value_to_json(value) == *new {
+				return Ok(());
+			}
+			let decor = value.decor().clone();
+			*value = new_value_node(new, path)?;
+			*value.decor_mut() = decor;
+			Ok(())
+		}
+	}
+}
+
+fn merge_array(arr: &mut Array, new: &[JsonValue], path: &str) -> Result<()> {
+	let old: Vec<JsonValue> = arr.iter().map(value_to_json).collect();
+	if let Some(removed) = single_removal(&old, new) {
+		arr.remove(removed);
+		return Ok(());
+	}
+	for (i, new_value) in new.iter().enumerate() {
+		match arr.get_mut(i) {
+			Some(value) => merge_value(value, new_value, &format!("{path}/{i}"))?,
+			None => arr.push_formatted(new_value_node(new_value, &format!("{path}/{i}"))?.decorated(" ", "")),
+		}
+	}
+	while arr.len() > new.len() {
+		arr.remove(arr.len() - 1);
+	}
+	Ok(())
+}
+
+fn merge_array_of_tables(tables: &mut ArrayOfTables, new: &[JsonValue], path: &str) -> Result<()> {
+	let old: Vec<JsonValue> = tables.iter().map(|t| table_to_json(t)).collect();
+	if let Some(removed) = single_removal(&old, new) {
+		tables.remove(removed);
+		return Ok(());
+	}
+	for (i, new_value) in new.iter().enumerate() {
+		let obj = new_value.as_object().expect("checked by the caller");
+		match tables.get_mut(i) {
+			Some(table) => merge_table_like(table, obj, &format!("{path}/{i}"), false)?,
+			None => tables.push(new_table(new_value, &format!("{path}/{i}"))?),
+		}
+	}
+	while tables.len() > new.len() {
+		tables.remove(tables.len() - 1);
+	}
+	Ok(())
+}
+
+/// If `new` is `old` with exactly one element taken out, returns the index of that element.
+fn single_removal(old: &[JsonValue], new: &[JsonValue]) -> Option<usize> {
+	if old.len() != new.len() + 1 {
+		return None;
+	}
+	let i = old.iter().zip(new).position(|(a, b)| a != b).unwrap_or(new.len());
+	(old[i + 1..] == new[i..]).then_some(i)
+}
+
+fn new_table(value: &JsonValue, path: &str) -> Result<Table> {
+	let doc = toml_edit::ser::to_document(value).map_err(|e| eyre!("`{path}` can't be represented in TOML: {e}"))?;
+	Ok(doc.as_table().clone())
+}
+
+fn new_value_node(value: &JsonValue, path: &str) -> Result<Value> {
+	value
+		.serialize(toml_edit::ser::ValueSerializer::new())
+		.map_err(|e| eyre!("`{path}` can't be represented in TOML: {e}"))
+}
+
+fn table_to_json(table: &dyn TableLike) -> JsonValue {
+	let map = table
+		.iter()
+		.map(|(k, item)| {
+			let v = match item {
+				Item::None => JsonValue::Null,
+				Item::Value(v) => value_to_json(v),
+				Item::Table(t) => table_to_json(t),
+				Item::ArrayOfTables(tables) => JsonValue::Array(tables.iter().map(|t| table_to_json(t)).collect()),
+			};
+			(k.to_owned(), v)
+		})
+		.collect();
+	JsonValue::Object(map)
+}
+
+/// Mirrors what [`parse`] produces for the same value, so that the two can be compared.
+fn value_to_json(value: &Value) -> JsonValue {
+	match value {
+		Value::String(s) => JsonValue::String(s.value().clone()),
+		Value::Integer(i) => JsonValue::from(*i.value()),
+		Value::Float(f) => JsonValue::from(*f.value()),
+		Value::Boolean(b) => JsonValue::Bool(*b.value()),
+		Value::Datetime(dt) => serde_json::to_value(dt.value()).unwrap_or(JsonValue::Null),
+		Value::Array(arr) => JsonValue::Array(arr.iter().map(value_to_json).collect()),
+		Value::InlineTable(t) => table_to_json(t),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const SOURCE: &str = r#"# service config
+name = "svc" # the name
+
+[server]
+host = "localhost"
+port = 8080 # default port
+limits = { rps = 10, burst = 20 }
+tags = ["a", "b", "c"]
+
+[[users]]
+name = "alice"
+
+[[users]]
+name = "bob"
+"#;
+
+	fn edited(f: impl FnOnce(&mut JsonValue)) -> String {
+		let mut value = parse(SOURCE).unwrap();
+		f(&mut value);
+		let out = serialize(Some(SOURCE), &value).unwrap();
+		assert_eq!(parse(&out).unwrap(), value);
+		out
+	}
+
+	fn changed_lines(out: &str) -> Vec<(&str, &str)> {
+		SOURCE.lines().zip(out.lines()).filter(|(a, b)| a != b).collect()
+	}
+
+	#[test]
+	fn untouched_roundtrip_is_identical() {
+		assert_eq!(edited(|_| {}), SOURCE);
+	}
+
+	#[test]
+	fn scalar_edit_is_a_one_line_diff() {
+		let out = edited(|v| v["server"]["port"] = json!(9090));
+		assert_eq!(out.lines().count(), SOURCE.lines().count());
+		assert_eq!(changed_lines(&out), vec![("port = 8080 # default port", "port = 9090 # default port")]);
+	}
+
+	#[test]
+	fn inline_table_and_array_edits_stay_inline() {
+		let out = edited(|v| {
+			v["server"]["limits"]["burst"] = json!(30);
+			v["server"]["tags"].as_array_mut().unwrap().remove(1);
+		});
+		assert_eq!(
+			changed_lines(&out),
+			vec![
+				("limits = { rps = 10, burst = 20 }", "limits = { rps = 10, burst = 30 }"),
+				("tags = [\"a\", \"b\", \"c\"]", "tags = [\"a\", \"c\"]"),
+			]
+		);
+	}
+
+	#[test]
+	fn array_of_tables_element_edit() {
+		let out = edited(|v| v["users"][1]["name"] = json!("carol"));
+		assert_eq!(changed_lines(&out), vec![("name = \"bob\"", "name = \"carol\"")]);
+	}
+
+	#[test]
+	fn null_is_rejected_with_path() {
+		let mut value = parse(SOURCE).unwrap();
+		value["server"]["host"] = JsonValue::Null;
+		let e = serialize(Some(SOURCE), &value).unwrap_err();
+		assert!(e.to_string().contains("/server/host"), "{e}");
+	}
+}
@@ -0,0 +1,558 @@
+//! YAML backend. Write-back splices only the changed nodes into the original text, so comments, anchors, aliases, flow/block style and quoting of everything else are kept.
+use std::path::Path;
+
+use saphyr_parser::{Event, Parser, ScalarStyle};
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+use v_utils::prelude::*;
+
+use super::{
+	Capabilities, FormatBackend, Options, extension,
+	splice::{self, Dialect, Edit, Entry, Node, NodeKind, column, indent_tail, line_end, line_start},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backend;
+impl FormatBackend for Backend {
+	fn name(&self) -> &'static str {
+		"YAML"
+	}
+
+	fn id(&self) -> &'static str {
+		"yaml"
+	}
+
+	fn detect(&self, path: &Path) -> bool {
+		matches!(extension(path), "yaml" | "yml")
+	}
+
+	/// Nearly any text is a YAML scalar, so only mappings count.
+	fn sniff(&self, content: &str) -> bool {
+		parse(content).is_ok_and(|value| value.as_object().is_some_and(|obj| !obj.is_empty()))
+	}
+
+	fn parse(&self, _path: &Path, content: &str, _options: &Options) -> Result<JsonValue> {
+		parse(content)
+	}
+
+	fn serialize(&self, _path: &Path, original: Option<&str>, value: &JsonValue, _options: &Options) -> Result<String> {
+		serialize(original, value)
+	}
+
+	fn language(&self) -> &'static str {
+		"yaml"
+	}
+
+	fn capabilities(&self) -> Capabilities {
+		Capabilities { null: true, comments: true }
+	}
+}
+
+pub fn parse(content: &str) -> Result<JsonValue> {
+	let yaml_value: YamlValue = serde_yaml::from_str(content).context("Failed to read YAML file")?;
+	serde_json::to_value(yaml_value).context("Failed to convert YAML to JSON")
+}
+
+/// Serialize `value`, reusing the layout of `original` for everything that did not change.
+pub fn serialize(original: Option<&str>, value: &JsonValue) -> Result<String> {
+	let Some(src) = original.filter(|s| !s.trim().is_empty()) else {
+		return to_string(value);
+	};
+	let old = parse(src)?;
+	let root = layout(src)?;
+	let out = splice::patch(&YamlDialect, src, &root, &old, value)?;
+	if parse(&out)? != *value {
+		bail!("The edit can't be written without also changing other nodes that share an anchor with it");
+	}
+	Ok(out)
+}
+
+fn to_string(value: &JsonValue) -> Result<String> {
+	let yaml_value: YamlValue = serde_json::from_value(value.clone()).context("Failed to convert JSON to YAML")?;
+	serde_yaml::to_string(&yaml_value).context("Failed to write YAML file")
+}
+
+#[derive(Clone, Debug, Default)]
+struct Meta {
+	/// Is, or sits inside of, a flow collection.
+	flow: bool,
+	/// For block collections the column their keys or dashes start at; for values in them the column of their own key or dash.
+	indent: usize,
+	/// Offset of the `-` for items of block sequences.
+	dash: Option<usize>,
+	style: Option<ScalarStyle>,
+	root: bool,
+}
+
+enum Frame {
+	Map { node: Node<Meta>, key: Option<(String, std::ops::Range<usize>)> },
+	Seq { node: Node<Meta> },
+}
+
+/// Parse `src` into a tree of byte spans.
+fn layout(src: &str) -> Result<Node<Meta>> {
+	// the parser reports char offsets
+	let mut byte_at: Vec<usize> = src.char_indices().map(|(i, _)| i).collect();
+	byte_at.push(src.len());
+
+	let mut stack: Vec<Frame> = Vec::new();
+	let mut root: Option<Node<Meta>> = None;
+	for event in Parser::new_from_str(src) {
+		let (event, span) = event.context("Failed to read YAML file")?;
+		let range = byte_at[span.start.index()]..byte_at[span.end.index().min(byte_at.len() - 1)];
+		let in_flow = stack.last().is_some_and(|f| frame_node(f).meta.flow);
+
+		let node = match event {
+			Event::Scalar(text, style, ..) => {
+				if let Some(Frame::Map { key: key @ None, .. }) = stack.last_mut() {
+					*key = Some((text.into_owned(), range));
+					continue;
+				}
+				Node {
+					span: range,
+					kind: NodeKind::Leaf,
+					meta: Meta {
+						flow: in_flow,
+						style: Some(style),
+						..Default::default()
+					},
+				}
+			}
+			Event::Alias(_) => Node {
+				span: range,
+				kind: NodeKind::Leaf,
+				meta: Meta {
+					flow: in_flow,
+					..Default::default()
+				},
+			},
+			Event::MappingStart(..) | Event::SequenceStart(..) => {
+				let flow = in_flow || src[range.clone()].starts_with(['{', '[']);
+				let node = Node {
+					span: range.start..range.end,
+					kind: match event {
+						Event::MappingStart(..) => NodeKind::Map(Vec::new()),
+						_ => NodeKind::Seq(Vec::new()),
+					},
+					meta: Meta { flow, ..Default::default() },
+				};
+				stack.push(match node.kind {
+					NodeKind::Map(_) => Frame::Map { node, key: None },
+					_ => Frame::Seq { node },
+				});
+				continue;
+			}
+			Event::MappingEnd | Event::SequenceEnd => {
+				let mut node = match stack.pop() {
+					Some(Frame::Map { node, .. } | Frame::Seq { node }) => node,
+					None => bail!("Unbalanced YAML collection"),
+				};
+				// block collections end wherever the next token starts, so pull the end in to their last value
+				let (first, last) = match &node.kind {
+					NodeKind::Map(entries) => (entries.first().map(|e| e.key_span.start), entries.last().map(|e| e.value.span.end)),
+					NodeKind::Seq(items) => (items.first().map(|i| i.meta.dash.unwrap_or(i.span.start)), items.last().map(|i| i.span.end)),
+					NodeKind::Leaf | NodeKind::Opaque => unreachable!(),
+				};
+				match node.meta.flow {
+					true => node.span.end = range.end,
+					false => {
+						node.span.end = last.unwrap_or(node.span.start);
+						node.meta.indent = column(src, first.unwrap_or(node.span.start));
+					}
+				}
+				node
+			}
+			_ => continue,
+		};
+
+		match stack.last_mut() {
+			Some(Frame::Map { node: map, key }) => {
+				let (key, key_span) = key.take().ok_or_eyre("YAML mapping value without a key")?;
+				let mut node = node;
+				if matches!(node.kind, NodeKind::Leaf) && !node.meta.flow {
+					node.meta.indent = column(src, key_span.start);
+				}
+				if let NodeKind::Map(entries) = &mut map.kind {
+					entries.push(Entry { key, key_span, value: node });
+				}
+			}
+			Some(Frame::Seq { node: seq }) => {
+				let mut node = node;
+				if !seq.meta.flow {
+					node.meta.dash = src[..node.span.start].rfind('-');
+					if matches!(node.kind, NodeKind::Leaf) {
+						node.meta.indent = node.meta.dash.map(|d| column(src, d)).unwrap_or_default();
+					}
+				}
+				if let NodeKind::Seq(items) = &mut seq.kind {
+					items.push(node);
+				}
+			}
+			None => {
+				let mut node = node;
+				node.meta.root = true;
+				root.get_or_insert(node);
+			}
+		}
+	}
+	root.ok_or_eyre("Empty YAML document")
+}
+
+fn frame_node(frame: &Frame) -> &Node<Meta> {
+	match frame {
+		Frame::Map { node, .. } | Frame::Seq { node } => node,
+	}
+}
+
+struct YamlDialect;
+impl YamlDialect {
+	fn scalar(value: &JsonValue, style: Option<ScalarStyle>, flow: bool) -> String {
+		match value {
+			JsonValue::String(s) => match style {
+				Some(ScalarStyle::SingleQuoted) if !s.contains(['\n', '\t', '\r']) => format!("'{}'", s.replace('\'', "''")),
+				Some(ScalarStyle::DoubleQuoted) => serde_json::to_string(s).unwrap(),
+				_ if is_plain_safe(s, flow) => s.clone(),
+				_ => serde_json::to_string(s).unwrap(),
+			},
+			JsonValue::Object(_) | JsonValue::Array(_) => serde_json::to_string(value).unwrap(),
+			JsonValue::Null => "null".to_owned(),
+			_ => value.to_string(),
+		}
+	}
+
+	fn key(key: &str, flow: bool) -> String {
+		Self::scalar(&JsonValue::String(key.to_owned()), None, flow)
+	}
+
+	/// Block-style lines for a non-empty collection, every line indented by `indent`.
+	fn block(value: &JsonValue, indent: usize) -> Result<String> {
+		let text = to_string(value)?;
+		let pad = " ".repeat(indent);
+		Ok(text.trim_end().lines().map(|l| format!("{pad}{l}")).collect::<Vec<_>>().join("\n"))
+	}
+
+	/// Text that goes after `key:` in a block mapping.
+	fn after_colon(value: &JsonValue, indent: usize) -> Result<String> {
+		Ok(match value {
+			JsonValue::Object(o) if !o.is_empty() => format!("\n{}", Self::block(value, indent + 2)?),
+			JsonValue::Array(a) if !a.is_empty() => format!("\n{}", Self::block(value, indent + 2)?),
+			_ => format!(" {}", Self::scalar(value, None, false)),
+		})
+	}
+
+	/// Text that goes after `- ` in a block sequence whose dashes are at `indent`.
+	fn after_dash(value: &JsonValue, indent: usize) -> Result<String> {
+		Ok(match value {
+			JsonValue::Object(o) if !o.is_empty() => indent_tail(&Self::block(value, 0)?, indent + 2),
+			JsonValue::Array(a) if !a.is_empty() => indent_tail(&Self::block(value, 0)?, indent + 2),
+			_ => Self::scalar(value, None, false),
+		})
+	}
+
+	/// Where to add a new line after a block node ending at `end`.
+	fn next_line(src: &str, end: usize) -> (usize, &'static str) {
+		match src[..end].ends_with('\n') {
+			true => (end, ""),
+			false => match line_end(src, end) {
+				at if at == src.len() && !src.ends_with('\n') => (at, "\n"),
+				at => (at, ""),
+			},
+		}
+	}
+
+	/// Range covering the whole line(s) of a block entry or item, from `start` to the end of the line its value ends on.
+	fn block_lines(src: &str, start: usize, end: usize) -> std::ops::Range<usize> {
+		let from = match src[line_start(src, start)..start].trim().is_empty() {
+			true => line_start(src, start),
+			false => start,
+		};
+		from..Self::next_line(src, end).0
+	}
+
+	/// Start of the text to replace when swapping out a block collection, so that the new value can pick its own line layout.
+	fn lead(src: &str, node: &Node<Meta>) -> usize {
+		src[..node.span.start].trim_end().len()
+	}
+}
+
+impl Dialect for YamlDialect {
+	type Meta = Meta;
+
+	fn replace(&self, src: &str, node: &Node<Meta>, value: &JsonValue, _path: &str) -> Result<Edit> {
+		let is_block_collection = matches!(node.kind, NodeKind::Map(_) | NodeKind::Seq(_)) && !node.meta.flow;
+		if node.meta.flow {
+			return Ok(Edit::replace(node.span.clone(), Self::scalar(value, node.meta.style, true)));
+		}
+		if node.meta.root {
+			let text = to_string(value)?;
+			return Ok(Edit::replace(node.span.clone(), text.trim_end()));
+		}
+		if matches!(node.meta.style, Some(ScalarStyle::Literal | ScalarStyle::Folded)) {
+			// the span only covers the content lines; take the `|`/`>` header with it
+			let header = src[..node.span.start].rfind(['|', '>']).unwrap_or(node.span.start);
+			let content_end = Self::next_line(src, src[..node.span.end].trim_end().len()).0;
+			let text = match value.as_str().filter(|s| s.contains('\n')) {
+				Some(s) => {
+					let indent = node.meta.indent + 2;
+					let chomp = match s.trim_end_matches('\n').len() {
+						l if l == s.len() => "-",
+						l if l + 1 == s.len() => "",
+						_ => "+",
+					};
+					let body = s
+						.trim_end_matches('\n')
+						.lines()
+						.map(|l| if l.is_empty() { String::new() } else { format!("{}{l}", " ".repeat(indent)) });
+					format!("|{chomp}\n{}\n", body.collect::<Vec<_>>().join("\n"))
+				}
+				None => format!("{}\n", Self::scalar(value, None, false)),
+			};
+			return Ok(Edit::replace(header..content_end, text));
+		}
+
+		let nested_indent = match is_block_collection {
+			true => node.meta.indent,
+			false => node.meta.indent + 2,
+		};
+		let text = match (value, node.meta.dash) {
+			(JsonValue::Object(o), _) if o.is_empty() => " {}".to_owned(),
+			(JsonValue::Array(a), _) if a.is_empty() => " []".to_owned(),
+			(JsonValue::Object(_) | JsonValue::Array(_), Some(dash)) => format!(" {}", Self::after_dash(value, column(src, dash))?),
+			(JsonValue::Object(_) | JsonValue::Array(_), None) => format!("\n{}", Self::block(value, nested_indent)?),
+			_ if !is_block_collection => return Ok(Edit::replace(node.span.clone(), Self::scalar(value, node.meta.style, false))),
+			_ => format!(" {}", Self::scalar(value, None, false)),
+		};
+		Ok(Edit::replace(Self::lead(src, node)..node.span.end, text))
+	}
+
+	fn insert_entry(&self, src: &str, map: &Node<Meta>, key: &str, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let last = entries.last().expect("map is not empty");
+		if map.meta.flow {
+			return Ok(vec![Edit::insert(
+				last.value.span.end,
+				format!(", {}: {}", Self::key(key, true), Self::scalar(value, None, true)),
+			)]);
+		}
+		let (at, prefix) = Self::next_line(src, last.value.span.end);
+		let indent = column(src, last.key_span.start);
+		Ok(vec![Edit::insert(
+			at,
+			format!("{prefix}{}{}:{}\n", " ".repeat(indent), Self::key(key, false), Self::after_colon(value, indent)?),
+		)])
+	}
+
+	fn remove_entry(&self, src: &str, map: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		let entry = &entries[idx];
+		let range = match (map.meta.flow, entries.get(idx + 1)) {
+			(true, Some(next)) => entry.key_span.start..next.key_span.start,
+			(true, None) => entries[idx - 1].value.span.end..entry.value.span.end,
+			(false, Some(next)) if !src[line_start(src, entry.key_span.start)..entry.key_span.start].trim().is_empty() => entry.key_span.start..next.key_span.start,
+			(false, _) => Self::block_lines(src, entry.key_span.start, entry.value.span.end),
+		};
+		Ok(vec![Edit::replace(range, "")])
+	}
+
+	fn remove_entries(&self, src: &str, map: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		let NodeKind::Map(entries) = &map.kind else { unreachable!() };
+		if !map.meta.flow {
+			let mut edits = Vec::new();
+			for &idx in indices {
+				edits.extend(self.remove_entry(src, map, idx)?);
+			}
+			return Ok(edits);
+		}
+		// neighbouring entries go as one, up to the entry after them, or at the end, from the one before them
+		Ok(splice::runs(indices)
+			.into_iter()
+			.map(|(first, last)| match entries.get(last + 1) {
+				Some(next) => Edit::replace(entries[first].key_span.start..next.key_span.start, ""),
+				None => Edit::replace(entries[first - 1].value.span.end..entries[last].value.span.end, ""),
+			})
+			.collect())
+	}
+
+	fn rename_key(&self, _src: &str, entry: &Entry<Meta>, new_key: &str) -> Result<Edit> {
+		Ok(Edit::replace(entry.key_span.clone(), Self::key(new_key, entry.value.meta.flow)))
+	}
+
+	fn insert_item(&self, src: &str, seq: &Node<Meta>, idx: usize, value: &JsonValue, _path: &str) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		if seq.meta.flow {
+			return Ok(vec![match items.get(idx) {
+				Some(item) => Edit::insert(item.span.start, format!("{}, ", Self::scalar(value, None, true))),
+				None => Edit::insert(items.last().expect("seq is not empty").span.end, format!(", {}", Self::scalar(value, None, true))),
+			}]);
+		}
+		let indent = seq.meta.indent;
+		Ok(vec![match items.get(idx).and_then(|i| i.meta.dash) {
+			Some(dash) => Edit::insert(dash, format!("- {}\n{}", Self::after_dash(value, indent)?, " ".repeat(indent))),
+			None => {
+				let (at, prefix) = Self::next_line(src, items.last().expect("seq is not empty").span.end);
+				Edit::insert(at, format!("{prefix}{}- {}\n", " ".repeat(indent), Self::after_dash(value, indent)?))
+			}
+		}])
+	}
+
+	fn remove_item(&self, src: &str, seq: &Node<Meta>, idx: usize) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		let item = &items[idx];
+		let range = match (seq.meta.flow, items.get(idx + 1)) {
+			(true, Some(next)) => item.span.start..next.span.start,
+			(true, None) => items[idx - 1].span.end..item.span.end,
+			(false, _) => {
+				let dash = item.meta.dash.unwrap_or(item.span.start);
+				Self::block_lines(src, dash, item.span.end)
+			}
+		};
+		Ok(vec![Edit::replace(range, "")])
+	}
+
+	fn remove_items(&self, src: &str, seq: &Node<Meta>, indices: &[usize]) -> Result<Vec<Edit>> {
+		let NodeKind::Seq(items) = &seq.kind else { unreachable!() };
+		if !seq.meta.flow {
+			let mut edits = Vec::new();
+			for &idx in indices {
+				edits.extend(self.remove_item(src, seq, idx)?);
+			}
+			return Ok(edits);
+		}
+		Ok(splice::runs(indices)
+			.into_iter()
+			.map(|(first, last)| match items.get(last + 1) {
+				Some(next) => Edit::replace(items[first].span.start..next.span.start, ""),
+				None => Edit::replace(items[first - 1].span.end..items[last].span.end, ""),
+			})
+			.collect())
+	}
+}
+
+/// Whether `s` reads back as the same string when written without quotes.
+fn is_plain_safe(s: &str, flow: bool) -> bool {
+	if s.is_empty() || s.trim() != s || s.contains(['\n', '\t', '\r']) || s.contains(": ") || s.contains(" #") || s.ends_with(':') {
+		return false;
+	}
+	if s.starts_with(['-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`']) {
+		return false;
+	}
+	if flow && s.contains([',', '[', ']', '{', '}']) {
+		return false;
+	}
+	matches!(serde_yaml::from_str::<YamlValue>(s), Ok(YamlValue::String(ref p)) if p == s)
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	const SOURCE: &str = r#"# service config
+name: svc # the name
+defaults: &defaults
+  timeout: 5
+  retries: "3"
+prod:
+  base: *defaults
+  host: 'example.com'
+servers:
+  - alpha
+  - beta # backup
+flow: {x: 1, y: [1, 2]}
+motd: |
+  hello
+  world
+"#;
+
+	fn edited(f: impl FnOnce(&mut JsonValue)) -> String {
+		let mut value = parse(SOURCE).unwrap();
+		f(&mut value);
+		let out = serialize(Some(SOURCE), &value).unwrap();
+		assert_eq!(parse(&out).unwrap(), value);
+		out
+	}
+
+	fn diff_lines(out: &str) -> (Vec<&str>, Vec<&str>) {
+		let removed = SOURCE.lines().filter(|l| !out.lines().any(|o| o == *l)).collect();
+		let added = out.lines().filter(|l| !SOURCE.lines().any(|s| s == *l)).collect();
+		(removed, added)
+	}
+
+	#[test]
+	fn untouched_roundtrip_is_identical() {
+		assert_eq!(edited(|_| {}), SOURCE);
+	}
+
+	#[test]
+	fn scalar_edits_keep_quoting_and_comments() {
+		let out = edited(|v| {
+			v["name"] = json!("api");
+			v["prod"]["host"] = json!("example.org");
+			v["servers"][1] = json!("gamma");
+		});
+		assert_eq!(
+			diff_lines(&out),
+			(
+				vec!["name: svc # the name", "  host: 'example.com'", "  - beta # backup"],
+				vec!["name: api # the name", "  host: 'example.org'", "  - gamma # backup"]
+			)
+		);
+	}
+
+	#[test]
+	fn structural_edits() {
+		let out = edited(|v| {
+			v["servers"].as_array_mut().unwrap().push(json!("gamma"));
+			v["prod"].as_object_mut().unwrap().insert("port".to_owned(), json!(8080));
+			v["flow"]["y"].as_array_mut().unwrap().remove(0);
+			v.as_object_mut().unwrap().remove("motd");
+		});
+		assert_eq!(
+			diff_lines(&out),
+			(
+				vec!["flow: {x: 1, y: [1, 2]}", "motd: |", "  hello", "  world"],
+				vec!["  port: 8080", "  - gamma", "flow: {x: 1, y: [2]}"]
+			)
+		);
+		assert!(out.contains("base: *defaults"));
+	}
+
+	#[test]
+	fn several_flow_elements_removed_at_once() {
+		let src = "m: {a: 1, b: 2, c: 3}\nl: [1, 2, 3, 4]\n";
+		let write = |value: JsonValue| {
+			let out = serialize(Some(src), &value).unwrap();
+			assert_eq!(parse(&out).unwrap(), value);
+			out
+		};
+		assert_eq!(write(json!({"m": {"a": 1}, "l": [1]})), "m: {a: 1}\nl: [1]\n");
+		assert_eq!(write(json!({"m": {"c": 3}, "l": [1, 4]})), "m: {c: 3}\nl: [1, 4]\n");
+		assert_eq!(write(json!({"m": {"b": 2}, "l": [2, 3]})), "m: {b: 2}\nl: [2, 3]\n");
+	}
+
+	#[test]
+	fn crlf_files_keep_their_line_endings() {
+		let src = "name: svc # the name\r\nlist:\r\n  - a\r\n";
+		let mut value = parse(src).unwrap();
+		value["port"] = json!(8080);
+		value["list"].as_array_mut().unwrap().push(json!("b"));
+		value["nested"] = json!({"x": [1]});
+		let out = serialize(Some(src), &value).unwrap();
+		assert_eq!(out, "name: svc # the name\r\nlist:\r\n  - a\r\n  - b\r\nport: 8080\r\nnested:\r\n  x:\r\n  - 1\r\n");
+		assert_eq!(parse(&out).unwrap(), value);
+	}
+
+	#[test]
+	fn nested_value_replaces_scalar() {
+		let out = edited(|v| v["name"] = json!({"first": "a", "list": [1, 2]}));
+		assert!(out.contains("name:\n  first: a\n  list:\n  - 1\n  - 2"), "{out}");
+	}
+
+	#[test]
+	fn editing_anchored_node_is_refused() {
+		let mut value = parse(SOURCE).unwrap();
+		value["defaults"]["timeout"] = json!(10);
+		assert!(serialize(Some(SOURCE), &value).is_err());
+	}
+}
@@ -0,0 +1,7 @@
+//! Target files as editable data: reading and writing them in their own format, with history, git commits and schema checks. The bot in the binary is built on this; formats can be added with [`formats::Registry::register`] and loaded with [`data::Data::load_with`].
+#![allow(clippy::len_zero)]
+#![allow(clippy::get_first)]
+#![allow(clippy::comparison_to_empty)]
+pub mod data;
+pub mod formats;
+pub mod utils;
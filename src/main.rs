@@ -1,20 +1,19 @@
 #![allow(clippy::len_zero)] // wait, so are the ones in Cargo.toml not enough?
 #![allow(clippy::get_first)]
 #![allow(clippy::comparison_to_empty)]
-#![feature(trait_alias)]
-#![feature(type_changing_struct_update)]
 use std::{
-	sync::{Arc, RwLock},
+	collections::HashMap,
+	path::{Path, PathBuf},
+	sync::Arc,
 	time::Duration,
 };
 
 use clap::{Args, Parser, Subcommand};
 use config::{LiveSettings, SettingsFlags};
+use tg_admin::{data, formats, utils};
 use v_utils::io::ExpandedPath;
 pub mod config;
-pub mod data;
 pub mod telegram;
-pub mod utils;
 
 #[derive(Debug, Default, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -39,8 +38,36 @@ impl Default for Commands {
 
 #[derive(Args, Debug, Default)]
 pub struct ManageArgs {
-	/// Path to the target file
-	path: ExpandedPath,
+	/// Target files, or directories to manage every supported file in. Each gets its own entry in the bot's file picker.
+	#[arg(required = true)]
+	paths: Vec<ExpandedPath>,
+	/// Indentation for JSON/JSON5 writes: a number of spaces, or `tab`. Detected from the file by default.
+	#[arg(long, value_parser = parse_indent)]
+	indent: Option<String>,
+	/// Read and write every target as this format (json, json5, yaml, ron, toml, nix, jsonnet, ini, env or properties), rather than going by file names and contents.
+	#[arg(long, value_parser = parse_format)]
+	format: Option<Arc<dyn formats::FormatBackend>>,
+	/// Read unquoted INI, .env and .properties values that look like numbers or booleans as such, rather than as strings.
+	#[arg(long)]
+	infer_types: bool,
+	/// How long evaluating a Nix file may take, in seconds, before it is given up on.
+	#[arg(long, value_name = "SECONDS", default_value_t = formats::nix::DEFAULT_EVAL_TIMEOUT.as_secs())]
+	eval_timeout: u64,
+	/// Evaluate Nix files in pure mode: no environment variables, and no files they aren't pointed at directly.
+	#[arg(long)]
+	pure_eval: bool,
+	/// How many previous versions of each target to keep, for `/history` to restore; 0 keeps none.
+	#[arg(long, value_name = "N", default_value_t = data::Retention::default().max_versions)]
+	keep_versions: usize,
+	/// Drop previous versions older than this many days, however few there are.
+	#[arg(long, value_name = "DAYS")]
+	keep_days: Option<u64>,
+	/// Commit changes to targets in a git work tree to this branch, rather than the checked out one. It's created from the checked out commit if need be.
+	#[arg(long, value_name = "BRANCH")]
+	git_branch: Option<String>,
+	/// Don't commit changes to targets that are in a git work tree.
+	#[arg(long, conflicts_with = "git_branch")]
+	no_git: bool,
 	#[clap(flatten)]
 	settings_flags: SettingsFlags,
 }
@@ -59,17 +86,138 @@ async fn main() {
 					std::process::exit(1);
 				}
 			};
-			let target_data = match data::Data::load(args.path.as_ref()) {
-				Ok(data) => data,
+			let paths = match target_paths(&args.paths) {
+				Ok(paths) => paths,
 				Err(e) => {
-					eprintln!("Error: Failed to load data from the target file. Details: {e}");
+					eprintln!("Error: Failed to collect the target files. Details: {e}");
 					std::process::exit(1);
 				}
 			};
-			telegram::run(Arc::new(app_config), Arc::new(RwLock::new(target_data))).await.unwrap_or_else(|e| {
+			let settings = app_config.config().ok().unwrap_or_default();
+			let schemas = match target_schemas(&paths, settings.schema, &settings.schemas) {
+				Ok(schemas) => schemas,
+				Err(e) => {
+					eprintln!("Error: {e}");
+					std::process::exit(1);
+				}
+			};
+			// evaluating a target may depend on these, so it's read with them from the start
+			let options = formats::Options {
+				indent: args.indent.clone(),
+				infer_types: args.infer_types,
+				eval_timeout: Some(Duration::from_secs(args.eval_timeout)),
+				pure_eval: args.pure_eval,
+			};
+			let mut targets = Vec::with_capacity(paths.len());
+			for (path, schema) in paths.into_iter().zip(schemas) {
+				let loaded = match &args.format {
+					Some(format) => data::Data::load_as(&path, format.clone(), options.clone()),
+					None => data::Data::load_with(&path, &formats::Registry::default(), options.clone()),
+				};
+				let configured = loaded.and_then(|data| data.with_schema(schema.as_deref()));
+				let configured = configured.map(|data| match args.keep_versions {
+					0 => data,
+					max_versions => data.with_history(data::Retention {
+						max_versions,
+						max_age: args.keep_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+					}),
+				});
+				let configured = configured.map(|data| match args.no_git {
+					true => data,
+					false => data.with_git(args.git_branch.clone()),
+				});
+				match configured {
+					Ok(data) => {
+						if let Some(reference) = data.remote_schema() {
+							eprintln!(
+								"Warning: `{}` points to its schema at {reference}, which isn't fetched. Give it a local copy in `schemas` in the settings file (or --schema, if it's the only target) to check writes against it.",
+								path.display()
+							);
+						}
+						let violations = data.violations();
+						if !violations.is_empty() {
+							let violations = violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ");
+							eprintln!(
+								"Warning: `{}` doesn't fit its schema; edits are only refused for violations they add: {violations}",
+								path.display()
+							);
+						}
+						targets.push(data)
+					}
+					Err(e @ data::DataError::UnsupportedFormat(_)) => {
+						eprintln!("Error: {e}. Pass --format to say which it is.");
+						std::process::exit(1);
+					}
+					Err(e) => {
+						eprintln!("Error: Failed to load data from `{}`. Details: {e}", path.display());
+						std::process::exit(1);
+					}
+				}
+			}
+			telegram::run(Arc::new(app_config), targets).await.unwrap_or_else(|e| {
 				eprintln!("Error: Failed to start the telegram bot. Details: {e}");
 				std::process::exit(1);
 			})
 		}
 	}
 }
+
+/// Expand directories into the supported files directly inside them, dropping duplicates.
+fn target_paths(args: &[ExpandedPath]) -> std::io::Result<Vec<PathBuf>> {
+	let mut paths = Vec::new();
+	for arg in args {
+		let path: &Path = arg.as_ref();
+		match path.is_dir() {
+			true => {
+				let mut entries = std::fs::read_dir(path)?
+					.map(|entry| entry.map(|entry| entry.path()))
+					.collect::<std::io::Result<Vec<_>>>()?
+					.into_iter()
+					.filter(|path| path.is_file() && data::Data::supports(path))
+					.collect::<Vec<_>>();
+				entries.sort();
+				paths.extend(entries);
+			}
+			false => paths.push(path.to_path_buf()),
+		}
+	}
+	let mut seen = std::collections::HashSet::new();
+	paths.retain(|path| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())));
+	match paths.is_empty() {
+		true => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no supported files in the given directories")),
+		false => Ok(paths),
+	}
+}
+
+/// The schema each of `paths` is given in the settings, if any: its entry in `schemas`, keyed by target path, or else `schema` when there's just the one target.
+fn target_schemas(paths: &[PathBuf], schema: Option<String>, schemas: &HashMap<String, String>) -> Result<Vec<Option<PathBuf>>, String> {
+	if schema.is_some() && paths.len() > 1 {
+		return Err("--schema (or `schema` in the settings file) is for a single target. With several, give each its own in `schemas` in the settings file, keyed by its path.".to_owned());
+	}
+	let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+	let targets: Vec<PathBuf> = paths.iter().map(|path| canonical(path)).collect();
+	let mut by_target = HashMap::new();
+	for (target, schema) in schemas {
+		let target = canonical(Path::new(target));
+		if !targets.contains(&target) {
+			return Err(format!("`schemas` in the settings file has one for `{}`, which isn't a target.", target.display()));
+		}
+		by_target.insert(target, PathBuf::from(schema));
+	}
+	Ok(targets
+		.iter()
+		.map(|target| by_target.get(target).cloned().or_else(|| schema.clone().map(PathBuf::from)))
+		.collect())
+}
+
+fn parse_format(s: &str) -> Result<Arc<dyn formats::FormatBackend>, String> {
+	let registry = formats::Registry::default();
+	registry.get(s).ok_or_else(|| format!("expected one of {}, got `{s}`", registry.ids().join(", ")))
+}
+
+fn parse_indent(s: &str) -> Result<String, String> {
+	match s {
+		"tab" => Ok("\t".to_owned()),
+		_ => s.parse::<usize>().map(|n| " ".repeat(n)).map_err(|_| format!("expected a number of spaces or `tab`, got `{s}`")),
+	}
+}
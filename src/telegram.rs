@@ -1,4 +1,8 @@
-use std::sync::{Arc, RwLock};
+use std::{
+	collections::{HashMap, HashSet},
+	sync::{Arc, Mutex, RwLock},
+	time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -16,13 +20,217 @@ use v_utils::prelude::*;
 
 use crate::{
 	config::LiveSettings,
-	data::{Data, ValuePath},
+	data::{Data, DataError, Edit, MAX_VIOLATIONS, PatchOp, UpdateAction, ValuePath, history},
 	utils::{get_json_type, value_preview},
 };
 
 type MyDialogue = Dialogue<ChatState, InMemStorage<ChatState>>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
+/// How often the target files are checked for changes made outside the bot.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Chats that have been authorized, and so get told about changes to the target files.
+#[derive(Clone, Debug, Default)]
+struct AdminChats(Arc<RwLock<HashSet<ChatId>>>);
+impl AdminChats {
+	fn insert(&self, chat_id: ChatId) {
+		self.0.write().unwrap().insert(chat_id);
+	}
+
+	async fn notify(&self, bot: &Bot, text: &str) {
+		let chats: Vec<ChatId> = self.0.read().unwrap().iter().copied().collect();
+		for chat_id in chats {
+			if let Err(e) = bot.send_message(chat_id, text).await {
+				tracing::warn!("Failed to notify {chat_id}: {e}");
+			}
+		}
+	}
+}
+
+/// How many edits each chat can undo.
+const MAX_UNDO: usize = 50;
+
+/// Edits made from each chat, to undo and redo.
+#[derive(Clone, Debug, Default)]
+struct UndoStacks(Arc<Mutex<HashMap<ChatId, UndoStack>>>);
+#[derive(Debug, Default)]
+struct UndoStack {
+	next_id: u64,
+	/// Newest last.
+	done: Vec<Undoable>,
+	/// Most recently undone last; cleared by a new edit.
+	undone: Vec<Undoable>,
+}
+/// The edits of one write, made in `file`.
+#[derive(Clone, Debug)]
+struct Undoable {
+	id: u64,
+	file: usize,
+	edits: Vec<Edit>,
+}
+impl UndoStacks {
+	fn record(&self, chat_id: ChatId, file: usize, edits: Vec<Edit>) {
+		let mut stacks = self.0.lock().unwrap();
+		let stack = stacks.entry(chat_id).or_default();
+		stack.next_id += 1;
+		stack.done.push(Undoable { id: stack.next_id, file, edits });
+		if stack.done.len() > MAX_UNDO {
+			stack.done.remove(0);
+		}
+		stack.undone.clear();
+	}
+
+	/// Id of the edit `/undo` would undo next.
+	fn latest(&self, chat_id: ChatId) -> Option<u64> {
+		self.0.lock().unwrap().get(&chat_id)?.done.last().map(|undoable| undoable.id)
+	}
+
+	/// Take the edit to undo next, if it's the one with `id` or that doesn't matter.
+	fn take_undo(&self, chat_id: ChatId, id: Option<u64>) -> Option<Undoable> {
+		let mut stacks = self.0.lock().unwrap();
+		let done = &mut stacks.get_mut(&chat_id)?.done;
+		match id {
+			Some(id) if done.last()?.id != id => None,
+			_ => done.pop(),
+		}
+	}
+
+	fn take_redo(&self, chat_id: ChatId) -> Option<Undoable> {
+		self.0.lock().unwrap().get_mut(&chat_id)?.undone.pop()
+	}
+
+	/// Put back an edit that was undone (`to_redo`) or redone, or that couldn't be.
+	fn put(&self, chat_id: ChatId, undoable: Undoable, to_redo: bool) {
+		let mut stacks = self.0.lock().unwrap();
+		let stack = stacks.entry(chat_id).or_default();
+		match to_redo {
+			true => stack.undone.push(undoable),
+			false => stack.done.push(undoable),
+		}
+	}
+}
+
+/// The managed files, each behind its own lock. Chat state and callbacks refer to them by index.
+#[derive(Clone, Debug)]
+struct Targets(Arc<Vec<Target>>);
+#[derive(Debug)]
+struct Target {
+	/// What the file is called in menus: its file name, or the full path if another target shares the file name.
+	name: String,
+	data: Arc<RwLock<Data>>,
+	/// Taken for the whole of a write or reload, which may evaluate the file for a while; `data` itself is only locked to copy it and put the result back, so reading it isn't held up meanwhile.
+	writer: Mutex<()>,
+	paths: Mutex<PathIds>,
+}
+
+/// How many paths of a file are remembered for buttons; past that the older ones are forgotten, outdating the menus that showed them.
+const MAX_PATH_IDS: usize = 10_000;
+
+/// Stands for a path in the callback data of buttons acting on it: Telegram caps callback data at 64 bytes, which long keys would go over.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+struct PathId(u32);
+impl PathId {
+	/// The top of every file, which is never forgotten.
+	const TOP: Self = Self(0);
+}
+
+/// The paths of a file that buttons have been made for, by id. A path keeps its id even if it goes away and comes back, and ids are never reused, so a forgotten one can't be taken for another path.
+#[derive(Debug)]
+struct PathIds {
+	ids: HashMap<ValuePath, PathId>,
+	paths: Vec<ValuePath>,
+	/// The id of `paths[0]`.
+	first: u32,
+}
+impl Default for PathIds {
+	fn default() -> Self {
+		Self {
+			ids: HashMap::new(),
+			paths: Vec::new(),
+			first: 1,
+		}
+	}
+}
+impl Targets {
+	fn new(targets: Vec<Data>) -> Self {
+		let file_name = |data: &Data| data.path().file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+		let names: Vec<String> = targets.iter().map(file_name).collect();
+		let targets = targets
+			.into_iter()
+			.zip(&names)
+			.map(|(data, name)| {
+				let name = match names.iter().filter(|other| *other == name).count() {
+					1 => name.clone(),
+					_ => data.path().display().to_string(),
+				};
+				Target {
+					name,
+					data: Arc::new(RwLock::new(data)),
+					writer: Mutex::default(),
+					paths: Mutex::default(),
+				}
+			})
+			.collect();
+		Self(Arc::new(targets))
+	}
+
+	fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	/// `file` has to be in range; indices are checked when callbacks come in, and chat state is only ever derived from those.
+	fn data(&self, file: usize) -> &Arc<RwLock<Data>> {
+		&self.0[file].data
+	}
+
+	fn name(&self, file: usize) -> &str {
+		&self.0[file].name
+	}
+
+	/// The id buttons in `file` acting on `path` carry.
+	fn path_id(&self, file: usize, path: &ValuePath) -> PathId {
+		if path.is_top() {
+			return PathId::TOP;
+		}
+		let mut paths = self.0[file].paths.lock().unwrap();
+		if let Some(id) = paths.ids.get(path) {
+			return *id;
+		}
+		if paths.paths.len() >= MAX_PATH_IDS {
+			paths.first += paths.paths.len() as u32;
+			paths.paths.clear();
+			paths.ids.clear();
+		}
+		let id = PathId(paths.first + paths.paths.len() as u32);
+		paths.paths.push(path.clone());
+		paths.ids.insert(path.clone(), id);
+		id
+	}
+
+	/// The path `id` stands for in `file`; `None` if it has been forgotten since the button was made.
+	fn path(&self, file: usize, id: PathId) -> Option<ValuePath> {
+		if id == PathId::TOP {
+			return Some(ValuePath::default());
+		}
+		let paths = self.0[file].paths.lock().unwrap();
+		let index = id.0.checked_sub(paths.first)?;
+		paths.paths.get(index as usize).cloned()
+	}
+
+	/// Run `change` on a copy of the data of `file`, then put the copy in its place. Changes to the same file take turns.
+	///
+	/// Blocks for as long as `change` takes, so call it off the async threads.
+	fn modify<T>(&self, file: usize, change: impl FnOnce(&mut Data) -> T) -> T {
+		let target = &self.0[file];
+		let _turn = target.writer.lock().unwrap();
+		let mut data = target.data.read().unwrap().clone();
+		let result = change(&mut data);
+		*target.data.write().unwrap() = data;
+		result
+	}
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 enum ChatState {
 	/// Most actions are prohibited from this state. Other states can be reached only through authorization from here.
@@ -34,9 +242,29 @@ enum ChatState {
 		message_id: i32,
 	},
 	Input(ValueInput),
+	/// Waiting for the name of a key to create in `parent`.
+	NewKey {
+		file: usize,
+		parent: ValuePath,
+	},
+	/// Waiting for a JSON Patch document to apply.
+	Patch {
+		file: usize,
+	},
+	/// Waiting for the type of the new key at `path` to be picked. Its buttons don't carry the path, which could be too long for callback data.
+	NewKeyType {
+		file: usize,
+		path: ValuePath,
+	},
+	/// Waiting for the deletion of `path` to be confirmed, for the same reason.
+	Delete {
+		file: usize,
+		path: ValuePath,
+	},
 }
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, derive_new::new)]
 struct ValueInput {
+	file: usize,
 	input_type: InputValueType,
 	value_path: ValuePath,
 }
@@ -45,6 +273,54 @@ pub enum InputValueType {
 	UpdateAt,
 	AddTo,
 	RemoveFrom,
+	Insert(NewValueType),
+	Rename,
+}
+impl From<InputValueType> for UpdateAction {
+	fn from(action: InputValueType) -> Self {
+		match action {
+			InputValueType::UpdateAt => Self::Set,
+			InputValueType::AddTo => Self::AddTo,
+			InputValueType::RemoveFrom => Self::RemoveFrom,
+			InputValueType::Insert(_) => Self::Insert,
+			InputValueType::Rename => Self::Rename,
+		}
+	}
+}
+/// What a newly created key holds; containers and null are created right away, the rest ask for a value.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum NewValueType {
+	String,
+	Number,
+	Boolean,
+	Object,
+	Array,
+	Null,
+}
+impl NewValueType {
+	const ALL: [Self; 6] = [Self::String, Self::Number, Self::Boolean, Self::Object, Self::Array, Self::Null];
+
+	/// Same naming as [`get_json_type`].
+	fn name(self) -> &'static str {
+		match self {
+			Self::String => "String",
+			Self::Number => "Number",
+			Self::Boolean => "Boolean",
+			Self::Object => "Object",
+			Self::Array => "Array",
+			Self::Null => "Null",
+		}
+	}
+
+	/// The value to create right away, if there is nothing to ask for.
+	fn empty_value(self) -> Option<Value> {
+		match self {
+			Self::Object => Some(json!({})),
+			Self::Array => Some(json!([])),
+			Self::Null => Some(Value::Null),
+			Self::String | Self::Number | Self::Boolean => None,
+		}
+	}
 }
 #[derive(BotCommands, Clone, Debug)]
 #[command(description = "Commands:", rename_rule = "lowercase")]
@@ -57,10 +333,18 @@ enum Command {
 	Abort,
 	#[command(description = "Show full config file contents")]
 	Full,
+	#[command(description = "Apply a JSON Patch (RFC 6902) document")]
+	Patch,
+	#[command(description = "Show previous versions of the file, to compare with and restore")]
+	History,
+	#[command(description = "Undo your last edit")]
+	Undo,
+	#[command(description = "Redo the edit you last undid")]
+	Redo,
 }
 
 #[tracing::instrument]
-pub async fn run(settings: Arc<LiveSettings>, data: Arc<RwLock<Data>>) -> Result<()> {
+pub async fn run(settings: Arc<LiveSettings>, targets: Vec<Data>) -> Result<()> {
 	let token = settings.config()?.tg_token;
 	let bot = Bot::new(token);
 	let me = bot.get_me().await?;
@@ -68,8 +352,13 @@ pub async fn run(settings: Arc<LiveSettings>, data: Arc<RwLock<Data>>) -> Result
 	println!("Interfacing with bot: @{bot_username}");
 	bot.set_my_commands(Command::bot_commands()).await?;
 	info!("Starting telegram bot...");
+	let targets = Targets::new(targets);
+	let admin_chats = AdminChats::default();
+	for file in 0..targets.len() {
+		spawn_file_watcher(bot.clone(), targets.clone(), file, admin_chats.clone());
+	}
 	Dispatcher::builder(bot, schema())
-		.dependencies(dptree::deps![data, settings, InMemStorage::<ChatState>::new()])
+		.dependencies(dptree::deps![targets, settings, admin_chats, UndoStacks::default(), InMemStorage::<ChatState>::new()])
 		.error_handler(LoggingErrorHandler::with_custom_text("An error has occurred in the dispatcher"))
 		.enable_ctrlc_handler()
 		.build()
@@ -78,6 +367,43 @@ pub async fn run(settings: Arc<LiveSettings>, data: Arc<RwLock<Data>>) -> Result
 	Ok(())
 }
 
+/// Reload the target whenever it is changed outside the bot, and tell admins about it.
+fn spawn_file_watcher(bot: Bot, targets: Targets, file: usize, admin_chats: AdminChats) {
+	let subject = match targets.len() {
+		1 => "The target file".to_owned(),
+		_ => format!("`{}`", targets.name(file)),
+	};
+	tokio::spawn(async move {
+		// a file that can't be reloaded stays changed until it's fixed; only say so once
+		let mut failure_reported = false;
+		loop {
+			tokio::time::sleep(WATCH_INTERVAL).await;
+			let reloaded = tokio::task::block_in_place(|| {
+				// checked before taking a copy, which most of the time isn't needed
+				let changed = targets.data(file).read().unwrap().changed_on_disk();
+				changed.then(|| targets.modify(file, |data| data.changed_on_disk().then(|| data.reload()))).flatten()
+			});
+			let Some(reloaded) = reloaded else { continue };
+			let text = match reloaded {
+				Ok(()) => {
+					failure_reported = false;
+					format!("{subject} was changed outside the bot and has been reloaded. Menus opened before this may be out of date.")
+				}
+				Err(_) if failure_reported => continue,
+				Err(e) => {
+					failure_reported = true;
+					format!(
+						"{subject} was changed outside the bot but couldn't be reloaded, so edits are refused until it can be.\n{}",
+						friendly_error(&e)
+					)
+				}
+			};
+			info!("{text}");
+			admin_chats.notify(&bot, &text).await;
+		}
+	});
+}
+
 fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
 	use dptree::case;
 
@@ -85,16 +411,22 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
 		.branch(case![Command::Help].endpoint(help_handler))
 		.branch(case![Command::Admin].endpoint(admin_handler))
 		.branch(case![Command::Abort].endpoint(abort_handler))
-		.branch(case![Command::Full].endpoint(full_handler));
+		.branch(case![Command::Full].endpoint(full_handler))
+		.branch(case![Command::Patch].endpoint(patch_handler))
+		.branch(case![Command::History].endpoint(history_handler))
+		.branch(case![Command::Undo].endpoint(undo_handler))
+		.branch(case![Command::Redo].endpoint(redo_handler));
 
 	let message_handler = Update::filter_message()
 		.branch(command_handler)
 		.branch(case![ChatState::Input(value_input)].endpoint(value_input_handler))
+		.branch(case![ChatState::NewKey { file, parent }].endpoint(new_key_handler))
+		.branch(case![ChatState::Patch { file }].endpoint(patch_input_handler))
 		.branch(dptree::endpoint(invalid_state_handler));
 
 	let callback_query_handler = Update::filter_callback_query().endpoint(callback_query_handler);
 
-	let auth_handler = dptree::filter_map_async(|dialogue: MyDialogue, settings: Arc<LiveSettings>, update: Update| async move {
+	let auth_handler = dptree::filter_map_async(|dialogue: MyDialogue, settings: Arc<LiveSettings>, admin_chats: AdminChats, update: Update| async move {
 		match dialogue.get().await {
 			Ok(Some(ChatState::Unauthorized)) => {
 				if let Some(admin_list) = &settings.config().ok()?.admin_list {
@@ -115,6 +447,7 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
 					}
 				}
 				dialogue.update(ChatState::Authorized).await.ok()?;
+				admin_chats.insert(dialogue.chat_id());
 				Some(()) // Authorized
 			}
 			Ok(Some(_)) => Some(()), // Already authorized
@@ -128,70 +461,260 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
 		.branch(callback_query_handler)
 }
 
-async fn admin_handler(bot: Bot, msg: Message, dialogue: MyDialogue, data: Arc<RwLock<Data>>) -> HandlerResult {
-	let value_path = ValuePath::default();
-	let (header, markup) = {
-		let data = data.read().unwrap();
-		render_header_and_markup(&data, &value_path)
+async fn admin_handler(bot: Bot, dialogue: MyDialogue, targets: Targets) -> HandlerResult {
+	send_menu(&bot, &dialogue, top_menu(&targets)).await
+}
+
+async fn value_input_handler(bot: Bot, dialogue: MyDialogue, msg: Message, value_input: ValueInput, targets: Targets, undo: UndoStacks) -> HandlerResult {
+	let Some(text) = msg.text() else {
+		bot.send_message(msg.chat.id, "Please send the new value.").await?;
+		return Ok(());
 	};
-	let sent_message = bot.send_message(msg.chat.id, &header).reply_markup(markup).await?;
-	dialogue.update(ChatState::Navigation { message_id: sent_message.id.0 }).await?;
+	let new_value = match value_input.input_type {
+		InputValueType::Rename => Value::String(text.trim().to_owned()),
+		// plain text is taken as is where only a string makes sense
+		InputValueType::Insert(NewValueType::String) => serde_json::from_str::<Value>(text)
+			.ok()
+			.filter(Value::is_string)
+			.unwrap_or_else(|| Value::String(text.to_owned())),
+		_ => match serde_json::from_str::<Value>(text) {
+			Ok(value) => value,
+			Err(_) => {
+				bot.send_message(msg.chat.id, "Invalid value. Input valid JSON value.").await?;
+				return Ok(());
+			}
+		},
+	};
+	if let InputValueType::Insert(value_type) = value_input.input_type {
+		if get_json_type(&new_value) != value_type.name() {
+			bot.send_message(msg.chat.id, format!("That's not a {}. Try again, or /abort to cancel.", value_type.name()))
+				.await?;
+			return Ok(());
+		}
+	}
+
+	let file = value_input.file;
+	match commit(&bot, msg.chat.id, &targets, file, &undo, editor(msg.from.as_ref()), |data| {
+		data.commit_at(&value_input.value_path, new_value.clone(), value_input.input_type)
+	})
+	.await?
+	{
+		Ok(_) => {
+			let path = &value_input.value_path;
+			let affirmation_menu = match value_input.input_type {
+				InputValueType::UpdateAt => format!("Value of `{path}` has been updated to `{new_value}`"),
+				InputValueType::AddTo => format!("`{new_value}` has been added to `{path}`"),
+				InputValueType::RemoveFrom => format!("`{new_value}` has been removed from `{path}`"),
+				InputValueType::Insert(_) => format!("`{path}` has been created with `{new_value}`"),
+				InputValueType::Rename => format!("`{path}` has been renamed to `{}`", text.trim()),
+			};
+			let mut affirmation = bot.send_message(msg.chat.id, affirmation_menu);
+			if let Some(id) = undo.latest(msg.chat.id) {
+				affirmation = affirmation.reply_markup(InlineKeyboardMarkup::new([[callback_button("Undo", file, CallbackAction::Undo(id))]]));
+			}
+			affirmation.await?;
+
+			let new_path = match value_input.input_type {
+				InputValueType::UpdateAt | InputValueType::Insert(_) | InputValueType::Rename => path.parent(),
+				InputValueType::AddTo | InputValueType::RemoveFrom => path.clone(),
+			};
+			send_menu(&bot, &dialogue, render_header_and_markup(&targets, file, &new_path)).await?;
+		}
+		Err(e) => {
+			bot.send_message(msg.chat.id, friendly_error(&e)).await?;
+		}
+	}
 	Ok(())
 }
 
-async fn value_input_handler(bot: Bot, dialogue: MyDialogue, msg: Message, value_input: ValueInput, data: Arc<RwLock<Data>>) -> HandlerResult {
-	match msg.text().map(ToOwned::to_owned) {
-		Some(new_value) => {
-			if let Ok(new_value) = serde_json::from_str::<Value>(&new_value) {
-				let update_result = {
-					let mut data_lock = data.write().unwrap();
-					let result = data_lock.update_at(&value_input.value_path, new_value.clone(), value_input.input_type);
-					data_lock.write().unwrap();
-					result
-				};
+async fn new_key_handler(bot: Bot, dialogue: MyDialogue, msg: Message, (file, parent): (usize, ValuePath), targets: Targets) -> HandlerResult {
+	let Some(name) = msg.text().map(str::trim).filter(|name| !name.is_empty()) else {
+		bot.send_message(msg.chat.id, "Please send the name of the new key.").await?;
+		return Ok(());
+	};
+	let path = parent.join(name);
+	if targets.data(file).read().unwrap().at(&path).is_ok() {
+		bot.send_message(msg.chat.id, friendly_error(&DataError::AlreadyExists(path))).await?;
+		return Ok(());
+	}
 
-				match update_result {
-					Ok(_) => {
-						let affirmation_menu = match value_input.input_type {
-							InputValueType::UpdateAt => {
-								format!("Value of `{}` has been updated to `{}`", &value_input.value_path, &new_value.to_string())
-							}
-							InputValueType::AddTo => {
-								format!("`{}` has been added to `{}`", &new_value.to_string(), &value_input.value_path)
-							}
-							InputValueType::RemoveFrom => {
-								format!("`{}` has been removed from `{}`", &new_value.to_string(), &value_input.value_path)
-							}
-						};
-						bot.send_message(msg.chat.id, affirmation_menu).await?;
-
-						// Resend the nav menu
-						let (header, markup) = {
-							let data = data.read().unwrap();
-							let new_path = match value_input.input_type {
-								InputValueType::UpdateAt => value_input.value_path.parent(),
-								InputValueType::AddTo | InputValueType::RemoveFrom => value_input.value_path,
-							};
-							render_header_and_markup(&data, &new_path)
-						};
-						let sent_message = bot.send_message(dialogue.chat_id(), &header).reply_markup(markup).await?;
-						dialogue.update(ChatState::Navigation { message_id: sent_message.id.0 }).await?;
-					}
-					Err(e) => {
-						bot.send_message(msg.chat.id, e).await?;
-					}
-				}
-			} else {
-				bot.send_message(msg.chat.id, "Invalid value. Input valid JSON value.").await?;
+	bot.send_message(msg.chat.id, format!("What should `{path}` hold?"))
+		.reply_markup(new_key_type_markup(file))
+		.await?;
+	dialogue.update(ChatState::NewKeyType { file, path }).await?;
+	Ok(())
+}
+
+async fn patch_handler(bot: Bot, msg: Message, dialogue: MyDialogue, targets: Targets) -> HandlerResult {
+	match targets.len() {
+		1 => start_patch(&bot, &dialogue, 0).await,
+		_ => {
+			let (_, markup) = file_picker(&targets, CallbackAction::Patch);
+			bot.send_message(msg.chat.id, "Which file should the patch apply to?").reply_markup(markup).await?;
+			Ok(())
+		}
+	}
+}
+
+async fn start_patch(bot: &Bot, dialogue: &MyDialogue, file: usize) -> HandlerResult {
+	bot.send_message(
+		dialogue.chat_id(),
+		"Send a JSON Patch (RFC 6902) document: an array of add/remove/replace/move/copy/test operations. It's applied all at once, or not at all. /abort to cancel.",
+	)
+	.await?;
+	dialogue.update(ChatState::Patch { file }).await?;
+	Ok(())
+}
+
+async fn patch_input_handler(bot: Bot, dialogue: MyDialogue, msg: Message, file: usize, targets: Targets, undo: UndoStacks) -> HandlerResult {
+	let ops = match msg.text().map(serde_json::from_str::<Vec<PatchOp>>) {
+		Some(Ok(ops)) => ops,
+		Some(Err(e)) => {
+			bot.send_message(msg.chat.id, friendly_error(&DataError::InvalidPatch(e.to_string()))).await?;
+			return Ok(());
+		}
+		None => {
+			bot.send_message(msg.chat.id, "Please send the patch as text.").await?;
+			return Ok(());
+		}
+	};
+	match commit(&bot, msg.chat.id, &targets, file, &undo, editor(msg.from.as_ref()), |data| data.apply_patch(&ops)).await? {
+		Ok(()) => {
+			bot.send_message(msg.chat.id, format!("Applied {} operation(s).", ops.len())).await?;
+			send_menu(&bot, &dialogue, render_header_and_markup(&targets, file, &ValuePath::default())).await?;
+		}
+		Err(e) => {
+			bot.send_message(msg.chat.id, format!("{}\nNothing was changed.", friendly_error(&e))).await?;
+		}
+	}
+	Ok(())
+}
+
+/// Run an edit by `editor` in `file` that writes it, such as [`Data::commit_at`], and make it the one `/undo` in `chat_id` undoes next.
+async fn commit(
+	bot: &Bot,
+	chat_id: ChatId,
+	targets: &Targets,
+	file: usize,
+	undo: &UndoStacks,
+	editor: Option<String>,
+	edit: impl FnOnce(&mut Data) -> Result<(), DataError>,
+) -> Result<Result<(), DataError>, teloxide::RequestError> {
+	let result = write_edit(bot, chat_id, targets, file, editor, edit).await?;
+	Ok(result.map(|edits| {
+		// restoring a version isn't undone this way, but from /history
+		if !edits.is_empty() {
+			undo.record(chat_id, file, edits);
+		}
+	}))
+}
+
+/// Run an edit by `editor` that writes the file, returning what it changed; reloads the data if the edit was based on an outdated view of the file.
+///
+/// Writing may evaluate the file, which can take a while, so it happens off the async threads that serve other chats, and on a copy of the data that menus can keep reading from meanwhile. A write that went through but couldn't be committed to git, or left the file out of its schema as it already was, is reported to `chat_id` right away; the edit's own outcome is left to the caller.
+async fn write_edit(
+	bot: &Bot,
+	chat_id: ChatId,
+	targets: &Targets,
+	file: usize,
+	editor: Option<String>,
+	edit: impl FnOnce(&mut Data) -> Result<(), DataError>,
+) -> Result<Result<Vec<Edit>, DataError>, teloxide::RequestError> {
+	let (result, git_failure, violations) = tokio::task::block_in_place(|| {
+		targets.modify(file, |data| {
+			data.set_editor(editor);
+			let result = edit(data).map(|()| data.last_edits().to_vec());
+			if let Err(DataError::Conflict(_)) = result {
+				// start the admin over from what is on disk now
+				let _ = data.reload();
 			}
+			(result, data.take_git_failure(), data.violations())
+		})
+	});
+	if let Some(e) = git_failure {
+		bot.send_message(chat_id, format!("The change was saved, but committing it to git failed:\n{e}")).await?;
+	}
+	if result.is_ok() && !violations.is_empty() {
+		let violations = violations.iter().take(MAX_VIOLATIONS).map(|violation| format!("\n• {violation}")).collect::<String>();
+		bot.send_message(
+			chat_id,
+			format!(
+				"Note: {} was already out of its schema, and still is. Edits are only refused for what they break.{violations}",
+				targets.name(file)
+			),
+		)
+		.await?;
+	}
+	Ok(result)
+}
+
+async fn undo_handler(bot: Bot, msg: Message, dialogue: MyDialogue, targets: Targets, undo: UndoStacks) -> HandlerResult {
+	match undo.take_undo(msg.chat.id, None) {
+		Some(undoable) => revert(&bot, &dialogue, &targets, &undo, editor(msg.from.as_ref()), undoable, false).await,
+		None => {
+			bot.send_message(msg.chat.id, "Nothing to undo.").await?;
+			Ok(())
 		}
+	}
+}
+
+async fn redo_handler(bot: Bot, msg: Message, dialogue: MyDialogue, targets: Targets, undo: UndoStacks) -> HandlerResult {
+	match undo.take_redo(msg.chat.id) {
+		Some(undoable) => revert(&bot, &dialogue, &targets, &undo, editor(msg.from.as_ref()), undoable, true).await,
 		None => {
-			bot.send_message(msg.chat.id, "Please send the new value.").await?;
+			bot.send_message(msg.chat.id, "Nothing to redo.").await?;
+			Ok(())
+		}
+	}
+}
+
+/// Undo `undoable`, or redo it if it was undone, moving it to the other stack; refused if what it changed has been changed again since.
+async fn revert(bot: &Bot, dialogue: &MyDialogue, targets: &Targets, undo: &UndoStacks, editor: Option<String>, undoable: Undoable, redo: bool) -> HandlerResult {
+	let chat_id = dialogue.chat_id();
+	if undoable.file >= targets.len() {
+		bot.send_message(chat_id, "That edit was made in a file that isn't managed anymore.").await?;
+		return Ok(());
+	}
+	let (edits, done, again) = match redo {
+		true => (undoable.edits.iter().rev().map(Edit::inverse).collect(), "Redone", "/redo"),
+		false => (undoable.edits.clone(), "Undone", "/undo"),
+	};
+	let paths = undoable.edits.iter().map(|edit| format!("`{}`", edit.path)).collect::<Vec<_>>().join(", ");
+	match write_edit(bot, chat_id, targets, undoable.file, editor, |data| data.revert(&edits)).await? {
+		Ok(_) => {
+			bot.send_message(chat_id, format!("{done}: the edit of {paths}.")).await?;
+			let (file, menu_path) = (undoable.file, undoable.edits[0].path.parent());
+			undo.put(chat_id, undoable, !redo);
+			send_menu(bot, dialogue, render_header_and_markup(targets, file, &menu_path)).await?;
+		}
+		Err(e @ DataError::ChangedSince(_)) => {
+			// it never will be possible again, but the one before it might be
+			bot.send_message(chat_id, format!("{} It has been dropped; {again} again to go on with the one before it.", friendly_error(&e)))
+				.await?;
+		}
+		Err(e) => {
+			bot.send_message(chat_id, friendly_error(&e)).await?;
+			undo.put(chat_id, undoable, redo);
 		}
 	}
 	Ok(())
 }
 
+/// How `user` is named in the history and commits of the files they edit.
+fn editor(user: Option<&teloxide::types::User>) -> Option<String> {
+	user.map(|user| match &user.username {
+		Some(username) => format!("@{username}"),
+		None => user.full_name(),
+	})
+}
+
+/// Send a fresh menu, making it the one navigation edits.
+async fn send_menu(bot: &Bot, dialogue: &MyDialogue, (header, markup): (String, InlineKeyboardMarkup)) -> HandlerResult {
+	let sent_message = bot.send_message(dialogue.chat_id(), &header).reply_markup(markup).await?;
+	dialogue.update(ChatState::Navigation { message_id: sent_message.id.0 }).await?;
+	Ok(())
+}
+
 async fn invalid_state_handler(bot: Bot, msg: Message) -> HandlerResult {
 	bot.send_message(msg.chat.id, "Unable to handle the message. Type /help to see available commands.").await?;
 	Ok(())
@@ -200,51 +723,146 @@ async fn help_handler(bot: Bot, msg: Message) -> HandlerResult {
 	bot.send_message(msg.chat.id, Command::descriptions().to_string()).await?;
 	Ok(())
 }
-async fn abort_handler(bot: Bot, msg: Message, dialogue: MyDialogue, data: Arc<RwLock<Data>>) -> HandlerResult {
+async fn abort_handler(bot: Bot, msg: Message, dialogue: MyDialogue, targets: Targets) -> HandlerResult {
 	let state = dialogue.get().await?.unwrap_or_default();
-	match state {
-		ChatState::Input(value_input) => {
-			bot.send_message(msg.chat.id, "Input aborted.").await?;
-			let (header, markup) = {
-				let data = data.read().unwrap();
-				let path = value_input.value_path.parent();
-				render_header_and_markup(&data, &path)
-			};
-			let sent_message = bot.send_message(dialogue.chat_id(), &header).reply_markup(markup).await?;
-			dialogue.update(ChatState::Navigation { message_id: sent_message.id.0 }).await?;
-		}
+	let (file, return_to) = match state {
+		ChatState::Input(value_input) => (value_input.file, value_input.value_path.parent()),
+		ChatState::NewKey { file, parent } => (file, parent),
+		ChatState::Patch { file } => (file, ValuePath::default()),
+		ChatState::NewKeyType { file, path } | ChatState::Delete { file, path } => (file, path.parent()),
 		_ => {
 			bot.send_message(msg.chat.id, "Nothing to abort.").await?;
+			return Ok(());
+		}
+	};
+	bot.send_message(msg.chat.id, "Input aborted.").await?;
+	send_menu(&bot, &dialogue, render_header_and_markup(&targets, file, &return_to)).await
+}
+async fn full_handler(bot: Bot, msg: Message, targets: Targets) -> HandlerResult {
+	match targets.len() {
+		1 => send_full(&bot, msg.chat.id, &targets, 0).await,
+		_ => {
+			let (_, markup) = file_picker(&targets, CallbackAction::Full);
+			bot.send_message(msg.chat.id, "Which file should be shown?").reply_markup(markup).await?;
+			Ok(())
 		}
 	}
-	Ok(())
 }
-async fn full_handler(bot: Bot, msg: Message, data: Arc<RwLock<Data>>) -> HandlerResult {
+
+async fn send_full(bot: &Bot, chat_id: ChatId, targets: &Targets, file: usize) -> HandlerResult {
 	let read_result = {
-		let data = data.read().unwrap();
+		let data = targets.data(file).read().unwrap();
 		data.read_raw()
 	};
 	match read_result {
-		Ok((content, ext)) => {
-			let lang = match ext.as_str() {
-				"json" | "json5" => "json",
-				"yaml" | "yml" => "yaml",
-				"toml" => "toml",
-				"nix" => "nix",
-				_ => "",
-			};
+		Ok((content, lang)) => {
 			let escaped = escape_markdown_v2(&content);
-			bot.send_message(msg.chat.id, format!("```{lang}\n{escaped}```"))
+			bot.send_message(chat_id, format!("```{lang}\n{escaped}```"))
 				.parse_mode(teloxide::types::ParseMode::MarkdownV2)
 				.await?;
 		}
 		Err(e) => {
-			bot.send_message(msg.chat.id, format!("Failed to read config file: {e}")).await?;
+			bot.send_message(chat_id, friendly_error(&e)).await?;
+		}
+	}
+	Ok(())
+}
+
+async fn history_handler(bot: Bot, msg: Message, targets: Targets) -> HandlerResult {
+	match targets.len() {
+		1 => send_history(&bot, msg.chat.id, &targets, 0).await,
+		_ => {
+			let (_, markup) = file_picker(&targets, CallbackAction::History);
+			bot.send_message(msg.chat.id, "Which file's history should be shown?").reply_markup(markup).await?;
+			Ok(())
 		}
 	}
+}
+
+/// List the kept versions of the file, newest first, each opening a preview of what restoring it would change.
+async fn send_history(bot: &Bot, chat_id: ChatId, targets: &Targets, file: usize) -> HandlerResult {
+	let versions = targets.data(file).read().unwrap().history().map(|history| history.versions());
+	let versions = match versions {
+		None => {
+			bot.send_message(chat_id, "No previous versions are kept. Start the bot with --keep-versions to keep some.")
+				.await?;
+			return Ok(());
+		}
+		Some(Err(e)) => {
+			bot.send_message(chat_id, friendly_error(&DataError::Io(e))).await?;
+			return Ok(());
+		}
+		Some(Ok(versions)) if versions.is_empty() => {
+			bot.send_message(chat_id, "The file hasn't been edited through the bot yet, so there are no previous versions.")
+				.await?;
+			return Ok(());
+		}
+		Some(Ok(versions)) => versions,
+	};
+	let keyboard = versions.iter().map(|version| {
+		let mut text = format!("#{} · {}", version.id, version.time.strftime("%Y-%m-%d %H:%M UTC"));
+		if let Some(editor) = &version.editor {
+			text.push_str(&format!(" · {editor}"));
+		}
+		text.push_str(&format!(" · {}", version.change));
+		vec![callback_button(text, file, CallbackAction::Version(version.id))]
+	});
+	let header = match targets.len() {
+		1 => "Previous versions, newest first. Each is the file as it was before the change it's labeled with.".to_owned(),
+		_ => format!(
+			"Previous versions of {}, newest first. Each is the file as it was before the change it's labeled with.",
+			targets.name(file)
+		),
+	};
+	bot.send_message(chat_id, header).reply_markup(InlineKeyboardMarkup::new(keyboard)).await?;
 	Ok(())
 }
 
+/// Show what restoring version `id` would change in the file, with a button to do it.
+async fn send_version(bot: &Bot, chat_id: ChatId, targets: &Targets, file: usize, id: u64) -> HandlerResult {
+	let contents = {
+		let data = targets.data(file).read().unwrap();
+		let version = data.history().and_then(|history| history.content(id).ok()).ok_or(DataError::VersionNotFound(id));
+		version.and_then(|version| Ok((data.read_raw()?.0, version)))
+	};
+	let (current, version) = match contents {
+		Ok(contents) => contents,
+		Err(e) => {
+			bot.send_message(chat_id, friendly_error(&e)).await?;
+			return Ok(());
+		}
+	};
+	let back = callback_button("Back", file, CallbackAction::History);
+	let diff = history::diff(&current, &version, &format!("#{id}"));
+	if diff.is_empty() {
+		bot.send_message(chat_id, format!("Version #{id} is the same as the current file."))
+			.reply_markup(InlineKeyboardMarkup::new([[back]]))
+			.await?;
+		return Ok(());
+	}
+	let markup = InlineKeyboardMarkup::new([[callback_button(format!("Restore #{id}"), file, CallbackAction::Restore(id)), back]]);
+	bot.send_message(chat_id, format!("```diff\n{}```", escape_markdown_v2(&truncate(&diff, MAX_DIFF_LEN))))
+		.parse_mode(teloxide::types::ParseMode::MarkdownV2)
+		.reply_markup(markup)
+		.await?;
+	Ok(())
+}
+
+/// Leaves room in a message for what surrounds the diff; Telegram allows 4096 characters.
+const MAX_DIFF_LEN: usize = 3500;
+
+/// Cut `s` down to at most `max` characters, at a line boundary, saying that it was.
+fn truncate(s: &str, max: usize) -> std::borrow::Cow<'_, str> {
+	if s.chars().count() <= max {
+		return s.into();
+	}
+	let mut end = s.char_indices().nth(max).map_or(s.len(), |(i, _)| i);
+	if let Some(newline) = s[..end].rfind('\n') {
+		end = newline + 1;
+	}
+	format!("{}… (cut short)\n", &s[..end]).into()
+}
+
 fn escape_markdown_v2(s: &str) -> String {
 	let mut result = String::with_capacity(s.len());
 	for c in s.chars() {
@@ -256,131 +874,579 @@ fn escape_markdown_v2(s: &str) -> String {
 	result
 }
 
-async fn callback_query_handler(bot: Bot, dialogue: MyDialogue, q: CallbackQuery, data: Arc<RwLock<Data>>) -> HandlerResult {
+async fn callback_query_handler(bot: Bot, dialogue: MyDialogue, q: CallbackQuery, targets: Targets, undo: UndoStacks) -> HandlerResult {
 	bot.answer_callback_query(q.id.clone()).await?; // normally this is done after, but I like how it stops for a moment before the action is performed. Otherwise looks cut.
 	if let Some(j) = q.data {
-		let action: CallbackAction = serde_json::from_str(&j).unwrap();
+		// menus sent before the callback data last changed shape, or anything else that doesn't parse
+		let Ok(Callback(file, action)) = serde_json::from_str(&j) else {
+			bot.send_message(dialogue.chat_id(), OUTDATED_MENU).await?;
+			return Ok(());
+		};
+		if file >= targets.len() {
+			bot.send_message(dialogue.chat_id(), "That menu is for a file that isn't managed anymore. Use /admin to start over.")
+				.await?;
+			return Ok(());
+		}
+		let value_path = match action.path_id().map(|id| targets.path(file, id)) {
+			Some(Some(path)) => path,
+			Some(None) => {
+				bot.send_message(dialogue.chat_id(), OUTDATED_MENU).await?;
+				return Ok(());
+			}
+			None => ValuePath::default(),
+		};
+		let data = targets.data(file);
 		match action {
-			CallbackAction::Go(value_path) => {
-				continue_navigation(bot.clone(), dialogue, data, value_path).await?;
+			CallbackAction::Files => {
+				continue_navigation(&bot, &dialogue, file_picker(&targets, CallbackAction::Go(PathId::TOP))).await?;
+			}
+			CallbackAction::Full => {
+				send_full(&bot, dialogue.chat_id(), &targets, file).await?;
+			}
+			CallbackAction::Patch => {
+				start_patch(&bot, &dialogue, file).await?;
+			}
+			CallbackAction::Undo(id) => match undo.take_undo(dialogue.chat_id(), Some(id)) {
+				Some(undoable) => revert(&bot, &dialogue, &targets, &undo, editor(Some(&q.from)), undoable, false).await?,
+				None => {
+					bot.send_message(
+						dialogue.chat_id(),
+						"That edit has been undone already, or isn't the latest one anymore. /undo undoes edits one by one, latest first.",
+					)
+					.await?;
+				}
+			},
+			CallbackAction::History => {
+				send_history(&bot, dialogue.chat_id(), &targets, file).await?;
+			}
+			CallbackAction::Version(id) => {
+				send_version(&bot, dialogue.chat_id(), &targets, file, id).await?;
+			}
+			CallbackAction::Restore(id) => match commit(&bot, dialogue.chat_id(), &targets, file, &undo, editor(Some(&q.from)), |data| data.restore(id)).await? {
+				Ok(()) => {
+					bot.send_message(
+						dialogue.chat_id(),
+						format!("Version #{id} has been restored. The version it replaced is kept in /history as well."),
+					)
+					.await?;
+					send_menu(&bot, &dialogue, render_header_and_markup(&targets, file, &ValuePath::default())).await?;
+				}
+				Err(e) => {
+					bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+				}
+			},
+			CallbackAction::Go(_) => {
+				continue_navigation(&bot, &dialogue, render_header_and_markup(&targets, file, &value_path)).await?;
 			}
-			CallbackAction::UpdateAt(value_path) => {
-				dialogue.update(ChatState::Input(ValueInput::new(InputValueType::UpdateAt, value_path.clone()))).await?;
+			CallbackAction::UpdateAt(_) => {
+				let prompt = update_prompt(&targets, file, &value_path);
+				match prompt {
+					Ok((text, markup)) => {
+						dialogue.update(ChatState::Input(ValueInput::new(file, InputValueType::UpdateAt, value_path.clone()))).await?;
+						bot.send_message(dialogue.chat_id(), text).reply_markup(markup).await?;
+					}
+					Err(e) => {
+						bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+					}
+				}
+			}
+			CallbackAction::Toggle(_) => {
+				let current = data.read().unwrap().at(&value_path);
+				match current {
+					Ok(Value::Bool(current)) => match commit(&bot, dialogue.chat_id(), &targets, file, &undo, editor(Some(&q.from)), |data| {
+						data.commit_at(&value_path, Value::Bool(!current), UpdateAction::Set)
+					})
+					.await?
+					{
+						Ok(()) => continue_navigation(&bot, &dialogue, render_header_and_markup(&targets, file, &value_path.parent())).await?,
+						Err(e) => {
+							bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+						}
+					},
+					// it isn't a boolean anymore; the menu shows what it is now
+					Ok(_) => continue_navigation(&bot, &dialogue, render_header_and_markup(&targets, file, &value_path.parent())).await?,
+					Err(e) => {
+						bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+					}
+				}
+			}
+			CallbackAction::Choose(..) | CallbackAction::Step(..) => {
+				let new_value = {
+					let data = data.read().unwrap();
+					match (&action, data.at(&value_path)) {
+						(_, Err(e)) => Err(friendly_error(&e)),
+						(CallbackAction::Choose(_, index), Ok(current)) => match choices(&data, &value_path, &current).get(*index) {
+							Some(choice) if *choice == current => return Ok(()),
+							Some(choice) => Ok(choice.clone()),
+							None => Err("That option isn't offered anymore. Pick another one, or send the value.".to_owned()),
+						},
+						(_, Ok(current)) => {
+							let up = matches!(action, CallbackAction::Step(_, true));
+							let range = data.schema().and_then(|schema| schema.range(&value_path));
+							match range.zip(current.as_f64()).and_then(|(range, current)| range.stepped(current, up)) {
+								Some(stepped) => Ok(number(stepped)),
+								None => Err("That would take it out of its range.".to_owned()),
+							}
+						}
+					}
+				};
+				let new_value = match new_value {
+					Ok(new_value) => new_value,
+					Err(text) => {
+						bot.send_message(dialogue.chat_id(), text).await?;
+						return Ok(());
+					}
+				};
+				match commit(&bot, dialogue.chat_id(), &targets, file, &undo, editor(Some(&q.from)), |data| {
+					data.commit_at(&value_path, new_value, UpdateAction::Set)
+				})
+				.await?
+				{
+					Ok(()) => {
+						let prompt = update_prompt(&targets, file, &value_path);
+						match (prompt, q.message.as_ref()) {
+							(Ok((text, markup)), Some(message)) => {
+								// the prompt the button was on now shows the new value
+								if let Err(e) = bot.edit_message_text(dialogue.chat_id(), message.id(), &text).reply_markup(markup.clone()).await {
+									tracing::debug!("Couldn't edit the prompt: {e}");
+									bot.send_message(dialogue.chat_id(), text).reply_markup(markup).await?;
+								}
+							}
+							(Ok((text, markup)), None) => {
+								bot.send_message(dialogue.chat_id(), text).reply_markup(markup).await?;
+							}
+							(Err(e), _) => {
+								bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+							}
+						}
+					}
+					Err(e) => {
+						bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+					}
+				}
+			}
+			CallbackAction::AddTo(_) => {
+				let expected = expected(&data.read().unwrap(), &value_path.join("-"));
+				dialogue.update(ChatState::Input(ValueInput::new(file, InputValueType::AddTo, value_path.clone()))).await?;
 				bot.send_message(
 					dialogue.chat_id(),
-					format!("You're updating `{}: {}`.\nInsert the new value, or /abort to cancel.", &value_path.basename(), {
-						let data_lock = data.read().unwrap();
-						get_json_type(&data_lock.at(&value_path).unwrap())
-					}),
+					format!("You're adding to {value_path}.{expected}\nProvide the value to add, or /abort to cancel."),
 				)
 				.await?;
 			}
-			CallbackAction::AddTo(value_path) => {
-				dialogue.update(ChatState::Input(ValueInput::new(InputValueType::AddTo, value_path.clone()))).await?;
-				bot.send_message(dialogue.chat_id(), format!("You're adding to {value_path}.\nProvide the value to add, or /abort to cancel."))
-					.await?;
-			}
-			CallbackAction::RemoveFrom(value_path) => {
-				dialogue.update(ChatState::Input(ValueInput::new(InputValueType::RemoveFrom, value_path.clone()))).await?;
+			CallbackAction::RemoveFrom(_) => {
+				dialogue.update(ChatState::Input(ValueInput::new(file, InputValueType::RemoveFrom, value_path.clone()))).await?;
 				bot.send_message(
 					dialogue.chat_id(),
 					format!("You're removing from {value_path}.\nProvide exact value to remove, or /abort to cancel."),
 				)
 				.await?;
 			}
+			CallbackAction::AddKey(_) => {
+				dialogue.update(ChatState::NewKey { file, parent: value_path.clone() }).await?;
+				bot.send_message(dialogue.chat_id(), format!("You're adding a key to {value_path}.\nSend its name, or /abort to cancel."))
+					.await?;
+			}
+			CallbackAction::NewKeyType(value_type) => {
+				let Some(ChatState::NewKeyType { file, path: value_path }) = dialogue.get().await? else {
+					bot.send_message(dialogue.chat_id(), OUTDATED_MENU).await?;
+					return Ok(());
+				};
+				match value_type.empty_value() {
+					Some(value) => match commit(&bot, dialogue.chat_id(), &targets, file, &undo, editor(Some(&q.from)), |data| {
+						data.commit_at(&value_path, value, UpdateAction::Insert)
+					})
+					.await?
+					{
+						Ok(()) => {
+							bot.send_message(dialogue.chat_id(), format!("`{value_path}` has been created")).await?;
+							let menu_path = match value_type {
+								NewValueType::Object | NewValueType::Array => value_path,
+								_ => value_path.parent(),
+							};
+							send_menu(&bot, &dialogue, render_header_and_markup(&targets, file, &menu_path)).await?;
+						}
+						Err(e) => {
+							bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+						}
+					},
+					None => {
+						let expected = expected(&targets.data(file).read().unwrap(), &value_path);
+						dialogue
+							.update(ChatState::Input(ValueInput::new(file, InputValueType::Insert(value_type), value_path.clone())))
+							.await?;
+						bot.send_message(
+							dialogue.chat_id(),
+							format!(
+								"You're creating `{}: {}`.{expected}\nInsert its value, or /abort to cancel.",
+								value_path.basename(),
+								value_type.name()
+							),
+						)
+						.await?;
+					}
+				}
+			}
+			CallbackAction::Rename(_) => {
+				dialogue.update(ChatState::Input(ValueInput::new(file, InputValueType::Rename, value_path.clone()))).await?;
+				bot.send_message(dialogue.chat_id(), format!("You're renaming {value_path}.\nSend the new name, or /abort to cancel."))
+					.await?;
+			}
+			CallbackAction::Delete(_) => {
+				bot.send_message(dialogue.chat_id(), format!("Delete {value_path} and everything under it?"))
+					.reply_markup(delete_markup(&targets, file, &value_path))
+					.await?;
+				dialogue.update(ChatState::Delete { file, path: value_path }).await?;
+			}
+			CallbackAction::ConfirmDelete => {
+				let Some(ChatState::Delete { file, path: value_path }) = dialogue.get().await? else {
+					bot.send_message(dialogue.chat_id(), OUTDATED_MENU).await?;
+					return Ok(());
+				};
+				match commit(&bot, dialogue.chat_id(), &targets, file, &undo, editor(Some(&q.from)), |data| {
+					data.commit_at(&value_path, Value::Null, UpdateAction::Delete)
+				})
+				.await?
+				{
+					Ok(()) => {
+						bot.send_message(dialogue.chat_id(), format!("`{value_path}` has been deleted")).await?;
+						send_menu(&bot, &dialogue, render_header_and_markup(&targets, file, &value_path.parent())).await?;
+					}
+					Err(e) => {
+						bot.send_message(dialogue.chat_id(), friendly_error(&e)).await?;
+					}
+				}
+			}
 		}
 	}
 	Ok(())
 }
 
-async fn continue_navigation(bot: Bot, dialogue: MyDialogue, data: Arc<RwLock<Data>>, value_path: ValuePath) -> HandlerResult {
-	let (header, markup) = {
-		let data = data.read().unwrap();
-		render_header_and_markup(&data, &value_path)
-	};
-
-	let state = dialogue.get().await.unwrap().unwrap();
-	let message_id = match state {
-		ChatState::Navigation { message_id } => message_id,
-		_ => unreachable!(),
+/// Show a menu by editing the current navigation message.
+async fn continue_navigation(bot: &Bot, dialogue: &MyDialogue, (header, markup): (String, InlineKeyboardMarkup)) -> HandlerResult {
+	// buttons of an old menu may be pressed while something else is going on; that simply starts a new menu
+	let Some(ChatState::Navigation { message_id }) = dialogue.get().await? else {
+		return send_menu(bot, dialogue, (header, markup)).await;
 	};
 
 	match bot.edit_message_text(dialogue.chat_id(), MessageId(message_id), &header).reply_markup(markup.clone()).await {
 		Ok(_) => Ok(()),
 		//TODO!: assert that the err is about message being too old, as it's the only recoverable one.
 		Err(err) => {
-			dbg!(err);
-			let sent_message = bot.send_message(dialogue.chat_id(), &header).reply_markup(markup).await?;
-			dialogue.update(ChatState::Navigation { message_id: sent_message.id.0 }).await?;
-			Ok(())
+			tracing::debug!("Couldn't edit the menu, sending a new one instead: {err}");
+			send_menu(bot, dialogue, (header, markup)).await
 		}
 	}
 }
 
+/// Callback data: the index of the target file, and what to do in it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Callback(usize, CallbackAction);
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, derive_new::new)]
 enum CallbackAction {
-	Go(ValuePath),
-	UpdateAt(ValuePath),
-	AddTo(ValuePath),
-	RemoveFrom(ValuePath),
+	/// Back to the file picker; the file index is ignored.
+	Files,
+	Full,
+	Patch,
+	/// Undo the edit with this id, if it's still the latest one of the chat.
+	Undo(u64),
+	History,
+	/// Preview restoring the version with this id.
+	Version(u64),
+	Restore(u64),
+	Go(PathId),
+	UpdateAt(PathId),
+	/// Flip the boolean at the path.
+	Toggle(PathId),
+	/// Set the path to the choice with this index, out of what [`choices`] offers for it.
+	Choose(PathId, usize),
+	/// Step the number at the path up (`true`) or down by its schema's step.
+	Step(PathId, bool),
+	AddTo(PathId),
+	RemoveFrom(PathId),
+	AddKey(PathId),
+	/// Create the key kept in [`ChatState::NewKeyType`], holding this type.
+	NewKeyType(NewValueType),
+	Rename(PathId),
+	Delete(PathId),
+	/// Delete what [`ChatState::Delete`] holds.
+	ConfirmDelete,
+}
+
+impl CallbackAction {
+	/// The path the action is on, if it's on one.
+	fn path_id(&self) -> Option<PathId> {
+		match self {
+			Self::Go(id)
+			| Self::UpdateAt(id)
+			| Self::Toggle(id)
+			| Self::Choose(id, _)
+			| Self::Step(id, _)
+			| Self::AddTo(id)
+			| Self::RemoveFrom(id)
+			| Self::AddKey(id)
+			| Self::Rename(id)
+			| Self::Delete(id) => Some(*id),
+			_ => None,
+		}
+	}
+}
+
+fn callback_button(text: impl Into<String>, file: usize, action: CallbackAction) -> InlineKeyboardButton {
+	InlineKeyboardButton::callback(text, serde_json::to_string(&Callback(file, action)).unwrap())
+}
+
+/// A line on what the schema expects at `path`, to go in a prompt; empty without one.
+fn expected(data: &Data, path: &ValuePath) -> String {
+	match data.schema().and_then(|schema| schema.expectation(path)) {
+		Some(expectation) => format!("\nExpected: {expectation}."),
+		None => String::new(),
+	}
+}
+
+/// The prompt for a new value at `value_path`, with buttons to set it in a tap where the options are known.
+fn update_prompt(targets: &Targets, file: usize, value_path: &ValuePath) -> Result<(String, InlineKeyboardMarkup), DataError> {
+	let data = targets.data(file).read().unwrap();
+	let current = data.at(value_path)?;
+	let mut keyboard = quick_edit_rows(targets, &data, file, value_path, &current);
+	let pick = match keyboard.is_empty() {
+		true => "",
+		false => " or pick one",
+	};
+	let text = format!(
+		"You're updating `{}: {}`, currently `{current}`.{}\nInsert the new value{pick}, or /abort to cancel.",
+		value_path.basename(),
+		get_json_type(&current),
+		expected(&data, value_path)
+	);
+	keyboard.push(structural_buttons(targets, &data, file, value_path));
+	Ok((text, InlineKeyboardMarkup::new(keyboard)))
+}
+
+/// Buttons setting the value at `value_path` in a tap: one per choice, the current one marked, and steps up and down for numbers the schema bounds.
+fn quick_edit_rows(targets: &Targets, data: &Data, file: usize, value_path: &ValuePath, current: &Value) -> Vec<Vec<InlineKeyboardButton>> {
+	let id = targets.path_id(file, value_path);
+	let buttons: Vec<_> = choices(data, value_path, current)
+		.iter()
+		.enumerate()
+		.map(|(index, choice)| {
+			let label = match choice {
+				Value::String(s) => s.clone(),
+				_ => choice.to_string(),
+			};
+			let label = match choice == current {
+				true => format!("• {label}"),
+				false => label,
+			};
+			callback_button(label, file, CallbackAction::Choose(id, index))
+		})
+		.collect();
+	let mut rows: Vec<_> = buttons.chunks(3).map(<[_]>::to_vec).collect();
+
+	if let (Some(range), Some(number)) = (data.schema().and_then(|schema| schema.range(value_path)), current.as_f64()) {
+		let step = range.step();
+		let mut row = Vec::new();
+		if range.stepped(number, false).is_some() {
+			row.push(callback_button(format!("− {step}"), file, CallbackAction::Step(id, false)));
+		}
+		if range.stepped(number, true).is_some() {
+			row.push(callback_button(format!("+ {step}"), file, CallbackAction::Step(id, true)));
+		}
+		if !row.is_empty() {
+			rows.push(row);
+		}
+	}
+	rows
+}
+
+/// What the value at `value_path` can be set to in a tap: the schema's `enum` values, or both booleans for a boolean.
+fn choices(data: &Data, value_path: &ValuePath, current: &Value) -> Vec<Value> {
+	match data.schema().and_then(|schema| schema.choices(value_path)) {
+		Some(choices) => choices.to_vec(),
+		None if current.is_boolean() => vec![Value::Bool(true), Value::Bool(false)],
+		None => Vec::new(),
+	}
+}
+
+/// A stepped number as JSON, without a fraction when it has none.
+fn number(n: f64) -> Value {
+	match n.fract() == 0.0 && n.abs() < 2f64.powi(53) {
+		true => Value::from(n as i64),
+		false => Value::from(n),
+	}
 }
 
-fn render_header_and_markup(data: &Data, value_path: &ValuePath) -> (String, InlineKeyboardMarkup) {
+/// Sent when a button is pressed on a menu that doesn't apply anymore.
+const OUTDATED_MENU: &str = "This menu is outdated. Use /admin to start over.";
+
+/// What to tell the user when a [`DataError`] comes up.
+fn friendly_error(e: &DataError) -> String {
+	match e {
+		DataError::PathNotFound(path) => format!("`{path}` doesn't exist (anymore?). Use /admin to start from the top."),
+		DataError::TypeMismatch { expected, actual, .. } => format!("This array holds {expected} values, but you sent a {actual}. Try again, or /abort to cancel."),
+		DataError::NotAContainer { path, expected, actual } => format!("`{path}` is a {actual}, not an {expected}, so that can't be done there."),
+		DataError::AlreadyExists(path) => format!("`{path}` already exists. Send another name, or /abort to cancel."),
+		DataError::AtRoot => "The top level itself can't be created, deleted or renamed.".to_owned(),
+		DataError::NotInArray { path, value } => format!("`{value}` isn't in `{path}`. Send the exact value to remove, or /abort to cancel."),
+		DataError::NotRepresentable { format, reason } => format!("Can't save this as {format}, so nothing was changed.\n{reason}"),
+		DataError::Parse { format, reason } => format!("The {format} file couldn't be read.\n{reason}"),
+		DataError::Conflict(_) =>
+			"The file was changed outside the bot since this menu was opened, so your edit wasn't applied. The latest version has been loaded; use /admin to make the edit again.".to_owned(),
+		DataError::TestFailed { path, expected } => format!("`{path}` is no longer `{expected}`, so the patch doesn't apply."),
+		DataError::InvalidPatch(reason) => format!("That's not a valid JSON Patch: {reason}. Fix it and send it again, or /abort to cancel."),
+		DataError::Patch { index, source } => format!("Operation #{index}: {}", friendly_error(source)),
+		DataError::ChangedSince(path) => format!("`{path}` has been changed again since, so that can't be done without losing the newer change."),
+		DataError::SchemaViolation(violations) => format!(
+			"That doesn't fit the schema, so nothing was changed.\n{}",
+			violations.iter().map(|violation| format!("• {violation}")).collect::<Vec<_>>().join("\n")
+		),
+		DataError::InvalidSchema { .. } => e.to_string(),
+		DataError::VersionNotFound(id) => format!("Version #{id} isn't kept anymore. Use /history to see the ones that are."),
+		DataError::UnsupportedFormat(path) => format!("Couldn't tell what format `{}` is in.", path.display()),
+		DataError::Io(e) => format!("Couldn't access the file: {e}"),
+	}
+}
+
+/// The menu `/admin` opens: the top of the only file, or a picker when there are several.
+fn top_menu(targets: &Targets) -> (String, InlineKeyboardMarkup) {
+	match targets.len() {
+		1 => render_header_and_markup(targets, 0, &ValuePath::default()),
+		_ => file_picker(targets, CallbackAction::Go(PathId::TOP)),
+	}
+}
+
+/// One button per target file, each doing `action` in its file.
+fn file_picker(targets: &Targets, action: CallbackAction) -> (String, InlineKeyboardMarkup) {
+	let keyboard = (0..targets.len()).map(|file| vec![callback_button(targets.name(file), file, action.clone())]);
+	("Pick a file".to_owned(), InlineKeyboardMarkup::new(keyboard))
+}
+
+fn render_header_and_markup(targets: &Targets, file: usize, value_path: &ValuePath) -> (String, InlineKeyboardMarkup) {
+	let data = targets.data(file).read().unwrap();
 	let mut keyboard = Vec::new();
-	let current_value_at_path = &data.at(value_path).unwrap();
-	let mut header = value_path.to_string();
+	let current_value_at_path = match data.at(value_path) {
+		Ok(value @ (Value::Object(_) | Value::Array(_))) => value,
+		Ok(value) => {
+			let e = DataError::NotAContainer {
+				path: value_path.clone(),
+				expected: "Object",
+				actual: get_json_type(&value),
+			};
+			return error_header_and_markup(&e, file);
+		}
+		Err(e) => return error_header_and_markup(&e, file),
+	};
+	let mut header = match targets.len() {
+		1 => value_path.to_string(),
+		_ => format!("{}: {value_path}", targets.name(file)),
+	};
 
-	// Add parent navigation button if not at top level
+	// Add parent navigation button if not at top level; the top of a file leads back to the file picker
 	if !value_path.is_top() {
-		let callback_action = CallbackAction::Go(value_path.parent());
-		let button = InlineKeyboardButton::callback("..", serde_json::to_string(&callback_action).unwrap());
-		keyboard.push(vec![button]);
+		keyboard.push(vec![callback_button("..", file, CallbackAction::Go(targets.path_id(file, &value_path.parent())))]);
+	} else if targets.len() > 1 {
+		keyboard.push(vec![callback_button("..", file, CallbackAction::Files)]);
 	}
 
-	match current_value_at_path {
-		Value::Object(map) =>
+	match &current_value_at_path {
+		Value::Object(map) => {
 			for (key, val) in map {
+				let id = targets.path_id(file, &value_path.join(key));
 				let (display_text, callback_data) = match val {
-					Value::Object(_) | Value::Array(_) => (value_preview(key, val), CallbackAction::Go(value_path.join(key))),
-					_ => (value_preview(key, val), CallbackAction::UpdateAt(value_path.join(key))),
+					Value::Object(_) | Value::Array(_) => (value_preview(key, val), CallbackAction::Go(id)),
+					_ => (value_preview(key, val), CallbackAction::UpdateAt(id)),
 				};
 
-				let button = InlineKeyboardButton::callback(display_text, serde_json::to_string(&callback_data).unwrap());
-				keyboard.push(vec![button]);
-			},
+				let mut row = vec![callback_button(display_text, file, callback_data)];
+				if val.is_boolean() {
+					row.push(callback_button("Toggle", file, CallbackAction::Toggle(id)));
+				}
+				keyboard.push(row);
+			}
+			keyboard.push(vec![callback_button("Add key", file, CallbackAction::AddKey(targets.path_id(file, value_path)))]);
+		}
 		Value::Array(arr) => {
 			header.push_str(&format!(" [{}]", arr.len()));
 
 			let start = arr.len().saturating_sub(25);
-			let mut array_str = "\n```json\n".to_owned();
-			for a in arr.iter().skip(start) {
-				array_str.push_str(&format!("{a}\n"));
+			if start > 0 {
+				header.push_str(&format!(", showing the last {}", arr.len() - start));
+			}
+			for (i, val) in arr.iter().enumerate().skip(start) {
+				let key = i.to_string();
+				let id = targets.path_id(file, &value_path.join(&key));
+				let callback_data = match val {
+					Value::Object(_) | Value::Array(_) => CallbackAction::Go(id),
+					_ => CallbackAction::UpdateAt(id),
+				};
+				let mut row = vec![callback_button(value_preview(&key, val), file, callback_data)];
+				if val.is_boolean() {
+					row.push(callback_button("Toggle", file, CallbackAction::Toggle(id)));
+				}
+				keyboard.push(row);
 			}
-			array_str.push_str("```");
-			header += &array_str;
 
+			let id = targets.path_id(file, value_path);
 			let bottom_row = vec![
-				InlineKeyboardButton::callback("Add", serde_json::to_string(&CallbackAction::AddTo(value_path.clone())).unwrap()),
-				InlineKeyboardButton::callback("Remove", serde_json::to_string(&CallbackAction::RemoveFrom(value_path.clone())).unwrap()),
+				callback_button("Add", file, CallbackAction::AddTo(id)),
+				callback_button("Remove", file, CallbackAction::RemoveFrom(id)),
 			];
 			//TODO!: make doubled horizontally `<-` and `->` buttons that modify starting position of the count
 			keyboard.push(bottom_row);
 		}
-		_ => {
-			unreachable!();
-		}
+		_ => unreachable!("only containers are rendered"),
+	}
+	let structural = structural_buttons(targets, &data, file, value_path);
+	if !structural.is_empty() {
+		keyboard.push(structural);
 	}
 
 	(header, InlineKeyboardMarkup::new(keyboard))
 }
 
+/// A button per type a new key can hold.
+fn new_key_type_markup(file: usize) -> InlineKeyboardMarkup {
+	let buttons = NewValueType::ALL.map(|value_type| callback_button(value_type.name(), file, CallbackAction::NewKeyType(value_type)));
+	InlineKeyboardMarkup::new(buttons.chunks(3).map(<[_]>::to_vec))
+}
+
+/// Confirming or cancelling the deletion of `value_path`.
+fn delete_markup(targets: &Targets, file: usize, value_path: &ValuePath) -> InlineKeyboardMarkup {
+	InlineKeyboardMarkup::new([[
+		callback_button("Yes, delete", file, CallbackAction::ConfirmDelete),
+		callback_button("Cancel", file, CallbackAction::Go(targets.path_id(file, &value_path.parent()))),
+	]])
+}
+
+/// Rename and Delete for the value at `value_path`; renaming only applies to object keys.
+fn structural_buttons(targets: &Targets, data: &Data, file: usize, value_path: &ValuePath) -> Vec<InlineKeyboardButton> {
+	if value_path.is_top() {
+		return Vec::new();
+	}
+	let id = targets.path_id(file, value_path);
+	let mut buttons = Vec::new();
+	if matches!(data.at(&value_path.parent()), Ok(Value::Object(_))) {
+		buttons.push(callback_button("Rename", file, CallbackAction::Rename(id)));
+	}
+	buttons.push(callback_button("Delete", file, CallbackAction::Delete(id)));
+	buttons
+}
+
+/// Shown instead of a menu when the path can't be rendered; offers a way back to the top.
+fn error_header_and_markup(e: &DataError, file: usize) -> (String, InlineKeyboardMarkup) {
+	let button = callback_button("Back to top", file, CallbackAction::Go(PathId::TOP));
+	(friendly_error(e), InlineKeyboardMarkup::new(vec![vec![button]]))
+}
+
 #[cfg(test)]
 mod tests {
 	use serde_json::json;
+	use teloxide::types::InlineKeyboardButtonKind;
 
 	use super::*;
 
-	fn gen_data() -> (Data, ValuePath) {
+	fn gen_data() -> (Targets, ValuePath) {
 		let json_value = json!({
 			"name": "Alice",
 			"age": 25,
@@ -390,13 +1456,13 @@ mod tests {
 			},
 			"emails": ["alice@example.com", "a@example.com"]
 		});
-		(Data::mock(json_value), ValuePath::default())
+		(Targets::new(vec![Data::mock(json_value)]), ValuePath::default())
 	}
 
 	#[test]
 	fn test_top_value_path_representation() {
 		let (data, value_path) = gen_data();
-		let (_h, r) = render_header_and_markup(&data, &value_path);
+		let (_h, r) = render_header_and_markup(&data, 0, &value_path);
 
 		insta::assert_json_snapshot!(
 			r,
@@ -405,26 +1471,32 @@ mod tests {
     "inline_keyboard": [
       [
         {
-          "text": "{} address",
-          "callback_data": "{\"Go\":\"/address\"}"
+          "text": "name: \"Alice\"",
+          "callback_data": "[0,{\"UpdateAt\":1}]"
         }
       ],
       [
         {
           "text": "age: 25",
-          "callback_data": "{\"UpdateAt\":\"/age\"}"
+          "callback_data": "[0,{\"UpdateAt\":2}]"
+        }
+      ],
+      [
+        {
+          "text": "{} address",
+          "callback_data": "[0,{\"Go\":3}]"
         }
       ],
       [
         {
           "text": "[2] emails",
-          "callback_data": "{\"Go\":\"/emails\"}"
+          "callback_data": "[0,{\"Go\":4}]"
         }
       ],
       [
         {
-          "text": "name: \"Alice\"",
-          "callback_data": "{\"UpdateAt\":\"/name\"}"
+          "text": "Add key",
+          "callback_data": "[0,{\"AddKey\":0}]"
         }
       ]
     ]
@@ -437,7 +1509,7 @@ mod tests {
 	fn test_nested_value_path_representation() {
 		let (data, mut value_path) = gen_data();
 		value_path.push("address");
-		let (_h, r) = render_header_and_markup(&data, &value_path);
+		let (_h, r) = render_header_and_markup(&data, 0, &value_path);
 		insta::assert_json_snapshot!(
 			r,
 			@r###"
@@ -446,19 +1518,35 @@ mod tests {
       [
         {
           "text": "..",
-          "callback_data": "{\"Go\":\"/\"}"
+          "callback_data": "[0,{\"Go\":0}]"
+        }
+      ],
+      [
+        {
+          "text": "street: \"456 Another St\"",
+          "callback_data": "[0,{\"UpdateAt\":1}]"
         }
       ],
       [
         {
           "text": "city: \"Elsewhere\"",
-          "callback_data": "{\"UpdateAt\":\"/address/city\"}"
+          "callback_data": "[0,{\"UpdateAt\":2}]"
         }
       ],
       [
         {
-          "text": "street: \"456 Another St\"",
-          "callback_data": "{\"UpdateAt\":\"/address/street\"}"
+          "text": "Add key",
+          "callback_data": "[0,{\"AddKey\":3}]"
+        }
+      ],
+      [
+        {
+          "text": "Rename",
+          "callback_data": "[0,{\"Rename\":3}]"
+        },
+        {
+          "text": "Delete",
+          "callback_data": "[0,{\"Delete\":3}]"
         }
       ]
     ]
@@ -470,15 +1558,9 @@ mod tests {
 	fn test_array_value_path_representation() {
 		let (data, mut value_path) = gen_data();
 		value_path.push("emails");
-		let (h, r) = render_header_and_markup(&data, &value_path);
+		let (h, r) = render_header_and_markup(&data, 0, &value_path);
 
-		insta::assert_snapshot!(h, @r###"
-/emails [2]
-```json
-"alice@example.com"
-"a@example.com"
-```
-"###);
+		insta::assert_snapshot!(h, @"/emails [2]");
 
 		insta::assert_json_snapshot!(
 			r,
@@ -488,17 +1570,39 @@ mod tests {
       [
         {
           "text": "..",
-          "callback_data": "{\"Go\":\"/\"}"
+          "callback_data": "[0,{\"Go\":0}]"
+        }
+      ],
+      [
+        {
+          "text": "0: \"alice@example.com\"",
+          "callback_data": "[0,{\"UpdateAt\":1}]"
+        }
+      ],
+      [
+        {
+          "text": "1: \"a@example.com\"",
+          "callback_data": "[0,{\"UpdateAt\":2}]"
         }
       ],
       [
         {
           "text": "Add",
-          "callback_data": "{\"AddTo\":\"/emails\"}"
+          "callback_data": "[0,{\"AddTo\":3}]"
         },
         {
           "text": "Remove",
-          "callback_data": "{\"RemoveFrom\":\"/emails\"}"
+          "callback_data": "[0,{\"RemoveFrom\":3}]"
+        }
+      ],
+      [
+        {
+          "text": "Rename",
+          "callback_data": "[0,{\"Rename\":3}]"
+        },
+        {
+          "text": "Delete",
+          "callback_data": "[0,{\"Delete\":3}]"
         }
       ]
     ]
@@ -506,4 +1610,179 @@ mod tests {
   "###
 		);
 	}
+
+	#[test]
+	fn several_files_get_a_picker() {
+		let targets = Targets::new(vec![
+			Data::new(json!({"a": 1}), "/etc/one/config.json".into()),
+			Data::new(json!({"b": 2}), "/etc/two/config.json".into()),
+			Data::new(json!({"c": [3]}), "/etc/two/other.yaml".into()),
+		]);
+		let (h, r) = top_menu(&targets);
+		assert_eq!(h, "Pick a file");
+		let names: Vec<_> = r.inline_keyboard.iter().map(|row| row[0].text.as_str()).collect();
+		assert_eq!(names, ["/etc/one/config.json", "/etc/two/config.json", "other.yaml"]);
+
+		let (h, r) = render_header_and_markup(&targets, 2, &ValuePath::from("/c"));
+		assert_eq!(h, "other.yaml: /c [1]");
+		insta::assert_json_snapshot!(r.inline_keyboard[1][0], @r###"
+  {
+    "text": "0: 3",
+    "callback_data": "[2,{\"UpdateAt\":1}]"
+  }
+  "###);
+		let (_, r) = render_header_and_markup(&targets, 2, &ValuePath::default());
+		insta::assert_json_snapshot!(r.inline_keyboard[0][0], @r###"
+  {
+    "text": "..",
+    "callback_data": "[2,\"Files\"]"
+  }
+  "###);
+	}
+
+	#[test]
+	fn values_with_known_options_are_set_in_a_tap() {
+		let dir = tempfile::tempdir().unwrap();
+		let schema = dir.path().join("schema.json");
+		std::fs::write(
+			&schema,
+			json!({"properties": {
+				"level": {"enum": ["debug", "info", 3]},
+				"workers": {"type": "integer", "minimum": 1, "maximum": 8}
+			}})
+			.to_string(),
+		)
+		.unwrap();
+		let data = Data::mock(json!({"debug": false, "level": "info", "workers": 1, "name": "x"}))
+			.with_schema(Some(&schema))
+			.unwrap();
+		let targets = Targets::new(vec![data]);
+		let labels = |path: &str| {
+			let (_, markup) = update_prompt(&targets, 0, &ValuePath::from(path)).unwrap();
+			markup
+				.inline_keyboard
+				.iter()
+				.map(|row| row.iter().map(|button| button.text.as_str()).collect::<Vec<_>>().join(" | "))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(labels("/debug"), ["true | • false", "Rename | Delete"]);
+		assert_eq!(labels("/level"), ["debug | • info | 3", "Rename | Delete"]);
+		// already at the minimum
+		assert_eq!(labels("/workers"), ["+ 1", "Rename | Delete"]);
+		assert_eq!(labels("/name"), ["Rename | Delete"]);
+		let (text, _) = update_prompt(&targets, 0, &ValuePath::from("/workers")).unwrap();
+		assert_eq!(
+			text,
+			"You're updating `workers: Number`, currently `1`.\nExpected: integer, from 1 to 8.\nInsert the new value or pick one, or /abort to cancel."
+		);
+
+		let (_, markup) = render_header_and_markup(&targets, 0, &ValuePath::default());
+		insta::assert_json_snapshot!(markup.inline_keyboard[0], @r###"
+  [
+    {
+      "text": "debug: false",
+      "callback_data": "[0,{\"UpdateAt\":1}]"
+    },
+    {
+      "text": "Toggle",
+      "callback_data": "[0,{\"Toggle\":1}]"
+    }
+  ]
+  "###);
+		assert_eq!(number(2.0), json!(2));
+		assert_eq!(number(0.5), json!(0.5));
+		assert!(
+			serde_json::to_string(&Callback(usize::MAX, CallbackAction::Choose(PathId(u32::MAX), u16::MAX.into())))
+				.unwrap()
+				.len() <= 64
+		);
+	}
+
+	#[test]
+	fn long_keys_stay_out_of_callback_data() {
+		let dir = tempfile::tempdir().unwrap();
+		let schema = dir.path().join("schema.json");
+		let long = "a very long key name ".repeat(5);
+		std::fs::write(
+			&schema,
+			json!({"properties": {&long: {"properties": {
+				&long: {"enum": ["debug", "info"]},
+				"workers": {"type": "integer", "minimum": 1, "maximum": 8}
+			}}}})
+			.to_string(),
+		)
+		.unwrap();
+		let data = Data::mock(json!({&long: {&long: "info", "workers": 2, "flag": true, "list": [true], "nested": {&long: 1}}}))
+			.with_schema(Some(&schema))
+			.unwrap();
+		let targets = Targets::new(vec![data]);
+		let path = ValuePath::default().join(&long);
+		let mut buttons = vec![new_key_type_markup(0), delete_markup(&targets, 0, &path.join(&long))];
+		for value_path in [ValuePath::default(), path.clone(), path.join("list"), path.join("nested")] {
+			buttons.push(render_header_and_markup(&targets, 0, &value_path).1);
+		}
+		for value_path in [path.join(&long), path.join("workers"), path.join("flag"), path.join("list").join("0"), path.join("nested")] {
+			buttons.push(update_prompt(&targets, 0, &value_path).unwrap().1);
+		}
+		let all: Vec<_> = buttons.iter().flat_map(|markup| markup.inline_keyboard.iter().flatten()).collect();
+		for action in ["Go", "UpdateAt", "Toggle", "Choose", "Step", "AddTo", "RemoveFrom", "AddKey", "Rename", "Delete"] {
+			assert!(
+				all.iter()
+					.any(|button| matches!(&button.kind, InlineKeyboardButtonKind::CallbackData(data) if data.contains(action))),
+				"no {action} button"
+			);
+		}
+		for button in all {
+			let InlineKeyboardButtonKind::CallbackData(data) = &button.kind else { panic!("{button:?}") };
+			assert!(data.len() <= 64, "{data}");
+		}
+
+		// once forgotten, an id stays outdated rather than standing for some later path
+		let first = targets.path_id(0, &path);
+		for i in 0..MAX_PATH_IDS {
+			targets.path_id(0, &ValuePath::from("/x").join(&i.to_string()));
+		}
+		assert_eq!(targets.path(0, first), None);
+		assert_eq!(targets.path(0, PathId::TOP), Some(ValuePath::default()));
+		assert_ne!(targets.path_id(0, &path), first);
+	}
+
+	#[test]
+	fn long_diffs_are_cut_at_a_line() {
+		assert_eq!(truncate("-a\n+b\n", 10), "-a\n+b\n");
+		assert_eq!(truncate("-aaa\n+bbb\n ccc\n", 12), "-aaa\n+bbb\n… (cut short)\n");
+		// Telegram allows at most 64 bytes of callback data
+		assert!(serde_json::to_string(&Callback(usize::MAX, CallbackAction::Restore(u64::MAX))).unwrap().len() <= 64);
+		// callback data of menus sent before it named the file doesn't parse, and is answered as outdated
+		assert!(serde_json::from_str::<Callback>(r#"{"Go":"/"}"#).is_err());
+	}
+
+	#[test]
+	fn undo_stacks_are_per_chat_and_redo_is_cleared_by_new_edits() {
+		let undo = UndoStacks::default();
+		let (alice, bob) = (ChatId(1), ChatId(2));
+		let edit = |path: &str| {
+			vec![Edit {
+				path: ValuePath::from(path),
+				before: Some(json!(1)),
+				after: Some(json!(2)),
+			}]
+		};
+		undo.record(alice, 0, edit("/a"));
+		undo.record(alice, 0, edit("/b"));
+		undo.record(bob, 1, edit("/c"));
+		assert_eq!(undo.latest(alice), Some(2));
+
+		// an old Undo button only undoes its own edit, and only while it's the latest one
+		assert!(undo.take_undo(alice, Some(1)).is_none());
+		let latest = undo.take_undo(alice, Some(2)).unwrap();
+		assert_eq!(latest.edits, edit("/b"));
+		undo.put(alice, latest, true);
+		assert_eq!(undo.take_undo(alice, None).unwrap().edits, edit("/a"));
+		assert!(undo.take_undo(alice, None).is_none());
+		assert_eq!(undo.take_undo(bob, None).unwrap().file, 1);
+
+		undo.record(alice, 0, edit("/d"));
+		assert!(undo.take_redo(alice).is_none());
+	}
 }
@@ -1,6 +1,12 @@
+use std::{
+	fs::OpenOptions,
+	io::{self, Write as _},
+	path::Path,
+};
+
 use serde_json::Value;
 
-pub fn get_json_type(value: &Value) -> &str {
+pub fn get_json_type(value: &Value) -> &'static str {
 	match value {
 		Value::Null => "Null",
 		Value::Bool(_) => "Boolean",
@@ -15,6 +21,52 @@ pub fn value_preview(key: &String, value: &Value) -> String {
 	match value {
 		Value::Object(_) => format!("{{}} {}", key),
 		Value::Array(arr) => format!("[{}] {}", arr.len(), key),
-		_ => format!("{}: {}", key, &value.to_string()),
+		_ => format!("{}: {}", key, value),
+	}
+}
+
+/// Replace the contents of `path` without ever leaving it truncated or half-written.
+///
+/// The content goes to a temporary file next to the target, which is fsynced, given the target's permissions and ownership, and renamed over it. Symlinks are followed, so the file they point to is the one replaced.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+	let path = match std::fs::canonicalize(path) {
+		Ok(resolved) => resolved,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+		Err(e) => return Err(e),
+	};
+	let file_name = path
+		.file_name()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "target path has no file name"))?
+		.to_string_lossy();
+	let tmp = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+	let result = (|| -> io::Result<()> {
+		let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+		file.write_all(content)?;
+		if let Ok(metadata) = std::fs::metadata(&path) {
+			file.set_permissions(metadata.permissions())?;
+			#[cfg(unix)]
+			{
+				use std::os::unix::fs::MetadataExt as _;
+				// only root can give files away; for everyone else this is a no-op as long as they own the target
+				if let Err(e) = std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid())) {
+					tracing::warn!("Couldn't keep ownership of {}: {e}", path.display());
+				}
+			}
+		}
+		file.sync_all()?;
+		std::fs::rename(&tmp, &path)?;
+		Ok(())
+	})();
+	if result.is_err() {
+		let _ = std::fs::remove_file(&tmp);
+	}
+	result?;
+
+	// make the rename itself durable
+	#[cfg(unix)]
+	if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+		std::fs::File::open(dir).and_then(|d| d.sync_all())?;
 	}
+	Ok(())
 }